@@ -15,7 +15,7 @@
 
 use crate::{error::Error, Crypto};
 use beefy_light_client_primitives::{MerkleHasher, SignatureWithAuthorityIndex};
-use codec::{Decode, Encode};
+use codec::{Decode, DecodeAll, Encode};
 use frame_support::sp_runtime::traits::Convert;
 use sp_core::keccak_256;
 use sp_runtime::traits::BlakeTwo256;
@@ -67,34 +67,71 @@ pub async fn fetch_timestamp_extrinsic_with_proof<T: Config>(
 	})?;
 
 	let extrinsics = block.block.extrinsics.into_iter().map(|e| e.0.encode()).collect::<Vec<_>>();
+	if extrinsics.is_empty() {
+		return Err(From::from("Block has no extrinsics".to_string()))
+	}
+	let index = find_timestamp_inherent(&extrinsics, None).ok_or_else(|| {
+		Error::Custom(format!(
+			"[get_parachain_headers] Block with hash :{:?} has no timestamp inherent",
+			block_hash
+		))
+	})?;
 
-	let (ext, proof) = {
-		if extrinsics.is_empty() {
-			return Err(From::from("Block has no extrinsics".to_string()))
-		}
-		let timestamp_ext = extrinsics[0].clone();
+	prove_extrinsic(&extrinsics, index)
+}
 
-		let mut db = sp_trie::MemoryDB::<BlakeTwo256>::default();
+/// Finds the position of the timestamp `set` inherent among the SCALE-encoded extrinsics of a
+/// block, by decoding the pallet and call indices of the unsigned extrinsics rather than assuming
+/// it's the first. `call` is the expected `(pallet_index, call_index)` of the inherent, without it
+/// the first unsigned call at index 0 taking a single compact `u64` is taken, which is the shape
+/// of `Timestamp::set` whatever the pallet's index in the runtime.
+pub fn find_timestamp_inherent(extrinsics: &[Vec<u8>], call: Option<(u8, u8)>) -> Option<usize> {
+	let decode = |extrinsic: &Vec<u8>| -> Option<(u8, u8, u8)> {
+		let call_data = Vec::<u8>::decode_all(&mut &extrinsic[..]).ok()?;
+		let (version, pallet_index, call_index, _timestamp) =
+			<(u8, u8, u8, codec::Compact<u64>)>::decode_all(&mut &call_data[..]).ok()?;
+		Some((version, pallet_index, call_index))
+	};
 
-		let root = {
-			let mut root = Default::default();
-			let mut trie =
-				<TrieDBMutBuilder<sp_trie::LayoutV0<BlakeTwo256>>>::new(&mut db, &mut root).build();
+	extrinsics.iter().position(|extrinsic| match decode(extrinsic) {
+		// version 4 without the signed bit
+		Some((4, pallet_index, call_index)) => match call {
+			Some(expected) => expected == (pallet_index, call_index),
+			None => call_index == 0,
+		},
+		_ => false,
+	})
+}
 
-			for (i, ext) in extrinsics.into_iter().enumerate() {
-				let key = codec::Compact(i as u32).encode();
-				trie.insert(&key, &ext)?;
-			}
-			*trie.root()
-		};
+/// Proves the extrinsic at `index` among the SCALE-encoded extrinsics of a block, against the
+/// block's extrinsics root.
+pub fn prove_extrinsic(
+	extrinsics: &[Vec<u8>],
+	index: usize,
+) -> Result<TimeStampExtWithProof, Error> {
+	let ext = extrinsics
+		.get(index)
+		.cloned()
+		.ok_or_else(|| Error::Custom(format!("Block has no extrinsic at index {index}")))?;
 
-		let key = codec::Compact::<u32>(0u32).encode();
-		let extrinsic_proof =
-			generate_trie_proof::<sp_trie::LayoutV0<BlakeTwo256>, _, _, _>(&db, root, vec![&key])?;
+	let mut db = sp_trie::MemoryDB::<BlakeTwo256>::default();
 
-		(timestamp_ext, extrinsic_proof)
+	let root = {
+		let mut root = Default::default();
+		let mut trie =
+			<TrieDBMutBuilder<sp_trie::LayoutV0<BlakeTwo256>>>::new(&mut db, &mut root).build();
+
+		for (i, ext) in extrinsics.iter().enumerate() {
+			let key = codec::Compact(i as u32).encode();
+			trie.insert(&key, ext)?;
+		}
+		*trie.root()
 	};
 
+	let key = codec::Compact::<u32>(index as u32).encode();
+	let proof =
+		generate_trie_proof::<sp_trie::LayoutV0<BlakeTwo256>, _, _, _>(&db, root, vec![&key])?;
+
 	Ok(TimeStampExtWithProof { ext, proof })
 }
 
@@ -216,11 +216,15 @@ where
 				.relay_client
 				.storage()
 				.at(Some(header.hash()))
-				.await
-				.expect("Storage client")
+				.await?
 				.fetch(&key)
 				.await?
-				.expect("Header exists in its own changeset; qed");
+				.ok_or_else(|| {
+					Error::Custom(format!(
+						"[get_parachain_headers] Paras::Heads not found at block {:?}",
+						changes.block
+					))
+				})?;
 
 			let para_header = T::Header::decode(&mut &head.0[..])
 				.map_err(|_| Error::Custom(format!("Failed to decode header")))?;
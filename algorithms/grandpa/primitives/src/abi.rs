@@ -0,0 +1,253 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ABI encoding of GRANDPA proofs for Solidity verifiers. A proof is encoded as
+//! `abi.encode(EvmProof)` of:
+//!
+//! ```solidity
+//! struct EvmProof {
+//!     bytes32 finalizedBlock;
+//!     bytes justification;
+//!     // keccak256(justification), so the verifier can commit to it without rehashing calldata
+//!     bytes32 justificationHash;
+//!     // SCALE encoded relay chain headers
+//!     bytes[] unknownHeaders;
+//!     EvmParachainHeader[] parachainHeaders;
+//! }
+//!
+//! struct EvmParachainHeader {
+//!     bytes32 relayHash;
+//!     bytes[] stateProof;
+//!     bytes extrinsic;
+//!     bytes[] extrinsicProof;
+//! }
+//! ```
+//!
+//! Parachain headers are ordered by relay chain block hash.
+
+use crate::ParachainHeadersWithFinalityProof;
+use codec::{Codec, Encode};
+use sp_std::prelude::*;
+
+/// A value in the ABI encoding, only the types used by the proof layout are supported.
+enum Token {
+	/// `bytes32`
+	FixedBytes([u8; 32]),
+	/// `bytes`
+	Bytes(Vec<u8>),
+	/// `T[]`
+	Array(Vec<Token>),
+	/// A struct
+	Tuple(Vec<Token>),
+}
+
+impl Token {
+	fn is_dynamic(&self) -> bool {
+		match self {
+			Token::FixedBytes(_) => false,
+			Token::Bytes(_) | Token::Array(_) => true,
+			Token::Tuple(tokens) => tokens.iter().any(Token::is_dynamic),
+		}
+	}
+
+	fn encode(&self) -> Vec<u8> {
+		match self {
+			Token::FixedBytes(bytes) => bytes.to_vec(),
+			Token::Bytes(bytes) => {
+				let mut encoded = encode_length(bytes.len());
+				encoded.extend_from_slice(bytes);
+				encoded.resize(32 + padded_length(bytes.len()), 0);
+				encoded
+			},
+			Token::Array(tokens) => {
+				let mut encoded = encode_length(tokens.len());
+				encoded.extend(encode_tuple(tokens));
+				encoded
+			},
+			Token::Tuple(tokens) => encode_tuple(tokens),
+		}
+	}
+}
+
+/// Encodes the tokens one after the other, static tokens in place and dynamic tokens as offsets
+/// to their encoding in the tail.
+fn encode_tuple(tokens: &[Token]) -> Vec<u8> {
+	let encoded = tokens.iter().map(Token::encode).collect::<Vec<_>>();
+	let heads_length = tokens
+		.iter()
+		.zip(&encoded)
+		.map(|(token, encoded)| if token.is_dynamic() { 32 } else { encoded.len() })
+		.sum::<usize>();
+
+	let mut heads = vec![];
+	let mut tail = vec![];
+	for (token, encoded) in tokens.iter().zip(encoded) {
+		if token.is_dynamic() {
+			heads.extend(encode_length(heads_length + tail.len()));
+			tail.extend(encoded);
+		} else {
+			heads.extend(encoded);
+		}
+	}
+	heads.extend(tail);
+
+	heads
+}
+
+fn encode_length(length: usize) -> Vec<u8> {
+	let mut word = [0u8; 32];
+	word[24..].copy_from_slice(&(length as u64).to_be_bytes());
+	word.to_vec()
+}
+
+fn padded_length(length: usize) -> usize {
+	(length + 31) / 32 * 32
+}
+
+fn bytes_array(items: &[Vec<u8>]) -> Token {
+	Token::Array(items.iter().cloned().map(Token::Bytes).collect())
+}
+
+impl<H> ParachainHeadersWithFinalityProof<H>
+where
+	H: Codec,
+{
+	/// ABI encodes the proof in the layout expected by Solidity GRANDPA verifiers, see the module
+	/// docs.
+	pub fn to_evm_proof(&self) -> Vec<u8> {
+		let finality_proof = &self.finality_proof;
+		let unknown_headers = finality_proof
+			.unknown_headers
+			.iter()
+			.map(|header| header.encode())
+			.collect::<Vec<_>>();
+		let parachain_headers = self
+			.parachain_headers
+			.iter()
+			.map(|(relay_hash, proofs)| {
+				Token::Tuple(vec![
+					Token::FixedBytes(relay_hash.0),
+					bytes_array(&proofs.state_proof),
+					Token::Bytes(proofs.extrinsic.clone()),
+					bytes_array(&proofs.extrinsic_proof),
+				])
+			})
+			.collect();
+
+		let proof = Token::Tuple(vec![
+			Token::FixedBytes(finality_proof.block.0),
+			Token::Bytes(finality_proof.justification.clone()),
+			Token::FixedBytes(sp_io::hashing::keccak_256(&finality_proof.justification)),
+			bytes_array(&unknown_headers),
+			Token::Array(parachain_headers),
+		]);
+
+		// `abi.encode` of a single dynamic struct is prefixed by its offset.
+		encode_tuple(&[proof])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{FinalityProof, ParachainHeaderProofs, ParachainHeadersWithFinalityProof};
+	use sp_core::H256;
+	use sp_runtime::traits::{BlakeTwo256, Header as _};
+
+	type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+	fn from_hex(words: &[&str]) -> Vec<u8> {
+		let hex = words.concat();
+		(0..hex.len())
+			.step_by(2)
+			.map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn test_evm_proof_layout() {
+		let unknown_header = RelayHeader::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let proof = ParachainHeadersWithFinalityProof::<RelayHeader> {
+			finality_proof: FinalityProof {
+				block: H256::repeat_byte(1),
+				justification: vec![0xaa, 0xbb],
+				unknown_headers: vec![unknown_header],
+			},
+			parachain_headers: [(
+				H256::repeat_byte(2),
+				ParachainHeaderProofs {
+					state_proof: vec![vec![0xcc]],
+					extrinsic: vec![0xdd, 0xee],
+					extrinsic_proof: vec![],
+					storage_key: None,
+					extrinsic_index: 0,
+				},
+			)]
+			.into_iter()
+			.collect(),
+		};
+
+		let expected = from_hex(&[
+			// offset of the proof struct
+			"0000000000000000000000000000000000000000000000000000000000000020",
+			// finalizedBlock
+			"0101010101010101010101010101010101010101010101010101010101010101",
+			// offset of justification
+			"00000000000000000000000000000000000000000000000000000000000000a0",
+			// justificationHash
+			"65b043cdd93fde12ee6629de2d9ce786ba7d5b4c514afecea4d1b4b2c740087c",
+			// offset of unknownHeaders
+			"00000000000000000000000000000000000000000000000000000000000000e0",
+			// offset of parachainHeaders
+			"00000000000000000000000000000000000000000000000000000000000001c0",
+			// justification
+			"0000000000000000000000000000000000000000000000000000000000000002",
+			"aabb000000000000000000000000000000000000000000000000000000000000",
+			// unknownHeaders, the SCALE encoded header is 98 bytes
+			"0000000000000000000000000000000000000000000000000000000000000001",
+			"0000000000000000000000000000000000000000000000000000000000000020",
+			"0000000000000000000000000000000000000000000000000000000000000062",
+			"0000000000000000000000000000000000000000000000000000000000000000",
+			"0400000000000000000000000000000000000000000000000000000000000000",
+			"0000000000000000000000000000000000000000000000000000000000000000",
+			"0000000000000000000000000000000000000000000000000000000000000000",
+			// parachainHeaders
+			"0000000000000000000000000000000000000000000000000000000000000001",
+			"0000000000000000000000000000000000000000000000000000000000000020",
+			// relayHash
+			"0202020202020202020202020202020202020202020202020202020202020202",
+			// offsets of stateProof, extrinsic and extrinsicProof
+			"0000000000000000000000000000000000000000000000000000000000000080",
+			"0000000000000000000000000000000000000000000000000000000000000100",
+			"0000000000000000000000000000000000000000000000000000000000000140",
+			// stateProof
+			"0000000000000000000000000000000000000000000000000000000000000001",
+			"0000000000000000000000000000000000000000000000000000000000000020",
+			"0000000000000000000000000000000000000000000000000000000000000001",
+			"cc00000000000000000000000000000000000000000000000000000000000000",
+			// extrinsic
+			"0000000000000000000000000000000000000000000000000000000000000002",
+			"ddee000000000000000000000000000000000000000000000000000000000000",
+			// extrinsicProof
+			"0000000000000000000000000000000000000000000000000000000000000000",
+		]);
+		assert_eq!(proof.to_evm_proof(), expected);
+	}
+}
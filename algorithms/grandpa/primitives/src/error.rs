@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use alloc::vec::Vec;
 use derive_more::{Display, From};
 
 ///
@@ -22,4 +23,48 @@ pub enum Error {
 	Anyhow(anyhow::Error),
 	/// scale codec error
 	Codec(codec::Error),
+	/// The storage key the proof was generated for isn't the one expected by the verifier
+	#[from(ignore)]
+	#[display(fmt = "Storage key mismatch, expected: {:?}, found: {:?}", expected, found)]
+	StorageKeyMismatch {
+		/// Storage key computed by the verifier
+		expected: Vec<u8>,
+		/// Storage key carried by the proof
+		found: Vec<u8>,
+	},
+	/// The parachain head is an abridged `(BlockNumber, Hash)` tuple, which lacks the header roots
+	/// needed for verification
+	#[from(ignore)]
+	#[display(
+		fmt = "Abridged parachain head for block {} is unsupported, the full header is required",
+		number
+	)]
+	AbridgedHeadUnsupported {
+		/// Parachain block number
+		number: u32,
+	},
+	/// The finality proof is wrapped in a version the verifier doesn't know about
+	#[from(ignore)]
+	#[display(fmt = "Unsupported finality proof version: {}", version)]
+	UnsupportedFinalityProofVersion {
+		/// Version of the finality proof wrapper
+		version: u8,
+	},
+	/// The proof ends between the signal of an authority set change and the last block of the set
+	/// it replaces, so the client would never apply the change
+	#[from(ignore)]
+	#[display(
+		fmt = "Set change signalled at {} and enacted at {} is pending at the finalized block {}",
+		signalled_at,
+		enacted_at,
+		finalized
+	)]
+	PendingAuthoritySetChange {
+		/// The block signalling the change
+		signalled_at: u32,
+		/// The block the change is enacted at
+		enacted_at: u32,
+		/// The block finalized by the proof
+		finalized: u32,
+	},
 }
@@ -0,0 +1,277 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the prover and the verifier. They don't depend on `std`, so they're available
+//! to `no_std` verifiers as well, and the prover re-exports them rather than keeping its own
+//! copies.
+
+use crate::{
+	justification::{find_forced_change, find_scheduled_change, AncestryChain},
+	ClientState,
+};
+use alloc::collections::BTreeSet;
+use codec::Decode;
+use core::ops::Bound;
+use sp_core::H256;
+use sp_finality_grandpa::ScheduledChange;
+use sp_runtime::traits::{Header, One, Saturating, Zero};
+use sp_std::prelude::*;
+
+/// Sorts and deduplicates the nodes of a storage proof. Storage proofs are an unordered set of trie
+/// nodes, but nodes may return them in any order, so this keeps proofs byte-identical for
+/// identical chain state.
+pub fn canonical_storage_proof(mut nodes: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+	nodes.sort();
+	nodes.dedup();
+	nodes
+}
+
+/// A change to the parachain's `Paras::Heads` entry, as reported by `state_queryStorage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParaHeadChange {
+	/// The head was updated, contains the new head data.
+	Updated(Vec<u8>),
+	/// The head was deleted, which happens when the parachain is offboarded.
+	Removed,
+}
+
+impl ParaHeadChange {
+	/// Decodes the change from the raw storage value, a value of `None` means it was deleted.
+	pub fn decode(value: Option<&[u8]>) -> Result<Self, codec::Error> {
+		match value {
+			Some(mut value) => Ok(ParaHeadChange::Updated(Vec::<u8>::decode(&mut value)?)),
+			None => Ok(ParaHeadChange::Removed),
+		}
+	}
+}
+
+/// Whether the parachain header with the given number should be proven, the genesis header never
+/// is.
+///
+/// With async backing a relay chain block can include several candidates of the parachain, but
+/// `Paras::Heads` only holds the head of the last one, so the heads of the earlier candidates
+/// can't be proven. If the head advanced past a requested header since `previous_number`, the
+/// head it advanced to, which is the one actually included, is proven instead.
+pub fn is_requested_para_header<N>(
+	number: &N,
+	previous_number: Option<&N>,
+	header_numbers: &[N],
+) -> bool
+where
+	N: Ord + Zero,
+{
+	if number.is_zero() {
+		return false
+	}
+	let superseded = previous_number.map_or(false, |previous| {
+		header_numbers
+			.iter()
+			.any(|requested| previous < requested && requested < number)
+	});
+
+	superseded || header_numbers.contains(number)
+}
+
+/// Same as [`is_requested_para_header`], looking the number up in a set of requested numbers, which
+/// takes logarithmic rather than linear time in the number of requested headers.
+pub fn is_requested_para_header_in<N>(
+	number: &N,
+	previous_number: Option<&N>,
+	header_numbers: &BTreeSet<N>,
+) -> bool
+where
+	N: Ord + Zero,
+{
+	if number.is_zero() {
+		return false
+	}
+	let superseded = previous_number.map_or(false, |previous| {
+		previous < number &&
+			header_numbers
+				.range((Bound::Excluded(previous), Bound::Excluded(number)))
+				.next()
+				.is_some()
+	});
+
+	superseded || header_numbers.contains(number)
+}
+
+/// Whether the relay chain header signals a scheduled or forced authority set change, making it a
+/// mandatory header for clients following the authority set rotations.
+pub fn is_mandatory_header<H: Header>(header: &H) -> bool {
+	find_scheduled_change(header).is_some() || find_forced_change(header).is_some()
+}
+
+/// An authority set change signalled in a relay chain header's GRANDPA digest, along with the
+/// block it's enacted at, see [`authority_set_changes`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthoritySetChange<N> {
+	/// A standard change, enacted once the block `delay` blocks after the signal is finalized,
+	/// which is the last block finalized by the current set.
+	Scheduled {
+		/// The block signalling the change
+		signalled_at: N,
+		/// The block the change is enacted at
+		enacted_at: N,
+		/// The signalled change
+		change: ScheduledChange<N>,
+	},
+	/// A forced change, enacted once the block `delay` blocks after the signal is imported,
+	/// regardless of finality. That block is finalized by the next set, which resumes finalizing
+	/// from `median_last_finalized`.
+	Forced {
+		/// The block signalling the change
+		signalled_at: N,
+		/// The block the change is enacted at
+		enacted_at: N,
+		/// The median last finalized block when the change was signalled
+		median_last_finalized: N,
+		/// The signalled change
+		change: ScheduledChange<N>,
+	},
+}
+
+impl<N: Copy + One + Saturating> AuthoritySetChange<N> {
+	/// The block signalling the change.
+	pub fn signalled_at(&self) -> N {
+		match self {
+			AuthoritySetChange::Scheduled { signalled_at, .. } |
+			AuthoritySetChange::Forced { signalled_at, .. } => *signalled_at,
+		}
+	}
+
+	/// The block the change is enacted at.
+	pub fn enacted_at(&self) -> N {
+		match self {
+			AuthoritySetChange::Scheduled { enacted_at, .. } |
+			AuthoritySetChange::Forced { enacted_at, .. } => *enacted_at,
+		}
+	}
+
+	/// The last block finalized by the set the change replaces. For a standard change this is
+	/// the block it's enacted at, a forced change is enacted before its block is finalized.
+	pub fn last_block_of_previous_set(&self) -> N {
+		match self {
+			AuthoritySetChange::Scheduled { enacted_at, .. } => *enacted_at,
+			AuthoritySetChange::Forced { enacted_at, .. } => (*enacted_at).saturating_sub(N::one()),
+		}
+	}
+
+	/// The signalled change.
+	pub fn into_change(self) -> ScheduledChange<N> {
+		match self {
+			AuthoritySetChange::Scheduled { change, .. } |
+			AuthoritySetChange::Forced { change, .. } => change,
+		}
+	}
+}
+
+/// Parses the standard and forced authority set changes signalled by the relay chain header, a
+/// header can signal one of each.
+pub fn authority_set_changes<H: Header>(header: &H) -> Vec<AuthoritySetChange<H::Number>> {
+	let signalled_at = *header.number();
+	let mut changes = vec![];
+	if let Some(change) = find_scheduled_change(header) {
+		let enacted_at = signalled_at.saturating_add(change.delay);
+		changes.push(AuthoritySetChange::Scheduled { signalled_at, enacted_at, change });
+	}
+	if let Some((median_last_finalized, change)) = find_forced_change(header) {
+		let enacted_at = signalled_at.saturating_add(change.delay);
+		changes.push(AuthoritySetChange::Forced {
+			signalled_at,
+			enacted_at,
+			median_last_finalized,
+			change,
+		});
+	}
+
+	changes
+}
+
+/// Returns the client state with the authority set that votes on the relay chain block at
+/// `height`, applying the set changes signalled between the client's latest relay chain block and
+/// `target` that are enacted by `height`. The headers are walked from `target` down through
+/// `unknown_headers`, so only the changes on its chain are applied.
+///
+/// A change is applied to the blocks after the last block finalized by the set it replaces, see
+/// [`AuthoritySetChange::last_block_of_previous_set`]. For a standard change signalled at block
+/// `n` with a delay of `d` that's `n + d`, while a forced change's enactment block is already
+/// finalized by the new set, which for a delay of zero is the signalling block itself. Changes are
+/// applied in the order they're signalled, so a change isn't applied before the ones signalled
+/// ahead of it.
+pub fn authority_set_at<H>(
+	client_state: &ClientState,
+	unknown_headers: &[H],
+	target: H256,
+	height: u32,
+) -> ClientState
+where
+	H: Header<Hash = H256, Number = u32>,
+{
+	let mut client_state = client_state.clone();
+	for change in set_changes_since(&client_state, unknown_headers, target) {
+		if change.last_block_of_previous_set() >= height {
+			break
+		}
+		client_state.current_set_id += 1;
+		client_state.current_authorities = change.into_change().next_authorities;
+	}
+
+	client_state
+}
+
+/// Returns the first authority set change signalled between the client's latest relay chain block
+/// and `target` that the set voting on the blocks after `height` doesn't apply yet, see
+/// [`authority_set_at`]. Clients don't track pending changes, and only look for changes above
+/// their latest relay chain block, so a client updated to `target` at `height` would never apply
+/// it. Updates ending between a change's signal and the last block of the set it replaces have to
+/// be rejected instead.
+pub fn pending_set_change<H>(
+	client_state: &ClientState,
+	unknown_headers: &[H],
+	target: H256,
+	height: u32,
+) -> Option<AuthoritySetChange<u32>>
+where
+	H: Header<Hash = H256, Number = u32>,
+{
+	set_changes_since(client_state, unknown_headers, target)
+		.into_iter()
+		.find(|change| change.last_block_of_previous_set() > height)
+}
+
+/// The authority set changes signalled by the headers above the client's latest relay chain block
+/// on the chain of `target`, in the order they're signalled.
+fn set_changes_since<H>(
+	client_state: &ClientState,
+	unknown_headers: &[H],
+	target: H256,
+) -> Vec<AuthoritySetChange<u32>>
+where
+	H: Header<Hash = H256, Number = u32>,
+{
+	let ancestry = AncestryChain::<H>::new(unknown_headers);
+	let mut headers = vec![];
+	let mut hash = target;
+	while let Some(header) = ancestry
+		.header(&hash)
+		.filter(|header| *header.number() > client_state.latest_relay_height)
+	{
+		hash = *header.parent_hash();
+		headers.push(header);
+	}
+
+	headers.into_iter().rev().flat_map(authority_set_changes).collect()
+}
@@ -55,6 +55,24 @@ where
 	where
 		Host: HostFunctions,
 	{
+		// bail before checking any signatures if the precommits can't possibly reach the threshold.
+		let precommit_voters = self
+			.commit
+			.precommits
+			.iter()
+			.map(|precommit| &precommit.id)
+			.collect::<BTreeSet<_>>();
+		let voter_weight = authorities
+			.iter()
+			.filter(|(id, _)| precommit_voters.contains(id))
+			.fold(0u64, |total, (_, weight)| total.saturating_add(*weight));
+		let required_weight = required_voter_weight(authorities);
+		if voter_weight < required_weight {
+			Err(anyhow!(
+				"Justification has voter weight {voter_weight}, {required_weight} is required"
+			))?
+		}
+
 		// It's safe to assume that the authority list will not contain duplicates,
 		// since this list is extracted from a verified relaychain header.
 		let voters =
@@ -155,6 +173,120 @@ where
 	}
 }
 
+/// A precommit referencing its voter by index into the authority set, rather than by public key.
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq, Eq)]
+pub struct CompactSignedPrecommit<H: HeaderT> {
+	/// The precommit message which has been signed.
+	pub precommit: finality_grandpa::Precommit<H::Hash, H::Number>,
+	/// The signature on the message.
+	pub signature: AuthoritySignature,
+	/// Index of the signer in the authority set.
+	#[codec(compact)]
+	pub authority_index: u32,
+}
+
+/// A [`GrandpaJustification`] whose precommits reference their voters by index into the authority
+/// set, which saves 32 bytes per precommit. Expanding it requires the authority set it was
+/// compacted against.
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[derive(Clone, Encode, Decode, PartialEq, Eq)]
+pub struct CompactGrandpaJustification<H: HeaderT> {
+	/// Current voting round number, monotonically increasing
+	pub round: u64,
+	/// The target block's hash.
+	pub target_hash: H::Hash,
+	/// The target block's number.
+	pub target_number: H::Number,
+	/// Precommits for target block or any block after it that justify this commit.
+	pub precommits: Vec<CompactSignedPrecommit<H>>,
+	/// Contains the path from a [`PreCommit`]'s target hash to the GHOST finalized block.
+	pub votes_ancestries: Vec<H>,
+}
+
+impl<H: HeaderT> GrandpaJustification<H> {
+	/// Replaces the voter of every precommit with its index in `authorities`, fails if a voter
+	/// isn't in the authority set.
+	pub fn compact(
+		&self,
+		authorities: &AuthorityList,
+	) -> Result<CompactGrandpaJustification<H>, error::Error> {
+		let indices = authorities
+			.iter()
+			.enumerate()
+			.map(|(index, (id, _))| (id, index as u32))
+			.collect::<BTreeMap<_, _>>();
+		let precommits = self
+			.commit
+			.precommits
+			.iter()
+			.map(|signed| {
+				let authority_index = *indices.get(&signed.id).ok_or_else(|| {
+					anyhow!("Precommit voter {:?} isn't in the authority set", signed.id)
+				})?;
+				Ok(CompactSignedPrecommit {
+					precommit: signed.precommit.clone(),
+					signature: signed.signature.clone(),
+					authority_index,
+				})
+			})
+			.collect::<Result<_, error::Error>>()?;
+
+		Ok(CompactGrandpaJustification {
+			round: self.round,
+			target_hash: self.commit.target_hash,
+			target_number: self.commit.target_number,
+			precommits,
+			votes_ancestries: self.votes_ancestries.clone(),
+		})
+	}
+}
+
+impl<H: HeaderT> CompactGrandpaJustification<H> {
+	/// Reconstructs the full justification, looking up each voter in the authority set the
+	/// justification was compacted against.
+	pub fn expand(
+		self,
+		authorities: &AuthorityList,
+	) -> Result<GrandpaJustification<H>, error::Error> {
+		let precommits = self
+			.precommits
+			.into_iter()
+			.map(|compact| {
+				let (id, _) =
+					authorities.get(compact.authority_index as usize).ok_or_else(|| {
+						anyhow!("Authority index {} is out of bounds", compact.authority_index)
+					})?;
+				Ok(finality_grandpa::SignedPrecommit {
+					precommit: compact.precommit,
+					signature: compact.signature,
+					id: id.clone(),
+				})
+			})
+			.collect::<Result<_, error::Error>>()?;
+
+		Ok(GrandpaJustification {
+			round: self.round,
+			commit: finality_grandpa::Commit {
+				target_hash: self.target_hash,
+				target_number: self.target_number,
+				precommits,
+			},
+			votes_ancestries: self.votes_ancestries,
+		})
+	}
+
+	/// Expands the justification against `authorities` and verifies it, see
+	/// [`GrandpaJustification::verify`].
+	pub fn verify<Host>(&self, set_id: u64, authorities: &AuthorityList) -> Result<(), error::Error>
+	where
+		Host: HostFunctions,
+		H::Number: finality_grandpa::BlockNumberOps,
+	{
+		self.clone().expand(authorities)?.verify::<Host>(set_id, authorities)
+	}
+}
+
 /// A utility trait implementing `finality_grandpa::Chain` using a given set of headers.
 /// This is useful when validating commits, using the given set of headers to
 /// verify a valid ancestry route to the target commit block.
@@ -233,6 +365,18 @@ pub fn find_forced_change<H: HeaderT>(
 	header.digest().convert_first(|l| l.try_to(id).and_then(filter_log))
 }
 
+/// Returns the voter weight a commit needs for the given authority set to finalize a block, this is
+/// the same threshold `finality-grandpa` uses: the total weight less the most that can be faulty,
+/// `(total - 1) / 3`.
+pub fn required_voter_weight(authorities: &AuthorityList) -> u64 {
+	let total_weight = authorities
+		.iter()
+		.fold(0u64, |total, (_, weight)| total.saturating_add(*weight));
+	let faulty_weight = total_weight.saturating_sub(1) / 3;
+
+	total_weight - faulty_weight
+}
+
 /// Check a message signature by encoding the message and verifying the provided signature using the
 /// expected authority id.
 pub fn check_message_signature<Host, H, N>(
@@ -250,7 +394,7 @@ where
 	log::trace!(target: "pallet_ibc", "Justification Message {:?}", (round, set_id));
 	let buf = (message, round, set_id).encode();
 
-	if !Host::ed25519_verify(signature.as_ref(), &buf, id.as_ref()) {
+	if !Host::verify_authority_signature(signature, &buf, id) {
 		Err(anyhow!("invalid signature for precommit in grandpa justification"))?
 	}
 
@@ -343,4 +487,27 @@ mod tests {
 
 		assert_eq!(route, expected);
 	}
+
+	#[test]
+	fn test_required_voter_weight() {
+		let authorities = |weights: &[u64]| {
+			weights
+				.iter()
+				.enumerate()
+				.map(|(i, weight)| {
+					(sp_core::ed25519::Public::from_raw([i as u8; 32]).into(), *weight)
+				})
+				.collect::<AuthorityList>()
+		};
+
+		// (number of equally weighted authorities, required voters)
+		let known = [(1, 1), (2, 2), (3, 3), (4, 3), (5, 4), (6, 5), (7, 5), (10, 7), (100, 67)];
+		for (size, required) in known {
+			assert_eq!(required_voter_weight(&authorities(&vec![1; size])), required, "{size}");
+		}
+
+		// weighted sets use the total weight
+		assert_eq!(required_voter_weight(&authorities(&[3, 3, 3, 1])), 7);
+		assert_eq!(required_voter_weight(&authorities(&[])), 0);
+	}
 }
@@ -21,19 +21,30 @@
 
 extern crate alloc;
 
-use alloc::collections::BTreeMap;
-use codec::{Decode, Encode};
+use alloc::collections::{BTreeMap, BTreeSet};
+use anyhow::anyhow;
+use codec::{Decode, DecodeAll, Encode};
 use core::fmt::Debug;
-use sp_core::{ed25519, sp_std, H256};
+use sp_core::{ed25519, sp_std, sr25519, H256};
 use sp_finality_grandpa::{AuthorityId, AuthorityList, AuthoritySignature};
-use sp_runtime::traits::Header;
+use sp_runtime::{
+	traits::{BlakeTwo256, Header},
+	DigestItem,
+};
 use sp_std::prelude::*;
 use sp_storage::StorageKey;
 
+/// ABI encoding of proofs for EVM verifiers
+#[cfg(feature = "abi")]
+pub mod abi;
 /// GRANPA errors
 pub mod error;
+/// Helpers shared by the prover and the verifier
+pub mod helpers;
 /// GRANDPA justification utilities
 pub mod justification;
+/// Checks of proofs against pallet versions
+pub mod lint;
 /// Represents a Hash in this library
 pub type Hash = H256;
 /// A commit message for this chain's block type.
@@ -47,7 +58,7 @@ pub type Commit<H> = finality_grandpa::Commit<
 /// Finality for block B is proved by providing:
 /// 1) the justification for the descendant block F;
 /// 2) headers sub-chain (B; F] if B != F;
-#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Encode, Decode, Clone)]
 pub struct FinalityProof<H: codec::Codec> {
 	/// The hash of block F for which justification is provided.
 	pub block: Hash,
@@ -57,6 +68,60 @@ pub struct FinalityProof<H: codec::Codec> {
 	pub unknown_headers: Vec<H>,
 }
 
+/// A [`FinalityProof`] wrapped in its version, as returned by newer relay chain nodes.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub enum VersionedFinalityProof<H: codec::Codec> {
+	/// The current version
+	#[codec(index = 1)]
+	V1(FinalityProof<H>),
+}
+
+/// Version of [`VersionedFinalityProof::V1`].
+pub const FINALITY_PROOF_V1: u8 = 1;
+
+impl<H: codec::Codec> FinalityProof<H> {
+	/// Decodes a finality proof that's either bare or wrapped in a [`VersionedFinalityProof`]. A
+	/// bare proof is tried first and has to span the whole input, otherwise the first byte is the
+	/// version of the wrapper.
+	pub fn decode_versioned(encoded: &[u8]) -> Result<Self, error::Error> {
+		if let Ok(proof) = Self::decode_all(&mut &encoded[..]) {
+			return Ok(proof)
+		}
+
+		match encoded.first() {
+			Some(&FINALITY_PROOF_V1) => {
+				let VersionedFinalityProof::V1(proof) =
+					VersionedFinalityProof::decode_all(&mut &encoded[..])?;
+				Ok(proof)
+			},
+			Some(&version) => Err(error::Error::UnsupportedFinalityProofVersion { version }),
+			None => Err(codec::Error::from("Empty finality proof").into()),
+		}
+	}
+}
+
+impl<H> FinalityProof<H>
+where
+	H: Header<Hash = Hash> + codec::Codec,
+{
+	/// Returns the unknown header with the given hash, if the proof has it.
+	pub fn header_by_hash(&self, hash: &H::Hash) -> Option<&H> {
+		self.unknown_headers.iter().find(|header| header.hash() == *hash)
+	}
+
+	/// Returns the state root of the unknown header with the given hash, which the state proofs
+	/// of the parachain headers included in it are checked against.
+	pub fn state_root_of(&self, relay_hash: &H::Hash) -> Option<H256> {
+		self.header_by_hash(relay_hash).map(|header| *header.state_root())
+	}
+
+	/// The authority set changes signalled by the unknown headers, in the order of the headers,
+	/// along with the blocks they're enacted at.
+	pub fn authority_set_changes(&self) -> Vec<helpers::AuthoritySetChange<H::Number>> {
+		self.unknown_headers.iter().flat_map(helpers::authority_set_changes).collect()
+	}
+}
+
 /// Previous light client state.
 #[derive(Clone)]
 pub struct ClientState {
@@ -74,8 +139,54 @@ pub struct ClientState {
 	pub para_id: u32,
 }
 
+/// A trusted finalized relay chain header, periodically published along with the authority set
+/// that finalizes its descendants. Provers anchored at a snapshot only need the relay chain
+/// ancestry back to the snapshot, rather than to the last client update, which might have been
+/// pruned already.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct SnapshotAnchor {
+	/// Hash of the finalized relay chain header.
+	pub hash: Hash,
+	/// Height of the finalized relay chain header.
+	pub height: u32,
+	/// Id of the authority set that finalizes the descendants of this header.
+	pub set_id: u64,
+	/// Authority set that finalizes the descendants of this header.
+	pub authorities: AuthorityList,
+}
+
+impl SnapshotAnchor {
+	/// Re-anchors the client state at this snapshot if it's ahead of the client's latest relay
+	/// chain height, returning whether the client state was modified.
+	pub fn anchor(&self, client_state: &mut ClientState) -> bool {
+		if self.height <= client_state.latest_relay_height {
+			return false
+		}
+
+		client_state.latest_relay_hash = self.hash;
+		client_state.latest_relay_height = self.height;
+		client_state.current_set_id = self.set_id;
+		client_state.current_authorities = self.authorities.clone();
+		true
+	}
+}
+
+/// Proof of the timestamp inherent of a finalized relay chain block, which establishes the time
+/// independently of the parachain.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct RelayTimestampProof {
+	/// Hash of the relay chain block
+	pub block: Hash,
+	/// Timestamp extrinsic of the relay chain block
+	pub extrinsic: Vec<u8>,
+	/// Proof of the timestamp extrinsic against the block's extrinsics root
+	pub extrinsic_proof: Vec<Vec<u8>>,
+	/// Index of the timestamp extrinsic in the block, which it's proven at
+	pub extrinsic_index: u32,
+}
+
 /// Holds relavant parachain proofs for both header and timestamp extrinsic.
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Encode, Decode)]
 pub struct ParachainHeaderProofs {
 	/// State proofs that prove a parachain header exists at a given relay chain height
 	pub state_proof: Vec<Vec<u8>>,
@@ -83,6 +194,83 @@ pub struct ParachainHeaderProofs {
 	pub extrinsic: Vec<u8>,
 	/// Timestamp extrinsic proof for previously proven parachain header.
 	pub extrinsic_proof: Vec<Vec<u8>>,
+	/// The `Paras::Heads` storage key that the state proof was generated for, used to detect
+	/// provers built against a different runtime metadata than the verifier.
+	pub storage_key: Option<Vec<u8>>,
+	/// Index of the timestamp extrinsic in the parachain block, which it's proven at. Runtimes
+	/// may order their inherents so that it isn't the first extrinsic.
+	pub extrinsic_index: u32,
+}
+
+/// [`ParachainHeaderProofs`] as they were encoded before carrying the index of their timestamp
+/// extrinsic, which was always the first, see
+/// [`ParachainHeadersWithFinalityProof::decode_versioned`].
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ParachainHeaderProofsV2 {
+	/// State proofs that prove a parachain header exists at a given relay chain height
+	pub state_proof: Vec<Vec<u8>>,
+	/// Timestamp extrinsic for ibc
+	pub extrinsic: Vec<u8>,
+	/// Timestamp extrinsic proof for previously proven parachain header.
+	pub extrinsic_proof: Vec<Vec<u8>>,
+	/// The `Paras::Heads` storage key that the state proof was generated for.
+	pub storage_key: Option<Vec<u8>>,
+}
+
+impl From<ParachainHeaderProofsV2> for ParachainHeaderProofs {
+	fn from(proofs: ParachainHeaderProofsV2) -> Self {
+		let ParachainHeaderProofsV2 { state_proof, extrinsic, extrinsic_proof, storage_key } =
+			proofs;
+		Self { state_proof, extrinsic, extrinsic_proof, storage_key, extrinsic_index: 0 }
+	}
+}
+
+/// [`ParachainHeaderProofs`] as they were encoded before carrying the proven storage key, see
+/// [`ParachainHeadersWithFinalityProof::decode_versioned`].
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ParachainHeaderProofsV1 {
+	/// State proofs that prove a parachain header exists at a given relay chain height
+	pub state_proof: Vec<Vec<u8>>,
+	/// Timestamp extrinsic for ibc
+	pub extrinsic: Vec<u8>,
+	/// Timestamp extrinsic proof for previously proven parachain header.
+	pub extrinsic_proof: Vec<Vec<u8>>,
+}
+
+impl From<ParachainHeaderProofsV1> for ParachainHeaderProofs {
+	fn from(proofs: ParachainHeaderProofsV1) -> Self {
+		let ParachainHeaderProofsV1 { state_proof, extrinsic, extrinsic_proof } = proofs;
+		Self { state_proof, extrinsic, extrinsic_proof, storage_key: None, extrinsic_index: 0 }
+	}
+}
+
+/// A finality proof that ships only the relay chain headers referenced by the justification, along
+/// with an MMR proof of their hashes in place of the ancestry linking them to the client's latest
+/// relay chain block. The MMR's `n`th leaf commits to the hash of relay chain block `n` and to the
+/// number of headers up to it that signal authority set changes, which shows the skipped headers
+/// don't signal any.
+#[derive(Clone, Encode, Decode)]
+pub struct MmrAncestryProof<H: codec::Codec> {
+	/// The finalized relay chain block hash.
+	pub block: H256,
+	/// SCALE encoded justification finalizing `block`.
+	pub justification: Vec<u8>,
+	/// The relay chain headers referenced by the justification, including the finalized block's,
+	/// and every header between the client's latest relay chain block and the finalized block
+	/// that signals an authority set change.
+	pub headers: Vec<H>,
+	/// Number of headers signalling authority set changes up to each of `headers`, in the same
+	/// order.
+	pub set_changes_signalled: Vec<u64>,
+	/// Number of headers signalling authority set changes up to the client's latest relay chain
+	/// block, whose leaf is proven along with those of `headers`.
+	pub anchor_set_changes_signalled: u64,
+	/// Number of leaves in the MMR the headers are proven against.
+	pub mmr_leaf_count: u64,
+	/// MMR proof of the leaves of `headers` and the client's latest relay chain block.
+	pub mmr_proof: Vec<H256>,
+	/// Proofs of the parachain headers included in `headers`, keyed by relay chain block hash.
+	pub parachain_headers: BTreeMap<H256, ParachainHeaderProofs>,
 }
 
 /// Parachain headers with a Grandpa finality proof.
@@ -97,6 +285,191 @@ pub struct ParachainHeadersWithFinalityProof<H: codec::Codec> {
 	pub parachain_headers: BTreeMap<Hash, ParachainHeaderProofs>,
 }
 
+impl<H: codec::Codec> ParachainHeadersWithFinalityProof<H> {
+	/// Decodes a proof in the current encoding, or in the encodings of proofs generated before the
+	/// parachain header proofs carried the index of their timestamp extrinsic, see
+	/// [`ParachainHeaderProofsV2`], or their storage key, see [`ParachainHeaderProofsV1`]. The
+	/// newest encoding is tried first and has to span the whole input.
+	pub fn decode_versioned(encoded: &[u8]) -> Result<Self, codec::Error> {
+		if let Ok(proof) = Self::decode_all(&mut &encoded[..]) {
+			return Ok(proof)
+		}
+
+		if let Ok((finality_proof, parachain_headers)) = <(
+			FinalityProof<H>,
+			BTreeMap<Hash, ParachainHeaderProofsV2>,
+		)>::decode_all(&mut &encoded[..])
+		{
+			return Ok(Self {
+				finality_proof,
+				parachain_headers: parachain_headers
+					.into_iter()
+					.map(|(hash, proofs)| (hash, proofs.into()))
+					.collect(),
+			})
+		}
+
+		let (finality_proof, parachain_headers) = <(
+			FinalityProof<H>,
+			BTreeMap<Hash, ParachainHeaderProofsV1>,
+		)>::decode_all(&mut &encoded[..])?;
+		Ok(Self {
+			finality_proof,
+			parachain_headers: parachain_headers
+				.into_iter()
+				.map(|(hash, proofs)| (hash, proofs.into()))
+				.collect(),
+		})
+	}
+}
+
+impl<H> ParachainHeadersWithFinalityProof<H>
+where
+	H: Header<Hash = Hash> + codec::Codec,
+{
+	/// A proof finalizing `finality_block` with the given justification, without any unknown
+	/// headers or parachain headers. Use this to build up a proof incrementally.
+	pub fn empty(finality_block: Hash, justification: Vec<u8>) -> Self {
+		Self {
+			finality_proof: FinalityProof {
+				block: finality_block,
+				justification,
+				unknown_headers: vec![],
+			},
+			parachain_headers: BTreeMap::new(),
+		}
+	}
+
+	/// Adds the proofs for the parachain header included in the given relay chain block, replacing
+	/// any proofs previously added for it.
+	pub fn push_parachain_header(&mut self, relay_hash: Hash, proofs: ParachainHeaderProofs) {
+		self.parachain_headers.insert(relay_hash, proofs);
+	}
+
+	/// Splits a proof spanning authority set changes into sub-proofs that can be verified in order,
+	/// each ending at the last block finalized by a set, see
+	/// [`AuthoritySetChange::last_block_of_previous_set`](helpers::AuthoritySetChange). That's the
+	/// enactment block of a standard change, `delay` blocks after its signal, and the block before
+	/// the enactment block of a forced change. The proof only carries the justification of its
+	/// latest block, so the justifications of the blocks ending the other sub-proofs must be
+	/// provided. Fails for forced changes whose previous set ends before the proof's first block.
+	pub fn split_at_set_changes(
+		&self,
+		set_change_justifications: &BTreeMap<Hash, Vec<u8>>,
+	) -> Result<Vec<Self>, error::Error> {
+		let mut headers = self.finality_proof.unknown_headers.clone();
+		headers.sort_by_key(|header| *header.number());
+
+		let mut set_ends = BTreeSet::new();
+		for change in headers.iter().flat_map(helpers::authority_set_changes) {
+			let last_block = change.last_block_of_previous_set();
+			if headers.first().map_or(false, |first| last_block < *first.number()) {
+				Err(anyhow!("Set change enacted at {:?} can't be split at", change.enacted_at()))?
+			}
+			set_ends.insert(last_block);
+		}
+
+		let mut proofs = vec![];
+		let mut unknown_headers = vec![];
+		for header in headers {
+			let hash = header.hash();
+			let ends_set = hash != self.finality_proof.block && set_ends.contains(header.number());
+			unknown_headers.push(header);
+			if ends_set {
+				let justification = set_change_justifications.get(&hash).ok_or_else(|| {
+					anyhow!("Missing justification for set change block {hash:?}")
+				})?;
+				let unknown_headers = core::mem::take(&mut unknown_headers);
+				proofs.push(self.sub_proof(hash, justification.clone(), unknown_headers));
+			}
+		}
+
+		if !unknown_headers.is_empty() {
+			let justification = self.finality_proof.justification.clone();
+			proofs.push(self.sub_proof(self.finality_proof.block, justification, unknown_headers));
+		}
+
+		Ok(proofs)
+	}
+
+	/// Proof finalizing `block` with the given headers, including only their parachain headers.
+	fn sub_proof(&self, block: Hash, justification: Vec<u8>, unknown_headers: Vec<H>) -> Self {
+		let parachain_headers = self
+			.parachain_headers
+			.iter()
+			.filter(|(hash, _)| unknown_headers.iter().any(|header| header.hash() == **hash))
+			.map(|(hash, proofs)| (*hash, proofs.clone()))
+			.collect();
+
+		Self {
+			finality_proof: FinalityProof { block, justification, unknown_headers },
+			parachain_headers,
+		}
+	}
+}
+
+/// [`ParachainHeaderProofs`] whose state proof references trie nodes shared across relay chain
+/// blocks, see [`CompactParachainHeadersWithFinalityProof`].
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct CompactParachainHeaderProofs {
+	/// Hashes of the state proof's trie nodes, in the order of the original proof
+	pub state_proof: Vec<Hash>,
+	/// Timestamp extrinsic for ibc
+	pub extrinsic: Vec<u8>,
+	/// Timestamp extrinsic proof for previously proven parachain header.
+	pub extrinsic_proof: Vec<Vec<u8>>,
+	/// The `Paras::Heads` storage key that the state proof was generated for.
+	pub storage_key: Option<Vec<u8>>,
+	/// Index of the timestamp extrinsic in the parachain block, which it's proven at.
+	pub extrinsic_index: u32,
+}
+
+/// A [`ParachainHeadersWithFinalityProof`] that holds every state proof trie node once. The state
+/// proofs of consecutive relay chain blocks share most of their nodes, which would otherwise be
+/// submitted once per block.
+#[derive(Clone, Encode, Decode)]
+pub struct CompactParachainHeadersWithFinalityProof<H: codec::Codec> {
+	/// The grandpa finality proof
+	pub finality_proof: FinalityProof<H>,
+	/// Trie nodes of all the state proofs, keyed by their blake2-256 hash
+	pub state_proof_nodes: BTreeMap<Hash, Vec<u8>>,
+	/// Parachain header proofs keyed by relay chain header hash, referencing `state_proof_nodes`
+	pub parachain_headers: BTreeMap<Hash, CompactParachainHeaderProofs>,
+}
+
+impl<H: codec::Codec> From<ParachainHeadersWithFinalityProof<H>>
+	for CompactParachainHeadersWithFinalityProof<H>
+{
+	fn from(proof: ParachainHeadersWithFinalityProof<H>) -> Self {
+		let mut state_proof_nodes = BTreeMap::new();
+		let parachain_headers = proof
+			.parachain_headers
+			.into_iter()
+			.map(|(hash, proofs)| {
+				let state_proof = proofs
+					.state_proof
+					.into_iter()
+					.map(|node| {
+						let node_hash = Hash::from(sp_io::hashing::blake2_256(&node));
+						state_proof_nodes.entry(node_hash).or_insert(node);
+						node_hash
+					})
+					.collect();
+				let proofs = CompactParachainHeaderProofs {
+					state_proof,
+					extrinsic: proofs.extrinsic,
+					extrinsic_proof: proofs.extrinsic_proof,
+					storage_key: proofs.storage_key,
+					extrinsic_index: proofs.extrinsic_index,
+				};
+				(hash, proofs)
+			})
+			.collect();
+
+		Self { finality_proof: proof.finality_proof, state_proof_nodes, parachain_headers }
+	}
+}
+
 /// Host functions that allow the light client perform cryptographic operations in native.
 pub trait HostFunctions: light_client_common::HostFunctions + 'static {
 	/// RelayChain header type.
@@ -104,6 +477,11 @@ pub trait HostFunctions: light_client_common::HostFunctions + 'static {
 
 	/// Verify an ed25519 signature
 	fn ed25519_verify(sig: &ed25519::Signature, msg: &[u8], pub_key: &ed25519::Public) -> bool;
+	/// Verify a GRANDPA authority's signature of a vote message. GRANDPA authorities sign with
+	/// ed25519, override this for relay chains configured with a different key scheme.
+	fn verify_authority_signature(sig: &AuthoritySignature, msg: &[u8], id: &AuthorityId) -> bool {
+		Self::ed25519_verify(sig.as_ref(), msg, id.as_ref())
+	}
 	/// Stores the given list of RelayChain header hashes in the light client's storage.
 	fn insert_relay_header_hashes(headers: &[<Self::Header as Header>::Hash]);
 	/// Checks if a RelayChain header hash exists in the light client's storage.
@@ -118,3 +496,458 @@ pub fn parachain_header_storage_key(para_id: u32) -> StorageKey {
 	storage_key.extend_from_slice(&encoded_para_id);
 	StorageKey(storage_key)
 }
+
+/// This returns the storage key for the id of the relay chain's current GRANDPA authority set.
+pub fn current_set_id_storage_key() -> StorageKey {
+	StorageKey(frame_support::storage::storage_prefix(b"Grandpa", b"CurrentSetId").to_vec())
+}
+
+/// Decodes the `Grandpa::CurrentSetId` storage value, which is absent until the first set change.
+pub fn decode_current_set_id(value: Option<&[u8]>) -> Result<u64, error::Error> {
+	match value {
+		Some(mut value) => Ok(u64::decode(&mut value)?),
+		None => Ok(0),
+	}
+}
+
+/// Length of an abridged `(BlockNumber, Hash)` parachain head, which is always shorter than a full
+/// header since that carries three hashes.
+pub const ABRIDGED_HEAD_LEN: usize = 4 + 32;
+
+/// A parachain head as stored in `Paras::Heads`. Some relay runtime versions store an abridged
+/// `(BlockNumber, Hash)` tuple rather than the full header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParachainHead<H> {
+	/// The full parachain header.
+	Full(H),
+	/// The parachain block number and hash, without any of the header's roots.
+	Abridged {
+		/// Parachain block number
+		number: u32,
+		/// Parachain block hash
+		hash: H256,
+	},
+}
+
+impl<H> ParachainHead<H>
+where
+	H: Header<Hash = H256, Number = u32>,
+{
+	/// Decodes the SCALE encoded head data, recognizing both the full and abridged formats.
+	pub fn decode(head: &[u8]) -> Result<Self, error::Error> {
+		if head.len() == ABRIDGED_HEAD_LEN {
+			let (number, hash) = <(u32, H256)>::decode(&mut &head[..])?;
+			return Ok(ParachainHead::Abridged { number, hash })
+		}
+
+		Ok(ParachainHead::Full(H::decode(&mut &head[..])?))
+	}
+
+	/// The parachain block number.
+	pub fn number(&self) -> u32 {
+		match self {
+			ParachainHead::Full(header) => *header.number(),
+			ParachainHead::Abridged { number, .. } => *number,
+		}
+	}
+
+	/// The parachain state root, which only full heads carry.
+	pub fn state_root(&self) -> Option<H256> {
+		match self {
+			ParachainHead::Full(header) => Some(*header.state_root()),
+			ParachainHead::Abridged { .. } => None,
+		}
+	}
+
+	/// Returns the full header, failing with [`error::Error::AbridgedHeadUnsupported`] for
+	/// abridged heads.
+	pub fn into_header(self) -> Result<H, error::Error> {
+		match self {
+			ParachainHead::Full(header) => Ok(header),
+			ParachainHead::Abridged { number, .. } =>
+				Err(error::Error::AbridgedHeadUnsupported { number }),
+		}
+	}
+}
+
+/// Consensus engine id of AURA, which most parachains use for collator selection.
+pub const AURA_ENGINE_ID: sp_runtime::ConsensusEngineId = *b"aura";
+
+/// Recovers the collator that authored the given SCALE encoded parachain header, from the AURA slot
+/// in its pre-runtime digest and the parachain's AURA `authorities`, checking that the header was
+/// sealed by that collator. Returns `None` for headers that aren't sealed with AURA.
+pub fn parachain_author(
+	para_header_bytes: &[u8],
+	authorities: &[sr25519::Public],
+) -> Result<Option<sr25519::Public>, error::Error> {
+	let mut header =
+		sp_runtime::generic::Header::<u32, BlakeTwo256>::decode(&mut &para_header_bytes[..])?;
+	// the seal is always the last digest item, and isn't part of the pre-seal hash.
+	let seal = match header.digest_mut().pop() {
+		Some(DigestItem::Seal(id, seal)) if id == AURA_ENGINE_ID => seal,
+		_ => return Ok(None),
+	};
+	let signature = sr25519::Signature::decode(&mut &seal[..])?;
+
+	let slot = header
+		.digest()
+		.logs()
+		.iter()
+		.find_map(|log| match log {
+			DigestItem::PreRuntime(id, slot) if *id == AURA_ENGINE_ID => Some(slot),
+			_ => None,
+		})
+		.ok_or_else(|| anyhow!("AURA sealed header is missing its slot"))?;
+	let slot = u64::decode(&mut &slot[..])?;
+
+	if authorities.is_empty() {
+		Err(anyhow!("AURA authority set is empty"))?
+	}
+	let author = authorities[(slot % authorities.len() as u64) as usize];
+	if !sp_io::crypto::sr25519_verify(&signature, header.hash().as_ref(), &author) {
+		Err(anyhow!("AURA seal isn't signed by the slot author"))?
+	}
+
+	Ok(Some(author))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn client_state(latest_relay_height: u32) -> ClientState {
+		ClientState {
+			current_authorities: vec![],
+			current_set_id: 1,
+			latest_relay_height,
+			latest_para_height: 10,
+			latest_relay_hash: H256::repeat_byte(1),
+			para_id: 2000,
+		}
+	}
+
+	#[test]
+	fn test_snapshot_anchor() {
+		let authority = sp_core::ed25519::Public::from_raw([7; 32]);
+		let anchor = SnapshotAnchor {
+			hash: H256::repeat_byte(2),
+			height: 500,
+			set_id: 4,
+			authorities: vec![(authority.into(), 1)],
+		};
+
+		let mut lagging = client_state(100);
+		assert!(anchor.anchor(&mut lagging));
+		assert_eq!(lagging.latest_relay_hash, anchor.hash);
+		assert_eq!(lagging.latest_relay_height, anchor.height);
+		assert_eq!(lagging.current_set_id, anchor.set_id);
+		assert_eq!(lagging.current_authorities, anchor.authorities);
+		// parachain progress is kept
+		assert_eq!(lagging.latest_para_height, 10);
+
+		// snapshots older than the client are ignored
+		let mut ahead = client_state(600);
+		assert!(!anchor.anchor(&mut ahead));
+		assert_eq!(ahead.latest_relay_height, 600);
+		assert_eq!(ahead.latest_relay_hash, H256::repeat_byte(1));
+	}
+
+	#[test]
+	fn test_parachain_author() {
+		use sp_core::Pair;
+
+		let collators = (1..=3u8).map(|i| sr25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+		let authorities = collators.iter().map(|pair| pair.public()).collect::<Vec<_>>();
+
+		let sealed_header = |slot: u64, signer: &sr25519::Pair| {
+			let mut header = sp_runtime::generic::Header::<u32, BlakeTwo256>::new(
+				10,
+				Default::default(),
+				Default::default(),
+				Default::default(),
+				Default::default(),
+			);
+			header.digest_mut().push(DigestItem::PreRuntime(AURA_ENGINE_ID, slot.encode()));
+			let signature = signer.sign(header.hash().as_ref());
+			header.digest_mut().push(DigestItem::Seal(AURA_ENGINE_ID, signature.encode()));
+			header.encode()
+		};
+
+		// slot 4 belongs to the second collator
+		let header = sealed_header(4, &collators[1]);
+		assert_eq!(parachain_author(&header, &authorities).unwrap(), Some(authorities[1]));
+
+		// sealed by a collator that doesn't own the slot
+		let header = sealed_header(4, &collators[0]);
+		assert!(parachain_author(&header, &authorities).is_err());
+
+		// headers without an AURA seal have no known author
+		let header = sp_runtime::generic::Header::<u32, BlakeTwo256>::new(
+			10,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		assert_eq!(parachain_author(&header.encode(), &authorities).unwrap(), None);
+	}
+
+	#[test]
+	fn test_build_proof_incrementally() {
+		type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let proofs = |byte: u8| ParachainHeaderProofs {
+			state_proof: vec![vec![byte; 4]],
+			extrinsic: vec![byte],
+			extrinsic_proof: vec![vec![byte; 2]],
+			storage_key: None,
+			extrinsic_index: 0,
+		};
+		let block = H256::repeat_byte(9);
+		let justification = vec![1, 2, 3];
+
+		let empty =
+			ParachainHeadersWithFinalityProof::<RelayHeader>::empty(block, justification.clone());
+		assert!(empty.parachain_headers.is_empty());
+		assert!(empty.finality_proof.unknown_headers.is_empty());
+
+		let mut incremental = empty.clone();
+		incremental.push_parachain_header(H256::repeat_byte(1), proofs(1));
+		incremental.push_parachain_header(H256::repeat_byte(2), proofs(2));
+
+		let direct = ParachainHeadersWithFinalityProof::<RelayHeader> {
+			finality_proof: FinalityProof { block, justification, unknown_headers: vec![] },
+			parachain_headers: [
+				(H256::repeat_byte(1), proofs(1)),
+				(H256::repeat_byte(2), proofs(2)),
+			]
+			.into_iter()
+			.collect(),
+		};
+		assert_eq!(incremental.encode(), direct.encode());
+
+		// pushing the same relay chain block again replaces its proofs
+		incremental.push_parachain_header(H256::repeat_byte(2), proofs(3));
+		assert_eq!(incremental.parachain_headers.len(), 2);
+		assert_eq!(incremental.parachain_headers[&H256::repeat_byte(2)].extrinsic, vec![3]);
+	}
+
+	#[test]
+	fn test_decode_parachain_head() {
+		type ParaHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let header = ParaHeader::new(
+			10,
+			H256::repeat_byte(1),
+			H256::repeat_byte(2),
+			H256::repeat_byte(3),
+			Default::default(),
+		);
+		let head = ParachainHead::<ParaHeader>::decode(&header.encode()).unwrap();
+		assert_eq!(head, ParachainHead::Full(header.clone()));
+		assert_eq!(head.number(), 10);
+		assert_eq!(head.state_root(), Some(H256::repeat_byte(2)));
+		assert_eq!(head.into_header().unwrap(), header);
+
+		let abridged = (10u32, header.hash()).encode();
+		assert_eq!(abridged.len(), ABRIDGED_HEAD_LEN);
+		let head = ParachainHead::<ParaHeader>::decode(&abridged).unwrap();
+		assert_eq!(head, ParachainHead::Abridged { number: 10, hash: header.hash() });
+		assert_eq!(head.number(), 10);
+		assert_eq!(head.state_root(), None);
+		assert!(matches!(
+			head.into_header(),
+			Err(error::Error::AbridgedHeadUnsupported { number: 10 })
+		));
+
+		// neither format
+		assert!(ParachainHead::<ParaHeader>::decode(&[1, 2, 3]).is_err());
+	}
+
+	#[test]
+	fn test_decode_versioned_finality_proof() {
+		type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let unknown_header = RelayHeader::new(
+			1,
+			Default::default(),
+			Default::default(),
+			Default::default(),
+			Default::default(),
+		);
+		let proof = FinalityProof::<RelayHeader> {
+			block: H256::repeat_byte(1),
+			justification: vec![1, 2, 3],
+			unknown_headers: vec![unknown_header],
+		};
+
+		// bare proofs, as returned by older nodes, still decode
+		assert_eq!(FinalityProof::decode_versioned(&proof.encode()).unwrap(), proof);
+
+		// the current version is unwrapped
+		let wrapped = VersionedFinalityProof::V1(proof.clone()).encode();
+		assert_eq!(wrapped[0], FINALITY_PROOF_V1);
+		assert_eq!(FinalityProof::<RelayHeader>::decode_versioned(&wrapped).unwrap(), proof);
+
+		// a future version isn't mistaken for the current one
+		let mut future = wrapped;
+		future[0] = 2;
+		assert!(matches!(
+			FinalityProof::<RelayHeader>::decode_versioned(&future),
+			Err(error::Error::UnsupportedFinalityProofVersion { version: 2 })
+		));
+
+		assert!(FinalityProof::<RelayHeader>::decode_versioned(&[]).is_err());
+	}
+
+	#[test]
+	fn test_decode_versioned_parachain_headers() {
+		type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let finality_proof = FinalityProof::<RelayHeader> {
+			block: H256::repeat_byte(1),
+			justification: vec![1, 2, 3],
+			unknown_headers: vec![],
+		};
+		let legacy = ParachainHeaderProofsV1 {
+			state_proof: vec![vec![1, 2], vec![3]],
+			extrinsic: vec![4],
+			extrinsic_proof: vec![vec![5]],
+		};
+		let proof = ParachainHeadersWithFinalityProof::<RelayHeader> {
+			finality_proof: finality_proof.clone(),
+			parachain_headers: [(H256::repeat_byte(2), legacy.clone().into())]
+				.into_iter()
+				.collect(),
+		};
+
+		// proofs in the current encoding decode as they are
+		let mut with_key = proof.clone();
+		with_key.parachain_headers.values_mut().for_each(|proofs| {
+			proofs.storage_key = Some(parachain_header_storage_key(2000).0);
+			proofs.extrinsic_index = 2;
+		});
+		let decoded =
+			ParachainHeadersWithFinalityProof::<RelayHeader>::decode_versioned(&with_key.encode())
+				.unwrap();
+		assert_eq!(decoded.encode(), with_key.encode());
+
+		// proofs encoded before the extrinsic index was added still decode, at the first extrinsic
+		let without_index = with_key
+			.parachain_headers
+			.iter()
+			.map(|(hash, proofs)| {
+				let proofs = ParachainHeaderProofsV2 {
+					state_proof: proofs.state_proof.clone(),
+					extrinsic: proofs.extrinsic.clone(),
+					extrinsic_proof: proofs.extrinsic_proof.clone(),
+					storage_key: proofs.storage_key.clone(),
+				};
+				(*hash, proofs)
+			})
+			.collect::<BTreeMap<_, _>>();
+		let encoded = (finality_proof.clone(), without_index).encode();
+		let decoded =
+			ParachainHeadersWithFinalityProof::<RelayHeader>::decode_versioned(&encoded).unwrap();
+		assert!(decoded.parachain_headers.values().all(|proofs| proofs.extrinsic_index == 0));
+		assert_eq!(
+			decoded.parachain_headers[&H256::repeat_byte(2)].storage_key,
+			Some(parachain_header_storage_key(2000).0)
+		);
+
+		// proofs encoded before the storage key was added still decode, without a key
+		let encoded = (
+			finality_proof,
+			[(H256::repeat_byte(2), legacy)].into_iter().collect::<BTreeMap<_, _>>(),
+		)
+			.encode();
+		assert!(
+			ParachainHeadersWithFinalityProof::<RelayHeader>::decode(&mut &encoded[..]).is_err()
+		);
+		let decoded =
+			ParachainHeadersWithFinalityProof::<RelayHeader>::decode_versioned(&encoded).unwrap();
+		assert_eq!(decoded.encode(), proof.encode());
+
+		assert!(ParachainHeadersWithFinalityProof::<RelayHeader>::decode_versioned(&[1]).is_err());
+	}
+
+	#[test]
+	fn test_proofs_compare_by_value() {
+		use std::collections::HashSet;
+		type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let proofs = || ParachainHeaderProofs {
+			state_proof: vec![vec![1, 2], vec![3]],
+			extrinsic: vec![4],
+			extrinsic_proof: vec![vec![5]],
+			storage_key: Some(vec![6]),
+			extrinsic_index: 0,
+		};
+		assert_eq!(proofs(), proofs());
+		// the order of the proof nodes is part of the value
+		let mut reordered = proofs();
+		reordered.state_proof.reverse();
+		assert_ne!(reordered, proofs());
+		let deduplicated =
+			[proofs(), reordered.clone(), proofs()].into_iter().collect::<HashSet<_>>();
+		assert_eq!(deduplicated.len(), 2);
+
+		let header = |number: u32, parent_hash: H256| {
+			RelayHeader::new(
+				number,
+				Default::default(),
+				Default::default(),
+				parent_hash,
+				Default::default(),
+			)
+		};
+		let finality_proof = || {
+			let first = header(1, H256::zero());
+			let second = header(2, first.hash());
+			FinalityProof::<RelayHeader> {
+				block: second.hash(),
+				justification: vec![1, 2, 3],
+				unknown_headers: vec![first, second],
+			}
+		};
+		assert_eq!(finality_proof(), finality_proof());
+		// as is the order of the headers
+		let mut reordered = finality_proof();
+		reordered.unknown_headers.reverse();
+		assert_ne!(reordered, finality_proof());
+	}
+
+	#[test]
+	fn test_header_by_hash() {
+		type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+		let first = RelayHeader::new(
+			1,
+			Default::default(),
+			H256::repeat_byte(1),
+			H256::zero(),
+			Default::default(),
+		);
+		let second = RelayHeader::new(
+			2,
+			Default::default(),
+			H256::repeat_byte(2),
+			first.hash(),
+			Default::default(),
+		);
+		let proof = FinalityProof::<RelayHeader> {
+			block: second.hash(),
+			justification: vec![1, 2, 3],
+			unknown_headers: vec![first.clone(), second.clone()],
+		};
+
+		assert_eq!(proof.header_by_hash(&first.hash()), Some(&first));
+		assert_eq!(proof.header_by_hash(&second.hash()), Some(&second));
+		assert_eq!(proof.state_root_of(&second.hash()), Some(H256::repeat_byte(2)));
+
+		// headers the proof doesn't have aren't found
+		assert_eq!(proof.header_by_hash(&H256::repeat_byte(9)), None);
+		assert_eq!(proof.state_root_of(&H256::repeat_byte(9)), None);
+		assert_eq!(proof.state_root_of(&first.parent_hash), None);
+	}
+}
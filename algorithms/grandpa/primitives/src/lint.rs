@@ -0,0 +1,193 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks generated proofs against what a pallet-ibc GRANDPA client version expects of them, so
+//! relayers can catch proofs that would be rejected before submitting them.
+
+use crate::ParachainHeadersWithFinalityProof;
+use codec::{Codec, Encode};
+use sp_core::H256;
+use sp_std::prelude::*;
+
+/// What a pallet-ibc GRANDPA client version expects of the proofs it's given.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PalletVersion {
+	/// Largest SCALE encoded proof the client accepts, usually bounded by the runtime's maximum
+	/// extrinsic size.
+	pub max_proof_size: Option<usize>,
+	/// Largest number of unknown relay chain headers the client accepts in a single proof.
+	pub max_unknown_headers: Option<usize>,
+	/// Whether the client decodes [`ParachainHeaderProofs::storage_key`], clients that predate it
+	/// ignore the key and can't detect proofs generated against another runtime's metadata.
+	///
+	/// [`ParachainHeaderProofs::storage_key`]: crate::ParachainHeaderProofs::storage_key
+	pub supports_storage_key: bool,
+}
+
+impl PalletVersion {
+	/// The client in this repository, which doesn't limit proofs beyond the runtime's extrinsic
+	/// size.
+	pub const CURRENT: Self =
+		Self { max_proof_size: None, max_unknown_headers: None, supports_storage_key: true };
+
+	/// Clients that predate the parachain header storage key.
+	pub const LEGACY: Self = Self { supports_storage_key: false, ..Self::CURRENT };
+}
+
+/// A reason a pallet version may reject a proof, or verify it differently than expected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+	/// The encoded proof is larger than the pallet accepts.
+	ProofTooLarge {
+		/// Size of the encoded proof
+		size: usize,
+		/// Largest proof the pallet accepts
+		limit: usize,
+	},
+	/// The proof contains more unknown headers than the pallet accepts.
+	TooManyUnknownHeaders {
+		/// Number of unknown headers in the proof
+		count: usize,
+		/// Largest number of unknown headers the pallet accepts
+		limit: usize,
+	},
+	/// The proof has no unknown headers, so its justification can't be anchored to the client's
+	/// latest relay chain block.
+	NoUnknownHeaders,
+	/// The parachain header proof included in this relay chain block has a storage key that the
+	/// pallet ignores.
+	UnsupportedStorageKey {
+		/// Relay chain block of the parachain header proof
+		relay_hash: H256,
+	},
+}
+
+impl<H> ParachainHeadersWithFinalityProof<H>
+where
+	H: Codec,
+{
+	/// Checks the proof against the known pitfalls of the given pallet version, returning a
+	/// warning for each of them the proof runs into. No warnings doesn't guarantee the proof
+	/// verifies, only that it can be decoded and isn't rejected for its shape.
+	pub fn lint_for_pallet_version(&self, version: PalletVersion) -> Vec<Warning> {
+		let mut warnings = vec![];
+
+		if let Some(limit) = version.max_proof_size {
+			let size = self.encoded_size();
+			if size > limit {
+				warnings.push(Warning::ProofTooLarge { size, limit });
+			}
+		}
+
+		let count = self.finality_proof.unknown_headers.len();
+		if count == 0 {
+			warnings.push(Warning::NoUnknownHeaders);
+		}
+		if let Some(limit) = version.max_unknown_headers {
+			if count > limit {
+				warnings.push(Warning::TooManyUnknownHeaders { count, limit });
+			}
+		}
+
+		if !version.supports_storage_key {
+			warnings.extend(
+				self.parachain_headers
+					.iter()
+					.filter(|(_, proofs)| proofs.storage_key.is_some())
+					.map(|(relay_hash, _)| Warning::UnsupportedStorageKey {
+						relay_hash: *relay_hash,
+					}),
+			);
+		}
+
+		warnings
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PalletVersion, Warning};
+	use crate::{FinalityProof, ParachainHeaderProofs, ParachainHeadersWithFinalityProof};
+	use codec::Encode;
+	use sp_core::H256;
+	use sp_runtime::traits::BlakeTwo256;
+
+	type RelayHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
+
+	fn proof(unknown_headers: u32) -> ParachainHeadersWithFinalityProof<RelayHeader> {
+		let unknown_headers = (1..=unknown_headers)
+			.map(|number| {
+				RelayHeader::new(
+					number,
+					Default::default(),
+					Default::default(),
+					Default::default(),
+					Default::default(),
+				)
+			})
+			.collect();
+		ParachainHeadersWithFinalityProof {
+			finality_proof: FinalityProof {
+				block: H256::repeat_byte(1),
+				justification: vec![0; 64],
+				unknown_headers,
+			},
+			parachain_headers: [(
+				H256::repeat_byte(2),
+				ParachainHeaderProofs {
+					state_proof: vec![vec![0; 32]],
+					extrinsic: vec![0; 8],
+					extrinsic_proof: vec![],
+					storage_key: Some(vec![0; 32]),
+					extrinsic_index: 0,
+				},
+			)]
+			.into_iter()
+			.collect(),
+		}
+	}
+
+	#[test]
+	fn test_lint_for_pallet_version() {
+		let proof = proof(5);
+		assert!(proof.lint_for_pallet_version(PalletVersion::CURRENT).is_empty());
+		assert_eq!(
+			proof.lint_for_pallet_version(PalletVersion::LEGACY),
+			vec![Warning::UnsupportedStorageKey { relay_hash: H256::repeat_byte(2) }]
+		);
+
+		// a version that limits the headers in a proof
+		let version = PalletVersion { max_unknown_headers: Some(4), ..PalletVersion::CURRENT };
+		assert_eq!(
+			proof.lint_for_pallet_version(version),
+			vec![Warning::TooManyUnknownHeaders { count: 5, limit: 4 }]
+		);
+		assert!(proof
+			.lint_for_pallet_version(PalletVersion { max_unknown_headers: Some(5), ..version })
+			.is_empty());
+
+		let size = proof.encoded_size();
+		let version = PalletVersion { max_proof_size: Some(size - 1), ..PalletVersion::CURRENT };
+		assert_eq!(
+			proof.lint_for_pallet_version(version),
+			vec![Warning::ProofTooLarge { size, limit: size - 1 }]
+		);
+
+		assert_eq!(
+			self::proof(0).lint_for_pallet_version(PalletVersion::CURRENT),
+			vec![Warning::NoUnknownHeaders]
+		);
+	}
+}
@@ -0,0 +1,190 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks of proof assembly, run with `cargo bench -p grandpa-prover --features mocks`.
+//!
+//! The prover is driven against [`MockChain`]s of [`RELAY_BLOCKS`] relay chain blocks, in which
+//! the parachain's head changes every [`PARA_BLOCK_INTERVAL`] blocks. The chains are built from
+//! fixed seeds and served over an in-memory rpc transport, so runs are reproducible and don't
+//! touch the network. The size of the generated proofs is reported as each benchmark's
+//! throughput.
+
+use codec::{Compact, Encode};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use grandpa_prover::{
+	fetch_finality_proof, is_requested_para_header, is_requested_para_header_in,
+	mock::{mock_prover, MockChain},
+	Commit, GrandpaJustification, GrandpaProver,
+};
+use primitives::{parachain_header_storage_key, FinalityProof};
+use sp_runtime::traits::Header as _;
+use std::collections::BTreeSet;
+use subxt::{config::substrate::SubstrateHeader, PolkadotConfig};
+use tokio::runtime::Runtime;
+
+type Header = SubstrateHeader<u32, subxt::config::substrate::BlakeTwo256>;
+
+/// Number of relay chain blocks in the fixture.
+const RELAY_BLOCKS: u32 = 500;
+
+/// The parachain's head changes every `PARA_BLOCK_INTERVAL` relay chain blocks.
+const PARA_BLOCK_INTERVAL: u32 = 4;
+
+/// Number of other entries in each relay chain block's state, which the state proofs of the
+/// parachain's head are built against.
+const STATE_ENTRIES: u8 = 64;
+
+const PARA_ID: u32 = 2000;
+
+/// A prover of a parachain whose blocks, each with a timestamp inherent, are included in the relay
+/// chain blocks `1..=RELAY_BLOCKS`. The relay chain serves a finality proof of its last block.
+fn fixture(runtime: &Runtime) -> GrandpaProver<PolkadotConfig> {
+	let mut para_chain = MockChain::default();
+	let genesis = para_chain.headers().next().expect("Mock chains start at genesis; qed");
+	let mut para_heads = vec![genesis.encode().encode()];
+	for number in 1..=RELAY_BLOCKS / PARA_BLOCK_INTERVAL {
+		let timestamp = (4u8, 3u8, 0u8, Compact(u64::from(number) * 12_000)).encode();
+		let header = para_chain.push_block(vec![], vec![timestamp.encode()]);
+		para_heads.push(header.encode().encode());
+	}
+
+	let para_key = parachain_header_storage_key(PARA_ID).0;
+	let mut relay_chain = MockChain::default();
+	for number in 1..=RELAY_BLOCKS {
+		let state = (0..STATE_ENTRIES)
+			.map(|i| (vec![i; 32], [i, number as u8].repeat(64)))
+			.chain(Some((
+				para_key.clone(),
+				para_heads[(number / PARA_BLOCK_INTERVAL) as usize].clone(),
+			)))
+			.collect();
+		relay_chain.push_block(state, vec![]);
+	}
+
+	let target = relay_chain.headers().last().expect("Relay chain has blocks; qed").clone();
+	let justification = GrandpaJustification::<Header> {
+		round: 1,
+		commit: Commit {
+			target_hash: target.hash(),
+			target_number: *target.number(),
+			precommits: vec![],
+		},
+		votes_ancestries: vec![],
+	};
+	let finality_proof = FinalityProof::<Header> {
+		block: target.hash(),
+		justification: justification.encode(),
+		unknown_headers: vec![],
+	};
+	relay_chain.set_finality_proof(RELAY_BLOCKS, finality_proof.encode());
+
+	runtime
+		.block_on(mock_prover(relay_chain, para_chain, PARA_ID))
+		.expect("Mock chains serve the prover; qed")
+}
+
+/// The parachain header numbers of every head change.
+fn para_numbers() -> BTreeSet<u32> {
+	(1..=RELAY_BLOCKS / PARA_BLOCK_INTERVAL).collect()
+}
+
+fn finality_proof(c: &mut Criterion) {
+	let runtime = Runtime::new().expect("Tokio runtime; qed");
+	let prover = fixture(&runtime);
+	let fetch = || {
+		runtime
+			.block_on(fetch_finality_proof::<Header>(&*prover.grandpa_rpc, RELAY_BLOCKS))
+			.expect("Fixture finality proof decodes; qed")
+	};
+
+	let mut group = c.benchmark_group("finality_proof");
+	group.throughput(Throughput::Bytes(fetch().0.encode().len() as u64));
+	group.bench_function("fetch_and_decode", |b| b.iter(fetch));
+	group.finish();
+}
+
+fn requested_para_header_lookup(c: &mut Criterion) {
+	// every third parachain block of 5000 is requested, with heads advancing by one or two
+	let requested = (1..=5000u32).filter(|number| number % 3 == 0).collect::<Vec<_>>();
+	let requested_set = requested.iter().copied().collect::<BTreeSet<_>>();
+	let heads = (0..=5000u32).filter(|number| number % 7 != 0).collect::<Vec<_>>();
+	let proven = |is_requested: &dyn Fn(&u32, Option<&u32>) -> bool| {
+		let mut previous_number = None;
+		heads
+			.iter()
+			.filter(|number| {
+				let requested = is_requested(number, previous_number);
+				previous_number = Some(*number);
+				requested
+			})
+			.count()
+	};
+
+	let mut group = c.benchmark_group("requested_para_header_lookup");
+	group.throughput(Throughput::Elements(heads.len() as u64));
+	group.bench_function("slice", |b| {
+		b.iter(|| {
+			proven(&|number, previous| is_requested_para_header(number, previous, &requested))
+		})
+	});
+	group.bench_function("set", |b| {
+		b.iter(|| {
+			proven(&|number, previous| {
+				is_requested_para_header_in(number, previous, &requested_set)
+			})
+		})
+	});
+	group.finish();
+}
+
+fn assemble_proof(c: &mut Criterion) {
+	let runtime = Runtime::new().expect("Tokio runtime; qed");
+	let prover = fixture(&runtime);
+	let all = para_numbers();
+
+	let mut group = c.benchmark_group("assemble_proof");
+	for requested in [1, all.len() / 2, all.len()] {
+		let header_numbers = all.iter().rev().take(requested).copied().collect::<Vec<_>>();
+		let full = || {
+			runtime
+				.block_on(prover.query_finalized_parachain_headers_with_proof::<Header>(
+					1,
+					RELAY_BLOCKS,
+					None,
+					header_numbers.clone(),
+				))
+				.expect("Mock chains serve the prover; qed")
+		};
+		let compact = || {
+			runtime
+				.block_on(prover.query_compact_parachain_headers_with_proof::<Header>(
+					1,
+					RELAY_BLOCKS,
+					None,
+					header_numbers.clone(),
+				))
+				.expect("Mock chains serve the prover; qed")
+		};
+
+		group.throughput(Throughput::Bytes(full().encode().len() as u64));
+		group.bench_function(BenchmarkId::new("full", requested), |b| b.iter(full));
+		group.throughput(Throughput::Bytes(compact().encode().len() as u64));
+		group.bench_function(BenchmarkId::new("compact", requested), |b| b.iter(compact));
+	}
+	group.finish();
+}
+
+criterion_group!(benches, finality_proof, requested_para_header_lookup, assemble_proof);
+criterion_main!(benches);
@@ -0,0 +1,247 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "dry-run")]
+use crate::dry_run_verify;
+use crate::{
+	progress::ProgressHook,
+	rpc::{RateLimiter, RetryPolicy, RpcTimeouts},
+	AuthoritySetPin, GrandpaProver, ProverError,
+};
+#[cfg(feature = "dry-run")]
+use primitives::ClientState;
+use primitives::{ParachainHeadersWithFinalityProof, SnapshotAnchor};
+use sp_core::H256;
+use sp_runtime::traits::{One, Zero};
+use std::time::Duration;
+use subxt::{config::Header, Config};
+
+/// Configures and generates a parachain header finality proof, created with
+/// [`GrandpaProver::proof`]. Options that aren't set fall back to the prover's own configuration.
+pub struct ProofBuilder<'a, T: Config> {
+	prover: &'a GrandpaProver<T>,
+	previous_finalized_height: u32,
+	latest_finalized_height: u32,
+	latest_justification: Option<Vec<u8>>,
+	header_numbers: Vec<T::BlockNumber>,
+	pin: Option<AuthoritySetPin>,
+	snapshot: Option<SnapshotAnchor>,
+	timeouts: Option<RpcTimeouts>,
+	max_query_storage_blocks: Option<u32>,
+	max_head_age: Option<Duration>,
+	rate_limiter: Option<RateLimiter>,
+	max_concurrent_requests: Option<usize>,
+	retry_policy: Option<RetryPolicy>,
+	progress: Option<ProgressHook>,
+	#[cfg(feature = "dry-run")]
+	verify_against: Option<ClientState>,
+}
+
+impl<'a, T: Config> ProofBuilder<'a, T> {
+	pub(crate) fn new(prover: &'a GrandpaProver<T>) -> Self {
+		Self {
+			prover,
+			previous_finalized_height: 0,
+			latest_finalized_height: 0,
+			latest_justification: None,
+			header_numbers: vec![],
+			pin: None,
+			snapshot: None,
+			timeouts: None,
+			max_query_storage_blocks: None,
+			max_head_age: None,
+			rate_limiter: None,
+			max_concurrent_requests: None,
+			retry_policy: None,
+			progress: None,
+			#[cfg(feature = "dry-run")]
+			verify_against: None,
+		}
+	}
+
+	/// The relay chain height the client was last updated at.
+	pub fn from(mut self, previous_finalized_height: u32) -> Self {
+		self.previous_finalized_height = previous_finalized_height;
+		self
+	}
+
+	/// The relay chain height to prove finality up to.
+	pub fn to(mut self, latest_finalized_height: u32) -> Self {
+		self.latest_finalized_height = latest_finalized_height;
+		self
+	}
+
+	/// Proves finality with this justification, rather than fetching a finality proof.
+	pub fn justification(mut self, justification: Vec<u8>) -> Self {
+		self.latest_justification = Some(justification);
+		self
+	}
+
+	/// The parachain headers to prove.
+	pub fn headers(mut self, header_numbers: Vec<T::BlockNumber>) -> Self {
+		self.header_numbers = header_numbers;
+		self
+	}
+
+	/// Checks that the node agrees with the pinned authority set before proving, see
+	/// [`GrandpaProver::query_finalized_parachain_headers_with_proof_pinned`].
+	pub fn pinned(mut self, pin: AuthoritySetPin) -> Self {
+		self.pin = Some(pin);
+		self
+	}
+
+	/// Collects the relay chain ancestry back to the snapshot at most, see
+	/// [`GrandpaProver::query_finalized_parachain_headers_with_proof_from_snapshot`].
+	pub fn from_snapshot(mut self, anchor: SnapshotAnchor) -> Self {
+		self.snapshot = Some(anchor);
+		self
+	}
+
+	/// Overrides the prover's rpc timeouts.
+	pub fn timeouts(mut self, timeouts: RpcTimeouts) -> Self {
+		self.timeouts = Some(timeouts);
+		self
+	}
+
+	/// Overrides the number of relay chain blocks each `state_queryStorage` request spans.
+	pub fn max_query_storage_blocks(mut self, max_blocks: u32) -> Self {
+		self.max_query_storage_blocks = Some(max_blocks);
+		self
+	}
+
+	/// Overrides the maximum age of the relay chain's finalized head.
+	pub fn max_head_age(mut self, max_age: Duration) -> Self {
+		self.max_head_age = Some(max_age);
+		self
+	}
+
+	/// Overrides the prover's rate limiter.
+	pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+		self.rate_limiter = Some(rate_limiter);
+		self
+	}
+
+	/// Overrides the maximum number of rpc requests kept in flight at once.
+	pub fn max_concurrent_requests(mut self, max_concurrent: usize) -> Self {
+		self.max_concurrent_requests = Some(max_concurrent);
+		self
+	}
+
+	/// Overrides how the prover retries rpc reads that fail with transport errors.
+	pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+		self.retry_policy = Some(retry_policy);
+		self
+	}
+
+	/// Reports the progress of the proof to `hook`, rather than to the prover's own hook.
+	pub fn progress(mut self, hook: ProgressHook) -> Self {
+		self.progress = Some(hook);
+		self
+	}
+
+	/// Verifies the proof against `client_state` before returning it, see [`dry_run_verify`], so a
+	/// proof the light client would reject fails here rather than on-chain. Requires the `dry-run`
+	/// feature.
+	#[cfg(feature = "dry-run")]
+	pub fn verify_before_return(mut self, client_state: ClientState) -> Self {
+		self.verify_against = Some(client_state);
+		self
+	}
+}
+
+impl<T> ProofBuilder<'_, T>
+where
+	T: Config,
+	T::BlockNumber: Ord + Zero,
+	u32: From<T::BlockNumber>,
+	H256: From<T::Hash>,
+{
+	/// Generates the proof using the configured options.
+	pub async fn generate<H>(self) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let mut prover = self.prover.clone();
+		if let Some(timeouts) = self.timeouts {
+			prover.timeouts = timeouts;
+		}
+		if let Some(max_blocks) = self.max_query_storage_blocks {
+			prover.max_query_storage_blocks = Some(max_blocks);
+		}
+		if let Some(max_age) = self.max_head_age {
+			prover.max_head_age = Some(max_age);
+		}
+		if let Some(rate_limiter) = self.rate_limiter {
+			prover.rate_limiter = Some(rate_limiter);
+		}
+		if let Some(max_concurrent) = self.max_concurrent_requests {
+			prover.max_concurrent_requests = max_concurrent;
+		}
+		if let Some(retry_policy) = self.retry_policy {
+			prover.retry_policy = retry_policy;
+		}
+		if let Some(hook) = self.progress {
+			prover.progress = Some(hook);
+		}
+
+		let proof = match (self.pin, self.snapshot) {
+			(None, None) =>
+				prover
+					.query_finalized_parachain_headers_with_proof(
+						self.previous_finalized_height,
+						self.latest_finalized_height,
+						self.latest_justification,
+						self.header_numbers,
+					)
+					.await,
+			(Some(pin), None) =>
+				prover
+					.query_finalized_parachain_headers_with_proof_pinned(
+						&pin,
+						self.previous_finalized_height,
+						self.latest_finalized_height,
+						self.latest_justification,
+						self.header_numbers,
+					)
+					.await,
+			(None, Some(anchor)) =>
+				prover
+					.query_finalized_parachain_headers_with_proof_from_snapshot(
+						&anchor,
+						self.previous_finalized_height,
+						self.latest_finalized_height,
+						self.latest_justification,
+						self.header_numbers,
+					)
+					.await,
+			(Some(_), Some(_)) => Err(ProverError::Custom(
+				"A proof can't be both pinned and generated from a snapshot".into(),
+			)),
+		}?;
+
+		#[cfg(feature = "dry-run")]
+		if let Some(client_state) = self.verify_against {
+			let para_head_key = prover.para_head_storage_key(client_state.para_id);
+			dry_run_verify(client_state, &para_head_key, &proof)?;
+		}
+
+		Ok(proof)
+	}
+}
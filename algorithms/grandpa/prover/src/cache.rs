@@ -0,0 +1,121 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use sp_core::H256;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::sync::RwLock;
+
+/// Default number of entries held by each of the prover caches.
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
+/// A bounded cache that can be shared across clones and tasks. Once the capacity is reached, the
+/// least recently used entries are evicted first, a capacity of zero disables the cache.
+pub struct SharedCache<K, V> {
+	inner: Arc<RwLock<CacheInner<K, V>>>,
+	capacity: usize,
+}
+
+struct CacheInner<K, V> {
+	/// Entries along with the tick they were last used at
+	entries: BTreeMap<K, (V, u64)>,
+	/// Keys of the entries by the tick they were last used at, least recent first
+	order: BTreeMap<u64, K>,
+	tick: u64,
+}
+
+impl<K, V> Clone for SharedCache<K, V> {
+	fn clone(&self) -> Self {
+		Self { inner: self.inner.clone(), capacity: self.capacity }
+	}
+}
+
+impl<K: Ord + Clone, V: Clone> SharedCache<K, V> {
+	/// Creates an empty cache that holds at most `capacity` entries.
+	pub fn new(capacity: usize) -> Self {
+		let inner = CacheInner { entries: BTreeMap::new(), order: BTreeMap::new(), tick: 0 };
+		Self { inner: Arc::new(RwLock::new(inner)), capacity }
+	}
+
+	/// Returns a copy of the entry for the given key, marking it as the most recently used.
+	pub async fn get(&self, key: &K) -> Option<V> {
+		let mut inner = self.inner.write().await;
+		let CacheInner { entries, order, tick } = &mut *inner;
+		let (value, used) = entries.get_mut(key)?;
+		order.remove(&*used);
+		*tick += 1;
+		*used = *tick;
+		order.insert(*tick, key.clone());
+
+		Some(value.clone())
+	}
+
+	/// Inserts an entry, evicting the least recently used entries if the cache is full.
+	pub async fn insert(&self, key: K, value: V) {
+		let mut inner = self.inner.write().await;
+		let CacheInner { entries, order, tick } = &mut *inner;
+		*tick += 1;
+		if let Some((_, used)) = entries.insert(key.clone(), (value, *tick)) {
+			order.remove(&used);
+		}
+		order.insert(*tick, key);
+		while entries.len() > self.capacity {
+			match order.keys().next().copied() {
+				Some(oldest) =>
+					if let Some(key) = order.remove(&oldest) {
+						entries.remove(&key);
+					},
+				None => break,
+			}
+		}
+	}
+
+	/// Removes all entries from the cache.
+	pub async fn clear(&self) {
+		let mut inner = self.inner.write().await;
+		inner.entries.clear();
+		inner.order.clear();
+	}
+
+	/// Number of entries in the cache.
+	pub async fn len(&self) -> usize {
+		self.inner.read().await.entries.len()
+	}
+
+	/// Maximum number of entries held by the cache.
+	pub fn capacity(&self) -> usize {
+		self.capacity
+	}
+}
+
+/// Caches used by the prover, clones of the prover share the same underlying cache state.
+#[derive(Clone)]
+pub struct ProverCache {
+	/// SCALE encoded relay chain headers, keyed by their hash. Headers are immutable for a given
+	/// hash, so entries never go stale.
+	pub relay_headers: SharedCache<H256, Vec<u8>>,
+}
+
+impl ProverCache {
+	/// Creates the caches, holding at most `relay_headers` relay chain headers.
+	pub fn with_capacity(relay_headers: usize) -> Self {
+		Self { relay_headers: SharedCache::new(relay_headers) }
+	}
+}
+
+impl Default for ProverCache {
+	fn default() -> Self {
+		Self::with_capacity(DEFAULT_CACHE_CAPACITY)
+	}
+}
@@ -0,0 +1,334 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+/// Errors that are encountered by the prover
+#[derive(Debug, derive_more::From, derive_more::Display)]
+pub enum ProverError {
+	/// The relay chain doesn't expose the GRANDPA pallet or rpc, so finality can't be proven.
+	#[from(ignore)]
+	#[display(
+		fmt = "GRANDPA is not available on the relay chain, pallet found: {}, rpc found: {}",
+		pallet,
+		rpc
+	)]
+	GrandpaNotAvailable {
+		/// Whether the `Grandpa` pallet was found in the runtime metadata
+		pallet: bool,
+		/// Whether the `grandpa_proveFinality` rpc was found
+		rpc: bool,
+	},
+	/// The node returned a storage proof that doesn't prove the requested key.
+	#[from(ignore)]
+	#[display(fmt = "Storage proof doesn't prove the requested key: {:?}", key)]
+	ProofKeyMismatch {
+		/// The requested storage key
+		key: Vec<u8>,
+	},
+	/// The node serves a different authority set than the one pinned by the client.
+	#[from(ignore)]
+	#[display(
+		fmt = "Authority set mismatch, expected set id: {} with hash: {:?}, found set id: {} with hash: {:?}",
+		expected_set_id,
+		expected_authority_hash,
+		found_set_id,
+		found_authority_hash
+	)]
+	AuthoritySetMismatch {
+		/// Pinned set id
+		expected_set_id: u64,
+		/// Set id served by the node
+		found_set_id: u64,
+		/// Pinned authority list hash
+		expected_authority_hash: sp_core::H256,
+		/// Hash of the authority list served by the node
+		found_authority_hash: sp_core::H256,
+	},
+	/// The relay chain's latest finalized head is older than allowed, the node may be lagging.
+	#[from(ignore)]
+	#[display(fmt = "Latest finalized head is {:?} old, max allowed age is {:?}", age, max_age)]
+	StaleFinalizedHead {
+		/// Age of the latest finalized head
+		age: std::time::Duration,
+		/// Configured max age
+		max_age: std::time::Duration,
+	},
+	/// An rpc request didn't complete within its configured timeout.
+	#[from(ignore)]
+	#[display(fmt = "Request timed out after {:?}", timeout)]
+	Timeout {
+		/// The timeout that elapsed
+		timeout: std::time::Duration,
+	},
+	/// The parachain node hasn't imported a block the relay chain has finalized, even after
+	/// waiting for it to catch up.
+	#[from(ignore)]
+	#[display(
+		fmt = "Parachain node is behind the relay chain, block {:?} not imported after {:?}",
+		block,
+		waited
+	)]
+	ParaBehindRelay {
+		/// Hash of the parachain block
+		block: sp_core::H256,
+		/// How long the prover waited for the block
+		waited: std::time::Duration,
+	},
+	/// The proven range spans an authority set change, so the justification of its latest block
+	/// is signed by a set the client hasn't rotated to yet.
+	#[from(ignore)]
+	#[display(
+		fmt = "Proof spans the authority set change signalled in {:?}, prove up to block {} first",
+		signalled_in,
+		enacted_at
+	)]
+	SpansSetChange {
+		/// Hash of the relay chain block signalling the set change
+		signalled_in: sp_core::H256,
+		/// Relay chain block the set change is enacted at, the last finalized by the old set
+		enacted_at: u32,
+	},
+	/// The connection to the node failed in the middle of a request, which is worth retrying.
+	#[from(ignore)]
+	#[display(fmt = "Rpc transport error: {}", reason)]
+	Transport {
+		/// Error reported by the rpc client
+		reason: String,
+	},
+	/// The justification finalizes a block that isn't on the canonical chain, it was likely
+	/// reorged out.
+	#[from(ignore)]
+	#[display(
+		fmt = "Justification for block {:?} at {} isn't canonical, canonical block: {:?}",
+		found,
+		number,
+		canonical
+	)]
+	NonCanonicalJustification {
+		/// Number of the block finalized by the justification
+		number: u32,
+		/// Hash of the block finalized by the justification
+		found: sp_core::H256,
+		/// Hash of the canonical block at that number, if the node knows of one
+		canonical: Option<sp_core::H256>,
+	},
+	/// The relay chain block a proof was expected to start from isn't an ancestor of the
+	/// finalized chain, it was on a fork that was abandoned.
+	#[from(ignore)]
+	#[display(
+		fmt = "Relay chain reorged, block {:?} isn't an ancestor of {:?}",
+		expected,
+		found_tip
+	)]
+	Reorg {
+		/// Hash of the block the proof was expected to start from
+		expected: sp_core::H256,
+		/// Hash of the finalized block the ancestry was walked back from
+		found_tip: sp_core::H256,
+	},
+	/// The proof failed verification against the client state in a dry run, so the light client
+	/// would reject it.
+	#[from(ignore)]
+	#[display(fmt = "Proof rejected by the verifier: {}", reason)]
+	ProofRejected {
+		/// Error returned by the verifier
+		reason: String,
+	},
+	/// A relay chain block number exceeds `u32::MAX`, GRANDPA proofs are limited to 32-bit block
+	/// heights.
+	#[from(ignore)]
+	#[display(fmt = "Block number {} exceeds the 32-bit limit of GRANDPA proofs", number)]
+	BlockNumberOutOfRange {
+		/// The block number, formatted as the chain's block number type
+		number: String,
+	},
+	/// The relay chain doesn't have the `Paras` pallet, so it can't have parachains.
+	#[from(ignore)]
+	#[display(fmt = "The relay chain doesn't have the Paras pallet")]
+	ParasNotAvailable,
+	/// The relay chain has no head for the para id at its finalized head, the parachain isn't
+	/// registered or was offboarded.
+	#[from(ignore)]
+	#[display(fmt = "Para id {} is not registered on the relay chain", para_id)]
+	UnknownParaId {
+		/// The unknown para id
+		para_id: u32,
+	},
+	/// The para id is queried more than once, its proofs would overwrite each other.
+	#[from(ignore)]
+	#[display(fmt = "Para id {} is queried more than once", para_id)]
+	DuplicateParaId {
+		/// The duplicated para id
+		para_id: u32,
+	},
+	/// The node doesn't have the header with the given hash, it may have been pruned.
+	#[from(ignore)]
+	#[display(fmt = "Header with hash: {:?} not found", _0)]
+	HeaderNotFound(sp_core::H256),
+	/// The parachain block isn't finalized yet, the relay chain's finalized block includes an
+	/// older parachain head.
+	#[from(ignore)]
+	#[display(fmt = "Parachain block {} is not yet finalized, finalized: {}", number, finalized)]
+	ParaBlockNotFinalized {
+		/// Number of the parachain block
+		number: u32,
+		/// Number of the parachain head included in the relay chain's finalized block
+		finalized: u32,
+	},
+	/// The node doesn't have a justification for the given relay chain block, it may not be
+	/// finalized yet. Only returned by [`crate::fetch_finality_proof`], the prover polls for
+	/// justifications and returns [`Self::JustificationNotYetAvailable`] instead.
+	#[from(ignore)]
+	#[display(fmt = "No justification found for block: {}", _0)]
+	JustificationNotFound(u32),
+	/// The node still has no justification for a relay chain block it has finalized after polling
+	/// for it, it may not have stored it yet.
+	#[from(ignore)]
+	#[display(fmt = "Justification for block {} not yet available after {:?}", block, waited)]
+	JustificationNotYetAvailable {
+		/// Number of the relay chain block
+		block: u32,
+		/// How long the prover polled for the justification
+		waited: std::time::Duration,
+	},
+	/// A storage item the prover relies on is missing at the given block.
+	#[from(ignore)]
+	#[display(fmt = "Storage item {} not found at block: {:?}", item, at)]
+	StorageItemMissing {
+		/// Name of the storage item, as `Pallet::Item`
+		item: &'static str,
+		/// Hash of the block the item was read at
+		at: sp_core::H256,
+	},
+	/// The node has discarded the state of a relay chain block the proof reads from, full nodes
+	/// only keep the state of recent blocks.
+	#[from(ignore)]
+	#[display(fmt = "State of block {:?} has been pruned by the node", at)]
+	StatePruned {
+		/// Hash of the block whose state was read
+		at: sp_core::H256,
+	},
+	/// The block has no timestamp `set` inherent, or none with the expected pallet and call
+	/// indices.
+	#[from(ignore)]
+	#[display(fmt = "Timestamp inherent not found in block: {:?}", block)]
+	TimestampInherentNotFound {
+		/// Hash of the block
+		block: sp_core::H256,
+	},
+	/// subxt error
+	Subxt(subxt::error::Error),
+	/// Error of the rpc client, e.g. when connecting to the node fails.
+	#[display(fmt = "Rpc error: {}", _0)]
+	Rpc(jsonrpsee::core::Error),
+	/// The data fetched from the node fails to decode.
+	Decode(codec::Error),
+	/// Custom
+	Custom(String),
+}
+
+impl std::error::Error for ProverError {}
+
+impl From<anyhow::Error> for ProverError {
+	fn from(err: anyhow::Error) -> Self {
+		// errors raised as a `ProverError` deeper in the prover keep their variant.
+		let err = match err.downcast::<ProverError>() {
+			Ok(err) => return err,
+			Err(err) => err,
+		};
+		let err = match err.downcast::<subxt::error::Error>() {
+			Ok(err) => return ProverError::Subxt(err),
+			Err(err) => err,
+		};
+		let err = match err.downcast::<jsonrpsee::core::Error>() {
+			Ok(err) => return ProverError::Rpc(err),
+			Err(err) => err,
+		};
+		match err.downcast::<codec::Error>() {
+			Ok(err) => ProverError::Decode(err),
+			Err(err) => ProverError::Custom(format!("{err:#}")),
+		}
+	}
+}
+
+/// Summarizes an error returned by the prover in a single line, naming the root cause of the
+/// error chain along with a suggested fix for the common ones.
+pub fn summarize_error(err: &anyhow::Error) -> String {
+	const METADATA_HINT: &str =
+		"the prover's runtime metadata may not match the chain, regenerate it";
+
+	let root_cause = err.root_cause();
+	let prover_hint =
+		err.chain()
+			.find_map(|err| err.downcast_ref::<ProverError>())
+			.and_then(|err| match err {
+				ProverError::GrandpaNotAvailable { .. } =>
+					Some("the relay chain url may point to a chain without GRANDPA"),
+				ProverError::ProofKeyMismatch { .. } | ProverError::AuthoritySetMismatch { .. } =>
+					Some("the node may be faulty or malicious, try another node"),
+				ProverError::StaleFinalizedHead { .. } =>
+					Some("the node is lagging behind, wait for it to sync or try another node"),
+				ProverError::Timeout { .. } =>
+					Some("the node is overloaded or the timeout is too short, try increasing it"),
+				ProverError::ParaBehindRelay { .. } =>
+					Some("the parachain node is lagging, wait for it to sync or try another node"),
+				ProverError::Transport { .. } =>
+					Some("the connection to the node is unreliable, try another node"),
+				ProverError::NonCanonicalJustification { .. } =>
+					Some("the block was reorged out, prove finality with a newer justification"),
+				ProverError::Reorg { .. } =>
+					Some("the relay chain reorged, reset the client's cursor to a finalized block"),
+				ProverError::ProofRejected { .. } =>
+					Some("the client state may be outdated, verify against its latest state"),
+				ProverError::Decode(_) => Some(METADATA_HINT),
+				ProverError::ParasNotAvailable | ProverError::UnknownParaId { .. } =>
+					Some("the relay chain url or para id may be wrong, check the configuration"),
+				ProverError::HeaderNotFound(_) | ProverError::StorageItemMissing { .. } =>
+					Some("the block may have been pruned, use an archive node"),
+				ProverError::StatePruned { .. } =>
+					Some("the node only keeps recent state, use an archive node"),
+				ProverError::JustificationNotYetAvailable { .. } =>
+					Some("the node hasn't stored the justification yet, back off and retry"),
+				ProverError::TimestampInherentNotFound { .. } =>
+					Some("the runtime may be non-standard, set its timestamp call indices"),
+				ProverError::JustificationNotFound(_) |
+				ProverError::ParaBlockNotFinalized { .. } =>
+					Some("the block may not be finalized yet, retry once it is"),
+				ProverError::Rpc(_) =>
+					Some("the node isn't reachable, check its url and that it's running"),
+				ProverError::DuplicateParaId { .. } =>
+					Some("merge the header numbers queried for the parachain into one query"),
+				ProverError::BlockNumberOutOfRange { .. } |
+				ProverError::SpansSetChange { .. } |
+				ProverError::Subxt(_) |
+				ProverError::Custom(_) => None,
+			});
+	let message = root_cause.to_string().to_lowercase();
+	let hint = if prover_hint.is_some() {
+		prover_hint
+	} else if err.chain().any(|err| err.is::<codec::Error>()) {
+		Some(METADATA_HINT)
+	} else if message.contains("connection refused") || message.contains("connection reset") {
+		Some("the node isn't reachable, check its url and that it's running")
+	} else if message.contains("not found") {
+		Some("the block may have been pruned, use an archive node")
+	} else {
+		None
+	};
+
+	match hint {
+		Some(hint) => format!("{root_cause}: {hint}"),
+		None => root_cause.to_string(),
+	}
+}
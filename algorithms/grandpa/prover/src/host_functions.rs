@@ -19,8 +19,27 @@ use sp_runtime::{
 	app_crypto::RuntimePublic,
 	traits::{BlakeTwo256, Header},
 };
+use sp_wasm_interface::Function;
 use std::fmt::Debug;
 
+/// The runtime interfaces the verifier calls into when it's compiled to wasm, e.g. as part of a
+/// runtime: blake2 and keccak hashing, ed25519 signature verification for the GRANDPA votes and
+/// trie roots, along with the allocator every wasm blob needs. Executors running the verifier,
+/// such as `sc_executor::WasmExecutor`, take these as their host functions type parameter.
+pub type VerifierHostFunctions = (
+	sp_io::hashing::HostFunctions,
+	sp_io::crypto::HostFunctions,
+	sp_io::trie::HostFunctions,
+	sp_io::allocator::HostFunctions,
+	sp_io::logging::HostFunctions,
+);
+
+/// Returns the [`VerifierHostFunctions`], for executors that are given their host functions at
+/// runtime rather than as a type parameter.
+pub fn register_host_functions() -> Vec<&'static dyn Function> {
+	<VerifierHostFunctions as sp_wasm_interface::HostFunctions>::host_functions()
+}
+
 /// Only holds implementations for the relevant Host Functions for the verifier
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub struct HostFunctionsProvider;
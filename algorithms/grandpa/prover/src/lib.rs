@@ -16,38 +16,192 @@
 #![deny(missing_docs)]
 
 //! GRANDPA prover utilities
+//!
+//! The prover needs `std`, as it talks to the nodes over websockets. Its helpers that don't, such
+//! as [`canonical_storage_proof`], [`ParaHeadChange`], [`is_requested_para_header`],
+//! [`is_requested_para_header_in`] and [`is_mandatory_header`], live in the `no_std` primitives
+//! crate and are re-exported here, so verifiers can share them. The primitives crate is built
+//! without default features by `scripts/no_std_checks.sh`.
 
-use crate::polkadot::api::runtime_types::polkadot_parachain::primitives::Id;
 use anyhow::anyhow;
 pub use beefy_prover;
-use beefy_prover::helpers::{
-	fetch_timestamp_extrinsic_with_proof, unsafe_arc_cast, TimeStampExtWithProof,
-};
+use beefy_prover::helpers::{find_timestamp_inherent, prove_extrinsic, TimeStampExtWithProof};
+use builder::ProofBuilder;
+use cache::{ProverCache, SharedCache};
 use codec::{Decode, Encode};
-use finality_grandpa_rpc::GrandpaApiClient;
-use jsonrpsee::{async_client::Client, ws_client::WsClientBuilder};
+pub use error::ProverError;
+use futures::{Stream, StreamExt};
+#[cfg(feature = "dry-run")]
+use grandpa_light_client_verifier::verify_parachain_headers_with_grandpa_finality_proof_at_key;
+#[cfg(feature = "dry-run")]
+use host_functions::HostFunctionsProvider;
+use jsonrpsee::async_client::Client;
+pub use primitives::helpers::{
+	authority_set_changes, canonical_storage_proof, is_mandatory_header, is_requested_para_header,
+	is_requested_para_header_in, AuthoritySetChange, ParaHeadChange,
+};
 use primitives::{
-	parachain_header_storage_key, ClientState, FinalityProof, ParachainHeaderProofs,
-	ParachainHeadersWithFinalityProof,
+	current_set_id_storage_key, decode_current_set_id, justification::find_scheduled_change,
+	parachain_header_storage_key, ClientState, CompactParachainHeadersWithFinalityProof,
+	FinalityProof, ParachainHeaderProofs, ParachainHeadersWithFinalityProof, RelayTimestampProof,
+	SnapshotAnchor,
+};
+use progress::{ProgressHook, ProgressReporter};
+use rpc::{
+	concurrent_requests, connect, resubscribing_justifications, retry, stream_requests,
+	Connections, GrandpaRpc, JustificationStream, RateLimiter, RetryPolicy, RpcCallCounter,
+	RpcTimeouts, Subscriptions, WsGrandpaRpc, DEFAULT_MAX_CONCURRENT_REQUESTS,
+	MAX_RESUBSCRIBE_ATTEMPTS, RESUBSCRIBE_DELAY,
 };
 use serde::{Deserialize, Serialize};
-use sp_core::H256;
-use sp_finality_grandpa::{AuthorityId, AuthoritySignature};
-use sp_runtime::traits::{One, Zero};
+use sp_core::{storage::StorageKey, H256};
+use sp_finality_grandpa::{
+	AuthorityId, AuthorityList, AuthoritySignature, ScheduledChange, VersionedAuthorityList,
+	GRANDPA_AUTHORITIES_KEY,
+};
+use sp_runtime::{
+	generic,
+	traits::{BlakeTwo256, One, Zero},
+};
+use sp_trie::StorageProof;
 use std::{
 	collections::{BTreeMap, BTreeSet},
+	future::Future,
+	path::Path,
 	sync::Arc,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use subxt::{config::Header, Config, OnlineClient};
+use subxt::{config::Header, rpc::types::StorageChangeSet, Config, OnlineClient};
 
+/// Fluent api for configuring proofs
+pub mod builder;
+/// Caches shared across clones of the prover
+pub mod cache;
+/// Errors that can be encountered by the prover
+pub mod error;
 /// Host function implementation for the verifier
 pub mod host_functions;
+/// In-memory chains for testing the prover against
+#[cfg(any(test, feature = "mocks"))]
+pub mod mock;
 /// Subxt generated code for the parachain
 pub mod parachain;
 /// Subxt generated code for the relay chain
 pub mod polkadot;
+/// Progress reporting while proofs are generated
+pub mod progress;
+/// Transport agnostic GRANDPA rpc
+pub mod rpc;
+
+#[cfg(test)]
+mod tests;
+
+/// Name of the rpc method used for fetching GRANDPA finality proofs.
+pub const GRANDPA_PROVE_FINALITY_RPC: &str = "grandpa_proveFinality";
+
+/// Name of the GRANDPA pallet in the relay chain runtime.
+pub const GRANDPA_PALLET: &str = "Grandpa";
+
+/// Name of the pallet holding the parachain heads in the relay chain runtime.
+pub const PARAS_PALLET: &str = "Paras";
+
+mod sealed {
+	pub trait Sealed {}
+
+	impl<T: subxt::Config<BlockNumber = u32, Hash = sp_core::H256>> Sealed for T {}
+}
+
+/// A [`Config`] of the relay chain and parachain the prover can be used with, which is any config
+/// with `u32` block numbers and `H256` hashes. It implies the bounds on the block number and hash
+/// the prover's methods ask for, so generic code over the prover only needs this one.
+///
+/// ```
+/// use grandpa_prover::{GrandpaConfig, GrandpaProver, ProverError};
+///
+/// async fn latest_para_height<T: GrandpaConfig>(
+/// 	prover: &GrandpaProver<T>,
+/// ) -> Result<u32, ProverError> {
+/// 	prover.latest_finalized_para_height().await
+/// }
+///
+/// fn assert_grandpa_config<T: GrandpaConfig>() {}
+/// assert_grandpa_config::<subxt::PolkadotConfig>();
+/// ```
+///
+/// Configs that aren't known to conform, e.g. ones with `u64` block numbers, are rejected:
+///
+/// ```compile_fail
+/// use grandpa_prover::GrandpaConfig;
+///
+/// fn assert_grandpa_config<T: GrandpaConfig>() {}
+/// fn any_config<T: subxt::Config>() {
+/// 	assert_grandpa_config::<T>();
+/// }
+/// ```
+pub trait GrandpaConfig: Config<BlockNumber = u32, Hash = H256> + sealed::Sealed {}
+
+impl<T: Config<BlockNumber = u32, Hash = H256>> GrandpaConfig for T {}
+
+/// Configuration of a prover created with [`GrandpaProver::new_with_config`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProverConfig {
+	/// Timeout for connecting to the relay chain and parachain nodes, `None` uses the rpc
+	/// client's default.
+	pub connection_timeout: Option<Duration>,
+	/// Timeouts for the prover's rpc requests.
+	pub timeouts: RpcTimeouts,
+	/// Maximum number of relay chain headers kept in the header cache, `None` uses
+	/// [`cache::DEFAULT_CACHE_CAPACITY`] and zero disables the cache.
+	pub header_cache_capacity: Option<usize>,
+}
+
+/// Statistics about a generated proof, see [`GrandpaProver::query_with_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProofStats {
+	/// Number of relay chain headers in the finality proof's ancestry.
+	pub unknown_header_count: usize,
+	/// Number of parachain headers proven.
+	pub parachain_header_count: usize,
+	/// Combined size of the parachain headers' state proof nodes, in bytes.
+	pub total_state_proof_bytes: usize,
+	/// Number of rpc requests made while generating the proof.
+	pub rpc_calls: usize,
+	/// Time taken to generate the proof.
+	pub elapsed: Duration,
+}
+
+impl ProofStats {
+	/// Collects the statistics of `proof`, which took `rpc_calls` requests and `elapsed` to
+	/// generate.
+	pub fn new<H: codec::Codec>(
+		proof: &ParachainHeadersWithFinalityProof<H>,
+		rpc_calls: usize,
+		elapsed: Duration,
+	) -> Self {
+		Self {
+			unknown_header_count: proof.finality_proof.unknown_headers.len(),
+			parachain_header_count: proof.parachain_headers.len(),
+			total_state_proof_bytes: proof
+				.parachain_headers
+				.values()
+				.flat_map(|proofs| &proofs.state_proof)
+				.map(|node| node.len())
+				.sum(),
+			rpc_calls,
+			elapsed,
+		}
+	}
+}
 
 /// Contains methods useful for proving parachain header finality using GRANDPA
+///
+/// Clones of the prover share the same connections, which are closed once every clone has been
+/// dropped or any of them is shut down with [`GrandpaProver::close`]. Justification subscriptions
+/// opened with [`GrandpaProver::subscribe_justifications`] outlive the prover unless it's closed.
+///
+/// Relay chain block heights are limited to 32 bits, as GRANDPA commits and the
+/// `grandpa_proveFinality` rpc are `u32` typed. Heights read from the relay chain's headers are
+/// converted with [`checked_block_number`], which fails rather than truncating them.
 pub struct GrandpaProver<T: Config> {
 	/// Subxt client for the relay chain
 	pub relay_client: OnlineClient<T>,
@@ -59,6 +213,154 @@ pub struct GrandpaProver<T: Config> {
 	pub para_ws_client: Arc<Client>,
 	/// ParaId of the associated parachain
 	pub para_id: u32,
+	/// Caches shared by all clones of this prover.
+	pub cache: ProverCache,
+	/// Transport for the relay chain GRANDPA rpc.
+	pub grandpa_rpc: Arc<dyn GrandpaRpc>,
+	/// Justification subscriptions opened by this prover, clones track their own.
+	pub subscriptions: Subscriptions,
+	/// Closes the connection of the relay chain clients, shared by all clones of this prover. Only
+	/// closes the connections made by the prover itself, see [`connect`].
+	pub relay_connections: Connections,
+	/// Closes the connection of the parachain clients, see [`Self::relay_connections`].
+	pub para_connections: Connections,
+	/// Refuse to generate proofs if the relay chain's latest finalized head is older than this,
+	/// which happens when the node is lagging behind the network.
+	pub max_head_age: Option<Duration>,
+	/// Timeouts for the rpc requests made while generating proofs.
+	pub timeouts: RpcTimeouts,
+	/// Split `state_queryStorage` requests spanning more relay chain blocks than this, which
+	/// keeps them within the limits of the node.
+	pub max_query_storage_blocks: Option<u32>,
+	/// Limits the rate of the rpc requests made while generating proofs, shared by all clones of
+	/// this prover.
+	pub rate_limiter: Option<RateLimiter>,
+	/// How long to wait for the parachain node to import blocks the relay chain has finalized.
+	pub para_catch_up: ParaCatchUp,
+	/// Maximum number of rpc requests kept in flight at once while proving parachain headers.
+	pub max_concurrent_requests: usize,
+	/// Counts the rpc requests made while generating proofs, shared by all clones of this prover.
+	pub rpc_calls: RpcCallCounter,
+	/// Retries of the rpc reads made while generating proofs that fail with transport errors.
+	pub retry_policy: RetryPolicy,
+	/// Called as the prover makes progress generating a proof, which can take a while for long
+	/// relay chain ranges.
+	pub progress: Option<ProgressHook>,
+	/// Computes the storage keys of the parachain heads, `None` uses the canonical `Paras::Heads`
+	/// keys.
+	pub para_head_key: Option<ParaHeadKey>,
+	/// The `(pallet_index, call_index)` of the parachain's timestamp `set` inherent, for runtimes
+	/// where it can't be told apart by its shape, see [`prove_timestamp_inherent`].
+	pub timestamp_call: Option<(u8, u8)>,
+	/// How long to wait for the relay chain node to store the justification of a block it has just
+	/// finalized.
+	pub justification_poll: JustificationPoll,
+}
+
+impl<T: Config> Clone for GrandpaProver<T> {
+	fn clone(&self) -> Self {
+		Self {
+			relay_client: self.relay_client.clone(),
+			relay_ws_client: self.relay_ws_client.clone(),
+			para_client: self.para_client.clone(),
+			para_ws_client: self.para_ws_client.clone(),
+			para_id: self.para_id,
+			cache: self.cache.clone(),
+			grandpa_rpc: self.grandpa_rpc.clone(),
+			subscriptions: Subscriptions::default(),
+			relay_connections: self.relay_connections.clone(),
+			para_connections: self.para_connections.clone(),
+			max_head_age: self.max_head_age,
+			timeouts: self.timeouts,
+			max_query_storage_blocks: self.max_query_storage_blocks,
+			rate_limiter: self.rate_limiter.clone(),
+			para_catch_up: self.para_catch_up,
+			max_concurrent_requests: self.max_concurrent_requests,
+			rpc_calls: self.rpc_calls.clone(),
+			retry_policy: self.retry_policy,
+			progress: self.progress.clone(),
+			para_head_key: self.para_head_key.clone(),
+			timestamp_call: self.timestamp_call,
+			justification_poll: self.justification_poll,
+		}
+	}
+}
+
+impl<T: Config> GrandpaProver<T> {
+	/// Subscribes to the justifications of newly finalized relay chain blocks.
+	pub async fn subscribe_justifications(&self) -> Result<JustificationStream, ProverError> {
+		let subscription = self.grandpa_rpc.subscribe_justifications().await?;
+		Ok(self.subscriptions.track(subscription).await)
+	}
+
+	/// Notifications of the justifications of newly finalized relay chain blocks, for relayers that
+	/// update clients as finality advances rather than polling. Unlike
+	/// [`Self::subscribe_justifications`], the subscription is re-established when it fails, see
+	/// [`resubscribing_justifications`].
+	pub async fn finality_notifications(
+		&self,
+	) -> impl Stream<Item = Result<JustificationNotification, anyhow::Error>> + Send {
+		let subscription = resubscribing_justifications(
+			self.grandpa_rpc.clone(),
+			RESUBSCRIBE_DELAY,
+			MAX_RESUBSCRIBE_ATTEMPTS,
+		);
+		self.subscriptions.track(subscription).await.map(|justification| {
+			justification
+				.map(|justification| JustificationNotification(sp_core::Bytes(justification)))
+		})
+	}
+
+	/// Unsubscribes the justification subscriptions opened by this prover, then closes the
+	/// connections to the relay chain and parachain nodes. The connections are shared with the
+	/// prover's clones, whose requests fail from then on.
+	pub async fn close(self) {
+		self.subscriptions.close_all().await;
+		self.relay_connections.close();
+		self.para_connections.close();
+	}
+
+	/// Makes a storage or header read, subject to the rate limit and request timeout. Reads that
+	/// fail with transport errors are made again, as configured by [`Self::retry_policy`].
+	async fn request<R, E, Fut>(&self, mut request: impl FnMut() -> Fut) -> Result<R, anyhow::Error>
+	where
+		Fut: Future<Output = Result<R, E>>,
+		anyhow::Error: From<E>,
+	{
+		retry(self.retry_policy, || {
+			let request = request();
+			async move {
+				if let Some(rate_limiter) = &self.rate_limiter {
+					rate_limiter.until_ready().await;
+				}
+				self.rpc_calls.count(self.timeouts.request(request)).await
+			}
+		})
+		.await
+	}
+
+	/// Makes a finality proof request, subject to the rate limit and finality proof timeout.
+	/// Requests that fail with transport errors are made again, as configured by
+	/// [`Self::retry_policy`].
+	async fn finality_proof_request<R, E, Fut>(
+		&self,
+		mut request: impl FnMut() -> Fut,
+	) -> Result<R, anyhow::Error>
+	where
+		Fut: Future<Output = Result<R, E>>,
+		anyhow::Error: From<E>,
+	{
+		retry(self.retry_policy, || {
+			let request = request();
+			async move {
+				if let Some(rate_limiter) = &self.rate_limiter {
+					rate_limiter.until_ready().await;
+				}
+				self.rpc_calls.count(self.timeouts.finality_proof(request)).await
+			}
+		})
+		.await
+	}
 }
 
 // We redefine these here because we want the header to be bounded by subxt::config::Header in the
@@ -78,287 +380,3150 @@ pub struct GrandpaJustification<H: Header + codec::Decode> {
 	pub votes_ancestries: Vec<H>,
 }
 
+/// Relay chain header, used for reading fields that aren't exposed by [`subxt::config::Header`].
+pub type RelayHeader = generic::Header<u32, BlakeTwo256>;
+
 /// An encoded justification proving that the given header has been finalized
 #[derive(Clone, Serialize, Deserialize)]
 pub struct JustificationNotification(pub sp_core::Bytes);
 
-impl<T> GrandpaProver<T>
-where
-	T: Config,
-	T::BlockNumber: Ord + Zero,
-	u32: From<T::BlockNumber>,
-	sp_core::H256: From<T::Hash>,
-{
-	/// Initializes the parachain and relay chain clients given the ws urls.
-	pub async fn new(
-		relay_ws_url: &str,
-		para_ws_url: &str,
-		para_id: u32,
-	) -> Result<Self, anyhow::Error> {
-		let relay_ws_client = Arc::new(WsClientBuilder::default().build(relay_ws_url).await?);
-		let relay_client = OnlineClient::<T>::from_rpc_client(relay_ws_client.clone()).await?;
-		let para_ws_client = Arc::new(WsClientBuilder::default().build(para_ws_url).await?);
-		let para_client = OnlineClient::<T>::from_rpc_client(para_ws_client.clone()).await?;
+impl JustificationNotification {
+	/// The encoded justification.
+	pub fn as_bytes(&self) -> &[u8] {
+		&self.0 .0
+	}
 
-		Ok(Self { relay_ws_client, relay_client, para_ws_client, para_client, para_id })
+	/// Consumes the notification, returning the encoded justification.
+	pub fn into_inner(self) -> Vec<u8> {
+		self.0 .0
 	}
 
-	/// Construct the inital client state.
-	pub async fn initialize_client_state(&self) -> Result<ClientState, anyhow::Error> {
-		use sp_finality_grandpa::AuthorityList;
-		let latest_relay_hash = self.relay_client.rpc().finalized_head().await?;
-		let header = self
-			.relay_client
-			.rpc()
-			.header(Some(latest_relay_hash))
-			.await?
-			.ok_or_else(|| anyhow!("Header not found for hash: {latest_relay_hash:?}"))?;
+	/// Decodes the bytes as a [`FinalityProof`], as returned by the `grandpa_proveFinality` rpc,
+	/// either bare or versioned, see [`FinalityProof::decode_versioned`].
+	pub fn decode_finality_proof<H>(&self) -> Result<FinalityProof<H>, codec::Error>
+	where
+		H: Header + codec::Decode,
+	{
+		FinalityProof::<H>::decode_versioned(self.as_bytes()).map_err(|err| match err {
+			primitives::error::Error::Codec(err) => err,
+			_ => codec::Error::from("Unsupported finality proof version"),
+		})
+	}
+}
 
-		let current_set_id = {
-			let key = polkadot::api::storage().grandpa().current_set_id();
-			self.relay_client
-				.storage()
-				.at(Some(latest_relay_hash))
-				.await
-				.expect("Storage client")
-				.fetch(&key)
-				.await
-				.unwrap()
-				.expect("Failed to fetch current set id")
-		};
+/// Response of the `rpc_methods` rpc.
+#[derive(Deserialize)]
+struct RpcMethods {
+	methods: Vec<String>,
+}
 
-		let current_authorities = {
-			let bytes = self
-				.relay_client
-				.rpc()
-				.request::<String>(
-					"state_call",
-					subxt::rpc_params!(
-						"GrandpaApi_grandpa_authorities",
-						"0x",
-						Some(format!("{:?}", latest_relay_hash))
-					),
-				)
-				.await
-				.map(|res| hex::decode(&res[2..]))??;
+/// Checks that the relay chain has both the GRANDPA pallet and the `grandpa_proveFinality` rpc,
+/// chains that only run BABE (or no finality gadget at all) can't be proven with GRANDPA.
+pub fn ensure_grandpa_available(
+	has_grandpa_pallet: bool,
+	rpc_methods: &[String],
+) -> Result<(), ProverError> {
+	let has_rpc = rpc_methods.iter().any(|method| method == GRANDPA_PROVE_FINALITY_RPC);
+	if !has_grandpa_pallet || !has_rpc {
+		Err(ProverError::GrandpaNotAvailable { pallet: has_grandpa_pallet, rpc: has_rpc })?
+	}
 
-			AuthorityList::decode(&mut &bytes[..]).expect("Failed to scale decode authorities")
-		};
+	Ok(())
+}
 
-		// Ensure there are no duplicates in authority list
-		let mut set = BTreeSet::new();
-		for (id, ..) in &current_authorities {
-			if !set.insert(id) {
-				Err(anyhow!("Duplicate entries found in current authority set"))?
-			}
-		}
+/// Computes the storage key of a parachain's head on the relay chain from its para id, for relay
+/// chains that don't store the heads under the canonical `Paras::Heads` key, see
+/// [`GrandpaProver::with_para_head_key`].
+pub type ParaHeadKey = Arc<dyn Fn(u32) -> StorageKey + Send + Sync>;
 
-		let latest_relay_height = u32::from(header.number());
-		let finalized_para_header =
-			self.query_latest_finalized_parachain_header(latest_relay_height).await?;
+/// Returns the storage key of the head of `para_id`, computed by `custom` if given and the
+/// canonical `Paras::Heads` key otherwise.
+pub fn para_head_storage_key(custom: Option<&ParaHeadKey>, para_id: u32) -> StorageKey {
+	match custom {
+		Some(para_head_key) => para_head_key(para_id),
+		None => parachain_header_storage_key(para_id),
+	}
+}
 
-		Ok(ClientState {
-			current_authorities,
-			current_set_id,
-			latest_relay_height,
-			latest_relay_hash: latest_relay_hash.into(),
-			para_id: self.para_id,
-			// we'll set this below
-			latest_para_height: u32::from(finalized_para_header.number()),
-		})
+/// Checks that the relay chain has the `Paras` pallet and that `head`, the `Paras::Heads` value of
+/// `para_id` at the finalized head, exists. Returns [`ProverError::UnknownParaId`] otherwise.
+pub fn ensure_para_registered(
+	has_paras_pallet: bool,
+	para_id: u32,
+	head: Option<&[u8]>,
+) -> Result<(), ProverError> {
+	if !has_paras_pallet {
+		Err(ProverError::ParasNotAvailable)?
+	}
+	if head.is_none() {
+		Err(ProverError::UnknownParaId { para_id })?
 	}
 
-	/// Returns the latest finalized parachain header at the given finalized relay chain height.
-	pub async fn query_latest_finalized_parachain_header(
-		&self,
-		latest_finalized_height: u32,
-	) -> Result<T::Header, anyhow::Error> {
-		let latest_finalized_hash = self
-			.relay_client
-			.rpc()
-			.block_hash(Some(latest_finalized_height.into()))
-			.await?
-			.ok_or_else(|| anyhow!("Block hash not found for number: {latest_finalized_height}"))?;
-		let key = polkadot::api::storage().paras().heads(&Id(self.para_id));
-		let header = self
-			.relay_client
-			.storage()
-			.at(Some(latest_finalized_hash))
-			.await
-			.expect("Storage client")
-			.fetch(&key)
-			.await?
-			.ok_or_else(|| anyhow!("parachain header not found for para id: {}", self.para_id))?;
-		let header = T::Header::decode(&mut &header.0[..])
-			.map_err(|_| anyhow!("Failed to decode header"))?;
+	Ok(())
+}
 
-		Ok(header)
+/// Checks that the storage proof returned by the node actually proves the given key against the
+/// state root, rather than some unrelated keys.
+pub fn ensure_proof_contains_key(
+	state_root: H256,
+	proof: &[Vec<u8>],
+	key: &[u8],
+) -> Result<(), ProverError> {
+	let result = sp_state_machine::read_proof_check::<BlakeTwo256, _>(
+		state_root,
+		StorageProof::new(proof.to_vec()),
+		[key],
+	);
+	match result.ok().and_then(|mut values| values.remove(key)).flatten() {
+		Some(_) => Ok(()),
+		None => Err(ProverError::ProofKeyMismatch { key: key.to_vec() }),
 	}
+}
 
-	/// Returns a tuple of the finality proof for the given parachain `header_numbers` finalized by
-	/// `latest_finalized_height`.
-	pub async fn query_finalized_parachain_headers_with_proof<H>(
-		&self,
-		previous_finalized_height: u32,
-		mut latest_finalized_height: u32,
-		latest_justification: Option<Vec<u8>>,
-		header_numbers: Vec<T::BlockNumber>,
-	) -> Result<ParachainHeadersWithFinalityProof<H>, anyhow::Error>
-	where
-		H: Header + codec::Decode,
-		u32: From<<H as Header>::Number>,
-		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
-		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
-		H::Number: finality_grandpa::BlockNumberOps,
-		T::BlockNumber: One,
-	{
-		let mut finality_proof = if let Some(justification) = latest_justification {
-			let justification = GrandpaJustification::<H>::decode(&mut &*justification)?;
+/// Whether the node failed a state read because it has discarded the state of the block, as full
+/// nodes do for blocks older than their pruning depth. The rpc error doesn't have a code of its
+/// own, so this matches the "State already discarded" message of substrate's client, and reads
+/// failing with other messages aren't reported as pruned.
+pub fn is_state_pruned(err: &anyhow::Error) -> bool {
+	err.chain().any(|err| err.to_string().contains("State already discarded"))
+}
 
-			FinalityProof::<H> {
-				block: justification.commit.target_hash,
-				justification: justification.encode(),
-				unknown_headers: vec![],
-			}
-		} else {
-			let encoded = GrandpaApiClient::<JustificationNotification, H256, u32>::prove_finality(
-				// we cast between the same type but different crate versions.
-				&*unsafe {
-					unsafe_arc_cast::<_, jsonrpsee_ws_client::WsClient>(
-						self.relay_ws_client.clone(),
-					)
-				},
-				latest_finalized_height,
-			)
-			.await?
-			.ok_or_else(|| {
-				anyhow!("No justification found for block: {:?}", latest_finalized_height)
-			})?
-			.0;
+/// Maps the error of a state read at the relay chain block `at` to [`ProverError::StatePruned`] if
+/// the node has discarded the block's state, see [`is_state_pruned`].
+pub fn state_read_error(err: anyhow::Error, at: H256) -> anyhow::Error {
+	if is_state_pruned(&err) {
+		ProverError::StatePruned { at }.into()
+	} else {
+		err
+	}
+}
 
-			let mut finality_proof = FinalityProof::<H>::decode(&mut &encoded[..])?;
+/// Fetches the finality proof for the given relay chain block, returning it along with the number
+/// of the block finalized by its justification, which may be a descendant of the requested block.
+/// Returns [`ProverError::JustificationNotFound`] right away if the node has no justification for
+/// the block, the prover instead polls for it, see [`poll_finality_proof`].
+pub async fn fetch_finality_proof<H>(
+	rpc: &dyn GrandpaRpc,
+	block: u32,
+) -> Result<(FinalityProof<H>, u32), anyhow::Error>
+where
+	H: Header + codec::Decode,
+{
+	let encoded = rpc
+		.prove_finality(block)
+		.await?
+		.ok_or_else(|| ProverError::JustificationNotFound(block))?;
 
-			let justification =
-				GrandpaJustification::<H>::decode(&mut &finality_proof.justification[..])?;
+	decode_finality_proof(&encoded)
+}
 
-			finality_proof.block = justification.commit.target_hash;
+/// How long the prover waits for the node to store the justification of a block it has just
+/// finalized, checking `retries` times, `interval` apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JustificationPoll {
+	/// Number of times to check again after the first check
+	pub retries: u32,
+	/// Time between checks
+	pub interval: Duration,
+}
 
-			latest_finalized_height = u32::from(justification.commit.target_number);
-			finality_proof
-		};
+impl Default for JustificationPoll {
+	fn default() -> Self {
+		Self { retries: 3, interval: Duration::from_millis(500) }
+	}
+}
 
-		let start = self
-			.relay_client
-			.rpc()
-			.block_hash(Some(previous_finalized_height.into()))
-			.await?
-			.ok_or_else(|| anyhow!("Failed to fetch previous finalized hash + 1"))?;
+/// Same as [`fetch_finality_proof`], but with the finality proof of `block` requested with
+/// `prove_finality`, which is polled as configured by `poll` while the node has no justification
+/// for it. A node may not have stored the justification of a block it has only just finalized,
+/// so once the retries are exhausted [`ProverError::JustificationNotYetAvailable`] is returned,
+/// telling the caller to back off rather than give up. Every finality proof the prover fetches is
+/// polled for this way.
+pub async fn poll_finality_proof<H, F, Fut>(
+	poll: JustificationPoll,
+	block: u32,
+	prove_finality: F,
+) -> Result<(FinalityProof<H>, u32), anyhow::Error>
+where
+	H: Header + codec::Decode,
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<Option<Vec<u8>>, anyhow::Error>>,
+{
+	let encoded = poll_encoded_finality_proof(poll, block, prove_finality).await?;
+	decode_finality_proof(&encoded)
+}
 
-		let latest_finalized_hash = self
-			.relay_client
-			.rpc()
-			.block_hash(Some(latest_finalized_height.into()))
-			.await?
-			.ok_or_else(|| anyhow!("Failed to fetch previous finalized hash + 1"))?;
+/// Polls `prove_finality` for the SCALE encoded finality proof of `block`, see
+/// [`poll_finality_proof`].
+async fn poll_encoded_finality_proof<F, Fut>(
+	poll: JustificationPoll,
+	block: u32,
+	mut prove_finality: F,
+) -> Result<Vec<u8>, anyhow::Error>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<Option<Vec<u8>>, anyhow::Error>>,
+{
+	if let Some(encoded) = prove_finality().await? {
+		return Ok(encoded)
+	}
+	for _ in 0..poll.retries {
+		tokio::time::sleep(poll.interval).await;
+		if let Some(encoded) = prove_finality().await? {
+			return Ok(encoded)
+		}
+	}
 
-		let mut unknown_headers = vec![];
-		for height in previous_finalized_height..=latest_finalized_height {
-			let hash = self
-				.relay_client
-				.rpc()
-				.block_hash(Some(height.into()))
-				.await?
-				.ok_or_else(|| anyhow!("Failed to fetch block has for height {height}"))?;
+	Err(ProverError::JustificationNotYetAvailable { block, waited: poll.interval * poll.retries })?
+}
 
-			let header = self
-				.relay_client
-				.rpc()
-				.header(Some(hash))
-				.await?
-				.ok_or_else(|| anyhow!("Header with hash: {hash:?} not found!"))?;
+/// Decodes a finality proof returned by the `grandpa_proveFinality` rpc, along with the number of
+/// the block finalized by its justification.
+fn decode_finality_proof<H>(encoded: &[u8]) -> Result<(FinalityProof<H>, u32), anyhow::Error>
+where
+	H: Header + codec::Decode,
+{
+	let mut finality_proof =
+		FinalityProof::<H>::decode_versioned(encoded).map_err(|err| anyhow!("{err}"))?;
+	let justification = GrandpaJustification::<H>::decode(&mut &finality_proof.justification[..])?;
+	finality_proof.block = justification.commit.target_hash;
+
+	Ok((finality_proof, justification.commit.target_number))
+}
+
+/// The progress of a parachain header proof, so a long sync can be persisted with
+/// [`GrandpaProver::save_checkpoint`] and carried on with
+/// [`GrandpaProver::resume_from_checkpoint`] after a crash, rather than redoing its rpc requests.
+/// The proofs are keyed by relay chain block hash, so a resumed proof is byte-identical to one
+/// built in a single run.
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct ProverCheckpoint {
+	/// The encoded finality proof of the relay chain range
+	pub finality_proof: Vec<u8>,
+	/// The relay chain blocks including the requested parachain headers, in chain order, along
+	/// with the encoded parachain headers
+	pub collected: Vec<(H256, Vec<u8>)>,
+	/// The last relay chain block of `collected` whose parachain header has been proven
+	pub last_processed: Option<H256>,
+	/// The proofs of the parachain headers built so far
+	pub parachain_headers: BTreeMap<H256, ParachainHeaderProofs>,
+}
 
-			unknown_headers.push(H::decode(&mut &header.encode()[..])?);
+impl ProverCheckpoint {
+	/// A checkpoint of the given range, with none of its parachain headers proven yet.
+	pub fn new<H>(finality_proof: &FinalityProof<H>, collected: Vec<(H256, Vec<u8>)>) -> Self
+	where
+		H: Header + codec::Decode,
+	{
+		Self {
+			finality_proof: finality_proof.encode(),
+			collected,
+			last_processed: None,
+			parachain_headers: BTreeMap::new(),
 		}
+	}
 
-		// overwrite unknown headers
-		finality_proof.unknown_headers = unknown_headers;
+	/// The collected parachain headers that are yet to be proven.
+	pub fn pending(&self) -> &[(H256, Vec<u8>)] {
+		let next = self
+			.last_processed
+			.and_then(|last| self.collected.iter().position(|(block, _)| *block == last))
+			.map_or(0, |index| index + 1);
+		&self.collected[next..]
+	}
 
-		// we are interested only in the blocks where our parachain header changes.
-		let para_storage_key = parachain_header_storage_key(self.para_id);
-		let keys = vec![para_storage_key.as_ref()];
-		let mut parachain_headers_with_proof = BTreeMap::<H256, ParachainHeaderProofs>::default();
+	/// Records the proofs of the parachain header included in `block`.
+	pub fn record(&mut self, block: H256, proofs: ParachainHeaderProofs) {
+		self.parachain_headers.insert(block, proofs);
+		self.last_processed = Some(block);
+	}
 
-		let change_set = self
-			.relay_client
-			.rpc()
-			.query_storage(keys.clone(), start, Some(latest_finalized_hash))
-			.await?;
+	/// The proof of the range, once all of its parachain headers have been proven.
+	pub fn into_proof<H>(self) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+	{
+		let pending = self.pending().len();
+		if pending != 0 {
+			Err(ProverError::Custom(format!(
+				"Checkpoint has {pending} parachain headers left to prove"
+			)))?
+		}
 
-		for changes in change_set {
-			let header = self
-				.relay_client
-				.rpc()
-				.header(Some(changes.block))
-				.await?
-				.ok_or_else(|| anyhow!("block not found {:?}", changes.block))?;
-
-			let parachain_header_bytes = {
-				let key = polkadot::api::storage().paras().heads(&Id(self.para_id));
-				self.relay_client
-					.storage()
-					.at(Some(header.hash()))
-					.await
-					.expect("Storage client")
-					.fetch(&key)
-					.await?
-					.expect("Header exists in its own changeset; qed")
-					.0
-			};
+		Ok(ParachainHeadersWithFinalityProof {
+			finality_proof: FinalityProof::<H>::decode(&mut &self.finality_proof[..])?,
+			parachain_headers: self.parachain_headers,
+		})
+	}
+}
 
-			let para_header: T::Header = Decode::decode(&mut &parachain_header_bytes[..])?;
-			let para_block_number = para_header.number();
-			// skip genesis header or any unknown headers
-			if para_block_number == Zero::zero() || !header_numbers.contains(&para_block_number) {
-				continue
-			}
+/// Proves the pending parachain headers of `checkpoint` in chain order with `prove`, given the
+/// relay chain block including each header and the encoded header. The checkpoint is passed to
+/// `save` after each proof, so that a failure loses at most the proof being built.
+pub async fn advance_checkpoint<F, Fut, S>(
+	checkpoint: &mut ProverCheckpoint,
+	mut prove: F,
+	mut save: S,
+) -> Result<(), anyhow::Error>
+where
+	F: FnMut(H256, Vec<u8>) -> Fut,
+	Fut: Future<Output = Result<ParachainHeaderProofs, anyhow::Error>>,
+	S: FnMut(&ProverCheckpoint) -> Result<(), anyhow::Error>,
+{
+	for (block, para_header) in checkpoint.pending().to_vec() {
+		let proofs = prove(block, para_header).await?;
+		checkpoint.record(block, proofs);
+		save(checkpoint)?;
+	}
 
-			let state_proof = self
-				.relay_client
-				.rpc()
-				.read_proof(keys.clone(), Some(header.hash()))
-				.await?
-				.proof
-				.into_iter()
-				.map(|p| p.0)
-				.collect();
+	Ok(())
+}
+
+/// Selects the justification of the canonical finalized block out of `justifications`, which may
+/// include justifications of blocks at the same number that were reorged out. `block_hash` returns
+/// the hash of the canonical block at the given number. Justifications are considered in order of
+/// their encoding, so the choice doesn't depend on the order they were received in.
+pub async fn select_canonical_justification<H, F, Fut>(
+	mut justifications: Vec<Vec<u8>>,
+	mut block_hash: F,
+) -> Result<Vec<u8>, anyhow::Error>
+where
+	H: Header + codec::Decode,
+	F: FnMut(u32) -> Fut,
+	Fut: Future<Output = Result<Option<H256>, anyhow::Error>>,
+{
+	justifications.sort();
+	justifications.dedup();
+
+	let mut canonical_hashes = BTreeMap::new();
+	let mut rejected = None;
+	for encoded in justifications {
+		let justification = GrandpaJustification::<H>::decode(&mut &*encoded)?;
+		let number = justification.commit.target_number;
+		let canonical = match canonical_hashes.get(&number) {
+			Some(canonical) => *canonical,
+			None => {
+				let canonical = block_hash(number).await?;
+				canonical_hashes.insert(number, canonical);
+				canonical
+			},
+		};
 
-			let TimeStampExtWithProof { ext: extrinsic, proof: extrinsic_proof } =
-				fetch_timestamp_extrinsic_with_proof(&self.para_client, Some(para_header.hash()))
-					.await
-					.map_err(|err| anyhow!("Error fetching timestamp with proof: {err:?}"))?;
-			let proofs = ParachainHeaderProofs { state_proof, extrinsic, extrinsic_proof };
-			parachain_headers_with_proof.insert(header.hash().into(), proofs);
+		if canonical == Some(justification.commit.target_hash) {
+			return Ok(encoded)
 		}
+		rejected.get_or_insert(ProverError::NonCanonicalJustification {
+			number,
+			found: justification.commit.target_hash,
+			canonical,
+		});
+	}
 
-		Ok(ParachainHeadersWithFinalityProof {
-			finality_proof,
-			parachain_headers: parachain_headers_with_proof,
-		})
+	match rejected {
+		Some(err) => Err(err.into()),
+		None => Err(anyhow!("No justifications to select from")),
 	}
+}
 
-	/// Queries the block at which the epoch for the given block belongs to ends.
-	pub async fn session_start_and_end_for_block(
-		&self,
+/// Proves the finality of a standalone chain's own headers, up to the block finalized by
+/// `latest_justification` or, without it, the finality proof for `latest_finalized_height`
+/// fetched from `rpc`, polled for as configured by `poll`, see [`poll_finality_proof`]. The
+/// unknown headers are fetched with `header_at`, from `previous_finalized_height` up to the
+/// finalized block.
+pub async fn prove_standalone_finality<H, F, Fut>(
+	rpc: &dyn GrandpaRpc,
+	poll: JustificationPoll,
+	previous_finalized_height: u32,
+	latest_finalized_height: u32,
+	latest_justification: Option<Vec<u8>>,
+	mut header_at: F,
+) -> Result<FinalityProof<H>, anyhow::Error>
+where
+	H: Header + codec::Decode,
+	u32: From<<H as Header>::Number>,
+	F: FnMut(u32) -> Fut,
+	Fut: Future<Output = Result<H, anyhow::Error>>,
+{
+	let (mut finality_proof, target_number) = match latest_justification {
+		Some(justification) => {
+			let justification = GrandpaJustification::<H>::decode(&mut &*justification)?;
+			let finality_proof = FinalityProof::<H> {
+				block: justification.commit.target_hash,
+				justification: justification.encode(),
+				unknown_headers: vec![],
+			};
+			(finality_proof, u32::from(justification.commit.target_number))
+		},
+		None =>
+			poll_finality_proof::<H, _, _>(poll, latest_finalized_height, || {
+				rpc.prove_finality(latest_finalized_height)
+			})
+			.await?,
+	};
+
+	let mut unknown_headers = vec![];
+	for height in previous_finalized_height..=target_number {
+		unknown_headers.push(header_at(height).await?);
+	}
+	finality_proof.unknown_headers = unknown_headers;
+
+	Ok(finality_proof)
+}
+
+/// Proves the timestamp `set` inherent among the SCALE-encoded extrinsics of the block `block`,
+/// looked up by its pallet and call indices, see [`find_timestamp_inherent`]. `call` is the
+/// expected `(pallet_index, call_index)` of the inherent, for runtimes where other inherents share
+/// its shape. Returns the index of the inherent in the block, which the verifiers check its proof
+/// at, along with its proof, or [`ProverError::TimestampInherentNotFound`] if the block has no
+/// such inherent.
+pub fn prove_timestamp_inherent(
+	extrinsics: &[Vec<u8>],
+	call: Option<(u8, u8)>,
+	block: H256,
+) -> Result<(u32, TimeStampExtWithProof), ProverError> {
+	let index = find_timestamp_inherent(extrinsics, call)
+		.ok_or(ProverError::TimestampInherentNotFound { block })?;
+
+	let proof = prove_extrinsic(extrinsics, index).map_err(|err| {
+		ProverError::Custom(format!("Error proving the timestamp inherent of {block:?}: {err:?}"))
+	})?;
+	Ok((index as u32, proof))
+}
+
+/// Checks that the finalized head with the given timestamp (in milliseconds) isn't older than
+/// `max_age`, relative to `now` (the duration since the unix epoch).
+pub fn ensure_head_fresh(
+	head_timestamp: u64,
+	now: Duration,
+	max_age: Duration,
+) -> Result<(), ProverError> {
+	let age = now.saturating_sub(Duration::from_millis(head_timestamp));
+	if age > max_age {
+		Err(ProverError::StaleFinalizedHead { age, max_age })?
+	}
+
+	Ok(())
+}
+
+/// Decodes the numbers of the parachain headers in the given `Paras::Heads` storage values, as
+/// found in a storage change set. Removed values are skipped.
+pub fn decode_para_numbers<H>(
+	values: impl IntoIterator<Item = Option<Vec<u8>>>,
+) -> Result<Vec<H::Number>, codec::Error>
+where
+	H: Header + Decode,
+{
+	let mut numbers = vec![];
+	for value in values {
+		if let ParaHeadChange::Updated(head_data) = ParaHeadChange::decode(value.as_deref())? {
+			numbers.push(H::decode(&mut &head_data[..])?.number());
+		}
+	}
+
+	Ok(numbers)
+}
+
+/// Decodes the parachain headers from the `(relay block, Paras::Heads storage value)` pairs of a
+/// storage change set, stopping at the first header that would exceed `max_headers`. Returns the
+/// headers, along with the relay chain block that included the first header left out, if any, to
+/// continue from. Removed values are skipped.
+pub fn decode_para_headers_page<H, B>(
+	changes: impl IntoIterator<Item = (B, Option<Vec<u8>>)>,
+	max_headers: usize,
+) -> Result<(Vec<H>, Option<B>), codec::Error>
+where
+	H: Decode,
+{
+	let mut headers = vec![];
+	for (block, value) in changes {
+		if let ParaHeadChange::Updated(head_data) = ParaHeadChange::decode(value.as_deref())? {
+			if headers.len() == max_headers {
+				return Ok((headers, Some(block)))
+			}
+			headers.push(H::decode(&mut &head_data[..])?);
+		}
+	}
+
+	Ok((headers, None))
+}
+
+/// Finds the relay chain block that included the parachain header with the given hash, from the
+/// `(relay block, Paras::Heads storage value)` pairs of a storage change set.
+pub fn find_para_header_inclusion<H, B>(
+	changes: impl IntoIterator<Item = (B, Option<Vec<u8>>)>,
+	para_header_hash: H256,
+) -> Result<Option<B>, codec::Error>
+where
+	H: Header + Decode,
+	H256: From<<H::Hasher as subxt::config::Hasher>::Output>,
+{
+	for (block, value) in changes {
+		if let ParaHeadChange::Updated(head_data) = ParaHeadChange::decode(value.as_deref())? {
+			if H256::from(H::decode(&mut &head_data[..])?.hash()) == para_header_hash {
+				return Ok(Some(block))
+			}
+		}
+	}
+
+	Ok(None)
+}
+
+/// Finds the relay chain block that included the parachain header with the given number, from
+/// the `(relay block, Paras::Heads storage value)` pairs of a storage change set.
+pub fn find_para_number_inclusion<H, B>(
+	changes: impl IntoIterator<Item = (B, Option<Vec<u8>>)>,
+	para_block_number: u32,
+) -> Result<Option<B>, codec::Error>
+where
+	H: Header + Decode,
+	u32: From<H::Number>,
+{
+	for (block, value) in changes {
+		if let ParaHeadChange::Updated(head_data) = ParaHeadChange::decode(value.as_deref())? {
+			if u32::from(H::decode(&mut &head_data[..])?.number()) == para_block_number {
+				return Ok(Some(block))
+			}
+		}
+	}
+
+	Ok(None)
+}
+
+/// Checks that the parachain block `para_block_number` is finalized, given the number of the
+/// parachain head included in the relay chain's latest finalized block.
+pub fn ensure_para_block_finalized(
+	para_block_number: u32,
+	finalized_para_height: u32,
+) -> Result<(), ProverError> {
+	if para_block_number > finalized_para_height {
+		Err(ProverError::ParaBlockNotFinalized {
+			number: para_block_number,
+			finalized: finalized_para_height,
+		})?
+	}
+
+	Ok(())
+}
+
+/// Decodes the parachain header from its `Paras::Heads` storage value, returning `None` for the
+/// genesis header or any header that wasn't requested.
+pub fn decode_requested_para_header<H>(
+	head_data: &[u8],
+	header_numbers: &[H::Number],
+) -> Result<Option<H>, codec::Error>
+where
+	H: Header + Decode,
+	H::Number: Zero + Ord,
+{
+	let para_header = H::decode(&mut &head_data[..])?;
+	if !is_requested_para_header(&para_header.number(), None, header_numbers) {
+		return Ok(None)
+	}
+
+	Ok(Some(para_header))
+}
+
+/// Decodes the requested headers of several parachains from the `(relay block, changes)` pairs of
+/// a storage change set over their `Paras::Heads` entries, keyed by para id. See
+/// [`is_requested_para_header`] for the headers that are requested of each parachain. The heads
+/// are stored under the keys `para_head_key` returns for each para id.
+pub fn requested_para_headers_by_id<H, B>(
+	change_set: impl IntoIterator<Item = (B, Vec<(Vec<u8>, Option<Vec<u8>>)>)>,
+	header_numbers: &BTreeMap<u32, Vec<H::Number>>,
+	para_head_key: impl Fn(u32) -> StorageKey,
+) -> Result<BTreeMap<u32, Vec<(B, H)>>, codec::Error>
+where
+	H: Header + Decode,
+	H::Number: Ord + Zero,
+	B: Clone,
+{
+	let para_ids = header_numbers
+		.keys()
+		.map(|para_id| (para_head_key(*para_id).0, *para_id))
+		.collect::<BTreeMap<_, _>>();
+	let mut included = header_numbers
+		.keys()
+		.map(|para_id| (*para_id, vec![]))
+		.collect::<BTreeMap<_, _>>();
+	let mut previous_numbers = BTreeMap::new();
+	for (block, changes) in change_set {
+		for (key, value) in changes {
+			let para_id = match para_ids.get(&key) {
+				Some(para_id) => *para_id,
+				None => continue,
+			};
+			let head_data = match ParaHeadChange::decode(value.as_deref())? {
+				ParaHeadChange::Updated(head_data) => head_data,
+				ParaHeadChange::Removed => continue,
+			};
+			let para_header = H::decode(&mut &head_data[..])?;
+			let number = para_header.number();
+			let requested = is_requested_para_header(
+				&number,
+				previous_numbers.get(&para_id),
+				&header_numbers[&para_id],
+			);
+			previous_numbers.insert(para_id, number);
+			if requested {
+				included.entry(para_id).or_default().push((block.clone(), para_header));
+			}
+		}
+	}
+
+	Ok(included)
+}
+
+/// Returns [`ProverError::DuplicateParaId`] if a para id is queried more than once.
+pub fn ensure_distinct_para_ids(
+	para_ids: impl IntoIterator<Item = u32>,
+) -> Result<(), ProverError> {
+	let mut seen = BTreeSet::new();
+	match para_ids.into_iter().find(|para_id| !seen.insert(*para_id)) {
+		Some(para_id) => Err(ProverError::DuplicateParaId { para_id }),
+		None => Ok(()),
+	}
+}
+
+/// Combines the parachain header proofs of several parachains with the finality proof they share,
+/// into a proof per para id.
+pub fn share_finality_proof<H>(
+	finality_proof: FinalityProof<H>,
+	parachain_headers: BTreeMap<u32, BTreeMap<H256, ParachainHeaderProofs>>,
+) -> BTreeMap<u32, ParachainHeadersWithFinalityProof<H>>
+where
+	H: codec::Codec + Clone,
+{
+	parachain_headers
+		.into_iter()
+		.map(|(para_id, parachain_headers)| {
+			let proof = ParachainHeadersWithFinalityProof {
+				finality_proof: finality_proof.clone(),
+				parachain_headers,
+			};
+			(para_id, proof)
+		})
+		.collect()
+}
+
+/// A parachain to prove the headers of, see
+/// [`GrandpaProver::query_finalized_parachain_headers_with_proof_multi`].
+pub struct ParachainQuery<T: Config> {
+	/// Id of the parachain
+	pub para_id: u32,
+	/// Client of the parachain's node, the timestamp extrinsics of its headers are proven with it
+	pub para_client: OnlineClient<T>,
+	/// The parachain headers to prove
+	pub header_numbers: Vec<T::BlockNumber>,
+}
+
+/// The authority set a client expects the relay chain to be finalized by, pinning it prevents a
+/// compromised node from serving proofs under a forged authority set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthoritySetPin {
+	/// Expected authority set id
+	pub set_id: u64,
+	/// `blake2_256` hash of the SCALE encoded authority list
+	pub authority_hash: H256,
+}
+
+impl AuthoritySetPin {
+	/// Pins the authority set currently known to the client.
+	pub fn from_client_state(client_state: &ClientState) -> Self {
+		Self {
+			set_id: client_state.current_set_id,
+			authority_hash: authority_set_hash(&client_state.current_authorities),
+		}
+	}
+}
+
+/// Hash used to commit to an authority list in an [`AuthoritySetPin`].
+pub fn authority_set_hash(authorities: &AuthorityList) -> H256 {
+	sp_core::blake2_256(&authorities.encode()).into()
+}
+
+/// The GRANDPA authority set at a trusted relay chain block, along with a storage proof of it
+/// against the block's state root, which bootstraps a new light client, see
+/// [`GrandpaProver::query_client_init`].
+#[derive(Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct GrandpaClientInit {
+	/// Hash of the relay chain block
+	pub block: H256,
+	/// Number of the relay chain block
+	pub height: u32,
+	/// State root of the relay chain block, which the state proof is against
+	pub state_root: H256,
+	/// Id of the authority set
+	pub set_id: u64,
+	/// Authorities of the set
+	pub authorities: AuthorityList,
+	/// Proof of the `:grandpa_authorities` and `Grandpa::CurrentSetId` storage items
+	pub state_proof: Vec<Vec<u8>>,
+}
+
+impl GrandpaClientInit {
+	/// Checks that the state proof proves the set id and authorities against the state root.
+	/// Returns [`ProverError::ProofKeyMismatch`] if it doesn't prove the authorities, and
+	/// [`ProverError::AuthoritySetMismatch`] if it proves another authority set.
+	pub fn verify(&self) -> Result<(), ProverError> {
+		let set_id_key = current_set_id_storage_key().0;
+		// a proof that's missing nodes of either item, or is against another root, proves neither
+		let mut values = sp_state_machine::read_proof_check::<BlakeTwo256, _>(
+			self.state_root,
+			StorageProof::new(self.state_proof.clone()),
+			[GRANDPA_AUTHORITIES_KEY, &set_id_key[..]],
+		)
+		.unwrap_or_default();
+		let authorities = values.remove(GRANDPA_AUTHORITIES_KEY).flatten().ok_or_else(|| {
+			ProverError::ProofKeyMismatch { key: GRANDPA_AUTHORITIES_KEY.to_vec() }
+		})?;
+		let authorities =
+			AuthorityList::from(VersionedAuthorityList::decode(&mut &authorities[..])?);
+		let set_id = decode_current_set_id(values.remove(&set_id_key).flatten().as_deref())
+			.map_err(|err| ProverError::Custom(err.to_string()))?;
+
+		let pin = AuthoritySetPin {
+			set_id: self.set_id,
+			authority_hash: authority_set_hash(&self.authorities),
+		};
+		ensure_authority_set(&pin, set_id, &authorities)
+	}
+}
+
+/// Checks the authority set served by the node against the pinned one.
+pub fn ensure_authority_set(
+	pin: &AuthoritySetPin,
+	set_id: u64,
+	authorities: &AuthorityList,
+) -> Result<(), ProverError> {
+	let authority_hash = authority_set_hash(authorities);
+	if pin.set_id != set_id || pin.authority_hash != authority_hash {
+		Err(ProverError::AuthoritySetMismatch {
+			expected_set_id: pin.set_id,
+			found_set_id: set_id,
+			expected_authority_hash: pin.authority_hash,
+			found_authority_hash: authority_hash,
+		})?
+	}
+
+	Ok(())
+}
+
+/// How long the prover waits for the parachain node to import a block the relay chain has
+/// finalized, checking `retries` times, `interval` apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParaCatchUp {
+	/// Number of times to check again after the first check
+	pub retries: u32,
+	/// Time between checks
+	pub interval: Duration,
+}
+
+impl Default for ParaCatchUp {
+	fn default() -> Self {
+		Self { retries: 5, interval: Duration::from_secs(1) }
+	}
+}
+
+/// Waits until `is_imported` reports that the parachain node has imported `block`, returning
+/// [`ProverError::ParaBehindRelay`] if it still hasn't once the retries are exhausted.
+pub async fn wait_for_para_block<F, Fut>(
+	catch_up: ParaCatchUp,
+	block: H256,
+	mut is_imported: F,
+) -> Result<(), anyhow::Error>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<bool, anyhow::Error>>,
+{
+	if is_imported().await? {
+		return Ok(())
+	}
+	for _ in 0..catch_up.retries {
+		tokio::time::sleep(catch_up.interval).await;
+		if is_imported().await? {
+			return Ok(())
+		}
+	}
+
+	Err(ProverError::ParaBehindRelay { block, waited: catch_up.interval * catch_up.retries })?
+}
+
+/// Fetches the hash of the latest finalized block with `finalized_head`, then its number with
+/// `header_number`, returning [`ProverError::HeaderNotFound`] if the node doesn't have the header.
+pub async fn fetch_latest_finalized<Hash, N, HeadFut, NumberFut>(
+	finalized_head: impl FnOnce() -> HeadFut,
+	header_number: impl FnOnce(Hash) -> NumberFut,
+) -> Result<(Hash, N), ProverError>
+where
+	Hash: Copy + Into<H256>,
+	HeadFut: Future<Output = Result<Hash, anyhow::Error>>,
+	NumberFut: Future<Output = Result<Option<N>, anyhow::Error>>,
+{
+	let hash = finalized_head().await?;
+	let number = header_number(hash)
+		.await?
+		.ok_or_else(|| ProverError::HeaderNotFound(hash.into()))?;
+
+	Ok((hash, number))
+}
+
+/// Polls `finalized_para_height` every `poll_interval` until it reaches `target_para_height`,
+/// returning [`ProverError::Timeout`] if it hasn't within `timeout`.
+pub async fn wait_for_para_finality<F, Fut>(
+	target_para_height: u32,
+	poll_interval: Duration,
+	timeout: Duration,
+	mut finalized_para_height: F,
+) -> Result<(), anyhow::Error>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<u32, anyhow::Error>>,
+{
+	let poll = async {
+		while finalized_para_height().await? < target_para_height {
+			tokio::time::sleep(poll_interval).await;
+		}
+		Ok::<_, anyhow::Error>(())
+	};
+
+	tokio::time::timeout(timeout, poll)
+		.await
+		.map_err(|_| ProverError::Timeout { timeout })?
+}
+
+/// A single step in bringing a GRANDPA light client up to date, steps must be verified in the
+/// order they're produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofStep<P> {
+	/// Proof finalizing the last block of a session, which enacts an authority set change.
+	SetChange(P),
+	/// Proof finalizing the requested parachain headers under the current authority set.
+	Headers(P),
+}
+
+/// The latest finalized parachain header at the end of a relay chain range, see
+/// [`GrandpaProver::query_latest_finalized_parachain_header_with_proof`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LatestParaHeader<P> {
+	/// The parachain produced new headers within the range, the latest of which is proven.
+	Updated(P),
+	/// The parachain is stalled, it produced no new header within the range. Its last known
+	/// header is proven instead.
+	Stalled(P),
+}
+
+impl<P> LatestParaHeader<P> {
+	/// Classifies the proof of the parachain's head at the end of a relay chain range,
+	/// `latest_head`, as stalled if it's still the head at the start of the range.
+	pub fn classify(previous_head: Option<&[u8]>, latest_head: &[u8], proof: P) -> Self {
+		if previous_head == Some(latest_head) {
+			LatestParaHeader::Stalled(proof)
+		} else {
+			LatestParaHeader::Updated(proof)
+		}
+	}
+
+	/// Whether the parachain is stalled.
+	pub fn is_stalled(&self) -> bool {
+		matches!(self, LatestParaHeader::Stalled(_))
+	}
+
+	/// The proof of the latest parachain header, whether or not the parachain is stalled.
+	pub fn into_inner(self) -> P {
+		match self {
+			LatestParaHeader::Updated(proof) | LatestParaHeader::Stalled(proof) => proof,
+		}
+	}
+}
+
+/// Changes enacted by a relay chain block that the prover has to account for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RelayBlockEvent {
+	/// The block signals a GRANDPA authority set change.
+	AuthoritySetChange(ScheduledChange<u32>),
+	/// The block upgrades the runtime, bumping its `spec_version`.
+	RuntimeUpgrade {
+		/// `spec_version` of the block's parent
+		from: u32,
+		/// `spec_version` of the block
+		to: u32,
+	},
+	/// The block both signals an authority set change and upgrades the runtime. The set change
+	/// must be proven under the new runtime's metadata.
+	AuthoritySetChangeWithRuntimeUpgrade {
+		/// The signalled authority set change
+		change: ScheduledChange<u32>,
+		/// `spec_version` of the block's parent
+		from: u32,
+		/// `spec_version` of the block
+		to: u32,
+	},
+}
+
+impl RelayBlockEvent {
+	/// Detects the authority set change and runtime upgrade enacted by the given relay chain
+	/// block independently, combining them if the block does both. The runtime versions are those
+	/// of the block's parent and the block itself.
+	pub fn detect(
+		header: &RelayHeader,
+		parent_spec_version: u32,
+		spec_version: u32,
+	) -> Option<RelayBlockEvent> {
+		let set_change = find_scheduled_change::<RelayHeader>(header);
+		let runtime_upgraded = spec_version != parent_spec_version;
+
+		match (set_change, runtime_upgraded) {
+			(Some(change), true) => Some(RelayBlockEvent::AuthoritySetChangeWithRuntimeUpgrade {
+				change,
+				from: parent_spec_version,
+				to: spec_version,
+			}),
+			(Some(change), false) => Some(RelayBlockEvent::AuthoritySetChange(change)),
+			(None, true) => Some(RelayBlockEvent::RuntimeUpgrade {
+				from: parent_spec_version,
+				to: spec_version,
+			}),
+			(None, false) => None,
+		}
+	}
+
+	/// The authority set change signalled by the block, if any.
+	pub fn authority_set_change(&self) -> Option<&ScheduledChange<u32>> {
+		match self {
+			RelayBlockEvent::AuthoritySetChange(change) |
+			RelayBlockEvent::AuthoritySetChangeWithRuntimeUpgrade { change, .. } => Some(change),
+			RelayBlockEvent::RuntimeUpgrade { .. } => None,
+		}
+	}
+
+	/// The `(from, to)` spec versions of the runtime upgrade performed by the block, if any.
+	pub fn runtime_upgrade(&self) -> Option<(u32, u32)> {
+		match self {
+			RelayBlockEvent::RuntimeUpgrade { from, to } |
+			RelayBlockEvent::AuthoritySetChangeWithRuntimeUpgrade { from, to, .. } => Some((*from, *to)),
+			RelayBlockEvent::AuthoritySetChange(_) => None,
+		}
+	}
+}
+
+/// Splits the relay chain range `from..=to` into consecutive sub-ranges spanning at most
+/// `max_blocks` blocks each, or a single range if there's no limit.
+pub fn query_storage_ranges(from: u32, to: u32, max_blocks: Option<u32>) -> Vec<(u32, u32)> {
+	let max_blocks = max_blocks.unwrap_or(u32::MAX).max(1);
+	let mut ranges = vec![];
+	let mut start = from;
+	while start <= to {
+		let end = start.saturating_add(max_blocks - 1).min(to);
+		ranges.push((start, end));
+		if end == u32::MAX {
+			break
+		}
+		start = end + 1;
+	}
+
+	ranges
+}
+
+/// Outcome of a proof request, telling callers whether to submit the proof, wait for the
+/// requested parachain headers to be included, or retry once the relay chain has finalized more
+/// blocks.
+pub enum ProofOutcome<H: codec::Codec> {
+	/// Proof of the requested parachain headers, ready to be submitted.
+	Proof(ParachainHeadersWithFinalityProof<H>),
+	/// None of the requested parachain headers were included in the finalized relay chain range.
+	NothingToProve,
+	/// The relay chain hasn't finalized any blocks past the previously finalized height yet.
+	NotYetFinalized,
+}
+
+impl<H: codec::Codec> ProofOutcome<H> {
+	/// Generates the proof with `prove` if the relay chain has finalized blocks past
+	/// `previous_finalized_height`, classifying proofs without any parachain headers as
+	/// [`ProofOutcome::NothingToProve`].
+	pub async fn classify<F>(
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		prove: impl FnOnce() -> F,
+	) -> Result<Self, anyhow::Error>
+	where
+		F: Future<Output = Result<ParachainHeadersWithFinalityProof<H>, anyhow::Error>>,
+	{
+		if latest_finalized_height <= previous_finalized_height {
+			return Ok(ProofOutcome::NotYetFinalized)
+		}
+
+		let proof = prove().await?;
+		if proof.parachain_headers.is_empty() {
+			return Ok(ProofOutcome::NothingToProve)
+		}
+
+		Ok(ProofOutcome::Proof(proof))
+	}
+}
+
+/// Checks that no authority set change signalled in the ascending relay chain headers of the range
+/// `from..=to` is enacted below `to`. The blocks after a set change is enacted are finalized by
+/// the next set, so a proof of `to` spanning it can't be verified by a client at `from`, and has to
+/// be split at the last block of the current set instead. That's the enactment block of a standard
+/// change, and the block before it for a forced change, see [`AuthoritySetChange`].
+pub fn ensure_no_set_change_within(
+	headers: &[RelayHeader],
+	from: u32,
+	to: u32,
+) -> Result<(), ProverError> {
+	for header in headers.iter().filter(|header| header.number > from) {
+		for change in authority_set_changes(header) {
+			let last_block = change.last_block_of_previous_set();
+			if last_block < to {
+				Err(ProverError::SpansSetChange {
+					signalled_in: sp_runtime::traits::Header::hash(header),
+					enacted_at: last_block,
+				})?
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Fetches the finality proof of the last block of the current set for every authority set change
+/// signalled in the ascending relay chain headers of the range `from..=to` and enacted within it,
+/// see [`ensure_no_set_change_within`]. Each proof is signed by the set the change rotates away
+/// from, so a client at `from` can verify them in order, advancing its authority set at each. The
+/// proofs are returned along with the number of the block they were fetched for. Each is polled for
+/// as configured by `poll`, returning [`ProverError::JustificationNotYetAvailable`] if the node
+/// still doesn't have one of them, see [`poll_finality_proof`].
+pub async fn set_change_justifications<F, Fut>(
+	headers: &[RelayHeader],
+	from: u32,
+	to: u32,
+	poll: JustificationPoll,
+	mut prove_finality: F,
+) -> Result<Vec<(u32, JustificationNotification)>, anyhow::Error>
+where
+	F: FnMut(u32) -> Fut,
+	Fut: Future<Output = Result<Option<Vec<u8>>, anyhow::Error>>,
+{
+	let mut last_blocks = headers
+		.iter()
+		.filter(|header| header.number > from)
+		.flat_map(authority_set_changes)
+		.map(|change| change.last_block_of_previous_set())
+		.filter(|last_block| (from + 1..=to).contains(last_block))
+		.collect::<Vec<_>>();
+	last_blocks.sort();
+	last_blocks.dedup();
+
+	let mut justifications = vec![];
+	for block in last_blocks {
+		let finality_proof =
+			poll_encoded_finality_proof(poll, block, || prove_finality(block)).await?;
+		justifications.push((block, JustificationNotification(sp_core::Bytes(finality_proof))));
+	}
+
+	Ok(justifications)
+}
+
+/// Drops the unknown headers of the finality proof that aren't mandatory, see
+/// [`is_mandatory_header`], keeping the finalized block's header.
+pub fn mandatory_headers_only<H>(
+	finality_proof: FinalityProof<H>,
+) -> Result<FinalityProof<H>, codec::Error>
+where
+	H: codec::Codec,
+{
+	let FinalityProof { block, justification, unknown_headers } = finality_proof;
+	let mut mandatory_headers = vec![];
+	for header in unknown_headers {
+		let relay_header = RelayHeader::decode(&mut &header.encode()[..])?;
+		if sp_runtime::traits::Header::hash(&relay_header) == block ||
+			is_mandatory_header(&relay_header)
+		{
+			mandatory_headers.push(header);
+		}
+	}
+
+	Ok(FinalityProof { block, justification, unknown_headers: mandatory_headers })
+}
+
+/// Collects the relay chain headers from the block at `from` up to `tip`, in ascending order, by
+/// walking back from `tip` through the parent hashes. Headers are looked up in `cache` before
+/// they're fetched with `fetch`, and cached once fetched. The cache is keyed by hash, so walking
+/// the canonical chain after a reorg never returns headers of the fork that was reorged out.
+pub async fn walk_relay_headers<F, Fut>(
+	cache: &SharedCache<H256, Vec<u8>>,
+	from: u32,
+	tip: H256,
+	mut fetch: F,
+) -> Result<Vec<RelayHeader>, anyhow::Error>
+where
+	F: FnMut(H256) -> Fut,
+	Fut: Future<Output = Result<RelayHeader, anyhow::Error>>,
+{
+	let mut headers = vec![];
+	let mut hash = tip;
+	loop {
+		let header = match cache.get(&hash).await {
+			Some(encoded) => RelayHeader::decode(&mut &encoded[..])?,
+			None => {
+				let header = fetch(hash).await?;
+				let found = sp_runtime::traits::Header::hash(&header);
+				if found != hash {
+					Err(anyhow!("Requested relay chain header {hash:?}, got {found:?}"))?
+				}
+				cache.insert(hash, header.encode()).await;
+				header
+			},
+		};
+		let (number, parent_hash) = (header.number, header.parent_hash);
+		headers.push(header);
+		if number <= from {
+			break
+		}
+		hash = parent_hash;
+	}
+	headers.reverse();
+
+	Ok(headers)
+}
+
+/// Checks that the relay chain `headers`, collected with [`walk_relay_headers`], descend from the
+/// block `expected`, i.e. that the walk reached it. Otherwise `expected` is on a fork that was
+/// abandoned, which is returned as [`ProverError::Reorg`] along with the tip of the walk.
+pub fn ensure_descends_from(headers: &[RelayHeader], expected: H256) -> Result<(), ProverError> {
+	let hash = |header: &RelayHeader| sp_runtime::traits::Header::hash(header);
+	if headers.first().map(hash) == Some(expected) {
+		return Ok(())
+	}
+
+	Err(ProverError::Reorg { expected, found_tip: headers.last().map(hash).unwrap_or_default() })
+}
+
+/// Verifies `proof` against `client_state` the way the light client does, returning the client
+/// state it updates to. The parachain headers are read from `para_head_key`, see
+/// [`GrandpaProver::para_head_storage_key`]. The relay chain headers are re-decoded as
+/// [`RelayHeader`]s, which the verifier reads their digests and state roots from. A proof that
+/// fails here would be rejected on-chain, and is returned as [`ProverError::ProofRejected`].
+/// Requires the `dry-run` feature.
+#[cfg(feature = "dry-run")]
+pub fn dry_run_verify<H: codec::Codec>(
+	client_state: ClientState,
+	para_head_key: &StorageKey,
+	proof: &ParachainHeadersWithFinalityProof<H>,
+) -> Result<ClientState, ProverError> {
+	let proof = ParachainHeadersWithFinalityProof::<RelayHeader>::decode(&mut &proof.encode()[..])?;
+	verify_parachain_headers_with_grandpa_finality_proof_at_key::<
+		RelayHeader,
+		HostFunctionsProvider,
+	>(client_state, proof, &para_head_key.0)
+	.map_err(|err| ProverError::ProofRejected { reason: err.to_string() })
+}
+
+/// Converts a relay chain block number to the `u32` GRANDPA proofs are generated for, returning
+/// [`ProverError::BlockNumberOutOfRange`] if it exceeds `u32::MAX`.
+pub fn checked_block_number<N>(number: N) -> Result<u32, ProverError>
+where
+	N: TryInto<u32> + Copy + core::fmt::Debug,
+{
+	number
+		.try_into()
+		.map_err(|_| ProverError::BlockNumberOutOfRange { number: format!("{number:?}") })
+}
+
+/// Splits the relay chain range `from..=to` into the `(previous_finalized_height,
+/// latest_finalized_height)` ranges that need to be proven in order, one for every session end
+/// block in the range, followed by the remaining headers.
+pub fn proof_step_ranges(
+	from: u32,
+	to: u32,
+	mut session_end_block: u32,
+	session_length: u32,
+) -> Vec<ProofStep<(u32, u32)>> {
+	if session_end_block == from {
+		session_end_block += session_length;
+	}
+
+	let mut steps = vec![];
+	let mut previous_finalized_height = from;
+	while session_end_block < to {
+		steps.push(ProofStep::SetChange((previous_finalized_height, session_end_block)));
+		previous_finalized_height = session_end_block;
+		session_end_block += session_length;
+	}
+	steps.push(ProofStep::Headers((previous_finalized_height, to)));
+
+	steps
+}
+
+impl<T> GrandpaProver<T>
+where
+	T: Config,
+	T::BlockNumber: Ord + Zero,
+	u32: From<T::BlockNumber>,
+	sp_core::H256: From<T::Hash>,
+{
+	/// Initializes the parachain and relay chain clients given the ws urls, with the default
+	/// [`ProverConfig`]. Fails if the relay chain lacks GRANDPA or doesn't know of `para_id`.
+	pub async fn new(
+		relay_ws_url: &str,
+		para_ws_url: &str,
+		para_id: u32,
+	) -> Result<Self, ProverError> {
+		Self::new_with_config(relay_ws_url, para_ws_url, para_id, ProverConfig::default()).await
+	}
+
+	/// Same as [`Self::new`], with the given connection and request timeouts.
+	pub async fn new_with_config(
+		relay_ws_url: &str,
+		para_ws_url: &str,
+		para_id: u32,
+		config: ProverConfig,
+	) -> Result<Self, ProverError> {
+		let relay_connections = Connections::default();
+		let relay_ws_client =
+			Arc::new(connect(relay_ws_url, config.connection_timeout, &relay_connections).await?);
+		let para_connections = Connections::default();
+		let para_ws_client =
+			Arc::new(connect(para_ws_url, config.connection_timeout, &para_connections).await?);
+		let mut prover =
+			Self::from_clients(relay_ws_client, para_ws_client, para_id, config).await?;
+		prover.relay_connections = relay_connections;
+		prover.para_connections = para_connections;
+		prover.check_grandpa_available().await?;
+		prover.check_para_registered().await?;
+
+		Ok(prover)
+	}
+
+	/// Creates a prover from already connected relay chain and parachain clients, e.g. over a
+	/// transport other than WebSocket. Unlike [`Self::new`], the relay chain isn't checked for
+	/// GRANDPA or the parachain. [`Self::close`] leaves the clients' connections open.
+	pub async fn from_clients(
+		relay_ws_client: Arc<Client>,
+		para_ws_client: Arc<Client>,
+		para_id: u32,
+		config: ProverConfig,
+	) -> Result<Self, ProverError> {
+		let relay_client = OnlineClient::<T>::from_rpc_client(relay_ws_client.clone()).await?;
+		let para_client = OnlineClient::<T>::from_rpc_client(para_ws_client.clone()).await?;
+
+		let mut prover =
+			Self::from_parts(relay_client, relay_ws_client, para_client, para_ws_client, para_id);
+		if let Some(capacity) = config.header_cache_capacity {
+			prover.cache = ProverCache::with_capacity(capacity);
+		}
+		prover.timeouts = config.timeouts;
+
+		Ok(prover)
+	}
+
+	/// Creates a prover from the relay chain and parachain clients along with the rpc clients
+	/// they're built on, with the default configuration. Like [`Self::from_clients`], nothing is
+	/// checked and [`Self::close`] leaves the clients' connections open.
+	pub fn from_parts(
+		relay_client: OnlineClient<T>,
+		relay_ws_client: Arc<Client>,
+		para_client: OnlineClient<T>,
+		para_ws_client: Arc<Client>,
+		para_id: u32,
+	) -> Self {
+		Self {
+			grandpa_rpc: Arc::new(WsGrandpaRpc(relay_ws_client.clone())),
+			relay_ws_client,
+			relay_client,
+			para_ws_client,
+			para_client,
+			para_id,
+			cache: ProverCache::default(),
+			subscriptions: Subscriptions::default(),
+			relay_connections: Connections::default(),
+			para_connections: Connections::default(),
+			max_head_age: None,
+			timeouts: Default::default(),
+			max_query_storage_blocks: None,
+			rate_limiter: None,
+			para_catch_up: ParaCatchUp::default(),
+			max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+			rpc_calls: RpcCallCounter::default(),
+			retry_policy: RetryPolicy::default(),
+			progress: None,
+			para_head_key: None,
+			timestamp_call: None,
+			justification_poll: JustificationPoll::default(),
+		}
+	}
+
+	/// Switches the prover to another parachain or endpoints, keeping its configuration. The new
+	/// endpoints are connected to and checked before the prover is switched, so it's left unchanged
+	/// if any of that fails. The connections replaced are closed then, failing the requests of the
+	/// prover's clones still using them. Switching relay chain endpoints clears the caches, as the
+	/// node may serve another relay chain.
+	pub async fn reconfigure(
+		&mut self,
+		para_id: Option<u32>,
+		relay_url: Option<&str>,
+		para_url: Option<&str>,
+	) -> Result<(), ProverError> {
+		let mut prover = self.clone();
+		if let Err(err) = prover.switch_to(para_id, relay_url, para_url).await {
+			// close the connections made so far, rather than leaving them to be closed as the
+			// clone's clients are dropped.
+			if relay_url.is_some() {
+				prover.relay_connections.close();
+			}
+			if para_url.is_some() {
+				prover.para_connections.close();
+			}
+			return Err(err)
+		}
+		// the subscriptions opened so far stay with this prover rather than its clone.
+		prover.subscriptions = self.subscriptions.clone();
+		let replaced = std::mem::replace(self, prover);
+		if relay_url.is_some() {
+			replaced.relay_connections.close();
+		}
+		if para_url.is_some() {
+			replaced.para_connections.close();
+		}
+
+		Ok(())
+	}
+
+	/// Connects the prover to the given endpoints over new connections and checks them, see
+	/// [`Self::reconfigure`].
+	async fn switch_to(
+		&mut self,
+		para_id: Option<u32>,
+		relay_url: Option<&str>,
+		para_url: Option<&str>,
+	) -> Result<(), ProverError> {
+		if let Some(relay_url) = relay_url {
+			self.relay_connections = Connections::default();
+			let relay_ws_client =
+				Arc::new(connect(relay_url, None, &self.relay_connections).await?);
+			self.relay_client = OnlineClient::<T>::from_rpc_client(relay_ws_client.clone()).await?;
+			self.grandpa_rpc = Arc::new(WsGrandpaRpc(relay_ws_client.clone()));
+			self.relay_ws_client = relay_ws_client;
+			self.cache = ProverCache::with_capacity(self.cache.relay_headers.capacity());
+			self.check_grandpa_available().await?;
+		}
+		if let Some(para_url) = para_url {
+			self.para_connections = Connections::default();
+			let para_ws_client = Arc::new(connect(para_url, None, &self.para_connections).await?);
+			self.para_client = OnlineClient::<T>::from_rpc_client(para_ws_client.clone()).await?;
+			self.para_ws_client = para_ws_client;
+		}
+		if let Some(para_id) = para_id {
+			self.para_id = para_id;
+		}
+		if para_id.is_some() || relay_url.is_some() {
+			self.check_para_registered().await?;
+		}
+
+		Ok(())
+	}
+
+	/// Reads the parachain heads from the storage keys `para_head_key` computes, for relay chains
+	/// with a renamed `Paras` pallet or a different storage layout. Returns
+	/// [`ProverError::UnknownParaId`] if the key of the prover's para id doesn't resolve to a head
+	/// at the finalized head. The proofs carry the custom key, so the light client has to read the
+	/// heads from it too, see `verify_parachain_headers_with_grandpa_finality_proof_at_key`.
+	pub async fn with_para_head_key(
+		mut self,
+		para_head_key: ParaHeadKey,
+	) -> Result<Self, ProverError> {
+		self.para_head_key = Some(para_head_key);
+		self.check_para_registered().await?;
+
+		Ok(self)
+	}
+
+	/// Returns the storage key of the head of `para_id` on the relay chain, see
+	/// [`Self::with_para_head_key`].
+	pub fn para_head_storage_key(&self, para_id: u32) -> StorageKey {
+		para_head_storage_key(self.para_head_key.as_ref(), para_id)
+	}
+
+	/// Starts configuring a parachain header finality proof, finish with
+	/// [`ProofBuilder::generate`].
+	pub fn proof(&self) -> ProofBuilder<'_, T> {
+		ProofBuilder::new(self)
+	}
+
+	/// Returns [`ProverError::GrandpaNotAvailable`] if the relay chain lacks the GRANDPA pallet or
+	/// the `grandpa_proveFinality` rpc.
+	pub async fn check_grandpa_available(&self) -> Result<(), ProverError> {
+		let has_grandpa_pallet = self.relay_client.metadata().pallet(GRANDPA_PALLET).is_ok();
+		let RpcMethods { methods } =
+			self.relay_client.rpc().request("rpc_methods", subxt::rpc_params![]).await?;
+		ensure_grandpa_available(has_grandpa_pallet, &methods)?;
+
+		Ok(())
+	}
+
+	/// Returns [`ProverError::UnknownParaId`] if the relay chain has no head for the prover's para
+	/// id at its finalized head. Relay chains read with a custom [`Self::para_head_key`] don't
+	/// need to have the `Paras` pallet.
+	pub async fn check_para_registered(&self) -> Result<(), ProverError> {
+		let has_paras_pallet = self.para_head_key.is_some() ||
+			self.relay_client.metadata().pallet(PARAS_PALLET).is_ok();
+		let finalized_hash = self.request(|| self.relay_client.rpc().finalized_head()).await?;
+		let key = self.para_head_storage_key(self.para_id);
+		let head = self
+			.request(|| self.relay_client.rpc().storage(&key.0, Some(finalized_hash)))
+			.await?;
+		ensure_para_registered(
+			has_paras_pallet,
+			self.para_id,
+			head.as_ref().map(|data| &data.0[..]),
+		)
+	}
+
+	/// Fetches the relay chain header with the given hash, consulting the header cache first.
+	pub async fn relay_header(&self, hash: T::Hash) -> Result<T::Header, ProverError> {
+		let key = H256::from(hash);
+		if let Some(encoded) = self.cache.relay_headers.get(&key).await {
+			return Ok(T::Header::decode(&mut &encoded[..])?)
+		}
+
+		let header = self
+			.request(|| self.relay_client.rpc().header(Some(hash)))
+			.await?
+			.ok_or_else(|| ProverError::HeaderNotFound(key))?;
+		self.cache.relay_headers.insert(key, header.encode()).await;
+
+		Ok(header)
+	}
+
+	/// Queries the id of the GRANDPA authority set at the given relay chain block, which a verifier
+	/// needs to reject justifications signed by another set.
+	pub async fn current_set_id(&self, at: T::Hash) -> Result<u64, ProverError> {
+		let key = current_set_id_storage_key();
+		let value = self.request(|| self.relay_client.rpc().storage(&key.0, Some(at))).await?;
+		decode_current_set_id(value.as_ref().map(|data| &data.0[..]))
+			.map_err(|err| ProverError::Custom(err.to_string()))
+	}
+
+	/// Queries the GRANDPA set id and authorities at the given relay chain block.
+	pub async fn authority_set_at(
+		&self,
+		hash: T::Hash,
+	) -> Result<(u64, AuthorityList), ProverError> {
+		let set_id = self.current_set_id(hash).await?;
+
+		let authorities = {
+			let res = self
+				.relay_client
+				.rpc()
+				.request::<String>(
+					"state_call",
+					subxt::rpc_params!(
+						"GrandpaApi_grandpa_authorities",
+						"0x",
+						Some(format!("{:?}", hash))
+					),
+				)
+				.await?;
+			let bytes = hex::decode(&res[2..])
+				.map_err(|err| ProverError::Custom(format!("Invalid authorities: {err}")))?;
+
+			AuthorityList::decode(&mut &bytes[..])?
+		};
+
+		Ok((set_id, authorities))
+	}
+
+	/// Checks the age of the relay chain's latest finalized head against [`Self::max_head_age`].
+	pub async fn ensure_finalized_head_fresh(&self) -> Result<(), ProverError> {
+		let max_age = match self.max_head_age {
+			Some(max_age) => max_age,
+			None => return Ok(()),
+		};
+
+		let finalized_hash = self.request(|| self.relay_client.rpc().finalized_head()).await?;
+		let key = polkadot::api::storage().timestamp().now();
+		let storage = self.request(|| self.relay_client.storage().at(Some(finalized_hash))).await?;
+		let head_timestamp = self.request(|| storage.fetch(&key)).await?.ok_or_else(|| {
+			ProverError::StorageItemMissing { item: "Timestamp::Now", at: finalized_hash.into() }
+		})?;
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_err(|err| ProverError::Custom(err.to_string()))?;
+		ensure_head_fresh(head_timestamp, now, max_age)?;
+
+		Ok(())
+	}
+
+	/// Returns the hash and number of the relay chain's latest finalized block.
+	pub async fn latest_finalized_height(&self) -> Result<(T::Hash, T::BlockNumber), ProverError> {
+		fetch_latest_finalized(
+			|| self.request(|| self.relay_client.rpc().finalized_head()),
+			|hash| async move {
+				let header = self.request(|| self.relay_client.rpc().header(Some(hash))).await?;
+				Ok(header.map(|header| header.number()))
+			},
+		)
+		.await
+	}
+
+	/// Returns the number of the parachain head included in the relay chain's latest finalized
+	/// block, see [`Self::latest_finalized_height`].
+	pub async fn latest_finalized_para_height(&self) -> Result<T::BlockNumber, ProverError> {
+		let (hash, _) = self.latest_finalized_height().await?;
+		let key = self.para_head_storage_key(self.para_id);
+		let value = self.request(|| self.relay_client.rpc().storage(&key.0, Some(hash))).await?;
+		decode_para_numbers::<T::Header>([value.map(|data| data.0)])?
+			.pop()
+			.ok_or_else(|| ProverError::StorageItemMissing {
+				item: "Paras::Heads",
+				at: hash.into(),
+			})
+	}
+
+	/// Queries the GRANDPA authority set at the relay chain block `at`, along with a storage proof
+	/// of it, to initialize a new light client with. The proof is checked with
+	/// [`GrandpaClientInit::verify`] before it's returned.
+	pub async fn query_client_init(&self, at: T::Hash) -> Result<GrandpaClientInit, ProverError> {
+		let header = RelayHeader::decode(&mut &self.relay_header(at).await?.encode()[..])?;
+		let (set_id, authorities) = self.authority_set_at(at).await?;
+
+		let set_id_key = current_set_id_storage_key();
+		let keys = vec![GRANDPA_AUTHORITIES_KEY, set_id_key.as_ref()];
+		let state_proof = self
+			.request(|| self.relay_client.rpc().read_proof(keys.clone(), Some(at)))
+			.await?
+			.proof
+			.into_iter()
+			.map(|node| node.0)
+			.collect::<Vec<_>>();
+
+		let init = GrandpaClientInit {
+			block: at.into(),
+			height: header.number,
+			state_root: header.state_root,
+			set_id,
+			authorities,
+			state_proof: canonical_storage_proof(state_proof),
+		};
+		init.verify()?;
+
+		Ok(init)
+	}
+
+	/// Construct the inital client state.
+	pub async fn initialize_client_state(&self) -> Result<ClientState, ProverError> {
+		let latest_relay_hash = self.relay_client.rpc().finalized_head().await?;
+		let header = self
+			.relay_client
+			.rpc()
+			.header(Some(latest_relay_hash))
+			.await?
+			.ok_or_else(|| ProverError::HeaderNotFound(latest_relay_hash.into()))?;
+
+		let (current_set_id, current_authorities) =
+			self.authority_set_at(latest_relay_hash).await?;
+
+		// Ensure there are no duplicates in authority list
+		let mut set = BTreeSet::new();
+		for (id, ..) in &current_authorities {
+			if !set.insert(id) {
+				Err(anyhow!("Duplicate entries found in current authority set"))?
+			}
+		}
+
+		let latest_relay_height = u32::from(header.number());
+		let finalized_para_header =
+			self.query_latest_finalized_parachain_header(latest_relay_height).await?;
+
+		Ok(ClientState {
+			current_authorities,
+			current_set_id,
+			latest_relay_height,
+			latest_relay_hash: latest_relay_hash.into(),
+			para_id: self.para_id,
+			// we'll set this below
+			latest_para_height: u32::from(finalized_para_header.number()),
+		})
+	}
+
+	/// Selects the justification of the canonical finalized relay chain block out of
+	/// `justifications`, rejecting those of blocks that were reorged out, see
+	/// [`select_canonical_justification`].
+	pub async fn canonical_justification<H>(
+		&self,
+		justifications: Vec<Vec<u8>>,
+	) -> Result<Vec<u8>, ProverError>
+	where
+		H: Header + codec::Decode,
+	{
+		let justification =
+			select_canonical_justification::<H, _, _>(justifications, move |number| async move {
+				let hash = self
+					.request(|| self.relay_client.rpc().block_hash(Some(number.into())))
+					.await?;
+				Ok(hash.map(H256::from))
+			})
+			.await?;
+
+		Ok(justification)
+	}
+
+	/// Returns the latest finalized parachain header at the given finalized relay chain height.
+	pub async fn query_latest_finalized_parachain_header(
+		&self,
+		latest_finalized_height: u32,
+	) -> Result<T::Header, ProverError> {
+		let latest_finalized_hash = self
+			.request(|| self.relay_client.rpc().block_hash(Some(latest_finalized_height.into())))
+			.await?
+			.ok_or_else(|| anyhow!("Block hash not found for number: {latest_finalized_height}"))?;
+		let header = self.para_head_at(latest_finalized_hash).await?.ok_or_else(|| {
+			ProverError::StorageItemMissing {
+				item: "Paras::Heads",
+				at: latest_finalized_hash.into(),
+			}
+		})?;
+		let header = T::Header::decode(&mut &header[..])?;
+
+		Ok(header)
+	}
+
+	/// Returns a tuple of the finality proof for the given parachain `header_numbers` finalized by
+	/// `latest_finalized_height`.
+	pub async fn query_finalized_parachain_headers_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let header_numbers = header_numbers.into_iter().collect::<BTreeSet<_>>();
+		self.query_finalized_parachain_headers_with_proof_by_numbers(
+			previous_finalized_height,
+			latest_finalized_height,
+			latest_justification,
+			&header_numbers,
+		)
+		.await
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], with the requested header
+	/// numbers in a set, so relayers proving thousands of headers don't pay for a linear scan of
+	/// them at every relay chain block.
+	pub async fn query_finalized_parachain_headers_with_proof_by_numbers<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: &BTreeSet<T::BlockNumber>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let proof = self
+			.query_selected_parachain_headers_with_proof(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				|para_header, previous_number| {
+					is_requested_para_header_in(
+						&para_header.number(),
+						previous_number,
+						header_numbers,
+					)
+				},
+			)
+			.await?;
+
+		Ok(proof)
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], selecting the parachain
+	/// headers by hash, which tells apart headers of the same number on different forks. Requested
+	/// headers superseded within a relay chain block aren't proven, as `Paras::Heads` only holds
+	/// the last of them.
+	pub async fn query_finalized_parachain_headers_with_proof_by_hashes<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_hashes: &BTreeSet<H256>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let proof = self
+			.query_selected_parachain_headers_with_proof(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				|para_header, _| header_hashes.contains(&H256::from(para_header.hash())),
+			)
+			.await?;
+
+		Ok(proof)
+	}
+
+	/// Proves the parachain headers included in the relay chain range that `selected` picks, given
+	/// each header and the number of the parachain head before it.
+	async fn query_selected_parachain_headers_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		selected: impl FnMut(&T::Header, Option<&T::BlockNumber>) -> bool,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, anyhow::Error>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let progress = ProgressReporter::new(self.progress.clone());
+		let (finality_proof, included) = self
+			.select_parachain_headers::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				selected,
+				&progress,
+			)
+			.await?;
+
+		// the proofs for each block are independent, so their requests can overlap.
+		let progress = &progress;
+		let parachain_headers_with_proof = concurrent_requests(
+			included,
+			self.max_concurrent_requests,
+			move |(block, para_header)| async move {
+				let proofs = self.included_para_header_proofs(block, &para_header).await?;
+				progress.proof_built();
+				Ok(proofs)
+			},
+		)
+		.await?
+		.into_iter()
+		.collect::<BTreeMap<_, _>>();
+
+		Ok(ParachainHeadersWithFinalityProof {
+			finality_proof,
+			parachain_headers: parachain_headers_with_proof,
+		})
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof_by_numbers`], but rather than
+	/// collecting the proofs of the parachain headers, they're streamed as they're built, in the
+	/// order of the relay chain blocks that included them. The memory needed then doesn't grow
+	/// with the number of headers proven, as long as the caller doesn't hold on to the proofs.
+	/// Returns the finality proof, along with the stream of `(relay chain block hash, proofs)`.
+	pub async fn query_finalized_parachain_headers_stream<'a, H>(
+		&'a self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: &BTreeSet<T::BlockNumber>,
+	) -> Result<
+		(
+			FinalityProof<H>,
+			impl Stream<Item = Result<(H256, ParachainHeaderProofs), ProverError>> + 'a,
+		),
+		ProverError,
+	>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let progress = Arc::new(ProgressReporter::new(self.progress.clone()));
+		let (finality_proof, included) = self
+			.select_parachain_headers::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				|para_header, previous_number| {
+					is_requested_para_header_in(
+						&para_header.number(),
+						previous_number,
+						header_numbers,
+					)
+				},
+				&progress,
+			)
+			.await?;
+
+		let proofs =
+			stream_requests(included, self.max_concurrent_requests, move |(block, para_header)| {
+				let progress = progress.clone();
+				async move {
+					let proofs = self.included_para_header_proofs(block, &para_header).await?;
+					progress.proof_built();
+					Ok(proofs)
+				}
+			})
+			.map(|proofs| proofs.map_err(ProverError::from));
+
+		Ok((finality_proof, proofs))
+	}
+
+	/// Starts a checkpointed proof of the parachain headers `header_numbers` in the relay chain
+	/// range, fetching its finality proof and collecting the headers to prove. The returned
+	/// checkpoint is meant to be persisted with [`Self::save_checkpoint`] before the headers are
+	/// proven with [`Self::resume_from_checkpoint`].
+	pub async fn start_checkpoint<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: &BTreeSet<T::BlockNumber>,
+	) -> Result<ProverCheckpoint, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let progress = ProgressReporter::new(self.progress.clone());
+		let (finality_proof, included) = self
+			.select_parachain_headers::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				|para_header, previous_number| {
+					is_requested_para_header_in(
+						&para_header.number(),
+						previous_number,
+						header_numbers,
+					)
+				},
+				&progress,
+			)
+			.await?;
+		let collected = included
+			.into_iter()
+			.map(|(block, para_header)| (H256::from(block), para_header.encode()))
+			.collect();
+
+		Ok(ProverCheckpoint::new(&finality_proof, collected))
+	}
+
+	/// Writes `checkpoint` to `path`. The checkpoint is written next to it first, to the file name
+	/// with `.tmp` appended, and then moved into place, so a crash while saving leaves the previous
+	/// checkpoint intact.
+	pub fn save_checkpoint(
+		checkpoint: &ProverCheckpoint,
+		path: impl AsRef<Path>,
+	) -> Result<(), ProverError> {
+		let path = path.as_ref();
+		let mut tmp = path.file_name().unwrap_or_default().to_os_string();
+		tmp.push(".tmp");
+		let tmp = path.with_file_name(tmp);
+		std::fs::write(&tmp, checkpoint.encode())
+			.and_then(|_| std::fs::rename(&tmp, path))
+			.map_err(|err| {
+				ProverError::Custom(format!(
+					"Failed to save checkpoint to {}: {err}",
+					path.display()
+				))
+			})
+	}
+
+	/// Proves the parachain headers left in the checkpoint at `path`, saving it after each proof,
+	/// and returns the proof of the whole range. If interrupted, calling this again carries on
+	/// from the last saved proof. The headers are proven one at a time, in chain order.
+	pub async fn resume_from_checkpoint<H>(
+		&self,
+		path: impl AsRef<Path>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+	{
+		let path = path.as_ref();
+		let encoded = std::fs::read(path).map_err(|err| {
+			ProverError::Custom(format!("Failed to read checkpoint {}: {err}", path.display()))
+		})?;
+		let mut checkpoint = ProverCheckpoint::decode(&mut &encoded[..])?;
+		let progress = ProgressReporter::new(self.progress.clone());
+		advance_checkpoint(
+			&mut checkpoint,
+			|block, para_header| {
+				let progress = &progress;
+				async move {
+					let block = T::Hash::decode(&mut block.as_bytes())?;
+					let para_header = T::Header::decode(&mut &para_header[..])?;
+					let (_, proofs) = self.included_para_header_proofs(block, &para_header).await?;
+					progress.proof_built();
+					Ok(proofs)
+				}
+			},
+			|checkpoint| Ok(Self::save_checkpoint(checkpoint, path)?),
+		)
+		.await?;
+
+		checkpoint.into_proof()
+	}
+
+	/// Fetches the finality proof of the relay chain range, along with the parachain headers
+	/// included in it that `selected` picks and the relay chain blocks that included them.
+	async fn select_parachain_headers<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		mut selected: impl FnMut(&T::Header, Option<&T::BlockNumber>) -> bool,
+		progress: &ProgressReporter,
+	) -> Result<(FinalityProof<H>, Vec<(T::Hash, T::Header)>), anyhow::Error>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let (finality_proof, latest_finalized_height) = self
+			.finality_proof_for_range::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+			)
+			.await?;
+
+		// fail early, rather than on some historical read further in, if the node has pruned the
+		// state at the start of the range.
+		if let Some(header) = finality_proof.unknown_headers.first() {
+			self.ensure_state_available(header.hash().into()).await?;
+		}
+
+		// we are interested only in the blocks where our parachain header changes.
+		let para_storage_key = self.para_head_storage_key(self.para_id);
+		let keys = vec![para_storage_key.as_ref()];
+
+		let change_set = self
+			.query_storage_range_with(
+				&keys,
+				previous_finalized_height,
+				latest_finalized_height,
+				|height| progress.relay_height(height),
+			)
+			.await?;
+
+		let mut included = vec![];
+		let mut previous_number = None;
+		for changes in change_set {
+			// the change set already contains the new head data, so we can decide whether the
+			// block was requested before making any further requests.
+			let value =
+				match changes.changes.into_iter().find(|(key, _)| key.0 == para_storage_key.0) {
+					Some((_, value)) => value,
+					None => continue,
+				};
+			let head_data = match ParaHeadChange::decode(value.as_ref().map(|data| &data.0[..]))? {
+				ParaHeadChange::Updated(head_data) => head_data,
+				// the parachain was offboarded in this block, so there's no header to prove.
+				ParaHeadChange::Removed => continue,
+			};
+			let para_header = T::Header::decode(&mut &head_data[..])?;
+			let requested = selected(&para_header, previous_number.as_ref());
+			previous_number = Some(para_header.number());
+			if requested {
+				included.push((changes.block, para_header));
+				progress.header_collected();
+			}
+		}
+
+		Ok((finality_proof, included))
+	}
+
+	/// Proves the parachain header included in the relay chain block `block`.
+	async fn included_para_header_proofs(
+		&self,
+		block: T::Hash,
+		para_header: &T::Header,
+	) -> Result<(H256, ParachainHeaderProofs), anyhow::Error> {
+		let header = self.relay_header(block).await?;
+		let proofs = self.para_header_proofs(&header, para_header).await?;
+
+		Ok((H256::from(header.hash()), proofs))
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`] for several parachains of the
+	/// relay chain at once. The relay chain headers and finality proof are fetched once and shared
+	/// by the proofs of every parachain, and the changes to their heads are queried together.
+	/// Returns [`ProverError::DuplicateParaId`] if a parachain is queried more than once.
+	pub async fn query_finalized_parachain_headers_with_proof_multi<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		parachains: &[ParachainQuery<T>],
+	) -> Result<BTreeMap<u32, ParachainHeadersWithFinalityProof<H>>, ProverError>
+	where
+		H: Header + codec::Decode + Clone,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		ensure_distinct_para_ids(parachains.iter().map(|parachain| parachain.para_id))?;
+		let (finality_proof, latest_finalized_height) = self
+			.finality_proof_for_range::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+			)
+			.await?;
+
+		let para_storage_keys = parachains
+			.iter()
+			.map(|parachain| self.para_head_storage_key(parachain.para_id))
+			.collect::<Vec<_>>();
+		let keys = para_storage_keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+		let change_set = self
+			.query_storage_range(&keys, previous_finalized_height, latest_finalized_height)
+			.await?
+			.into_iter()
+			.map(|change_set| {
+				let changes = change_set
+					.changes
+					.into_iter()
+					.map(|(key, value)| (key.0, value.map(|data| data.0)))
+					.collect::<Vec<_>>();
+				(change_set.block, changes)
+			});
+		let header_numbers = parachains
+			.iter()
+			.map(|parachain| (parachain.para_id, parachain.header_numbers.clone()))
+			.collect::<BTreeMap<_, _>>();
+		let mut included =
+			requested_para_headers_by_id::<T::Header, _>(change_set, &header_numbers, |para_id| {
+				self.para_head_storage_key(para_id)
+			})?;
+
+		let mut parachain_headers = BTreeMap::new();
+		for parachain in parachains {
+			let included = included.remove(&parachain.para_id).unwrap_or_default();
+			let para_id = parachain.para_id;
+			let para_client = &parachain.para_client;
+			let proofs = concurrent_requests(
+				included,
+				self.max_concurrent_requests,
+				move |(block, para_header)| async move {
+					let header = self.relay_header(block).await?;
+					let proofs = self
+						.para_header_proofs_from(para_id, para_client, &header, &para_header)
+						.await?;
+					Ok((H256::from(header.hash()), proofs))
+				},
+			)
+			.await?
+			.into_iter()
+			.collect::<BTreeMap<_, _>>();
+			parachain_headers.insert(para_id, proofs);
+		}
+
+		Ok(share_finality_proof(finality_proof, parachain_headers))
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], also returning statistics
+	/// about the proof. Only the requests made for this proof are counted, not those of other
+	/// clones of the prover.
+	pub async fn query_with_stats<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<(ParachainHeadersWithFinalityProof<H>, ProofStats), ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let mut prover = self.clone();
+		prover.rpc_calls = RpcCallCounter::default();
+		let start = Instant::now();
+		let proof = prover
+			.query_finalized_parachain_headers_with_proof(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				header_numbers,
+			)
+			.await?;
+		let stats = ProofStats::new(&proof, prover.rpc_calls.calls(), start.elapsed());
+
+		Ok((proof, stats))
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], with the trie nodes shared
+	/// by the state proofs of different relay chain blocks included only once. Verifiers
+	/// reassemble the state proofs with `expand_compact_proof` before verifying them.
+	pub async fn query_compact_parachain_headers_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<CompactParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let proof = self
+			.query_finalized_parachain_headers_with_proof(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				header_numbers,
+			)
+			.await?;
+
+		Ok(proof.into())
+	}
+
+	/// Proves the finality of the relay chain from `previous_finalized_height` to
+	/// `latest_finalized_height` without any parachain headers, for clients tracking the relay
+	/// chain itself rather than one of its parachains. `Paras::Heads` isn't read, so the para id is
+	/// unused. The proof converts into a relay-only ics10-grandpa `Header`, which updates the
+	/// client's relay chain block and authority set.
+	pub async fn query_relay_chain_finality_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+	{
+		let (finality_proof, _) = self
+			.finality_proof_for_range::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+			)
+			.await?;
+
+		Ok(ParachainHeadersWithFinalityProof { finality_proof, parachain_headers: BTreeMap::new() })
+	}
+
+	/// Proves the finality of the relay chain's own headers from `previous_finalized_height` to
+	/// `latest_finalized_height`, for standalone chains with their own GRANDPA instance. The relay
+	/// chain client tracks the standalone chain, and the parachain and para id are unused.
+	pub async fn query_finalized_headers_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+	) -> Result<FinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+	{
+		self.ensure_finalized_head_fresh().await?;
+
+		let finality_proof = prove_standalone_finality(
+			&*self.grandpa_rpc,
+			self.justification_poll,
+			previous_finalized_height,
+			latest_finalized_height,
+			latest_justification,
+			move |height| async move {
+				let hash = self
+					.request(|| self.relay_client.rpc().block_hash(Some(height.into())))
+					.await?
+					.ok_or_else(|| anyhow!("Failed to fetch block hash for height {height}"))?;
+				let header = self.relay_header(hash).await?;
+				Ok(H::decode(&mut &header.encode()[..])?)
+			},
+		)
+		.await?;
+
+		Ok(finality_proof)
+	}
+
+	/// Same as [`Self::query_finalized_headers_with_proof`], with only the headers signalling an
+	/// authority set change and the latest finalized header, see [`mandatory_headers_only`]. The
+	/// proof is much smaller for clients that only follow the authority set rotations, but as the
+	/// headers no longer link up, it can't be verified against the relay chain ancestry.
+	pub async fn query_mandatory_headers_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+	) -> Result<FinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+	{
+		let finality_proof = self
+			.query_finalized_headers_with_proof(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+			)
+			.await?;
+
+		Ok(mandatory_headers_only(finality_proof)?)
+	}
+
+	/// Proves the parachain heads at each of the given relay chain blocks, which needn't be
+	/// contiguous, anchored to the finality proof of the latest of them. Blocks at which the
+	/// parachain has no head are skipped. The proof's unknown headers start at the parent of the
+	/// earliest block.
+	pub async fn prove_at_relay_blocks<H>(
+		&self,
+		hashes: Vec<T::Hash>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		self.ensure_finalized_head_fresh().await?;
+
+		let mut headers = vec![];
+		for hash in hashes {
+			headers.push(self.relay_header(hash).await?);
+		}
+		let numbers = headers
+			.iter()
+			.map(|header| checked_block_number(header.number()))
+			.collect::<Result<Vec<_>, _>>()?;
+		let (earliest, latest) = match (numbers.iter().min(), numbers.iter().max()) {
+			(Some(earliest), Some(latest)) => (*earliest, *latest),
+			_ => Err(anyhow!("No relay chain blocks to prove"))?,
+		};
+
+		let (mut finality_proof, latest_finalized_height) =
+			self.poll_finality_proof::<H>(latest).await?;
+		finality_proof.unknown_headers = self
+			.relay_headers_in_range(earliest.saturating_sub(1), latest_finalized_height)
+			.await?;
+
+		let mut parachain_headers = BTreeMap::<H256, ParachainHeaderProofs>::default();
+		for header in headers {
+			let para_header = match self.para_head_at(header.hash()).await? {
+				Some(head_data) => T::Header::decode(&mut &head_data[..])?,
+				None => continue,
+			};
+			// the genesis header is known to the client already.
+			if para_header.number() == Zero::zero() {
+				continue
+			}
+
+			let proofs = self.para_header_proofs(&header, &para_header).await?;
+			parachain_headers.insert(header.hash().into(), proofs);
+		}
+
+		Ok(ParachainHeadersWithFinalityProof { finality_proof, parachain_headers })
+	}
+
+	/// Fetches the relay chain headers `from..=to`, walking back from `to` through the parent
+	/// hashes, see [`walk_relay_headers`].
+	async fn relay_headers_in_range<H>(&self, from: u32, to: u32) -> Result<Vec<H>, anyhow::Error>
+	where
+		H: Header + codec::Decode,
+	{
+		if from > to {
+			return Ok(vec![])
+		}
+
+		self.walk_relay_chain(from, to)
+			.await?
+			.into_iter()
+			.map(|header| Ok(H::decode(&mut &header.encode()[..])?))
+			.collect()
+	}
+
+	/// Walks the canonical relay chain back from the block at `to` down to the one at `from`, see
+	/// [`walk_relay_headers`].
+	async fn walk_relay_chain(
+		&self,
+		from: u32,
+		to: u32,
+	) -> Result<Vec<RelayHeader>, anyhow::Error> {
+		let tip = self
+			.request(|| self.relay_client.rpc().block_hash(Some(to.into())))
+			.await?
+			.ok_or_else(|| anyhow!("Failed to fetch block has for height {to}"))?;
+
+		walk_relay_headers(&self.cache.relay_headers, from, tip.into(), |hash| async move {
+			let hash = T::Hash::decode(&mut hash.as_bytes())?;
+			let header = self
+				.request(|| self.relay_client.rpc().header(Some(hash)))
+				.await?
+				.ok_or_else(|| ProverError::HeaderNotFound(hash.into()))?;
+			Ok(RelayHeader::decode(&mut &header.encode()[..])?)
+		})
+		.await
+	}
+
+	/// Fetches the finality proofs of the authority set changes enacted in the relay chain range
+	/// `from..=to`, for clients that verify the range epoch by epoch, see
+	/// [`set_change_justifications`].
+	pub async fn query_set_change_justifications(
+		&self,
+		from: u32,
+		to: u32,
+	) -> Result<Vec<(u32, JustificationNotification)>, ProverError> {
+		let headers = self.walk_relay_chain(from, to).await?;
+		let justifications =
+			set_change_justifications(&headers, from, to, self.justification_poll, |block| {
+				self.finality_proof_request(move || self.grandpa_rpc.prove_finality(block))
+			})
+			.await?;
+
+		Ok(justifications)
+	}
+
+	/// Checks that the relay chain block `expected`, at height `from`, is an ancestor of the
+	/// canonical block at `to`. The walk back from `to` stops at `from`, so if `expected` was
+	/// abandoned by a reorg [`ProverError::Reorg`] is returned, rather than walking past it.
+	pub async fn ensure_relay_ancestry(
+		&self,
+		expected: H256,
+		from: u32,
+		to: u32,
+	) -> Result<(), ProverError> {
+		let headers = self.walk_relay_chain(from, to).await?;
+		ensure_descends_from(&headers, expected)
+	}
+
+	/// Fetches the finality proof of the relay chain block `block`, waiting for the node to store
+	/// its justification as configured by [`Self::justification_poll`], see
+	/// [`poll_finality_proof`].
+	async fn poll_finality_proof<H>(
+		&self,
+		block: u32,
+	) -> Result<(FinalityProof<H>, u32), anyhow::Error>
+	where
+		H: Header + codec::Decode,
+	{
+		poll_finality_proof::<H, _, _>(self.justification_poll, block, || {
+			self.finality_proof_request(move || self.grandpa_rpc.prove_finality(block))
+		})
+		.await
+	}
+
+	/// Fetches the finality proof of `latest_finalized_height`, or uses `latest_justification`,
+	/// along with the relay chain headers from `previous_finalized_height`. Returns the proof and
+	/// the number of the block it finalizes.
+	async fn finality_proof_for_range<H>(
+		&self,
+		previous_finalized_height: u32,
+		mut latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+	) -> Result<(FinalityProof<H>, u32), anyhow::Error>
+	where
+		H: Header + codec::Decode,
+	{
+		self.ensure_finalized_head_fresh().await?;
+
+		let mut finality_proof = if let Some(justification) = latest_justification {
+			let justification = self.canonical_justification::<H>(vec![justification]).await?;
+			let justification = GrandpaJustification::<H>::decode(&mut &*justification)?;
+
+			FinalityProof::<H> {
+				block: justification.commit.target_hash,
+				justification: justification.encode(),
+				unknown_headers: vec![],
+			}
+		} else {
+			let (finality_proof, target_number) =
+				self.poll_finality_proof::<H>(latest_finalized_height).await?;
+			latest_finalized_height = target_number;
+			finality_proof
+		};
+
+		// overwrite unknown headers
+		finality_proof.unknown_headers = self
+			.relay_headers_in_range(previous_finalized_height, latest_finalized_height)
+			.await?;
+		let relay_headers = finality_proof
+			.unknown_headers
+			.iter()
+			.map(|header| RelayHeader::decode(&mut &header.encode()[..]))
+			.collect::<Result<Vec<_>, _>>()?;
+		ensure_no_set_change_within(
+			&relay_headers,
+			previous_finalized_height,
+			latest_finalized_height,
+		)?;
+
+		Ok((finality_proof, latest_finalized_height))
+	}
+
+	/// Proves the given parachain header, stored in `Paras::Heads` at the relay chain block
+	/// `header`, along with its timestamp.
+	async fn para_header_proofs(
+		&self,
+		header: &T::Header,
+		para_header: &T::Header,
+	) -> Result<ParachainHeaderProofs, anyhow::Error> {
+		self.para_header_proofs_from(self.para_id, &self.para_client, header, para_header)
+			.await
+	}
+
+	/// Proves the head of the parachain `para_id` at the relay chain block `header`, with its
+	/// timestamp extrinsic proven from `para_client`.
+	async fn para_header_proofs_from(
+		&self,
+		para_id: u32,
+		para_client: &OnlineClient<T>,
+		header: &T::Header,
+		para_header: &T::Header,
+	) -> Result<ParachainHeaderProofs, anyhow::Error> {
+		let para_storage_key = self.para_head_storage_key(para_id);
+		let keys = vec![para_storage_key.as_ref()];
+		let state_proof = self
+			.request(|| self.relay_client.rpc().read_proof(keys.clone(), Some(header.hash())))
+			.await
+			.map_err(|err| state_read_error(err, header.hash().into()))?
+			.proof
+			.into_iter()
+			.map(|p| p.0)
+			.collect::<Vec<_>>();
+		let state_proof = canonical_storage_proof(state_proof);
+		let relay_header = RelayHeader::decode(&mut &header.encode()[..])?;
+		ensure_proof_contains_key(
+			relay_header.state_root,
+			&state_proof,
+			para_storage_key.as_ref(),
+		)?;
+
+		// the relay chain may have finalized parachain blocks the parachain node hasn't imported
+		// yet.
+		let para_hash = para_header.hash();
+		wait_for_para_block(self.para_catch_up, para_hash.into(), move || async move {
+			Ok(self.request(|| para_client.rpc().header(Some(para_hash))).await?.is_some())
+		})
+		.await?;
+
+		let (extrinsic_index, TimeStampExtWithProof { ext: extrinsic, proof: extrinsic_proof }) =
+			self.timestamp_inherent_with_proof(para_client, para_hash, self.timestamp_call)
+				.await?;
+
+		Ok(ParachainHeaderProofs {
+			state_proof,
+			extrinsic,
+			extrinsic_proof,
+			storage_key: Some(para_storage_key.0),
+			extrinsic_index,
+		})
+	}
+
+	/// Proves the timestamp inherent of the block `at` of the chain `client` is connected to, see
+	/// [`prove_timestamp_inherent`].
+	async fn timestamp_inherent_with_proof(
+		&self,
+		client: &OnlineClient<T>,
+		at: T::Hash,
+		call: Option<(u8, u8)>,
+	) -> Result<(u32, TimeStampExtWithProof), anyhow::Error> {
+		let block = self
+			.request(|| client.rpc().block(Some(at)))
+			.await?
+			.ok_or_else(|| ProverError::HeaderNotFound(at.into()))?;
+		let extrinsics =
+			block.block.extrinsics.into_iter().map(|e| e.0.encode()).collect::<Vec<_>>();
+
+		Ok(prove_timestamp_inherent(&extrinsics, call, at.into())?)
+	}
+
+	/// Queries the changes to `keys` in the relay chain blocks `from..=to`, split into
+	/// `state_queryStorage` calls spanning at most [`Self::max_query_storage_blocks`] blocks each.
+	pub async fn query_storage_range(
+		&self,
+		keys: &[&[u8]],
+		from: u32,
+		to: u32,
+	) -> Result<Vec<StorageChangeSet<T::Hash>>, ProverError> {
+		self.query_storage_range_with(keys, from, to, |_| ()).await
+	}
+
+	/// Same as [`Self::query_storage_range`], calling `on_queried` with the last block of each
+	/// `state_queryStorage` call once it's done.
+	async fn query_storage_range_with(
+		&self,
+		keys: &[&[u8]],
+		from: u32,
+		to: u32,
+		mut on_queried: impl FnMut(u32),
+	) -> Result<Vec<StorageChangeSet<T::Hash>>, ProverError> {
+		let mut change_sets = vec![];
+		// each query reports the values at its first block, even if they didn't change there.
+		let mut values = BTreeMap::new();
+		for (start, end) in query_storage_ranges(from, to, self.max_query_storage_blocks) {
+			let start_hash = self
+				.request(|| self.relay_client.rpc().block_hash(Some(start.into())))
+				.await?
+				.ok_or_else(|| anyhow!("Block hash not found for number: {start}"))?;
+			let end_hash = self
+				.request(|| self.relay_client.rpc().block_hash(Some(end.into())))
+				.await?
+				.ok_or_else(|| anyhow!("Block hash not found for number: {end}"))?;
+			let changes = self
+				.request(|| {
+					self.relay_client.rpc().query_storage(keys.to_vec(), start_hash, Some(end_hash))
+				})
+				.await?;
+
+			for mut change_set in changes {
+				change_set.changes.retain(|(key, value)| {
+					values.insert(key.clone(), value.clone()) != Some(value.clone())
+				});
+				if !change_set.changes.is_empty() {
+					change_sets.push(change_set);
+				}
+			}
+			on_queried(end);
+		}
+
+		Ok(change_sets)
+	}
+
+	/// Returns the numbers of the parachain headers included in the relay chain between the given
+	/// blocks, without generating any proofs for them.
+	pub async fn changed_para_numbers(
+		&self,
+		from: T::Hash,
+		to: T::Hash,
+	) -> Result<Vec<T::BlockNumber>, ProverError> {
+		let para_storage_key = self.para_head_storage_key(self.para_id);
+		let from = u32::from(self.relay_header(from).await?.number());
+		let to = u32::from(self.relay_header(to).await?.number());
+		let change_set = self.query_storage_range(&[para_storage_key.as_ref()], from, to).await?;
+		let values = change_set.into_iter().flat_map(|changes| {
+			changes.changes.into_iter().map(|(_, data)| data.map(|data| data.0))
+		});
+
+		Ok(decode_para_numbers::<T::Header>(values)?)
+	}
+
+	/// Returns the parachain headers included in the relay chain between the given blocks, at most
+	/// `max_headers` of them. If there are more, the relay chain block that included the first
+	/// header left out is also returned, so the caller can continue from it.
+	pub async fn query_finalized_parachain_headers_between(
+		&self,
+		from: T::Hash,
+		to: T::Hash,
+		max_headers: usize,
+	) -> Result<(Vec<T::Header>, Option<T::Hash>), ProverError> {
+		let para_storage_key = self.para_head_storage_key(self.para_id);
+		let from = u32::from(self.relay_header(from).await?.number());
+		let to = u32::from(self.relay_header(to).await?.number());
+		let change_set = self.query_storage_range(&[para_storage_key.as_ref()], from, to).await?;
+		let changes = change_set.into_iter().flat_map(|changes| {
+			let block = changes.block;
+			changes
+				.changes
+				.into_iter()
+				.map(move |(_, data)| (block, data.map(|data| data.0)))
+		});
+
+		Ok(decode_para_headers_page::<T::Header, _>(changes, max_headers)?)
+	}
+
+	/// Proves the parachain header with the given hash, by locating the relay chain block that
+	/// included it. The proof's unknown headers start at the parent of that block.
+	pub async fn prove_para_header_by_hash<H>(
+		&self,
+		para_header_hash: H256,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output> + From<H256>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let para_hash = T::Hash::from(para_header_hash);
+		let para_header = self
+			.request(|| self.para_client.rpc().header(Some(para_hash)))
+			.await?
+			.ok_or_else(|| ProverError::HeaderNotFound(para_header_hash))?;
+		let relay_parent_number = self.relay_parent_number(para_hash).await?;
+
+		let finalized_hash = self.relay_client.rpc().finalized_head().await?;
+		let finalized_height = u32::from(self.relay_header(finalized_hash).await?.number());
+		let changes = self.para_head_changes(relay_parent_number, finalized_height).await?;
+		let inclusion_block = find_para_header_inclusion::<T::Header, _>(
+			changes,
+			para_header_hash,
+		)?
+		.ok_or_else(|| {
+			anyhow!("Parachain header {para_header_hash:?} not included in a finalized block")
+		})?;
+
+		let inclusion_height = u32::from(self.relay_header(inclusion_block).await?.number());
+		self.query_finalized_parachain_headers_with_proof(
+			inclusion_height.saturating_sub(1),
+			finalized_height,
+			None,
+			vec![para_header.number()],
+		)
+		.await
+	}
+
+	/// Proves the parachain header with the given number on its own, by locating the relay chain
+	/// block that included it and proving the finality of that block, rather than of the latest
+	/// finalized one. Returns [`ProverError::ParaBlockNotFinalized`] if the header isn't finalized
+	/// yet.
+	pub async fn query_finalized_parachain_header_by_number<H>(
+		&self,
+		para_block_number: T::BlockNumber,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let number = u32::from(para_block_number);
+		let finalized_para_height = self.latest_finalized_para_height().await?;
+		ensure_para_block_finalized(number, u32::from(finalized_para_height))?;
+
+		let para_hash = self
+			.request(|| self.para_client.rpc().block_hash(Some(number.into())))
+			.await?
+			.ok_or_else(|| anyhow!("Parachain block hash not found for: {number}"))?;
+		let relay_parent_number = self.relay_parent_number(para_hash).await?;
+		let (_, finalized_height) = self.latest_finalized_height().await?;
+		let changes =
+			self.para_head_changes(relay_parent_number, u32::from(finalized_height)).await?;
+		let inclusion_block = find_para_number_inclusion::<T::Header, _>(changes, number)?
+			.ok_or_else(|| anyhow!("Parachain block {number} not included in a finalized block"))?;
+
+		let inclusion_height = u32::from(self.relay_header(inclusion_block).await?.number());
+		self.query_finalized_parachain_headers_with_proof(
+			inclusion_height.saturating_sub(1),
+			inclusion_height,
+			None,
+			vec![para_block_number],
+		)
+		.await
+	}
+
+	/// Proves the latest finalized parachain header at `latest_finalized_height`. The header is
+	/// read from `Paras::Heads` at the finalized relay chain block, rather than from the changes to
+	/// it within the range, so it's proven even if the parachain didn't produce a new header since
+	/// `previous_finalized_height`, which is returned as [`LatestParaHeader::Stalled`]. Fails with
+	/// [`ProverError::StorageItemMissing`] if the parachain has no head to prove.
+	pub async fn query_latest_finalized_parachain_header_with_proof<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+	) -> Result<LatestParaHeader<ParachainHeadersWithFinalityProof<H>>, ProverError>
+	where
+		H: Header + codec::Decode,
+		T::Hash: From<H256>,
+	{
+		let (finality_proof, _) = self
+			.finality_proof_for_range::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+			)
+			.await?;
+		let latest_finalized_hash = T::Hash::from(finality_proof.block);
+		let latest_head = self.para_head_at(latest_finalized_hash).await?.ok_or_else(|| {
+			ProverError::StorageItemMissing { item: "Paras::Heads", at: finality_proof.block }
+		})?;
+		let previous_finalized_hash = self
+			.request(|| self.relay_client.rpc().block_hash(Some(previous_finalized_height.into())))
+			.await?
+			.ok_or_else(|| {
+				anyhow!("Block hash not found for number: {previous_finalized_height}")
+			})?;
+		let previous_head = self.para_head_at(previous_finalized_hash).await?;
+
+		let para_header = T::Header::decode(&mut &latest_head[..])?;
+		let parachain_headers =
+			[self.included_para_header_proofs(latest_finalized_hash, &para_header).await?]
+				.into_iter()
+				.collect();
+		let proof = ParachainHeadersWithFinalityProof { finality_proof, parachain_headers };
+
+		Ok(LatestParaHeader::classify(previous_head.as_deref(), &latest_head, proof))
+	}
+
+	/// Checks that the node still has the state of the relay chain block `at`, returning
+	/// [`ProverError::StatePruned`] if it has been pruned. This is the pre-flight check made before
+	/// assembling a proof, it reads the parachain's head at `at`, which fails like the proof's own
+	/// reads would. Nodes don't report pruned state with a dedicated error, so it's told apart by
+	/// the error message, see [`is_state_pruned`].
+	pub async fn ensure_state_available(&self, at: T::Hash) -> Result<(), ProverError> {
+		let key = self.para_head_storage_key(self.para_id);
+		self.request(|| self.relay_client.rpc().storage(&key.0, Some(at)))
+			.await
+			.map_err(|err| state_read_error(err, at.into()))?;
+
+		Ok(())
+	}
+
+	/// Returns how many blocks below the relay chain's latest finalized head the oldest state read
+	/// of a proof starting at the relay chain block `from` goes. The node has to keep the state of
+	/// at least that many blocks, see its `--state-pruning` setting, or the proof has to be
+	/// generated by an archive node.
+	pub async fn requires_archive_depth(&self, from: u32) -> Result<u32, ProverError> {
+		let (_, finalized_height) = self.latest_finalized_height().await?;
+
+		Ok(u32::from(finalized_height).saturating_sub(from))
+	}
+
+	/// Returns the parachain's head data at the relay chain block `at`, read from the storage key
+	/// of its head, see [`Self::para_head_storage_key`].
+	async fn para_head_at(&self, at: T::Hash) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		let key = self.para_head_storage_key(self.para_id);
+		let head = self.request(|| self.relay_client.rpc().storage(&key.0, Some(at))).await?;
+		let head = head.map(|head| Vec::<u8>::decode(&mut &head.0[..])).transpose()?;
+
+		Ok(head)
+	}
+
+	/// Returns the number of the relay parent of the given parachain block, the parachain block
+	/// can only have been included in a relay chain block after it.
+	async fn relay_parent_number(&self, para_hash: T::Hash) -> Result<u32, ProverError> {
+		let validation_data = parachain::api::storage().parachain_system().validation_data();
+		let validation_data = self
+			.para_client
+			.storage()
+			.at(Some(para_hash))
+			.await?
+			.fetch(&validation_data)
+			.await?
+			.ok_or_else(|| ProverError::StorageItemMissing {
+				item: "ParachainSystem::ValidationData",
+				at: para_hash.into(),
+			})?;
+
+		Ok(validation_data.relay_parent_number)
+	}
+
+	/// Returns the `(relay block, Paras::Heads storage value)` pairs of the parachain's head
+	/// changes in the relay chain range `from..=to`.
+	async fn para_head_changes(
+		&self,
+		from: u32,
+		to: u32,
+	) -> Result<Vec<(T::Hash, Option<Vec<u8>>)>, ProverError> {
+		let para_storage_key = self.para_head_storage_key(self.para_id);
+		let change_set = self.query_storage_range(&[para_storage_key.as_ref()], from, to).await?;
+		let changes = change_set
+			.into_iter()
+			.flat_map(|changes| {
+				let block = changes.block;
+				changes
+					.changes
+					.into_iter()
+					.map(move |(_, data)| (block, data.map(|data| data.0)))
+			})
+			.collect();
+
+		Ok(changes)
+	}
+
+	/// Waits until the parachain header at `target_para_height` is finalized, polling the relay
+	/// chain every `poll_interval`, then proves it as in [`Self::prove_para_header_by_hash`].
+	/// Returns [`ProverError::Timeout`] if it isn't finalized within `timeout`.
+	pub async fn prove_when_finalized<H>(
+		&self,
+		target_para_height: T::BlockNumber,
+		poll_interval: Duration,
+		timeout: Duration,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output> + From<H256>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let target_para_height = u32::from(target_para_height);
+		wait_for_para_finality(target_para_height, poll_interval, timeout, move || async move {
+			let finalized_hash = self.request(|| self.relay_client.rpc().finalized_head()).await?;
+			let finalized_height = u32::from(self.relay_header(finalized_hash).await?.number());
+			let para_header =
+				self.query_latest_finalized_parachain_header(finalized_height).await?;
+			Ok(u32::from(para_header.number()))
+		})
+		.await?;
+
+		let para_hash = self
+			.request(|| self.para_client.rpc().block_hash(Some(target_para_height.into())))
+			.await?
+			.ok_or_else(|| anyhow!("Parachain block hash not found for: {target_para_height}"))?;
+
+		self.prove_para_header_by_hash(para_hash.into()).await
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], but the relay chain ancestry
+	/// is only collected back to the given snapshot if it's more recent than
+	/// `previous_finalized_height`. The client must be re-anchored at the snapshot using
+	/// [`SnapshotAnchor::anchor`] before verifying the proof.
+	pub async fn query_finalized_parachain_headers_with_proof_from_snapshot<H>(
+		&self,
+		anchor: &SnapshotAnchor,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let snapshot_hash =
+			self.relay_client
+				.rpc()
+				.block_hash(Some(anchor.height.into()))
+				.await?
+				.ok_or_else(|| anyhow!("Block hash not found for snapshot: {}", anchor.height))?;
+		if H256::from(snapshot_hash) != anchor.hash {
+			Err(anyhow!(
+				"Snapshot at {} is not in the canonical chain, expected: {:?}, found: {:?}",
+				anchor.height,
+				anchor.hash,
+				H256::from(snapshot_hash)
+			))?
+		}
+
+		self.query_finalized_parachain_headers_with_proof(
+			previous_finalized_height.max(anchor.height),
+			latest_finalized_height,
+			latest_justification,
+			header_numbers,
+		)
+		.await
+	}
+
+	/// Proves the given parachain headers from `from` to `to`, including proofs for every authority
+	/// set change in between. Each parachain header is included in the step whose relay chain range
+	/// it was included in.
+	pub async fn prove_with_set_changes<H>(
+		&self,
+		from: u32,
+		to: u32,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<Vec<ProofStep<ParachainHeadersWithFinalityProof<H>>>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let session_length = self.session_length().await?;
+		let (.., session_end_block) = self.session_start_and_end_for_block(from).await?;
+
+		let mut steps = vec![];
+		for step in proof_step_ranges(from, to, session_end_block, session_length) {
+			let step = match step {
+				ProofStep::SetChange((previous, latest)) => ProofStep::SetChange(
+					self.query_finalized_parachain_headers_with_proof(
+						previous,
+						latest,
+						None,
+						header_numbers.clone(),
+					)
+					.await?,
+				),
+				ProofStep::Headers((previous, latest)) => ProofStep::Headers(
+					self.query_finalized_parachain_headers_with_proof(
+						previous,
+						latest,
+						None,
+						header_numbers.clone(),
+					)
+					.await?,
+				),
+			};
+			steps.push(step);
+		}
+
+		Ok(steps)
+	}
+
+	/// Proves the next update for a client at `client_state`, including every parachain header
+	/// finalized since the client's latest parachain height. The update goes up to the relay
+	/// chain's latest finalized head, unless an authority set change signalled in between is
+	/// enacted by then. It then ends at the last block finalized by the client's set, and the next
+	/// call continues from there with the rotated set, so callers loop until `None` to catch up
+	/// across several set changes. `None` is also returned while the next update would end between
+	/// the signal of a set change and the last block of the set it replaces, which the client
+	/// rejects, see [`primitives::helpers::pending_set_change`].
+	///
+	/// Set changes are read from the digests of the relay chain headers above the client's latest
+	/// relay chain block, which must still be canonical, or [`ProverError::Reorg`] is returned. The
+	/// proof converts into the ics10-grandpa `Header` for submission.
+	pub async fn prove_next_update<H>(
+		&self,
+		client_state: &ClientState,
+	) -> Result<Option<ParachainHeadersWithFinalityProof<H>>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One + From<u32>,
+	{
+		let finalized_hash = self.request(|| self.relay_client.rpc().finalized_head()).await?;
+		let finalized_height = u32::from(self.relay_header(finalized_hash).await?.number());
+		let from = client_state.latest_relay_height;
+		if finalized_height <= from {
+			return Ok(None)
+		}
+
+		let headers = self.walk_relay_chain(from, finalized_height).await?;
+		// the client's latest relay block must still be canonical, or its cursor needs a reset
+		ensure_descends_from(&headers, client_state.latest_relay_hash)?;
+		// the update ends at the first block whose successors are finalized by the next set
+		let latest_finalized_height = headers
+			.iter()
+			.filter(|header| header.number > from)
+			.flat_map(authority_set_changes)
+			.map(|change| change.last_block_of_previous_set())
+			.filter(|last_block| *last_block > from)
+			.min()
+			.map_or(finalized_height, |last_block| last_block.min(finalized_height));
+		let target = headers
+			.iter()
+			.find(|header| header.number == latest_finalized_height)
+			.map(sp_runtime::traits::Header::hash)
+			.ok_or_else(|| anyhow!("Missing relay chain header {latest_finalized_height}"))?;
+		if primitives::helpers::pending_set_change(
+			client_state,
+			&headers,
+			target,
+			latest_finalized_height,
+		)
+		.is_some()
+		{
+			return Ok(None)
+		}
+
+		let finalized_para_header =
+			self.query_latest_finalized_parachain_header(latest_finalized_height).await?;
+		let latest_para_height = u32::from(finalized_para_header.number());
+		// notice the inclusive range
+		let header_numbers = ((client_state.latest_para_height + 1)..=latest_para_height)
+			.map(T::BlockNumber::from)
+			.collect();
+
+		let proof = self
+			.query_finalized_parachain_headers_with_proof(
+				from,
+				latest_finalized_height,
+				None,
+				header_numbers,
+			)
+			.await?;
+
+		Ok(Some(proof))
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], but also proves the
+	/// timestamp of the finalized relay chain block.
+	pub async fn query_finalized_parachain_headers_with_relay_timestamp<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<(ParachainHeadersWithFinalityProof<H>, RelayTimestampProof), ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output> + From<H256>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let proof = self
+			.query_finalized_parachain_headers_with_proof::<H>(
+				previous_finalized_height,
+				latest_finalized_height,
+				latest_justification,
+				header_numbers,
+			)
+			.await?;
+		let relay_timestamp = self.query_relay_timestamp_proof(proof.finality_proof.block).await?;
+
+		Ok((proof, relay_timestamp))
+	}
+
+	/// Proves the timestamp inherent of the given relay chain block.
+	pub async fn query_relay_timestamp_proof(
+		&self,
+		block: H256,
+	) -> Result<RelayTimestampProof, ProverError>
+	where
+		T::Hash: From<H256>,
+	{
+		let (extrinsic_index, TimeStampExtWithProof { ext: extrinsic, proof: extrinsic_proof }) =
+			self.timestamp_inherent_with_proof(&self.relay_client, block.into(), None)
+				.await?;
+
+		Ok(RelayTimestampProof { block, extrinsic, extrinsic_proof, extrinsic_index })
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], but returns a
+	/// [`ProofOutcome`] telling apart proofs worth submitting from requests with nothing to prove.
+	pub async fn prove_finalized_parachain_headers<H>(
+		&self,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<ProofOutcome<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let outcome = ProofOutcome::classify(
+			previous_finalized_height,
+			latest_finalized_height,
+			|| async move {
+				let proof = self
+					.query_finalized_parachain_headers_with_proof(
+						previous_finalized_height,
+						latest_finalized_height,
+						latest_justification,
+						header_numbers,
+					)
+					.await?;
+				Ok::<_, anyhow::Error>(proof)
+			},
+		)
+		.await?;
+
+		Ok(outcome)
+	}
+
+	/// Same as [`Self::query_finalized_parachain_headers_with_proof`], but first checks that the
+	/// node agrees with the pinned authority set at `previous_finalized_height`, which is where the
+	/// client was last updated.
+	pub async fn query_finalized_parachain_headers_with_proof_pinned<H>(
+		&self,
+		pin: &AuthoritySetPin,
+		previous_finalized_height: u32,
+		latest_finalized_height: u32,
+		latest_justification: Option<Vec<u8>>,
+		header_numbers: Vec<T::BlockNumber>,
+	) -> Result<ParachainHeadersWithFinalityProof<H>, ProverError>
+	where
+		H: Header + codec::Decode,
+		u32: From<<H as Header>::Number>,
+		<H::Hasher as subxt::config::Hasher>::Output: From<T::Hash>,
+		T::Hash: From<<H::Hasher as subxt::config::Hasher>::Output>,
+		H::Number: finality_grandpa::BlockNumberOps,
+		T::BlockNumber: One,
+	{
+		let hash = self
+			.relay_client
+			.rpc()
+			.block_hash(Some(previous_finalized_height.into()))
+			.await?
+			.ok_or_else(|| anyhow!("Block hash not found for: {previous_finalized_height}"))?;
+		let (set_id, authorities) = self.authority_set_at(hash).await?;
+		ensure_authority_set(pin, set_id, &authorities)?;
+
+		self.query_finalized_parachain_headers_with_proof(
+			previous_finalized_height,
+			latest_finalized_height,
+			latest_justification,
+			header_numbers,
+		)
+		.await
+	}
+
+	/// Detects the authority set change and runtime upgrade enacted by the given relay chain block.
+	pub async fn relay_block_event(
+		&self,
+		hash: T::Hash,
+	) -> Result<Option<RelayBlockEvent>, ProverError>
+	where
+		T::Hash: From<H256>,
+	{
+		let header = self.relay_header(hash).await?;
+		let relay_header = RelayHeader::decode(&mut &header.encode()[..])?;
+		let spec_version = self
+			.request(|| self.relay_client.rpc().runtime_version(Some(hash)))
+			.await?
+			.spec_version;
+		let parent_spec_version = self
+			.request(|| {
+				self.relay_client.rpc().runtime_version(Some(relay_header.parent_hash.into()))
+			})
+			.await?
+			.spec_version;
+
+		Ok(RelayBlockEvent::detect(&relay_header, parent_spec_version, spec_version))
+	}
+
+	/// Queries the block at which the epoch for the given block belongs to ends.
+	pub async fn session_start_and_end_for_block(
+		&self,
 		block: u32,
-	) -> Result<(u32, u32), anyhow::Error> {
+	) -> Result<(u32, u32), ProverError> {
 		let epoch_addr = polkadot::api::storage().babe().epoch_start();
 		let block_hash = self.relay_client.rpc().block_hash(Some(block.into())).await?;
 		let (previous_epoch_start, current_epoch_start) = self
 			.relay_client
 			.storage()
 			.at(block_hash)
-			.await
-			.expect("Storage client")
+			.await?
 			.fetch(&epoch_addr)
 			.await?
 			.ok_or_else(|| anyhow!("Failed to fetch epoch information"))?;
@@ -369,9 +3534,12 @@ where
 	}
 
 	/// Returns the session length in blocks
-	pub async fn session_length(&self) -> Result<u32, anyhow::Error> {
+	pub async fn session_length(&self) -> Result<u32, ProverError> {
 		let metadata = self.relay_client.rpc().metadata(None).await?;
-		let metadata = metadata.pallet("Babe")?.constant("EpochDuration")?;
+		let metadata = metadata
+			.pallet("Babe")
+			.and_then(|pallet| pallet.constant("EpochDuration"))
+			.map_err(subxt::error::Error::from)?;
 		Ok(Decode::decode(&mut &metadata.value[..])?)
 	}
 }
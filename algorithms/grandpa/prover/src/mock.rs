@@ -0,0 +1,304 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory chains served over a mock rpc transport, so the prover can be tested and benchmarked
+//! without a node. Only the rpc methods the prover calls while generating proofs are served, the
+//! runtime metadata has no pallets.
+
+use crate::{GrandpaProver, ProverConfig, ProverError, RelayHeader};
+use async_trait::async_trait;
+use jsonrpsee::{
+	async_client::{Client, ClientBuilder},
+	core::client::{ReceivedMessage, TransportReceiverT, TransportSenderT},
+};
+use serde_json::{json, Value};
+use sp_core::{Bytes, H256};
+use sp_runtime::{
+	traits::{BlakeTwo256, Header as _},
+	Digest,
+};
+use sp_trie::{LayoutV0, MemoryDB, TrieConfiguration, TrieDBMutBuilder, TrieMut};
+use std::{
+	collections::{BTreeMap, HashMap},
+	sync::Arc,
+};
+use subxt::Config;
+use tokio::sync::mpsc;
+
+/// SCALE encoded `RuntimeMetadataPrefixed` of a runtime without pallets or types.
+const EMPTY_METADATA: [u8; 11] = [b'm', b'e', b't', b'a', 14, 0, 0, 0, 4, 0, 0];
+
+/// A block of a [`MockChain`], along with its state and extrinsics.
+struct MockBlock {
+	header: RelayHeader,
+	state: BTreeMap<Vec<u8>, Vec<u8>>,
+	db: MemoryDB<BlakeTwo256>,
+	extrinsics: Vec<Vec<u8>>,
+}
+
+/// A chain of blocks starting at genesis, all of which are final. Each block holds the whole of
+/// its state, which its state root is computed over.
+pub struct MockChain {
+	blocks: Vec<MockBlock>,
+	hashes: HashMap<H256, usize>,
+	finality_proofs: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Default for MockChain {
+	fn default() -> Self {
+		let mut chain =
+			Self { blocks: vec![], hashes: HashMap::new(), finality_proofs: BTreeMap::new() };
+		chain.push_block(vec![], vec![]);
+		chain
+	}
+}
+
+impl MockChain {
+	/// Appends a block with the given state and extrinsics, returning its header. The extrinsics
+	/// are SCALE encoded along with their length, as in a block body.
+	pub fn push_block(
+		&mut self,
+		state: Vec<(Vec<u8>, Vec<u8>)>,
+		extrinsics: Vec<Vec<u8>>,
+	) -> RelayHeader {
+		self.push_block_with_digest(state, extrinsics, Default::default())
+	}
+
+	/// Same as [`Self::push_block`], with the given digest in the block's header.
+	pub fn push_block_with_digest(
+		&mut self,
+		state: Vec<(Vec<u8>, Vec<u8>)>,
+		extrinsics: Vec<Vec<u8>>,
+		digest: Digest,
+	) -> RelayHeader {
+		let state = state.into_iter().collect::<BTreeMap<_, _>>();
+		let mut db = MemoryDB::default();
+		let mut state_root = H256::default();
+		{
+			let mut trie =
+				TrieDBMutBuilder::<LayoutV0<BlakeTwo256>>::new(&mut db, &mut state_root).build();
+			for (key, value) in &state {
+				trie.insert(key, value).expect("In-memory trie doesn't fail; qed");
+			}
+		}
+
+		let parent_hash = self.blocks.last().map(|block| block.header.hash()).unwrap_or_default();
+		let header = RelayHeader::new(
+			self.blocks.len() as u32,
+			LayoutV0::<BlakeTwo256>::ordered_trie_root(&extrinsics),
+			state_root,
+			parent_hash,
+			digest,
+		);
+		self.hashes.insert(header.hash(), self.blocks.len());
+		self.blocks.push(MockBlock { header: header.clone(), state, db, extrinsics });
+
+		header
+	}
+
+	/// Serves `finality_proof`, SCALE encoded, as the finality proof of the block `number`.
+	pub fn set_finality_proof(&mut self, number: u32, finality_proof: Vec<u8>) {
+		self.finality_proofs.insert(number, finality_proof);
+	}
+
+	/// The headers of the chain, from genesis up.
+	pub fn headers(&self) -> impl Iterator<Item = &RelayHeader> {
+		self.blocks.iter().map(|block| &block.header)
+	}
+
+	/// Connects a client to the chain.
+	pub fn client(self: Arc<Self>) -> Client {
+		let (responses, receiver) = mpsc::unbounded_channel();
+		ClientBuilder::default()
+			.build_with_tokio(MockSender { chain: self, responses }, MockReceiver(receiver))
+	}
+
+	fn block(&self, hash: Option<H256>) -> Option<&MockBlock> {
+		match hash {
+			Some(hash) => self.hashes.get(&hash).map(|index| &self.blocks[*index]),
+			None => self.blocks.last(),
+		}
+	}
+
+	fn read_proof(block: &MockBlock, keys: &[Bytes]) -> Result<Vec<Bytes>, String> {
+		let backend =
+			sp_state_machine::TrieBackendBuilder::new(block.db.clone(), block.header.state_root)
+				.build();
+		let proof = sp_state_machine::prove_read(backend, keys.iter().map(|key| &key[..]))
+			.map_err(|err| err.to_string())?;
+		Ok(proof.into_iter_nodes().map(Bytes).collect())
+	}
+
+	/// The changes to `keys` in the blocks `from..=to`, as returned by `state_queryStorage`. The
+	/// values at `from` are always included.
+	fn query_storage(&self, keys: &[Bytes], from: usize, to: usize) -> Value {
+		let mut previous = None::<&MockBlock>;
+		let mut change_sets = vec![];
+		for block in &self.blocks[from..=to] {
+			let changes = keys
+				.iter()
+				.filter_map(|key| {
+					let value = block.state.get(&key.0);
+					let changed =
+						previous.map_or(true, |previous| previous.state.get(&key.0) != value);
+					changed.then(|| json!([key, value.cloned().map(Bytes)]))
+				})
+				.collect::<Vec<_>>();
+			if !changes.is_empty() {
+				change_sets.push(json!({ "block": block.header.hash(), "changes": changes }));
+			}
+			previous = Some(block);
+		}
+
+		Value::Array(change_sets)
+	}
+
+	/// Answers the rpc request `method`.
+	fn respond(&self, method: &str, params: &[Value]) -> Result<Value, String> {
+		let param = |index: usize| params.get(index).cloned().unwrap_or(Value::Null);
+		let hash = |index: usize| {
+			serde_json::from_value::<Option<H256>>(param(index)).map_err(|err| err.to_string())
+		};
+		let keys = |index: usize| {
+			serde_json::from_value::<Vec<Bytes>>(param(index)).map_err(|err| err.to_string())
+		};
+		let number = |index: usize| match param(index) {
+			Value::Null => Ok(None),
+			Value::Number(number) => Ok(number.as_u64()),
+			Value::String(hex) => u64::from_str_radix(hex.trim_start_matches("0x"), 16)
+				.map(Some)
+				.map_err(|err| err.to_string()),
+			value => Err(format!("Invalid block number: {value}")),
+		};
+		let block_number = |hash: Option<H256>| {
+			self.block(hash)
+				.map(|block| block.header.number as usize)
+				.ok_or_else(|| format!("Unknown block: {hash:?}"))
+		};
+
+		let result = match method {
+			"chain_getBlockHash" => {
+				let block = match number(0)? {
+					Some(number) => self.blocks.get(number as usize),
+					None => self.blocks.last(),
+				};
+				json!(block.map(|block| block.header.hash()))
+			},
+			"chain_getFinalizedHead" => json!(self.block(None).map(|block| block.header.hash())),
+			"chain_getHeader" => json!(self.block(hash(0)?).map(|block| &block.header)),
+			"chain_getBlock" => json!(self.block(hash(0)?).map(|block| json!({
+				"block": {
+					"header": block.header,
+					"extrinsics": block
+						.extrinsics
+						.iter()
+						.map(|extrinsic| Bytes(extrinsic.clone()))
+						.collect::<Vec<_>>(),
+				},
+				"justifications": null,
+			}))),
+			"state_getRuntimeVersion" => json!({ "specVersion": 0, "transactionVersion": 0 }),
+			"state_getMetadata" => json!(Bytes(EMPTY_METADATA.to_vec())),
+			"state_getStorage" => {
+				let key =
+					serde_json::from_value::<Bytes>(param(0)).map_err(|err| err.to_string())?;
+				let block = self.block(hash(1)?).ok_or("Unknown block")?;
+				json!(block.state.get(&key.0).cloned().map(Bytes))
+			},
+			"state_getReadProof" => {
+				let block = self.block(hash(1)?).ok_or("Unknown block")?;
+				json!({ "at": block.header.hash(), "proof": Self::read_proof(block, &keys(0)?)? })
+			},
+			"state_queryStorage" => {
+				let from = block_number(hash(1)?)?;
+				let to = block_number(hash(2)?)?;
+				self.query_storage(&keys(0)?, from, to)
+			},
+			"grandpa_proveFinality" => json!(number(0)?
+				.and_then(|number| self.finality_proofs.get(&(number as u32)))
+				.cloned()
+				.map(Bytes)),
+			method => return Err(format!("Method not found: {method}")),
+		};
+
+		Ok(result)
+	}
+}
+
+/// Sends the requests of a client to a [`MockChain`], queueing its responses for the
+/// [`MockReceiver`].
+struct MockSender {
+	chain: Arc<MockChain>,
+	responses: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait]
+impl TransportSenderT for MockSender {
+	type Error = std::io::Error;
+
+	async fn send(&mut self, msg: String) -> Result<(), Self::Error> {
+		let request = serde_json::from_str::<Value>(&msg)?;
+		let params = request["params"].as_array().cloned().unwrap_or_default();
+		let response =
+			match self.chain.respond(request["method"].as_str().unwrap_or_default(), &params) {
+				Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+				Err(message) => json!({
+					"jsonrpc": "2.0",
+					"id": request["id"],
+					"error": { "code": -32000, "message": message },
+				}),
+			};
+
+		self.responses.send(response.to_string()).map_err(|_| {
+			std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Mock receiver dropped")
+		})
+	}
+}
+
+/// Receives the responses of a [`MockChain`].
+struct MockReceiver(mpsc::UnboundedReceiver<String>);
+
+#[async_trait]
+impl TransportReceiverT for MockReceiver {
+	type Error = std::io::Error;
+
+	async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+		self.0.recv().await.map(ReceivedMessage::Text).ok_or_else(|| {
+			std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Mock sender dropped")
+		})
+	}
+}
+
+/// Creates a prover of the parachain `para_id` on `relay_chain`, with its
+/// [`GrandpaProver::grandpa_rpc`] served by `relay_chain` as well.
+pub async fn mock_prover<T>(
+	relay_chain: MockChain,
+	para_chain: MockChain,
+	para_id: u32,
+) -> Result<GrandpaProver<T>, ProverError>
+where
+	T: Config,
+	T::BlockNumber: Ord + sp_runtime::traits::Zero,
+	u32: From<T::BlockNumber>,
+	H256: From<T::Hash>,
+{
+	GrandpaProver::from_clients(
+		Arc::new(Arc::new(relay_chain).client()),
+		Arc::new(Arc::new(para_chain).client()),
+		para_id,
+		ProverConfig::default(),
+	)
+	.await
+}
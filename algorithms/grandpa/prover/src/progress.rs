@@ -0,0 +1,73 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex};
+
+/// Called with the progress of each proof the prover generates, see [`ProverProgress`].
+pub type ProgressHook = Arc<dyn Fn(ProverProgress) + Send + Sync>;
+
+/// Progress of a proof being generated, reported as the prover processes the change sets of the
+/// relay chain range and builds the proofs of the parachain headers in it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProverProgress {
+	/// The relay chain height the prover has processed the change sets up to.
+	pub relay_height: u32,
+	/// Number of parachain headers collected for proving so far.
+	pub headers_collected: usize,
+	/// Number of parachain header proofs built so far.
+	pub proofs_built: usize,
+}
+
+/// Accumulates the progress of a single proof, reporting it to the hook on every update. The
+/// reported relay chain height never decreases.
+pub struct ProgressReporter {
+	hook: Option<ProgressHook>,
+	progress: Mutex<ProverProgress>,
+}
+
+impl ProgressReporter {
+	/// Reports to `hook`, if any.
+	pub fn new(hook: Option<ProgressHook>) -> Self {
+		Self { hook, progress: Mutex::new(ProverProgress::default()) }
+	}
+
+	/// The change sets were processed up to `relay_height`.
+	pub fn relay_height(&self, relay_height: u32) {
+		self.update(|progress| progress.relay_height = progress.relay_height.max(relay_height))
+	}
+
+	/// Another parachain header was collected.
+	pub fn header_collected(&self) {
+		self.update(|progress| progress.headers_collected += 1)
+	}
+
+	/// Another parachain header proof was built.
+	pub fn proof_built(&self) {
+		self.update(|progress| progress.proofs_built += 1)
+	}
+
+	fn update(&self, update: impl FnOnce(&mut ProverProgress)) {
+		let hook = match &self.hook {
+			Some(hook) => hook,
+			None => return,
+		};
+		let progress = {
+			let mut progress = self.progress.lock().unwrap_or_else(|err| err.into_inner());
+			update(&mut progress);
+			*progress
+		};
+		hook(progress)
+	}
+}
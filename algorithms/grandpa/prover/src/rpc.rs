@@ -0,0 +1,483 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{JustificationNotification, ProverError};
+use async_trait::async_trait;
+use beefy_prover::helpers::unsafe_arc_cast;
+use finality_grandpa_rpc::GrandpaApiClient;
+use futures::{future::Either, Stream, StreamExt, TryStreamExt};
+use jsonrpsee::{
+	async_client::{Client, ClientBuilder},
+	client_transport::ws::{InvalidUri, Uri, WsTransportClientBuilder},
+	core::client::{ReceivedMessage, TransportReceiverT},
+};
+use sp_core::H256;
+use std::{
+	future::Future,
+	num::NonZeroU32,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	time::Duration,
+};
+use tokio::{
+	sync::{mpsc, watch, Mutex},
+	task::JoinHandle,
+	time::Instant,
+};
+
+/// Stream of SCALE encoded GRANDPA justifications.
+pub type JustificationStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, anyhow::Error>> + Send>>;
+
+/// The GRANDPA rpc methods the prover depends on, implement this to use a transport other than
+/// jsonrpsee over WebSocket.
+#[async_trait]
+pub trait GrandpaRpc: Send + Sync {
+	/// Returns the SCALE encoded finality proof for the given relay chain block, if any.
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error>;
+
+	/// Subscribes to the justifications of newly finalized relay chain blocks.
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error>;
+}
+
+/// [`GrandpaRpc`] over a jsonrpsee WebSocket client, this is what the prover uses by default.
+pub struct WsGrandpaRpc(pub Arc<Client>);
+
+impl WsGrandpaRpc {
+	fn client(&self) -> Arc<jsonrpsee_ws_client::WsClient> {
+		// we cast between the same type but different crate versions.
+		unsafe { unsafe_arc_cast::<_, jsonrpsee_ws_client::WsClient>(self.0.clone()) }
+	}
+}
+
+#[async_trait]
+impl GrandpaRpc for WsGrandpaRpc {
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		let proof = GrandpaApiClient::<JustificationNotification, H256, u32>::prove_finality(
+			&*self.client(),
+			block,
+		)
+		.await
+		.map_err(|err| match err {
+			// the node rejected the request, it would do so again.
+			jsonrpsee::core::Error::Call(_) => anyhow::Error::from(err),
+			err => ProverError::Transport { reason: err.to_string() }.into(),
+		})?;
+
+		Ok(proof.map(JustificationNotification::into_inner))
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		let subscription =
+			GrandpaApiClient::<JustificationNotification, H256, u32>::subscribe_justifications(
+				&*self.client(),
+			)
+			.await?;
+
+		let stream = subscription.map(|notification| {
+			notification
+				.map(JustificationNotification::into_inner)
+				.map_err(anyhow::Error::from)
+		});
+
+		Ok(Box::pin(stream))
+	}
+}
+
+/// Delay between attempts to resubscribe to justifications, see [`resubscribing_justifications`].
+pub const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Consecutive failed attempts to resubscribe to justifications before giving up.
+pub const MAX_RESUBSCRIBE_ATTEMPTS: u32 = 10;
+
+/// Subscribes to justifications through `rpc`, resubscribing after `retry_delay` whenever the
+/// subscription fails or is closed by the node, so transient socket errors don't end the stream.
+/// Justifications of blocks finalized while resubscribing are missed. Yields the last error and
+/// ends after `max_retries` consecutive failures without receiving a justification.
+pub fn resubscribing_justifications(
+	rpc: Arc<dyn GrandpaRpc>,
+	retry_delay: Duration,
+	max_retries: u32,
+) -> JustificationStream {
+	let stream = futures::stream::unfold(Some((None, 0)), move |state| {
+		let rpc = rpc.clone();
+		async move {
+			let (mut subscription, mut failures): (Option<JustificationStream>, u32) = state?;
+			loop {
+				let err = match subscription.as_mut() {
+					Some(stream) => match stream.next().await {
+						Some(Ok(justification)) =>
+							return Some((Ok(justification), Some((subscription, 0)))),
+						Some(Err(err)) => err,
+						None => anyhow::anyhow!("Justification subscription closed by the node"),
+					},
+					None => match rpc.subscribe_justifications().await {
+						Ok(stream) => {
+							subscription = Some(stream);
+							continue
+						},
+						Err(err) => err,
+					},
+				};
+
+				if failures == max_retries {
+					return Some((Err(err), None))
+				}
+				subscription = None;
+				failures += 1;
+				tokio::time::sleep(retry_delay).await;
+			}
+		}
+	});
+
+	Box::pin(stream)
+}
+
+/// Keeps track of the open justification subscriptions, so they can be closed together. Clones
+/// track the same subscriptions.
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+	tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Subscriptions {
+	/// Drives the subscription in the background until it's closed by [`Self::close_all`] or the
+	/// returned stream is dropped.
+	pub async fn track(&self, mut subscription: JustificationStream) -> JustificationStream {
+		let (sender, receiver) = mpsc::unbounded_channel();
+		let task = tokio::spawn(async move {
+			while let Some(item) = subscription.next().await {
+				if sender.send(item).is_err() {
+					break
+				}
+			}
+		});
+		let mut tasks = self.tasks.lock().await;
+		// subscriptions that have ended or whose stream was dropped need no closing.
+		tasks.retain(|task| !task.is_finished());
+		tasks.push(task);
+		drop(tasks);
+
+		let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+			receiver.recv().await.map(|item| (item, receiver))
+		});
+		Box::pin(stream)
+	}
+
+	/// Unsubscribes all tracked subscriptions, waiting until the underlying streams are dropped.
+	pub async fn close_all(&self) {
+		let tasks = self.tasks.lock().await.drain(..).collect::<Vec<_>>();
+		for task in tasks {
+			task.abort();
+			// the task is either cancelled or has already completed.
+			let _ = task.await;
+		}
+	}
+}
+
+/// Closes the WebSocket connections made through it, see [`connect`]. Clones close the same
+/// connections.
+#[derive(Clone, Debug)]
+pub struct Connections {
+	closed: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Connections {
+	fn default() -> Self {
+		Self { closed: Arc::new(watch::channel(false).0) }
+	}
+}
+
+impl Connections {
+	/// Closes the connections, requests made through them fail from then on.
+	pub fn close(&self) {
+		self.closed.send_replace(true);
+	}
+
+	/// Wraps the receiving half of a connection, so it's closed along with the others.
+	pub(crate) fn closable<R>(&self, receiver: R) -> ClosableReceiver<R> {
+		ClosableReceiver { receiver, closed: self.closed.subscribe() }
+	}
+}
+
+/// Connects to the node at the ws `url`, giving up after `timeout` if set. The connection is
+/// closed once `connections` are, or once the returned client is dropped.
+pub async fn connect(
+	url: &str,
+	timeout: Option<Duration>,
+	connections: &Connections,
+) -> Result<Client, jsonrpsee::core::Error> {
+	let uri: Uri = url
+		.parse()
+		.map_err(|err: InvalidUri| jsonrpsee::core::Error::Transport(err.into()))?;
+	let mut builder = WsTransportClientBuilder::default();
+	if let Some(timeout) = timeout {
+		builder = builder.connection_timeout(timeout);
+	}
+	let (sender, receiver) = builder.build(uri).await?;
+
+	Ok(ClientBuilder::default().build_with_tokio(sender, connections.closable(receiver)))
+}
+
+/// Error receiving from a connection made by [`connect`].
+#[derive(Debug, derive_more::Display)]
+pub enum ReceiveError<E> {
+	/// The connection was closed by [`Connections::close`].
+	#[display(fmt = "Connection closed by the prover")]
+	Closed,
+	/// Error in the underlying connection.
+	#[display(fmt = "{}", _0)]
+	Transport(E),
+}
+
+impl<E: std::error::Error> std::error::Error for ReceiveError<E> {}
+
+/// Receives from the connection until its [`Connections`] are closed. The client stops once
+/// receiving fails, which closes the connection.
+pub(crate) struct ClosableReceiver<R> {
+	receiver: R,
+	closed: watch::Receiver<bool>,
+}
+
+#[async_trait]
+impl<R: TransportReceiverT + Send> TransportReceiverT for ClosableReceiver<R> {
+	type Error = ReceiveError<R::Error>;
+
+	async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+		let Self { receiver, closed } = self;
+		let closed = async {
+			while !*closed.borrow() {
+				if closed.changed().await.is_err() {
+					// the connections can no longer be closed, so keep receiving.
+					futures::future::pending::<()>().await
+				}
+			}
+		};
+		let message = receiver.receive();
+		futures::pin_mut!(closed, message);
+
+		match futures::future::select(closed, message).await {
+			Either::Left(_) => Err(ReceiveError::Closed),
+			Either::Right((message, _)) => message.map_err(ReceiveError::Transport),
+		}
+	}
+}
+
+/// Timeouts for the prover's rpc requests, `None` waits for as long as the request takes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RpcTimeouts {
+	/// Applies to storage and header reads.
+	pub request: Option<Duration>,
+	/// Applies to `grandpa_proveFinality`, which is much slower than storage reads since the node
+	/// has to assemble the proof, so this is usually the longer of the two.
+	pub finality_proof: Option<Duration>,
+}
+
+impl RpcTimeouts {
+	/// Awaits a storage or header read, failing if it exceeds the request timeout.
+	pub async fn request<T, E>(
+		&self,
+		future: impl Future<Output = Result<T, E>>,
+	) -> Result<T, anyhow::Error>
+	where
+		anyhow::Error: From<E>,
+	{
+		with_timeout(self.request, future).await
+	}
+
+	/// Awaits a finality proof request, failing if it exceeds the finality proof timeout.
+	pub async fn finality_proof<T, E>(
+		&self,
+		future: impl Future<Output = Result<T, E>>,
+	) -> Result<T, anyhow::Error>
+	where
+		anyhow::Error: From<E>,
+	{
+		with_timeout(self.finality_proof, future).await
+	}
+}
+
+async fn with_timeout<T, E>(
+	timeout: Option<Duration>,
+	future: impl Future<Output = Result<T, E>>,
+) -> Result<T, anyhow::Error>
+where
+	anyhow::Error: From<E>,
+{
+	let result = match timeout {
+		Some(timeout) => tokio::time::timeout(timeout, future)
+			.await
+			.map_err(|_| ProverError::Timeout { timeout })?,
+		None => future.await,
+	};
+
+	Ok(result?)
+}
+
+/// Limits the rate of the prover's rpc requests by spacing them at least `interval` apart,
+/// requests made while the limit is exhausted wait their turn. Clones share the same limit.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+	interval: Duration,
+	next_request: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+	/// Allows at most `requests_per_second` requests each second.
+	pub fn per_second(requests_per_second: NonZeroU32) -> Self {
+		Self::with_interval(Duration::from_secs(1) / requests_per_second.get())
+	}
+
+	/// Allows at most one request every `interval`.
+	pub fn with_interval(interval: Duration) -> Self {
+		Self { interval, next_request: Arc::new(Mutex::new(Instant::now())) }
+	}
+
+	/// Waits until the limit allows another request.
+	pub async fn until_ready(&self) {
+		let ready_at = {
+			let mut next_request = self.next_request.lock().await;
+			let ready_at = (*next_request).max(Instant::now());
+			*next_request = ready_at + self.interval;
+			ready_at
+		};
+		tokio::time::sleep_until(ready_at).await;
+	}
+
+	/// Awaits the request once the limit allows it.
+	pub async fn limit<F: Future>(&self, request: F) -> F::Output {
+		self.until_ready().await;
+		request.await
+	}
+}
+
+/// Counts the prover's rpc requests. Clones share the same count.
+#[derive(Clone, Debug, Default)]
+pub struct RpcCallCounter(Arc<AtomicUsize>);
+
+impl RpcCallCounter {
+	/// Counts the request and awaits it.
+	pub async fn count<F: Future>(&self, request: F) -> F::Output {
+		self.0.fetch_add(1, Ordering::Relaxed);
+		request.await
+	}
+
+	/// Number of requests counted so far.
+	pub fn calls(&self) -> usize {
+		self.0.load(Ordering::Relaxed)
+	}
+}
+
+/// How the prover retries rpc reads that fail with transport errors, see [`retry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+	/// Number of times a failed request is retried, zero disables retries.
+	pub max_retries: u32,
+	/// Delay before the first retry, which doubles with every retry after it.
+	pub base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self { max_retries: 3, base_delay: Duration::from_millis(500) }
+	}
+}
+
+impl RetryPolicy {
+	/// Delay before the given retry, counting from zero. Up to half of the delay is added as
+	/// jitter, so that requests that failed together don't retry all at once. The delay saturates
+	/// at [`Duration::MAX`] rather than overflowing for large base delays.
+	pub fn delay(&self, retry: u32) -> Duration {
+		let delay = self.base_delay.saturating_mul(1 << retry.min(16));
+		// at most half of the delay, which can't overflow
+		let jitter = (delay / 2).mul_f64(rand::random::<f64>());
+		delay.saturating_add(jitter)
+	}
+}
+
+/// Whether a failed rpc request is worth retrying. Transport errors and timeouts are, while errors
+/// returned by the node and malformed responses aren't, since the node would respond the same way
+/// again.
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+	let retryable_subxt_error = |err: &subxt::Error| match err {
+		subxt::Error::Rpc(subxt::error::RpcError::ClientError(err)) => !matches!(
+			err.downcast_ref::<jsonrpsee::core::Error>(),
+			Some(jsonrpsee::core::Error::Call(_))
+		),
+		subxt::Error::Rpc(_) | subxt::Error::Io(_) => true,
+		_ => false,
+	};
+
+	err.chain().any(|err| match err.downcast_ref::<ProverError>() {
+		Some(ProverError::Transport { .. } | ProverError::Timeout { .. }) => true,
+		Some(ProverError::Subxt(err)) => retryable_subxt_error(err),
+		Some(_) => false,
+		None => err.downcast_ref::<subxt::Error>().map_or(false, retryable_subxt_error),
+	})
+}
+
+/// Makes `request` until it succeeds or fails with an error that isn't [retryable](is_retryable),
+/// backing off exponentially between at most [`RetryPolicy::max_retries`] retries.
+pub async fn retry<T, F, Fut>(policy: RetryPolicy, mut request: F) -> Result<T, anyhow::Error>
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<T, anyhow::Error>>,
+{
+	let mut retries = 0;
+	loop {
+		match request().await {
+			Err(err) if retries < policy.max_retries && is_retryable(&err) => {
+				tokio::time::sleep(policy.delay(retries)).await;
+				retries += 1;
+			},
+			result => return result,
+		}
+	}
+}
+
+/// Default number of rpc requests the prover keeps in flight while generating a proof.
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 10;
+
+/// Makes `request` for every item, with at most `max_concurrent` requests in flight at once.
+/// Returns the results in the order of the items, or the first error encountered.
+pub async fn concurrent_requests<I, F, Fut, T>(
+	items: I,
+	max_concurrent: usize,
+	request: F,
+) -> Result<Vec<T>, anyhow::Error>
+where
+	I: IntoIterator,
+	F: FnMut(I::Item) -> Fut,
+	Fut: Future<Output = Result<T, anyhow::Error>>,
+{
+	stream_requests(items, max_concurrent, request).try_collect().await
+}
+
+/// Same as [`concurrent_requests`], but the results are streamed in the order of the items as
+/// they come in, so that at most `max_concurrent` of them are held at once.
+pub fn stream_requests<I, F, Fut, T>(
+	items: I,
+	max_concurrent: usize,
+	request: F,
+) -> impl Stream<Item = Result<T, anyhow::Error>>
+where
+	I: IntoIterator,
+	F: FnMut(I::Item) -> Fut,
+	Fut: Future<Output = Result<T, anyhow::Error>>,
+{
+	futures::stream::iter(items).map(request).buffered(max_concurrent.max(1))
+}
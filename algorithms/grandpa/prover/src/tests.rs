@@ -0,0 +1,2879 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+	advance_checkpoint, authority_set_changes, authority_set_hash,
+	cache::SharedCache,
+	canonical_storage_proof, checked_block_number, decode_para_headers_page, decode_para_numbers,
+	decode_requested_para_header, ensure_authority_set, ensure_distinct_para_ids,
+	ensure_grandpa_available, ensure_head_fresh, ensure_no_set_change_within,
+	ensure_para_block_finalized, ensure_para_registered, ensure_proof_contains_key,
+	error::summarize_error,
+	fetch_finality_proof, fetch_latest_finalized, find_para_header_inclusion,
+	find_para_number_inclusion, is_requested_para_header, is_requested_para_header_in,
+	is_state_pruned, mandatory_headers_only, para_head_storage_key, poll_finality_proof,
+	proof_step_ranges, prove_standalone_finality, prove_timestamp_inherent, query_storage_ranges,
+	requested_para_headers_by_id,
+	rpc::{
+		concurrent_requests, is_retryable, retry, stream_requests, Connections, GrandpaRpc,
+		JustificationStream, RateLimiter, RetryPolicy, RpcCallCounter, RpcTimeouts, Subscriptions,
+	},
+	select_canonical_justification, set_change_justifications, share_finality_proof,
+	state_read_error, wait_for_para_block, wait_for_para_finality, walk_relay_headers,
+	AuthoritySetChange, AuthoritySetPin, Commit, GrandpaClientInit, GrandpaJustification,
+	JustificationNotification, JustificationPoll, LatestParaHeader, ParaCatchUp, ParaHeadChange,
+	ParaHeadKey, ProofOutcome, ProofStats, ProofStep, ProverCheckpoint, ProverError,
+	RelayBlockEvent, RelayHeader, GRANDPA_PROVE_FINALITY_RPC,
+};
+use async_trait::async_trait;
+use codec::Encode;
+use futures::StreamExt;
+use primitives::{
+	parachain_header_storage_key, FinalityProof, ParachainHeaderProofs,
+	ParachainHeadersWithFinalityProof,
+};
+use sp_core::H256;
+use sp_runtime::traits::{BlakeTwo256, Header as _};
+use subxt::config::{substrate::SubstrateHeader, Header as _};
+
+type ParaHeader = SubstrateHeader<u32, subxt::config::substrate::BlakeTwo256>;
+
+/// Storage proof of the given key, along with the state root it's proven against.
+fn read_proof(entries: &[(Vec<u8>, Vec<u8>)], key: &[u8]) -> (H256, Vec<Vec<u8>>) {
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let mut db = sp_trie::MemoryDB::<BlakeTwo256>::default();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMutBuilder::<LayoutV0<BlakeTwo256>>::new(&mut db, &mut root).build();
+		for (key, value) in entries {
+			trie.insert(key, value).unwrap();
+		}
+	}
+
+	let backend = sp_state_machine::TrieBackendBuilder::new(db, root).build();
+	let proof = sp_state_machine::prove_read(backend, &[key]).unwrap();
+	(root, proof.into_iter_nodes().collect())
+}
+
+/// Parachain header of the given number, with its other fields left empty.
+fn para_header(number: u32) -> RelayHeader {
+	RelayHeader::new(
+		number,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	)
+}
+
+/// The `Paras::Heads` storage value of [`para_header`], which stores the encoded header as
+/// `HeadData`, an encoded `Vec<u8>`.
+fn head_data(number: u32) -> Vec<u8> {
+	para_header(number).encode().encode()
+}
+
+/// A justification of `target` in round 1, signed by `pair` as the only authority of set 0.
+#[cfg(feature = "dry-run")]
+fn signed_justification(
+	pair: &sp_core::ed25519::Pair,
+	target: &RelayHeader,
+) -> GrandpaJustification<ParaHeader> {
+	signed_justification_in_set(pair, 0, target)
+}
+
+/// Same as [`signed_justification`], with `pair` as the only authority of the set `set_id`.
+#[cfg(feature = "dry-run")]
+fn signed_justification_in_set(
+	pair: &sp_core::ed25519::Pair,
+	set_id: u64,
+	target: &RelayHeader,
+) -> GrandpaJustification<ParaHeader> {
+	use sp_core::Pair;
+
+	let target_hash = sp_runtime::traits::Header::hash(target);
+	let precommit = finality_grandpa::Precommit { target_hash, target_number: target.number };
+	let message = sp_finality_grandpa::localized_payload(
+		1,
+		set_id,
+		&finality_grandpa::Message::Precommit(precommit.clone()),
+	);
+	GrandpaJustification {
+		round: 1,
+		commit: Commit {
+			target_hash,
+			target_number: target.number,
+			precommits: vec![finality_grandpa::SignedPrecommit {
+				precommit,
+				signature: pair.sign(&message).into(),
+				id: pair.public().into(),
+			}],
+		},
+		votes_ancestries: vec![],
+	}
+}
+
+/// A parachain of 6 blocks with the given extrinsics, and a relay chain of 12 blocks finalized by
+/// `pair` that stores the parachain's head under `para_key`, changing at every other block.
+#[cfg(feature = "dry-run")]
+fn finalized_mock_chains(
+	pair: &sp_core::ed25519::Pair,
+	para_key: &[u8],
+	extrinsics: impl Fn(u64) -> Vec<Vec<u8>>,
+) -> (crate::mock::MockChain, crate::mock::MockChain) {
+	use crate::mock::MockChain;
+
+	let mut para_chain = MockChain::default();
+	let mut para_heads = vec![para_chain.headers().next().unwrap().encode().encode()];
+	for number in 1..=6 {
+		let header = para_chain.push_block(vec![], extrinsics(number));
+		para_heads.push(header.encode().encode());
+	}
+	let mut relay_chain = MockChain::default();
+	for number in 1..=12 {
+		relay_chain.push_block(vec![(para_key.to_vec(), para_heads[number / 2].clone())], vec![]);
+	}
+	let target = relay_chain.headers().last().unwrap().clone();
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: sp_runtime::traits::Header::hash(&target),
+		justification: signed_justification(pair, &target).encode(),
+		unknown_headers: vec![],
+	};
+	relay_chain.set_finality_proof(12, finality_proof.encode());
+
+	(relay_chain, para_chain)
+}
+
+/// The client state of [`finalized_mock_chains`] at its first relay chain block.
+#[cfg(feature = "dry-run")]
+fn mock_client_state(
+	pair: &sp_core::ed25519::Pair,
+	relay_chain: &crate::mock::MockChain,
+	para_id: u32,
+) -> primitives::ClientState {
+	use sp_core::Pair;
+
+	let base = relay_chain.headers().nth(1).unwrap();
+	primitives::ClientState {
+		current_authorities: vec![(pair.public().into(), 1)],
+		current_set_id: 0,
+		latest_relay_height: 1,
+		latest_para_height: 0,
+		latest_relay_hash: sp_runtime::traits::Header::hash(base),
+		para_id,
+	}
+}
+
+#[test]
+fn test_verifier_host_functions() {
+	use crate::host_functions::{register_host_functions, HostFunctionsProvider};
+	use primitives::HostFunctions;
+	use sp_core::Pair;
+
+	let functions = register_host_functions();
+	let names = functions.iter().map(|function| function.name()).collect::<Vec<_>>();
+	for name in [
+		"ext_hashing_blake2_256_version_1",
+		"ext_hashing_keccak_256_version_1",
+		"ext_crypto_ed25519_verify_version_1",
+		"ext_trie_blake2_256_ordered_root_version_1",
+		"ext_allocator_malloc_version_1",
+	] {
+		assert!(names.contains(&name), "{name} isn't registered");
+	}
+
+	// a GRANDPA precommit signature verifies through the registered ed25519 host function
+	let pair = sp_core::ed25519::Pair::from_seed(&[1; 32]);
+	let precommit =
+		finality_grandpa::Precommit { target_hash: H256::repeat_byte(1), target_number: 10u32 };
+	let message = sp_finality_grandpa::localized_payload(
+		1,
+		0,
+		&finality_grandpa::Message::Precommit(precommit),
+	);
+	let signature = pair.sign(&message);
+	assert!(sp_io::crypto::ed25519_verify(&signature, &message, &pair.public()));
+	assert!(HostFunctionsProvider::ed25519_verify(&signature, &message, &pair.public()));
+
+	// a vote for another round doesn't
+	let message = [&message[..message.len() - 16], &2u64.encode(), &0u64.encode()].concat();
+	assert!(!sp_io::crypto::ed25519_verify(&signature, &message, &pair.public()));
+}
+
+#[test]
+fn test_grandpa_availability() {
+	let rpc_methods = vec!["chain_getHeader".to_string(), GRANDPA_PROVE_FINALITY_RPC.to_string()];
+	assert!(ensure_grandpa_available(true, &rpc_methods).is_ok());
+
+	// babe-only chain without the grandpa rpc
+	let rpc_methods = vec!["chain_getHeader".to_string(), "babe_epochAuthorship".to_string()];
+	assert!(matches!(
+		ensure_grandpa_available(true, &rpc_methods),
+		Err(ProverError::GrandpaNotAvailable { pallet: true, rpc: false })
+	));
+
+	// metadata without the grandpa pallet
+	let rpc_methods = vec![GRANDPA_PROVE_FINALITY_RPC.to_string()];
+	assert!(matches!(
+		ensure_grandpa_available(false, &rpc_methods),
+		Err(ProverError::GrandpaNotAvailable { pallet: false, rpc: true })
+	));
+}
+
+#[tokio::test]
+async fn test_shared_cache_evicts_least_recently_used() {
+	let cache = SharedCache::<u32, u32>::new(2);
+	cache.insert(1, 10).await;
+	cache.insert(2, 20).await;
+	// reading 1 makes 2 the least recently used entry
+	assert_eq!(cache.get(&1).await, Some(10));
+	cache.insert(3, 30).await;
+	assert_eq!(cache.get(&2).await, None);
+	assert_eq!(cache.get(&1).await, Some(10));
+	assert_eq!(cache.get(&3).await, Some(30));
+
+	// replacing an entry doesn't grow the cache
+	cache.insert(3, 31).await;
+	assert_eq!(cache.len().await, 2);
+	assert_eq!(cache.get(&3).await, Some(31));
+
+	// a cache without capacity holds nothing
+	let disabled = SharedCache::<u32, u32>::new(0);
+	disabled.insert(1, 10).await;
+	assert_eq!(disabled.get(&1).await, None);
+	assert_eq!(disabled.len().await, 0);
+}
+
+#[tokio::test]
+async fn test_walk_relay_headers_across_reorg() {
+	use std::{collections::BTreeMap, sync::Mutex};
+
+	// two forks of the relay chain on top of blocks 1..=5, diverging at block 6
+	let chain = |parent_hash: H256, from: u32, to: u32, fork: u8| {
+		let mut headers = Vec::<RelayHeader>::new();
+		for number in from..=to {
+			let parent_hash =
+				headers.last().map(sp_runtime::traits::Header::hash).unwrap_or(parent_hash);
+			headers.push(RelayHeader::new(
+				number,
+				Default::default(),
+				H256::repeat_byte(fork),
+				parent_hash,
+				Default::default(),
+			));
+		}
+		headers
+	};
+	let common = chain(H256::zero(), 1, 5, 0);
+	let common_tip = sp_runtime::traits::Header::hash(common.last().unwrap());
+	let fork_a = [common.clone(), chain(common_tip, 6, 10, 1)].concat();
+	let fork_b = [common, chain(common_tip, 6, 10, 2)].concat();
+
+	let node = [&fork_a, &fork_b]
+		.into_iter()
+		.flatten()
+		.map(|header| (sp_runtime::traits::Header::hash(header), header.clone()))
+		.collect::<BTreeMap<_, _>>();
+	let fetched = Mutex::new(vec![]);
+	let fetch = |hash: H256| {
+		fetched.lock().unwrap().push(hash);
+		let header = node.get(&hash).cloned().ok_or_else(|| anyhow::anyhow!("unknown header"));
+		async move { header }
+	};
+	let tip = |headers: &[RelayHeader]| sp_runtime::traits::Header::hash(headers.last().unwrap());
+
+	let cache = SharedCache::new(64);
+	let headers = walk_relay_headers(&cache, 1, tip(&fork_a), fetch).await.unwrap();
+	assert_eq!(headers, fork_a);
+	assert_eq!(fetched.lock().unwrap().len(), 10);
+
+	// after the reorg, the walk follows the new fork and only fetches its own headers
+	fetched.lock().unwrap().clear();
+	let headers = walk_relay_headers(&cache, 1, tip(&fork_b), fetch).await.unwrap();
+	assert_eq!(headers, fork_b);
+	let expected = fork_b[5..]
+		.iter()
+		.rev()
+		.map(sp_runtime::traits::Header::hash)
+		.collect::<Vec<_>>();
+	assert_eq!(*fetched.lock().unwrap(), expected);
+
+	// the walk stops at the requested height, served from the cache
+	fetched.lock().unwrap().clear();
+	let headers = walk_relay_headers(&cache, 7, tip(&fork_a), fetch).await.unwrap();
+	assert_eq!(headers, fork_a[6..]);
+	assert!(fetched.lock().unwrap().is_empty());
+
+	// a node serving the wrong header for a hash is rejected
+	let wrong = |_| async { Ok(fork_a[0].clone()) };
+	assert!(walk_relay_headers(&SharedCache::new(64), 1, tip(&fork_b), wrong).await.is_err());
+}
+
+#[tokio::test]
+async fn test_relay_ancestry_after_reorg() {
+	use std::collections::BTreeMap;
+
+	let chain = |parent_hash: H256, from: u32, to: u32, fork: u8| {
+		let mut headers = Vec::<RelayHeader>::new();
+		for number in from..=to {
+			let parent_hash =
+				headers.last().map(sp_runtime::traits::Header::hash).unwrap_or(parent_hash);
+			headers.push(RelayHeader::new(
+				number,
+				Default::default(),
+				H256::repeat_byte(fork),
+				parent_hash,
+				Default::default(),
+			));
+		}
+		headers
+	};
+	let hash = sp_runtime::traits::Header::hash;
+	let common = chain(H256::zero(), 1, 5, 0);
+	let common_tip = hash(common.last().unwrap());
+	// the client was last updated at block 8 of the abandoned fork
+	let abandoned = [common.clone(), chain(common_tip, 6, 8, 1)].concat();
+	let canonical = [common, chain(common_tip, 6, 20, 2)].concat();
+
+	let node = [&abandoned, &canonical]
+		.into_iter()
+		.flatten()
+		.map(|header| (hash(header), header.clone()))
+		.collect::<BTreeMap<_, _>>();
+	let fetch = |hash: H256| {
+		let header = node.get(&hash).cloned().ok_or_else(|| anyhow::anyhow!("unknown header"));
+		async move { header }
+	};
+	let cache = SharedCache::new(64);
+	let finalized = hash(canonical.last().unwrap());
+	let headers = walk_relay_headers(&cache, 8, finalized, fetch).await.unwrap();
+	// the walk is bounded by the client's height, rather than running to genesis
+	assert_eq!(headers, canonical[7..]);
+
+	let expected = hash(abandoned.last().unwrap());
+	match ensure_descends_from(&headers, expected) {
+		Err(ProverError::Reorg { expected: found, found_tip }) => {
+			assert_eq!(found, expected);
+			assert_eq!(found_tip, finalized);
+		},
+		res => panic!("Expected a reorg, got {res:?}"),
+	}
+	assert!(ensure_descends_from(&headers, hash(&canonical[7])).is_ok());
+	// blocks before the fork are still ancestors
+	let headers = walk_relay_headers(&cache, 4, finalized, fetch).await.unwrap();
+	assert!(ensure_descends_from(&headers, hash(&abandoned[3])).is_ok());
+}
+
+#[test]
+fn test_para_registration() {
+	let head = vec![1, 2, 3];
+	assert!(ensure_para_registered(true, 2000, Some(&head)).is_ok());
+
+	// no head at the finalized block
+	assert!(matches!(
+		ensure_para_registered(true, 2000, None),
+		Err(ProverError::UnknownParaId { para_id: 2000 })
+	));
+
+	// metadata without the paras pallet
+	assert!(matches!(
+		ensure_para_registered(false, 2000, Some(&head)),
+		Err(ProverError::ParasNotAvailable)
+	));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_shared_cache_concurrent_access() {
+	let cache = SharedCache::<u32, u32>::new(64);
+	let tasks = (0..16u32)
+		.map(|task| {
+			// every task gets its own clone, all clones share the same entries.
+			let cache = cache.clone();
+			tokio::spawn(async move {
+				for i in 0..100u32 {
+					let key = (task * 100 + i) % 128;
+					cache.insert(key, key * 2).await;
+					if let Some(value) = cache.get(&key).await {
+						assert_eq!(value, key * 2);
+					}
+				}
+			})
+		})
+		.collect::<Vec<_>>();
+
+	for task in tasks {
+		task.await.unwrap();
+	}
+
+	// capacity is respected under contention
+	assert!(cache.len().await <= 64);
+	cache.clear().await;
+	assert_eq!(cache.len().await, 0);
+}
+
+#[test]
+fn test_pruned_state() {
+	// the error a full node responds with to a read of a block below its pruning depth, as wrapped
+	// by the rpc client
+	let at = H256::repeat_byte(3);
+	let pruned = || {
+		anyhow::anyhow!(
+			"{{\"code\":4003,\"message\":\"Client error: UnknownBlock: State already discarded for \
+			 {at:?}\"}}"
+		)
+		.context("Rpc error")
+	};
+	assert!(is_state_pruned(&pruned()));
+	let err = ProverError::from(state_read_error(pruned(), at));
+	assert!(matches!(err, ProverError::StatePruned { at: pruned_at } if pruned_at == at));
+	assert_eq!(
+		summarize_error(&anyhow::Error::from(err)),
+		format!(
+			"State of block {at:?} has been pruned by the node: \
+			 the node only keeps recent state, use an archive node"
+		)
+	);
+
+	// other errors are kept as they are
+	let err = state_read_error(anyhow::anyhow!("connection reset"), at);
+	assert!(!is_state_pruned(&err));
+	assert!(matches!(ProverError::from(err), ProverError::Custom(_)));
+}
+
+#[tokio::test]
+async fn test_requires_archive_depth() {
+	use crate::mock::{mock_prover, MockChain};
+
+	let mut relay_chain = MockChain::default();
+	for _ in 1..=10 {
+		relay_chain.push_block(vec![], vec![]);
+	}
+	let prover = mock_prover::<subxt::PolkadotConfig>(relay_chain, MockChain::default(), 2000)
+		.await
+		.unwrap();
+
+	// the reads go from the start of the range up to the finalized head at 10
+	assert_eq!(prover.requires_archive_depth(4).await.unwrap(), 6);
+	assert_eq!(prover.requires_archive_depth(10).await.unwrap(), 0);
+	assert_eq!(prover.requires_archive_depth(12).await.unwrap(), 0);
+}
+
+#[test]
+fn test_proof_key_mismatch() {
+	let para_key = parachain_header_storage_key(2000).0;
+	let unrelated_key = b"unrelated".to_vec();
+	let entries = vec![(para_key.clone(), vec![1u8; 32]), (unrelated_key.clone(), vec![2u8; 32])];
+
+	let (root, proof) = read_proof(&entries, &para_key);
+	assert!(ensure_proof_contains_key(root, &proof, &para_key).is_ok());
+
+	// the node returned a proof for a different key
+	let (root, proof) = read_proof(&entries, &unrelated_key);
+	assert!(matches!(
+		ensure_proof_contains_key(root, &proof, &para_key),
+		Err(ProverError::ProofKeyMismatch { key }) if key == para_key
+	));
+}
+
+#[test]
+fn test_proof_step_ranges() {
+	// no set change in range
+	assert_eq!(proof_step_ranges(5, 9, 10, 10), vec![ProofStep::Headers((5, 9))]);
+
+	// range spans two set changes
+	assert_eq!(
+		proof_step_ranges(5, 25, 10, 10),
+		vec![
+			ProofStep::SetChange((5, 10)),
+			ProofStep::SetChange((10, 20)),
+			ProofStep::Headers((20, 25)),
+		]
+	);
+
+	// client is already at the end of a session, the next set change is a session later
+	assert_eq!(
+		proof_step_ranges(10, 25, 10, 10),
+		vec![ProofStep::SetChange((10, 20)), ProofStep::Headers((20, 25))]
+	);
+}
+
+#[test]
+fn test_decode_requested_para_header() {
+	let header_numbers = vec![2, 3];
+
+	// requested headers are decoded
+	let header =
+		decode_requested_para_header::<ParaHeader>(&para_header(2).encode(), &header_numbers)
+			.unwrap()
+			.unwrap();
+	assert_eq!(header.number(), 2);
+
+	// blocks that weren't requested are skipped before any proofs are fetched for them
+	assert!(decode_requested_para_header::<ParaHeader>(&para_header(4).encode(), &header_numbers)
+		.unwrap()
+		.is_none());
+
+	// genesis is always skipped
+	assert!(decode_requested_para_header::<ParaHeader>(&para_header(0).encode(), &[0])
+		.unwrap()
+		.is_none());
+}
+
+#[test]
+fn test_multiple_candidates_per_relay_block() {
+	// `Paras::Heads` at each relay chain block, block 101 included the candidates for both
+	// parachain blocks 5 and 6, so only the head of 6 is in its state.
+	let heads = [(100u32, 4u32), (101, 6), (102, 7)];
+
+	let proven = |header_numbers: &[u32]| {
+		let mut previous_number = None;
+		let mut proven = vec![];
+		for (block, number) in heads {
+			if is_requested_para_header(&number, previous_number.as_ref(), header_numbers) {
+				proven.push((block, number));
+			}
+			previous_number = Some(number);
+		}
+		proven
+	};
+
+	// the head that was actually included is proven in place of the superseded candidate
+	assert_eq!(proven(&[5]), vec![(101, 6)]);
+	assert_eq!(proven(&[5, 6]), vec![(101, 6)]);
+	assert_eq!(proven(&[5, 7]), vec![(101, 6), (102, 7)]);
+	// without a previous head, a superseded candidate can't be told apart from an unrequested one
+	assert!(!is_requested_para_header(&6, None, &[5]));
+	assert!(!is_requested_para_header(&0, Some(&0), &[0]));
+}
+
+#[test]
+fn test_requested_para_header_set_lookup() {
+	use std::collections::BTreeSet;
+
+	// every third parachain block of 5000 is requested, with heads advancing by one or two
+	let requested = (1..=5000u32).filter(|number| number % 3 == 0).collect::<Vec<_>>();
+	let requested_set = requested.iter().copied().collect::<BTreeSet<_>>();
+	let heads = (0..=5000u32).filter(|number| number % 7 != 0).collect::<Vec<_>>();
+
+	let proven = |is_requested: &dyn Fn(&u32, Option<&u32>) -> bool| {
+		let mut previous_number = None;
+		let mut proven = vec![];
+		for number in &heads {
+			if is_requested(number, previous_number) {
+				proven.push(*number);
+			}
+			previous_number = Some(number);
+		}
+		proven
+	};
+
+	let by_slice =
+		proven(&|number, previous| is_requested_para_header(number, previous, &requested));
+	let by_set =
+		proven(&|number, previous| is_requested_para_header_in(number, previous, &requested_set));
+	assert_eq!(by_slice, by_set);
+
+	// the superseded and genesis cases match the linear lookup
+	let set = [5].into_iter().collect::<BTreeSet<_>>();
+	assert!(is_requested_para_header_in(&6, Some(&4), &set));
+	assert!(!is_requested_para_header_in(&6, None, &set));
+	assert!(!is_requested_para_header_in(&6, Some(&6), &set));
+	assert!(!is_requested_para_header_in(&0, Some(&0), &[0].into_iter().collect()));
+}
+
+#[tokio::test]
+async fn test_fetch_latest_finalized() {
+	let finalized = H256::repeat_byte(3);
+	let (hash, number) = fetch_latest_finalized(
+		|| async move { Ok(finalized) },
+		|hash| async move { Ok(if hash == finalized { Some(42u32) } else { None }) },
+	)
+	.await
+	.unwrap();
+	assert_eq!((hash, number), (finalized, 42));
+
+	// the node doesn't have the finalized header
+	let err = fetch_latest_finalized::<_, u32, _, _>(
+		|| async move { Ok(finalized) },
+		|_| async move { Ok(None) },
+	)
+	.await
+	.unwrap_err();
+	assert!(matches!(err, ProverError::HeaderNotFound(hash) if hash == finalized));
+
+	// errors fetching the finalized head are returned as is
+	let err = fetch_latest_finalized::<H256, u32, _, _>(
+		|| async move {
+			Err(ProverError::Timeout { timeout: std::time::Duration::from_secs(1) }.into())
+		},
+		|_| async move { Ok(Some(1)) },
+	)
+	.await
+	.unwrap_err();
+	assert!(matches!(err, ProverError::Timeout { .. }));
+}
+
+/// [`GrandpaRpc`] serving a fixed finality proof and justifications.
+struct MockGrandpaRpc {
+	finality_proof: Option<Vec<u8>>,
+	justifications: Vec<Vec<u8>>,
+}
+
+#[async_trait]
+impl GrandpaRpc for MockGrandpaRpc {
+	async fn prove_finality(&self, _block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		Ok(self.finality_proof.clone())
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		let justifications = self.justifications.clone().into_iter().map(Ok);
+		Ok(Box::pin(futures::stream::iter(justifications)))
+	}
+}
+
+impl MockGrandpaRpc {
+	/// Serves the given finality proof, without any justifications to subscribe to.
+	fn new(finality_proof: &FinalityProof<ParaHeader>) -> Self {
+		Self { finality_proof: Some(finality_proof.encode()), justifications: vec![] }
+	}
+}
+
+/// Justification of relay chain block 12, whose hash is `0x0707…`, and the finality proof of it.
+fn mock_finality_proof() -> (GrandpaJustification<ParaHeader>, FinalityProof<ParaHeader>) {
+	let target_hash = H256::repeat_byte(7);
+	let justification = GrandpaJustification::<ParaHeader> {
+		round: 1,
+		commit: Commit { target_hash, target_number: 12, precommits: vec![] },
+		votes_ancestries: vec![],
+	};
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: target_hash,
+		justification: justification.encode(),
+		unknown_headers: vec![],
+	};
+
+	(justification, finality_proof)
+}
+
+/// [`GrandpaRpc`] whose subscriptions follow a script, each attempt to subscribe either fails or
+/// yields the scripted items and then closes.
+struct FlakyGrandpaRpc {
+	subscriptions: std::sync::Mutex<Vec<Result<Vec<Result<Vec<u8>, &'static str>>, &'static str>>>,
+}
+
+#[async_trait]
+impl GrandpaRpc for FlakyGrandpaRpc {
+	async fn prove_finality(&self, _block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		Ok(None)
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		let items = self.subscriptions.lock().unwrap().remove(0).map_err(anyhow::Error::msg)?;
+		let items = items.into_iter().map(|item| item.map_err(anyhow::Error::msg));
+		Ok(Box::pin(futures::stream::iter(items)))
+	}
+}
+
+/// [`GrandpaRpc`] that takes `delay` to assemble each finality proof.
+struct SlowGrandpaRpc {
+	inner: MockGrandpaRpc,
+	delay: std::time::Duration,
+}
+
+#[async_trait]
+impl GrandpaRpc for SlowGrandpaRpc {
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		tokio::time::sleep(self.delay).await;
+		self.inner.prove_finality(block).await
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		self.inner.subscribe_justifications().await
+	}
+}
+
+/// [`GrandpaRpc`] that records the number of finality proofs requested from it.
+struct CountingGrandpaRpc {
+	inner: MockGrandpaRpc,
+	calls: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait]
+impl GrandpaRpc for CountingGrandpaRpc {
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+		self.inner.prove_finality(block).await
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		self.inner.subscribe_justifications().await
+	}
+}
+
+/// [`GrandpaRpc`] whose finality proof requests fail with the scripted errors, in order, before
+/// succeeding.
+struct UnreliableGrandpaRpc {
+	inner: MockGrandpaRpc,
+	failures: std::sync::Mutex<Vec<anyhow::Error>>,
+	calls: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait]
+impl GrandpaRpc for UnreliableGrandpaRpc {
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+		let mut failures = self.failures.lock().unwrap();
+		if !failures.is_empty() {
+			return Err(failures.remove(0))
+		}
+		self.inner.prove_finality(block).await
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		self.inner.subscribe_justifications().await
+	}
+}
+
+#[test]
+fn test_justification_notification_round_trip() {
+	let header = RelayHeader::new(
+		12,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let header = <ParaHeader as codec::Decode>::decode(&mut &header.encode()[..]).unwrap();
+	let justification = GrandpaJustification::<ParaHeader> {
+		round: 3,
+		commit: Commit { target_hash: H256::repeat_byte(7), target_number: 12, precommits: vec![] },
+		votes_ancestries: vec![],
+	};
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: H256::repeat_byte(7),
+		justification: justification.encode(),
+		unknown_headers: vec![header],
+	};
+
+	let notification = JustificationNotification(sp_core::Bytes(finality_proof.encode()));
+	assert_eq!(notification.as_bytes(), &finality_proof.encode()[..]);
+	let decoded = notification.decode_finality_proof::<ParaHeader>().unwrap();
+	assert_eq!(decoded.encode(), finality_proof.encode());
+	assert_eq!(notification.into_inner(), finality_proof.encode());
+
+	// newer nodes wrap the proof in its version
+	let versioned = primitives::VersionedFinalityProof::V1(finality_proof.clone()).encode();
+	let notification = JustificationNotification(sp_core::Bytes(versioned));
+	let decoded = notification.decode_finality_proof::<ParaHeader>().unwrap();
+	assert_eq!(decoded.encode(), finality_proof.encode());
+	let notification = JustificationNotification(sp_core::Bytes(vec![9, 1, 2]));
+	assert!(notification.decode_finality_proof::<ParaHeader>().is_err());
+
+	// the bytes of a bare justification aren't a finality proof
+	let notification = JustificationNotification(sp_core::Bytes(vec![1, 2, 3]));
+	assert!(notification.decode_finality_proof::<ParaHeader>().is_err());
+}
+
+#[tokio::test]
+async fn test_fetch_finality_proof_with_mock_rpc() {
+	let (justification, mut finality_proof) = mock_finality_proof();
+	let target_hash = finality_proof.block;
+	finality_proof.block = H256::repeat_byte(1);
+	let rpc = MockGrandpaRpc {
+		finality_proof: Some(finality_proof.encode()),
+		justifications: vec![justification.encode()],
+	};
+
+	// the proof is for the block finalized by the justification, not the requested block
+	let (proof, target_number) = fetch_finality_proof::<ParaHeader>(&rpc, 10).await.unwrap();
+	assert_eq!(proof.block, target_hash);
+	assert_eq!(target_number, 12);
+
+	let mut justifications = rpc.subscribe_justifications().await.unwrap();
+	assert_eq!(justifications.next().await.unwrap().unwrap(), justification.encode());
+	assert!(justifications.next().await.is_none());
+
+	// no justification for the requested block
+	let rpc = MockGrandpaRpc { finality_proof: None, justifications: vec![] };
+	let err = fetch_finality_proof::<ParaHeader>(&rpc, 10).await.unwrap_err();
+	assert!(matches!(ProverError::from(err), ProverError::JustificationNotFound(10)));
+}
+
+/// [`GrandpaRpc`] that has no justification for the first `pending` finality proofs requested
+/// from it, as when the node hasn't stored the justification of a newly finalized block yet.
+struct PendingGrandpaRpc {
+	inner: MockGrandpaRpc,
+	pending: std::sync::atomic::AtomicUsize,
+	calls: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait]
+impl GrandpaRpc for PendingGrandpaRpc {
+	async fn prove_finality(&self, block: u32) -> Result<Option<Vec<u8>>, anyhow::Error> {
+		use std::sync::atomic::Ordering;
+
+		self.calls.fetch_add(1, Ordering::SeqCst);
+		let pending = self
+			.pending
+			.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| pending.checked_sub(1));
+		match pending {
+			Ok(_) => Ok(None),
+			Err(_) => self.inner.prove_finality(block).await,
+		}
+	}
+
+	async fn subscribe_justifications(&self) -> Result<JustificationStream, anyhow::Error> {
+		self.inner.subscribe_justifications().await
+	}
+}
+
+#[tokio::test]
+async fn test_poll_finality_proof() {
+	use std::{
+		sync::atomic::{AtomicUsize, Ordering},
+		time::Duration,
+	};
+
+	let target_hash = H256::repeat_byte(7);
+	let (_, finality_proof) = mock_finality_proof();
+	let rpc = |pending| PendingGrandpaRpc {
+		inner: MockGrandpaRpc::new(&finality_proof),
+		pending: AtomicUsize::new(pending),
+		calls: AtomicUsize::new(0),
+	};
+	let poll = JustificationPoll { retries: 3, interval: Duration::from_millis(1) };
+
+	// the justification is stored after two polls
+	let pending = rpc(2);
+	let (proof, target_number) =
+		poll_finality_proof::<ParaHeader, _, _>(poll, 12, || pending.prove_finality(12))
+			.await
+			.unwrap();
+	assert_eq!(proof, finality_proof);
+	assert_eq!(target_number, 12);
+	assert_eq!(pending.calls.load(Ordering::SeqCst), 3);
+
+	// or isn't stored in time
+	let pending = rpc(5);
+	let err = poll_finality_proof::<ParaHeader, _, _>(poll, 12, || pending.prove_finality(12))
+		.await
+		.unwrap_err();
+	assert!(matches!(
+		ProverError::from(err),
+		ProverError::JustificationNotYetAvailable { block: 12, waited }
+			if waited == Duration::from_millis(3)
+	));
+	assert_eq!(pending.calls.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_prove_standalone_finality() {
+	use std::time::Duration;
+
+	// a chain of headers from genesis
+	let mut headers: Vec<RelayHeader> = vec![];
+	for number in 0..=14u32 {
+		let parent_hash = headers.last().map(sp_runtime::traits::Header::hash).unwrap_or_default();
+		headers.push(RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			parent_hash,
+			Default::default(),
+		));
+	}
+	let header_at = |height: u32| {
+		let header =
+			<ParaHeader as codec::Decode>::decode(&mut &headers[height as usize].encode()[..]);
+		async move { Ok::<_, anyhow::Error>(header?) }
+	};
+
+	let justification = |target: &RelayHeader| GrandpaJustification::<ParaHeader> {
+		round: 1,
+		commit: Commit {
+			target_hash: sp_runtime::traits::Header::hash(target),
+			target_number: target.number,
+			precommits: vec![],
+		},
+		votes_ancestries: vec![],
+	};
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: H256::repeat_byte(1),
+		justification: justification(&headers[12]).encode(),
+		unknown_headers: vec![],
+	};
+	let rpc = MockGrandpaRpc::new(&finality_proof);
+	let poll = JustificationPoll { retries: 2, interval: Duration::from_millis(1) };
+
+	// the finality proof fetched for block 10 finalizes block 12
+	let proof = prove_standalone_finality::<ParaHeader, _, _>(&rpc, poll, 5, 10, None, header_at)
+		.await
+		.unwrap();
+	assert_eq!(proof.block, sp_runtime::traits::Header::hash(&headers[12]));
+	assert_eq!(proof.justification, justification(&headers[12]).encode());
+	let numbers = proof.unknown_headers.iter().map(|header| header.number).collect::<Vec<_>>();
+	assert_eq!(numbers, (5..=12).collect::<Vec<_>>());
+	// the unknown headers form a chain
+	for pair in proof.unknown_headers.windows(2) {
+		assert_eq!(pair[1].parent_hash, pair[0].hash());
+	}
+
+	// a given justification is used rather than fetching one
+	let rpc = MockGrandpaRpc { finality_proof: None, justifications: vec![] };
+	let latest_justification = Some(justification(&headers[14]).encode());
+	let proof = prove_standalone_finality::<ParaHeader, _, _>(
+		&rpc,
+		poll,
+		12,
+		14,
+		latest_justification,
+		header_at,
+	)
+	.await
+	.unwrap();
+	assert_eq!(proof.block, sp_runtime::traits::Header::hash(&headers[14]));
+	assert_eq!(proof.unknown_headers.len(), 3);
+
+	// without one, the node is polled for the justification like the prover's other requests
+	let err = prove_standalone_finality::<ParaHeader, _, _>(&rpc, poll, 12, 14, None, header_at)
+		.await
+		.unwrap_err();
+	assert!(matches!(
+		ProverError::from(err),
+		ProverError::JustificationNotYetAvailable { block: 14, .. }
+	));
+}
+
+#[test]
+fn test_authority_set_pinning() {
+	let authorities = (1..=3u8)
+		.map(|i| (sp_core::ed25519::Public::from_raw([i; 32]).into(), 1))
+		.collect::<sp_finality_grandpa::AuthorityList>();
+	let pin = AuthoritySetPin { set_id: 5, authority_hash: authority_set_hash(&authorities) };
+	assert!(ensure_authority_set(&pin, 5, &authorities).is_ok());
+
+	// node serves a different set id
+	assert!(matches!(
+		ensure_authority_set(&pin, 6, &authorities),
+		Err(ProverError::AuthoritySetMismatch { expected_set_id: 5, found_set_id: 6, .. })
+	));
+
+	// node serves a forged authority list under the expected set id
+	let forged = authorities[..2].to_vec();
+	match ensure_authority_set(&pin, 5, &forged) {
+		Err(ProverError::AuthoritySetMismatch {
+			expected_authority_hash,
+			found_authority_hash,
+			..
+		}) => {
+			assert_eq!(expected_authority_hash, pin.authority_hash);
+			assert_eq!(found_authority_hash, authority_set_hash(&forged));
+		},
+		_ => panic!("expected an authority set mismatch"),
+	}
+}
+
+#[tokio::test]
+async fn test_subscriptions_are_unsubscribed_on_close() {
+	use std::sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	};
+
+	/// Marks the subscription as unsubscribed once the stream holding it is dropped.
+	struct Unsubscribe(Arc<AtomicBool>);
+
+	impl Drop for Unsubscribe {
+		fn drop(&mut self) {
+			self.0.store(true, Ordering::SeqCst);
+		}
+	}
+
+	let unsubscribed = Arc::new(AtomicBool::new(false));
+	let guard = Unsubscribe(unsubscribed.clone());
+	let subscription =
+		futures::stream::pending::<Result<Vec<u8>, anyhow::Error>>().map(move |item| {
+			let _guard = &guard;
+			item
+		});
+
+	let subscriptions = Subscriptions::default();
+	let mut stream = subscriptions.track(Box::pin(subscription)).await;
+	assert!(!unsubscribed.load(Ordering::SeqCst));
+
+	subscriptions.close_all().await;
+	assert!(unsubscribed.load(Ordering::SeqCst));
+	// the stream ends once the subscription is closed
+	assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn test_connections_are_closed_on_close() {
+	use jsonrpsee::core::client::{
+		ClientBuilder, ReceivedMessage, TransportReceiverT, TransportSenderT,
+	};
+
+	/// Transport to a node that never responds.
+	struct Silent;
+
+	#[async_trait]
+	impl TransportSenderT for Silent {
+		type Error = std::io::Error;
+
+		async fn send(&mut self, _msg: String) -> Result<(), Self::Error> {
+			Ok(())
+		}
+	}
+
+	#[async_trait]
+	impl TransportReceiverT for Silent {
+		type Error = std::io::Error;
+
+		async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+			futures::future::pending().await
+		}
+	}
+
+	let connections = Connections::default();
+	let client = ClientBuilder::default().build_with_tokio(Silent, connections.closable(Silent));
+	// connections whose closer is dropped stay open
+	let other =
+		ClientBuilder::default().build_with_tokio(Silent, Connections::default().closable(Silent));
+	assert!(client.is_connected());
+
+	connections.clone().close();
+	client.on_disconnect().await;
+	assert!(!client.is_connected());
+	assert!(other.is_connected());
+}
+
+#[test]
+fn test_stale_finalized_head() {
+	use std::time::Duration;
+
+	let now = Duration::from_secs(1_700_000_000);
+	let max_age = Duration::from_secs(60);
+
+	// head finalized 12 seconds ago
+	let head_timestamp = (now - Duration::from_secs(12)).as_millis() as u64;
+	assert!(ensure_head_fresh(head_timestamp, now, max_age).is_ok());
+
+	// the node's finalized head is 10 minutes old
+	let head_timestamp = (now - Duration::from_secs(600)).as_millis() as u64;
+	assert!(matches!(
+		ensure_head_fresh(head_timestamp, now, max_age),
+		Err(ProverError::StaleFinalizedHead { age, .. }) if age == Duration::from_secs(600)
+	));
+}
+
+#[test]
+fn test_decode_para_numbers_from_change_set() {
+	// changes as returned by `state_queryStorage`, including a removed head
+	let changes = vec![Some(head_data(5)), Some(head_data(6)), None, Some(head_data(9))];
+
+	assert_eq!(decode_para_numbers::<ParaHeader>(changes).unwrap(), vec![5, 6, 9]);
+	assert!(decode_para_numbers::<ParaHeader>(vec![Some(vec![1, 2, 3])]).is_err());
+}
+
+#[test]
+fn test_proof_generation_is_deterministic() {
+	let para_key = parachain_header_storage_key(2000).0;
+	let entries = (0..32u8)
+		.map(|i| (vec![i; 32], vec![i; 64]))
+		.chain(Some((para_key.clone(), vec![1u8; 32])))
+		.collect::<Vec<_>>();
+	let (_, nodes) = read_proof(&entries, &para_key);
+
+	// the same proof, as served by two nodes returning the trie nodes in a different order
+	let mut shuffled = nodes.clone();
+	shuffled.reverse();
+	shuffled.push(nodes[0].clone());
+
+	let proof = |state_proof: Vec<Vec<u8>>| {
+		let proofs = ParachainHeaderProofs {
+			state_proof: canonical_storage_proof(state_proof),
+			extrinsic: vec![1, 2, 3],
+			extrinsic_proof: vec![vec![4, 5, 6]],
+			storage_key: Some(para_key.clone()),
+			extrinsic_index: 0,
+		};
+		ParachainHeadersWithFinalityProof::<ParaHeader> {
+			finality_proof: FinalityProof {
+				block: H256::repeat_byte(1),
+				justification: vec![],
+				unknown_headers: vec![],
+			},
+			parachain_headers: [
+				(H256::repeat_byte(2), proofs.clone()),
+				(H256::repeat_byte(3), proofs),
+			]
+			.into_iter()
+			.collect(),
+		}
+		.encode()
+	};
+
+	assert_eq!(proof(nodes), proof(shuffled));
+}
+
+#[test]
+fn test_para_head_deletion() {
+	let head_data = sp_runtime::generic::Header::<u32, BlakeTwo256>::new(
+		7,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	)
+	.encode();
+
+	// an update carries the head data
+	let change = ParaHeadChange::decode(Some(&head_data.encode()[..])).unwrap();
+	assert_eq!(change, ParaHeadChange::Updated(head_data.clone()));
+
+	// a deletion, as when the parachain is offboarded, isn't a decode error
+	assert_eq!(ParaHeadChange::decode(None).unwrap(), ParaHeadChange::Removed);
+
+	// garbage is still a decode error
+	assert!(ParaHeadChange::decode(Some(&[0xff][..])).is_err());
+
+	// deletions are skipped when listing the changed headers
+	let changes = vec![Some(head_data.encode()), None];
+	assert_eq!(decode_para_numbers::<ParaHeader>(changes).unwrap(), vec![7]);
+}
+
+#[tokio::test]
+async fn test_finality_proof_timeout() {
+	use std::time::Duration;
+
+	let (_, finality_proof) = mock_finality_proof();
+	let rpc = SlowGrandpaRpc {
+		inner: MockGrandpaRpc::new(&finality_proof),
+		delay: Duration::from_millis(100),
+	};
+	let timeouts = RpcTimeouts {
+		request: Some(Duration::from_millis(20)),
+		finality_proof: Some(Duration::from_secs(5)),
+	};
+
+	// the finality proof takes longer than the request timeout, but within its own timeout
+	let (_, target_number) = timeouts
+		.finality_proof(fetch_finality_proof::<ParaHeader>(&rpc, 10))
+		.await
+		.unwrap();
+	assert_eq!(target_number, 12);
+
+	// a storage read exceeding the request timeout still fails
+	let storage_read = async {
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		Ok::<_, anyhow::Error>(vec![0u8])
+	};
+	let err = timeouts.request(storage_read).await.unwrap_err();
+	assert!(matches!(
+		err.downcast_ref::<ProverError>(),
+		Some(ProverError::Timeout { timeout }) if *timeout == Duration::from_millis(20)
+	));
+
+	// with no timeouts configured requests wait for as long as they take
+	let (_, target_number) = RpcTimeouts::default()
+		.finality_proof(fetch_finality_proof::<ParaHeader>(&rpc, 10))
+		.await
+		.unwrap();
+	assert_eq!(target_number, 12);
+}
+
+#[tokio::test]
+async fn test_proof_outcome() {
+	let rpc = MockGrandpaRpc::new(&mock_finality_proof().1);
+	// proves the given parachain headers, from the mock rpc's finality proof
+	let prove = |relay_hashes: Vec<H256>| {
+		let rpc = &rpc;
+		move || async move {
+			let (finality_proof, _) = fetch_finality_proof::<ParaHeader>(rpc, 12).await?;
+			let mut proof = ParachainHeadersWithFinalityProof::<ParaHeader> {
+				finality_proof,
+				parachain_headers: Default::default(),
+			};
+			for relay_hash in relay_hashes {
+				let proofs = ParachainHeaderProofs {
+					state_proof: vec![],
+					extrinsic: vec![],
+					extrinsic_proof: vec![],
+					storage_key: None,
+					extrinsic_index: 0,
+				};
+				proof.push_parachain_header(relay_hash, proofs);
+			}
+			Ok::<_, anyhow::Error>(proof)
+		}
+	};
+
+	let outcome = ProofOutcome::classify(10, 12, prove(vec![H256::repeat_byte(1)])).await.unwrap();
+	assert!(matches!(outcome, ProofOutcome::Proof(proof) if proof.parachain_headers.len() == 1));
+
+	// none of the requested headers were included
+	let outcome = ProofOutcome::classify(10, 12, prove(vec![])).await.unwrap();
+	assert!(matches!(outcome, ProofOutcome::NothingToProve));
+
+	// nothing was finalized since the client was last updated, so no proof is generated
+	let outcome = ProofOutcome::<ParaHeader>::classify(12, 12, || async {
+		Err(anyhow::anyhow!("proof isn't generated before the relay chain finalizes new blocks"))
+	})
+	.await
+	.unwrap();
+	assert!(matches!(outcome, ProofOutcome::NotYetFinalized));
+
+	// errors generating the proof are passed through
+	let rpc = MockGrandpaRpc { finality_proof: None, justifications: vec![] };
+	let result = ProofOutcome::classify(10, 12, || async {
+		let (finality_proof, _) = fetch_finality_proof::<ParaHeader>(&rpc, 12).await?;
+		Ok::<_, anyhow::Error>(ParachainHeadersWithFinalityProof {
+			finality_proof,
+			parachain_headers: Default::default(),
+		})
+	})
+	.await;
+	assert!(result.is_err());
+}
+
+#[test]
+fn test_find_para_header_inclusion() {
+	let changes = vec![
+		(100u32, Some(head_data(5))),
+		(101, None),
+		(102, Some(head_data(6))),
+		(103, Some(head_data(7))),
+	];
+
+	let hash_of = |header: &sp_runtime::generic::Header<u32, BlakeTwo256>| {
+		H256::from(sp_core::blake2_256(&header.encode()))
+	};
+
+	let hash = hash_of(&para_header(6));
+	assert_eq!(
+		find_para_header_inclusion::<ParaHeader, _>(changes.clone(), hash).unwrap(),
+		Some(102)
+	);
+
+	// a header with the same number but a different hash isn't matched
+	let mut fork = para_header(6);
+	fork.parent_hash = H256::repeat_byte(1);
+	assert_eq!(
+		find_para_header_inclusion::<ParaHeader, _>(changes.clone(), hash_of(&fork)).unwrap(),
+		None
+	);
+
+	// headers that were never included aren't found
+	let hash = hash_of(&para_header(8));
+	assert_eq!(find_para_header_inclusion::<ParaHeader, _>(changes, hash).unwrap(), None);
+}
+
+#[test]
+fn test_progress_reporting() {
+	use crate::progress::{ProgressHook, ProgressReporter, ProverProgress};
+	use std::sync::{Arc, Mutex};
+
+	let events = Arc::new(Mutex::new(Vec::<ProverProgress>::new()));
+	let hook_events = events.clone();
+	let hook: ProgressHook = Arc::new(move |event| hook_events.lock().unwrap().push(event));
+	let progress = ProgressReporter::new(Some(hook));
+
+	// the change sets are queried in ranges, followed by the proofs of the collected headers
+	for (_, end) in query_storage_ranges(100, 350, Some(100)) {
+		progress.relay_height(end);
+	}
+	progress.header_collected();
+	progress.header_collected();
+	// a lower height reported late doesn't move the progress back
+	progress.relay_height(250);
+	progress.proof_built();
+	progress.proof_built();
+
+	let events = events.lock().unwrap();
+	let heights = events.iter().map(|event| event.relay_height).collect::<Vec<_>>();
+	assert_eq!(heights, vec![199, 299, 350, 350, 350, 350, 350, 350]);
+	assert!(events.windows(2).all(|pair| pair[0].relay_height <= pair[1].relay_height));
+	assert_eq!(
+		events.last().copied(),
+		Some(ProverProgress { relay_height: 350, headers_collected: 2, proofs_built: 2 })
+	);
+
+	// without a hook nothing is reported
+	ProgressReporter::new(None).relay_height(100);
+}
+
+#[test]
+fn test_find_para_number_inclusion() {
+	let changes = vec![
+		(100u32, Some(head_data(5))),
+		(101, None),
+		(102, Some(head_data(6))),
+		(103, Some(head_data(7))),
+	];
+
+	assert_eq!(find_para_number_inclusion::<ParaHeader, _>(changes.clone(), 6).unwrap(), Some(102));
+	assert_eq!(find_para_number_inclusion::<ParaHeader, _>(changes.clone(), 7).unwrap(), Some(103));
+	assert_eq!(find_para_number_inclusion::<ParaHeader, _>(changes, 8).unwrap(), None);
+
+	// the parachain block must be finalized before it's located
+	assert!(ensure_para_block_finalized(7, 7).is_ok());
+	assert!(matches!(
+		ensure_para_block_finalized(8, 7),
+		Err(ProverError::ParaBlockNotFinalized { number: 8, finalized: 7 })
+	));
+}
+
+#[test]
+fn test_query_storage_ranges() {
+	// without a limit the range is queried at once
+	assert_eq!(query_storage_ranges(100, 1_099, None), vec![(100, 1_099)]);
+
+	// a wide range is split into sub-ranges no wider than the limit
+	let ranges = query_storage_ranges(100, 1_099, Some(256));
+	assert_eq!(ranges, vec![(100, 355), (356, 611), (612, 867), (868, 1_099)]);
+	assert!(ranges.iter().all(|(start, end)| end - start + 1 <= 256));
+
+	// the range divides evenly
+	assert_eq!(query_storage_ranges(1, 10, Some(5)), vec![(1, 5), (6, 10)]);
+	// a single block
+	assert_eq!(query_storage_ranges(7, 7, Some(5)), vec![(7, 7)]);
+	// ranges reaching the end of the block number space don't overflow
+	assert_eq!(query_storage_ranges(u32::MAX - 2, u32::MAX, Some(2)).len(), 2);
+	// a limit of 0 is treated as 1
+	assert_eq!(query_storage_ranges(1, 3, Some(0)), vec![(1, 1), (2, 2), (3, 3)]);
+	// nothing to query
+	assert!(query_storage_ranges(10, 9, Some(5)).is_empty());
+}
+
+#[test]
+fn test_summarize_error() {
+	use anyhow::{anyhow, Context};
+
+	// the root cause is found in nested chains
+	let err = anyhow::Error::from(std::io::Error::new(
+		std::io::ErrorKind::ConnectionRefused,
+		"Connection refused (os error 111)",
+	))
+	.context("Error connecting to ws://127.0.0.1:9944")
+	.context("Failed to initialize prover");
+	assert_eq!(
+		summarize_error(&err),
+		"Connection refused (os error 111): the node isn't reachable, check its url and that it's running"
+	);
+
+	let err = anyhow!("Header with hash: 0x01 not found!").context("Failed to prove headers");
+	assert_eq!(
+		summarize_error(&err),
+		"Header with hash: 0x01 not found!: the block may have been pruned, use an archive node"
+	);
+
+	let err = anyhow::Error::from(codec::Error::from("Not enough data to fill buffer"))
+		.context("Failed to decode header");
+	assert_eq!(
+		summarize_error(&err),
+		"Not enough data to fill buffer: the prover's runtime metadata may not match the chain, \
+		 regenerate it"
+	);
+
+	let err =
+		anyhow::Error::from(ProverError::Timeout { timeout: std::time::Duration::from_secs(10) });
+	assert_eq!(
+		summarize_error(&err),
+		"Request timed out after 10s: the node is overloaded or the timeout is too short, try \
+		 increasing it"
+	);
+
+	// unknown errors are summarized by their root cause alone
+	let err = anyhow!("something else").context("while proving");
+	assert_eq!(summarize_error(&err), "something else");
+}
+
+#[tokio::test]
+async fn test_rate_limited_requests() {
+	use std::time::{Duration, Instant};
+
+	let (_, finality_proof) = mock_finality_proof();
+	let rpc = MockGrandpaRpc::new(&finality_proof);
+	let rate_limiter = RateLimiter::with_interval(Duration::from_millis(50));
+
+	// the first request goes through immediately, each of the rest waits out the interval. The
+	// limit is shared by clones, so alternating between them doesn't get around it.
+	let clone = rate_limiter.clone();
+	let start = Instant::now();
+	for block in 0..5 {
+		let limiter = if block % 2 == 0 { &rate_limiter } else { &clone };
+		limiter.limit(fetch_finality_proof::<ParaHeader>(&rpc, block)).await.unwrap();
+	}
+	assert!(start.elapsed() >= Duration::from_millis(200));
+
+	// requests per second are spaced evenly
+	let rate_limiter = RateLimiter::per_second(std::num::NonZeroU32::new(20).unwrap());
+	let start = Instant::now();
+	for block in 0..3 {
+		rate_limiter
+			.limit(fetch_finality_proof::<ParaHeader>(&rpc, block))
+			.await
+			.unwrap();
+	}
+	assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn test_relay_block_event_with_set_change_and_runtime_upgrade() {
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::DigestItem;
+
+	let change = ScheduledChange {
+		next_authorities: vec![(sp_core::ed25519::Public::from_raw([1; 32]).into(), 1)],
+		delay: 0,
+	};
+	let mut header = RelayHeader::new(
+		10,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	header.digest_mut().push(DigestItem::Consensus(
+		GRANDPA_ENGINE_ID,
+		ConsensusLog::ScheduledChange(change.clone()).encode(),
+	));
+
+	// both are reported for the same block
+	let event = RelayBlockEvent::detect(&header, 9300, 9310).unwrap();
+	assert_eq!(
+		event,
+		RelayBlockEvent::AuthoritySetChangeWithRuntimeUpgrade {
+			change: change.clone(),
+			from: 9300,
+			to: 9310
+		}
+	);
+	assert_eq!(event.authority_set_change(), Some(&change));
+	assert_eq!(event.runtime_upgrade(), Some((9300, 9310)));
+
+	// neither detector masks the other when only one of them fires
+	let event = RelayBlockEvent::detect(&header, 9300, 9300).unwrap();
+	assert_eq!(event, RelayBlockEvent::AuthoritySetChange(change));
+	assert_eq!(event.runtime_upgrade(), None);
+
+	let plain = RelayHeader::new(
+		10,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let event = RelayBlockEvent::detect(&plain, 9300, 9310).unwrap();
+	assert_eq!(event, RelayBlockEvent::RuntimeUpgrade { from: 9300, to: 9310 });
+	assert_eq!(event.authority_set_change(), None);
+
+	assert_eq!(RelayBlockEvent::detect(&plain, 9300, 9300), None);
+}
+
+#[tokio::test]
+async fn test_wait_for_lagging_para_node() {
+	use std::{sync::Mutex, time::Duration};
+
+	let catch_up = ParaCatchUp { retries: 3, interval: Duration::from_millis(10) };
+	let block = H256::repeat_byte(1);
+
+	// the para node imports the block after the first check
+	let checks = &Mutex::new(0);
+	wait_for_para_block(catch_up, block, move || async move {
+		let mut checks = checks.lock().unwrap();
+		*checks += 1;
+		Ok(*checks > 1)
+	})
+	.await
+	.unwrap();
+	assert_eq!(*checks.lock().unwrap(), 2);
+
+	// the para node never catches up
+	let checks = &Mutex::new(0);
+	let err = wait_for_para_block(catch_up, block, move || async move {
+		*checks.lock().unwrap() += 1;
+		Ok(false)
+	})
+	.await
+	.unwrap_err();
+	assert!(matches!(
+		err.downcast_ref::<ProverError>(),
+		Some(ProverError::ParaBehindRelay { block: behind, waited })
+			if *behind == block && *waited == Duration::from_millis(30)
+	));
+	assert_eq!(*checks.lock().unwrap(), 4);
+
+	// errors while checking aren't retried
+	let err = wait_for_para_block(catch_up, block, || async { Err(anyhow::anyhow!("rpc error")) })
+		.await
+		.unwrap_err();
+	assert_eq!(err.to_string(), "rpc error");
+}
+
+#[tokio::test]
+async fn test_wait_for_para_finality() {
+	use std::{sync::Mutex, time::Duration};
+
+	let poll_interval = Duration::from_millis(10);
+
+	// the target is finalized on the third poll
+	let polls = &Mutex::new(0);
+	wait_for_para_finality(12, poll_interval, Duration::from_secs(5), move || async move {
+		let mut polls = polls.lock().unwrap();
+		*polls += 1;
+		Ok(9 + *polls)
+	})
+	.await
+	.unwrap();
+	assert_eq!(*polls.lock().unwrap(), 3);
+
+	// the target is never finalized
+	let timeout = Duration::from_millis(50);
+	let err = wait_for_para_finality(12, poll_interval, timeout, || async { Ok(10) })
+		.await
+		.unwrap_err();
+	assert!(matches!(
+		err.downcast_ref::<ProverError>(),
+		Some(ProverError::Timeout { timeout: elapsed }) if *elapsed == timeout
+	));
+}
+
+#[tokio::test]
+async fn test_concurrent_requests_match_serial() {
+	use std::{
+		sync::atomic::{AtomicUsize, Ordering},
+		time::Duration,
+	};
+
+	let blocks = (1u8..=20).collect::<Vec<_>>();
+	let in_flight = &AtomicUsize::new(0);
+	let max_in_flight = &AtomicUsize::new(0);
+	// later blocks respond sooner, so the results only line up if they're kept in order.
+	let request = move |block: u8| async move {
+		let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+		max_in_flight.fetch_max(current, Ordering::SeqCst);
+		tokio::time::sleep(Duration::from_millis(25 - block as u64)).await;
+		in_flight.fetch_sub(1, Ordering::SeqCst);
+		let proofs = ParachainHeaderProofs {
+			state_proof: vec![vec![block]],
+			extrinsic: vec![block],
+			extrinsic_proof: vec![],
+			storage_key: None,
+			extrinsic_index: 0,
+		};
+		Ok((H256::repeat_byte(block), proofs))
+	};
+
+	let serial = concurrent_requests(blocks.clone(), 1, request).await.unwrap();
+	assert_eq!(max_in_flight.swap(0, Ordering::SeqCst), 1);
+
+	let concurrent = concurrent_requests(blocks.clone(), 10, request).await.unwrap();
+	assert_eq!(max_in_flight.load(Ordering::SeqCst), 10);
+
+	assert_eq!(concurrent.encode(), serial.encode());
+	let hashes = concurrent.iter().map(|(hash, _)| *hash).collect::<Vec<_>>();
+	assert_eq!(hashes, blocks.into_iter().map(H256::repeat_byte).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_streamed_requests_match_batch() {
+	use std::time::Duration;
+
+	let blocks = (1u8..=20).collect::<Vec<_>>();
+	// later blocks respond sooner, so the stream only lines up if it's kept in order.
+	let request = |block: u8| async move {
+		tokio::time::sleep(Duration::from_millis(25 - block as u64)).await;
+		let proofs = ParachainHeaderProofs {
+			state_proof: vec![vec![block]],
+			extrinsic: vec![block],
+			extrinsic_proof: vec![vec![block; 2]],
+			storage_key: None,
+			extrinsic_index: 0,
+		};
+		Ok((H256::repeat_byte(block), proofs))
+	};
+
+	let batch = concurrent_requests(blocks.clone(), 10, request).await.unwrap();
+	let streamed = stream_requests(blocks.clone(), 10, request)
+		.map(|proofs| proofs.unwrap())
+		.collect::<Vec<_>>()
+		.await;
+	assert_eq!(streamed.encode(), batch.encode());
+
+	// a failed request is streamed in its place, where the batch fails as a whole
+	let failing = |block: u8| async move {
+		match block {
+			3 => Err(anyhow::anyhow!("request failed")),
+			_ => request(block).await,
+		}
+	};
+	assert!(concurrent_requests(blocks.clone(), 10, failing).await.is_err());
+	let streamed = stream_requests(blocks, 10, failing).collect::<Vec<_>>().await;
+	assert!(streamed[..2].iter().all(Result::is_ok));
+	assert!(streamed[2].is_err());
+}
+
+#[tokio::test]
+async fn test_resubscribing_justifications() {
+	let rpc = FlakyGrandpaRpc {
+		subscriptions: std::sync::Mutex::new(vec![
+			Ok(vec![Ok(vec![1]), Err("socket closed")]),
+			Err("connection refused"),
+			// closed by the node after a justification, which resets the failures
+			Ok(vec![Ok(vec![2])]),
+			Err("connection refused"),
+			Err("node unreachable"),
+		]),
+	};
+	let stream = resubscribing_justifications(
+		std::sync::Arc::new(rpc),
+		std::time::Duration::from_millis(1),
+		2,
+	);
+
+	let items = stream.map(|item| item.map_err(|err| err.to_string())).collect::<Vec<_>>().await;
+	assert_eq!(items, vec![Ok(vec![1]), Ok(vec![2]), Err("node unreachable".to_string())]);
+}
+
+#[test]
+fn test_decode_current_set_id() {
+	use primitives::{current_set_id_storage_key, decode_current_set_id};
+
+	let key = current_set_id_storage_key().0;
+	assert_eq!(key, [sp_core::twox_128(b"Grandpa"), sp_core::twox_128(b"CurrentSetId")].concat());
+
+	assert_eq!(decode_current_set_id(Some(&7u64.encode())).unwrap(), 7);
+	// the value is only stored after the first set change
+	assert_eq!(decode_current_set_id(None).unwrap(), 0);
+	assert!(decode_current_set_id(Some(&[7, 0, 0])).is_err());
+}
+
+#[test]
+fn test_ensure_no_set_change_within() {
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::DigestItem;
+
+	// relay chain blocks 10..=20, where block 14 signals a set change enacted at 16
+	let mut headers = Vec::<RelayHeader>::new();
+	for number in 10..=20 {
+		let parent_hash = headers.last().map(sp_runtime::traits::Header::hash).unwrap_or_default();
+		let mut header = RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			parent_hash,
+			Default::default(),
+		);
+		if number == 14 {
+			let change = ScheduledChange {
+				next_authorities: vec![(sp_core::ed25519::Public::from_raw([1; 32]).into(), 1)],
+				delay: 2,
+			};
+			header.digest_mut().push(DigestItem::Consensus(
+				GRANDPA_ENGINE_ID,
+				ConsensusLog::ScheduledChange(change).encode(),
+			));
+		}
+		headers.push(header);
+	}
+	let range = |from: u32, to: u32| {
+		let headers = headers
+			.iter()
+			.filter(|header| (from..=to).contains(&header.number))
+			.cloned()
+			.collect::<Vec<_>>();
+		ensure_no_set_change_within(&headers, from, to)
+	};
+
+	// up to the enactment block, which is still finalized by the old set
+	range(10, 15).unwrap();
+	range(10, 16).unwrap();
+	// past it, the proof has to be split
+	let err = range(10, 20).unwrap_err();
+	assert!(matches!(
+		err,
+		ProverError::SpansSetChange { signalled_in, enacted_at: 16 }
+			if signalled_in == sp_runtime::traits::Header::hash(&headers[4])
+	));
+	// the client has rotated
+	range(16, 20).unwrap();
+}
+
+#[test]
+fn test_forced_change_activation() {
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::DigestItem;
+
+	// relay chain blocks 1..=30, block 8 schedules a set change and block 21 forces one
+	let change = |delay| ScheduledChange {
+		next_authorities: vec![(sp_core::ed25519::Public::from_raw([1; 32]).into(), 1)],
+		delay,
+	};
+	let mut headers = Vec::<RelayHeader>::new();
+	for number in 1..=30 {
+		let parent_hash = headers.last().map(sp_runtime::traits::Header::hash).unwrap_or_default();
+		let mut header = RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			parent_hash,
+			Default::default(),
+		);
+		let log = match number {
+			8 => Some(ConsensusLog::ScheduledChange(change(3))),
+			21 => Some(ConsensusLog::ForcedChange(15, change(5))),
+			_ => None,
+		};
+		if let Some(log) = log {
+			header.digest_mut().push(DigestItem::Consensus(GRANDPA_ENGINE_ID, log.encode()));
+		}
+		headers.push(header);
+	}
+
+	let forced = AuthoritySetChange::Forced {
+		signalled_at: 21,
+		enacted_at: 26,
+		median_last_finalized: 15,
+		change: change(5),
+	};
+	assert_eq!(authority_set_changes(&headers[20]), vec![forced.clone()]);
+	assert_eq!(forced.enacted_at(), 26);
+	// the forced change is enacted on import, so its block is finalized by the next set
+	assert_eq!(forced.last_block_of_previous_set(), 25);
+
+	let scheduled =
+		AuthoritySetChange::Scheduled { signalled_at: 8, enacted_at: 11, change: change(3) };
+	assert_eq!(scheduled.last_block_of_previous_set(), 11);
+	let finality_proof = FinalityProof {
+		block: sp_runtime::traits::Header::hash(&headers[29]),
+		justification: vec![],
+		unknown_headers: headers.clone(),
+	};
+	assert_eq!(finality_proof.authority_set_changes(), vec![scheduled, forced]);
+
+	// proofs are split before the block the forced change is enacted at
+	ensure_no_set_change_within(&headers[19..25], 20, 25).unwrap();
+	assert!(matches!(
+		ensure_no_set_change_within(&headers[19..26], 20, 26),
+		Err(ProverError::SpansSetChange { signalled_in, enacted_at: 25 })
+			if signalled_in == sp_runtime::traits::Header::hash(&headers[20])
+	));
+}
+
+#[tokio::test]
+async fn test_set_change_justifications() {
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::DigestItem;
+	use std::{
+		sync::atomic::{AtomicUsize, Ordering},
+		time::Duration,
+	};
+
+	// relay chain blocks 1..=30 spanning two set changes, signalled at 8 and 18 and enacted at the
+	// ends of their epochs, 10 and 20
+	let mut headers = Vec::<RelayHeader>::new();
+	for number in 1..=30 {
+		let parent_hash = headers.last().map(sp_runtime::traits::Header::hash).unwrap_or_default();
+		let mut header = RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			parent_hash,
+			Default::default(),
+		);
+		if number == 8 || number == 18 {
+			let change = ScheduledChange {
+				next_authorities: vec![(sp_core::ed25519::Public::from_raw([1; 32]).into(), 1)],
+				delay: 2,
+			};
+			header.digest_mut().push(DigestItem::Consensus(
+				GRANDPA_ENGINE_ID,
+				ConsensusLog::ScheduledChange(change).encode(),
+			));
+		}
+		headers.push(header);
+	}
+	let finality_proof = |block: u32| vec![block as u8; 4];
+	let requested = std::sync::Mutex::new(vec![]);
+	let prove_finality = |block: u32| {
+		requested.lock().unwrap().push(block);
+		async move { Ok(Some(finality_proof(block))) }
+	};
+
+	let poll = JustificationPoll { retries: 2, interval: Duration::from_millis(1) };
+	let justifications =
+		set_change_justifications(&headers, 1, 30, poll, prove_finality).await.unwrap();
+	let justifications = justifications
+		.into_iter()
+		.map(|(block, justification)| (block, justification.into_inner()))
+		.collect::<Vec<_>>();
+	assert_eq!(justifications, vec![(10, finality_proof(10)), (20, finality_proof(20))]);
+	assert_eq!(*requested.lock().unwrap(), vec![10, 20]);
+
+	// a client that has rotated at 10 only needs the second change
+	let justifications = set_change_justifications(&headers[9..], 10, 30, poll, prove_finality)
+		.await
+		.unwrap();
+	assert_eq!(justifications.iter().map(|(block, _)| *block).collect::<Vec<_>>(), vec![20]);
+	// and none before the epoch ends
+	let justifications = set_change_justifications(&headers[9..19], 10, 19, poll, prove_finality)
+		.await
+		.unwrap();
+	assert!(justifications.is_empty());
+
+	// the node doesn't store the justification of the first change in time
+	let err = set_change_justifications(&headers, 1, 30, poll, |block| async move {
+		Ok((block != 10).then(|| finality_proof(block)))
+	})
+	.await
+	.unwrap_err();
+	assert!(matches!(
+		ProverError::from(err),
+		ProverError::JustificationNotYetAvailable { block: 10, .. }
+	));
+
+	// or stores it while it's polled for
+	let pending = AtomicUsize::new(1);
+	let justifications = set_change_justifications(&headers, 1, 30, poll, |block| {
+		let stored = block != 10 ||
+			pending
+				.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| pending.checked_sub(1))
+				.is_err();
+		async move { Ok(stored.then(|| finality_proof(block))) }
+	})
+	.await
+	.unwrap();
+	assert_eq!(justifications.len(), 2);
+}
+
+#[test]
+fn test_mandatory_headers_only() {
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::DigestItem;
+
+	// relay chain blocks 1..=30, block 8 signals a set change and block 21 a forced change
+	let change = ScheduledChange {
+		next_authorities: vec![(sp_core::ed25519::Public::from_raw([1; 32]).into(), 1)],
+		delay: 0,
+	};
+	let mut headers = Vec::<RelayHeader>::new();
+	for number in 1..=30 {
+		let parent_hash = headers.last().map(sp_runtime::traits::Header::hash).unwrap_or_default();
+		let mut header = RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			parent_hash,
+			Default::default(),
+		);
+		let log = match number {
+			8 => Some(ConsensusLog::ScheduledChange(change.clone())),
+			21 => Some(ConsensusLog::ForcedChange(5, change.clone())),
+			_ => None,
+		};
+		if let Some(log) = log {
+			header.digest_mut().push(DigestItem::Consensus(GRANDPA_ENGINE_ID, log.encode()));
+		}
+		headers.push(header);
+	}
+
+	let block = sp_runtime::traits::Header::hash(&headers[29]);
+	let finality_proof =
+		FinalityProof { block, justification: vec![1, 2, 3], unknown_headers: headers };
+	let mandatory = mandatory_headers_only(finality_proof).unwrap();
+	let numbers = mandatory.unknown_headers.iter().map(|header| header.number).collect::<Vec<_>>();
+	assert_eq!(numbers, vec![8, 21, 30]);
+	assert_eq!(mandatory.block, block);
+	assert_eq!(mandatory.justification, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_decode_para_headers_page() {
+	let changes = vec![
+		(100u32, Some(head_data(5))),
+		(101, None),
+		(102, Some(head_data(6))),
+		(103, Some(head_data(7))),
+		(104, Some(head_data(8))),
+	];
+	let page = |changes: &[(u32, Option<Vec<u8>>)], max_headers| {
+		let (headers, continue_from) =
+			decode_para_headers_page::<ParaHeader, _>(changes.to_vec(), max_headers).unwrap();
+		(headers.iter().map(|header| header.number).collect::<Vec<_>>(), continue_from)
+	};
+
+	// exactly at the limit, there's nothing to continue from
+	assert_eq!(page(&changes, 4), (vec![5, 6, 7, 8], None));
+	assert_eq!(page(&changes, 10), (vec![5, 6, 7, 8], None));
+
+	// continues from the block of the first header left out
+	assert_eq!(page(&changes, 2), (vec![5, 6], Some(103)));
+	let rest = changes
+		.iter()
+		.skip_while(|(block, _)| *block != 103)
+		.cloned()
+		.collect::<Vec<_>>();
+	assert_eq!(page(&rest, 2), (vec![7, 8], None));
+
+	assert_eq!(page(&changes, 0), (vec![], Some(100)));
+}
+
+#[tokio::test]
+async fn test_proof_stats() {
+	use std::{sync::atomic::Ordering, time::Duration};
+
+	let unknown_headers = (1..=3u32)
+		.map(|number| {
+			RelayHeader::new(
+				number,
+				Default::default(),
+				Default::default(),
+				Default::default(),
+				Default::default(),
+			)
+		})
+		.collect();
+	let proofs = |state_proof: Vec<Vec<u8>>| ParachainHeaderProofs {
+		state_proof,
+		extrinsic: vec![1; 100],
+		extrinsic_proof: vec![vec![2; 50]],
+		storage_key: None,
+		extrinsic_index: 0,
+	};
+	let proof = ParachainHeadersWithFinalityProof::<RelayHeader> {
+		finality_proof: FinalityProof {
+			block: H256::repeat_byte(1),
+			justification: vec![3; 64],
+			unknown_headers,
+		},
+		parachain_headers: [
+			(H256::repeat_byte(2), proofs(vec![vec![0; 10], vec![0; 5]])),
+			(H256::repeat_byte(3), proofs(vec![vec![0; 7]])),
+		]
+		.into_iter()
+		.collect(),
+	};
+
+	// only the state proofs count towards their size, not the extrinsics
+	let stats = ProofStats::new(&proof, 4, Duration::from_millis(5));
+	assert_eq!(
+		stats,
+		ProofStats {
+			unknown_header_count: 3,
+			parachain_header_count: 2,
+			total_state_proof_bytes: 22,
+			rpc_calls: 4,
+			elapsed: Duration::from_millis(5),
+		}
+	);
+
+	// the counter agrees with the rpc's own record, also for requests made concurrently
+	let (_, finality_proof) = mock_finality_proof();
+	let rpc = CountingGrandpaRpc {
+		inner: MockGrandpaRpc::new(&finality_proof),
+		calls: Default::default(),
+	};
+	let counter = RpcCallCounter::default();
+	let (rpc, clone) = (&rpc, counter.clone());
+	concurrent_requests(0..5u32, 3, |block| {
+		let counter = clone.clone();
+		async move { counter.count(fetch_finality_proof::<ParaHeader>(rpc, block)).await }
+	})
+	.await
+	.unwrap();
+	assert_eq!(counter.calls(), 5);
+	assert_eq!(rpc.calls.load(Ordering::SeqCst), 5);
+}
+
+#[tokio::test]
+async fn test_retry_with_backoff() {
+	use std::{sync::atomic::Ordering, time::Duration};
+
+	let (_, finality_proof) = mock_finality_proof();
+	let rpc = |failures: Vec<anyhow::Error>| UnreliableGrandpaRpc {
+		inner: MockGrandpaRpc::new(&finality_proof),
+		failures: std::sync::Mutex::new(failures),
+		calls: Default::default(),
+	};
+	let transport_error =
+		|| anyhow::Error::from(ProverError::Transport { reason: "connection reset".into() });
+	let policy = RetryPolicy { max_retries: 3, base_delay: Duration::from_millis(1) };
+
+	// transient failures are retried until the request succeeds
+	let unreliable = rpc(vec![
+		transport_error(),
+		ProverError::Timeout { timeout: Duration::from_secs(1) }.into(),
+		transport_error(),
+	]);
+	let (proof, _) = retry(policy, || fetch_finality_proof::<ParaHeader>(&unreliable, 10))
+		.await
+		.unwrap();
+	assert_eq!(proof.block, H256::repeat_byte(7));
+	assert_eq!(unreliable.calls.load(Ordering::SeqCst), 4);
+
+	// gives up once the retries run out
+	let unreliable = rpc((0..3).map(|_| transport_error()).collect());
+	let policy = RetryPolicy { max_retries: 2, ..policy };
+	assert!(retry(policy, || fetch_finality_proof::<ParaHeader>(&unreliable, 10))
+		.await
+		.is_err());
+	assert_eq!(unreliable.calls.load(Ordering::SeqCst), 3);
+
+	// logical errors aren't retried, the node would return the same again
+	let failing = rpc(vec![anyhow::anyhow!("Header not found")]);
+	assert!(retry(policy, || fetch_finality_proof::<ParaHeader>(&failing, 10))
+		.await
+		.is_err());
+	assert_eq!(failing.calls.load(Ordering::SeqCst), 1);
+	assert!(!is_retryable(&ProverError::ProofKeyMismatch { key: vec![] }.into()));
+	assert!(is_retryable(&transport_error().context("Failed to fetch header")));
+
+	// the delay doubles with every retry, with up to half of it added as jitter
+	let policy = RetryPolicy { max_retries: 3, base_delay: Duration::from_millis(100) };
+	for (retry, base) in [(0, 100), (1, 200), (2, 400)] {
+		let delay = policy.delay(retry);
+		assert!(
+			delay >= Duration::from_millis(base) && delay <= Duration::from_millis(base * 3 / 2)
+		);
+	}
+
+	// large base delays saturate instead of overflowing
+	let policy = RetryPolicy { max_retries: 3, base_delay: Duration::MAX };
+	assert_eq!(policy.delay(0), Duration::MAX);
+	assert_eq!(policy.delay(16), Duration::MAX);
+	let policy = RetryPolicy { max_retries: 3, base_delay: Duration::from_secs(u64::MAX / 2) };
+	assert!(policy.delay(0) >= Duration::from_secs(u64::MAX / 2));
+	assert!(policy.delay(1) >= Duration::from_secs(u64::MAX - 1));
+}
+
+#[tokio::test]
+async fn test_select_canonical_justification() {
+	let justification = |target_hash: H256| {
+		GrandpaJustification::<ParaHeader> {
+			round: 1,
+			commit: Commit { target_hash, target_number: 12, precommits: vec![] },
+			votes_ancestries: vec![],
+		}
+		.encode()
+	};
+	let canonical = justification(H256::repeat_byte(1));
+	let reorged = justification(H256::repeat_byte(2));
+	let block_hash = |number: u32| async move {
+		assert_eq!(number, 12);
+		Ok::<_, anyhow::Error>(Some(H256::repeat_byte(1)))
+	};
+
+	// the node offers justifications for both blocks at number 12
+	for justifications in
+		[vec![reorged.clone(), canonical.clone()], vec![canonical.clone(), reorged.clone()]]
+	{
+		let rpc = MockGrandpaRpc { finality_proof: None, justifications };
+		let offered = rpc
+			.subscribe_justifications()
+			.await
+			.unwrap()
+			.map(Result::unwrap)
+			.collect::<Vec<_>>()
+			.await;
+		let selected = select_canonical_justification::<ParaHeader, _, _>(offered, block_hash)
+			.await
+			.unwrap();
+		assert_eq!(selected, canonical);
+	}
+
+	// off-canon justifications are rejected
+	let err = select_canonical_justification::<ParaHeader, _, _>(vec![reorged], block_hash)
+		.await
+		.unwrap_err();
+	assert!(matches!(
+		err.downcast_ref::<ProverError>(),
+		Some(ProverError::NonCanonicalJustification { number: 12, canonical: Some(_), .. })
+	));
+}
+
+#[test]
+fn test_prove_multiple_parachains() {
+	use std::collections::BTreeMap;
+
+	let key = |para_id: u32| parachain_header_storage_key(para_id).0;
+	// a single change set queried for the heads of parachains 2000 and 2001, and an unrelated key
+	let change_set = vec![
+		(100u32, vec![(key(2000), Some(head_data(5))), (key(2001), Some(head_data(40)))]),
+		(101, vec![(key(2001), Some(head_data(41))), (vec![1, 2, 3], Some(vec![4]))]),
+		(102, vec![(key(2000), Some(head_data(6))), (key(2001), None)]),
+	];
+	let header_numbers = [(2000, vec![5, 6]), (2001, vec![41]), (2002, vec![1])]
+		.into_iter()
+		.collect::<BTreeMap<_, _>>();
+
+	let included = requested_para_headers_by_id::<ParaHeader, _>(
+		change_set,
+		&header_numbers,
+		parachain_header_storage_key,
+	)
+	.unwrap();
+	let included = included
+		.iter()
+		.map(|(para_id, headers)| {
+			(*para_id, headers.iter().map(|(block, header)| (*block, header.number)).collect())
+		})
+		.collect::<BTreeMap<_, Vec<_>>>();
+	assert_eq!(included[&2000], vec![(100, 5), (102, 6)]);
+	assert_eq!(included[&2001], vec![(101, 41)]);
+	assert_eq!(included[&2002], vec![]);
+
+	// every parachain's proof shares the single finality proof
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: H256::repeat_byte(1),
+		justification: vec![1, 2, 3],
+		unknown_headers: vec![],
+	};
+	let proofs = |byte: u8| ParachainHeaderProofs {
+		state_proof: vec![vec![byte]],
+		extrinsic: vec![],
+		extrinsic_proof: vec![],
+		storage_key: None,
+		extrinsic_index: 0,
+	};
+	let parachain_headers = [
+		(
+			2000,
+			[(H256::repeat_byte(2), proofs(1)), (H256::repeat_byte(4), proofs(2))]
+				.into_iter()
+				.collect::<BTreeMap<_, _>>(),
+		),
+		(2001, [(H256::repeat_byte(3), proofs(3))].into_iter().collect()),
+	]
+	.into_iter()
+	.collect();
+
+	let proofs = share_finality_proof(finality_proof.clone(), parachain_headers);
+	assert_eq!(proofs.keys().copied().collect::<Vec<_>>(), vec![2000, 2001]);
+	for proof in proofs.values() {
+		assert_eq!(proof.finality_proof, finality_proof);
+	}
+	let relay_blocks =
+		|para_id: u32| proofs[&para_id].parachain_headers.keys().copied().collect::<Vec<_>>();
+	assert_eq!(relay_blocks(2000), vec![H256::repeat_byte(2), H256::repeat_byte(4)]);
+	assert_eq!(relay_blocks(2001), vec![H256::repeat_byte(3)]);
+	assert_eq!(proofs[&2001].parachain_headers[&H256::repeat_byte(3)].state_proof, vec![vec![3]]);
+
+	// the proofs are keyed by para id, so each parachain is queried once
+	ensure_distinct_para_ids([2000, 2001, 2002]).unwrap();
+	assert!(matches!(
+		ensure_distinct_para_ids([2000, 2001, 2000]),
+		Err(ProverError::DuplicateParaId { para_id: 2000 })
+	));
+}
+
+#[test]
+fn test_prover_error_from_anyhow() {
+	use anyhow::{anyhow, Context};
+
+	// typed errors keep their variant when passing through `anyhow`
+	let err = anyhow::Error::from(ProverError::HeaderNotFound(H256::repeat_byte(1)));
+	assert!(matches!(
+		ProverError::from(err),
+		ProverError::HeaderNotFound(hash) if hash == H256::repeat_byte(1)
+	));
+	let err = anyhow::Error::from(codec::Error::from("Not enough data to fill buffer"));
+	assert!(matches!(ProverError::from(err), ProverError::Decode(_)));
+	let err = anyhow::Error::from(jsonrpsee::core::Error::RequestTimeout);
+	assert!(matches!(ProverError::from(err), ProverError::Rpc(_)));
+
+	// other errors keep their whole chain
+	let err = anyhow!("Block hash not found for number: 10").context("Failed to prove headers");
+	match ProverError::from(err) {
+		ProverError::Custom(message) =>
+			assert_eq!(message, "Failed to prove headers: Block hash not found for number: 10"),
+		err => panic!("unexpected error: {err:?}"),
+	}
+
+	let err = anyhow::Error::from(ProverError::StorageItemMissing {
+		item: "Paras::Heads",
+		at: H256::repeat_byte(2),
+	});
+	assert_eq!(
+		summarize_error(&err),
+		format!(
+			"Storage item Paras::Heads not found at block: {:?}: the block may have been pruned, \
+			 use an archive node",
+			H256::repeat_byte(2)
+		)
+	);
+}
+
+#[test]
+fn test_timestamp_inherent_lookup() {
+	use sp_trie::{LayoutV0, TrieConfiguration};
+
+	// extrinsics as returned by the node, with their calls prefixed by the extrinsic version
+	let timestamp = (4u8, 3u8, 0u8, codec::Compact(1_668_000_000_000u64)).encode().encode();
+	let validation_data = (4u8, 1u8, 0u8, vec![7u8; 40]).encode().encode();
+	let other_inherent = (4u8, 9u8, 0u8, codec::Compact(5u64)).encode().encode();
+	let signed = (0x84u8, 3u8, 0u8, codec::Compact(1u64)).encode().encode();
+	let block = H256::repeat_byte(1);
+
+	let proven_at = |extrinsics: &[Vec<u8>], call, index: u32| {
+		let (found, proof) = match prove_timestamp_inherent(extrinsics, call, block) {
+			Ok(proof) => proof,
+			Err(err) => panic!("{err}"),
+		};
+		assert_eq!(found, index);
+		assert_eq!(proof.ext, timestamp);
+		let extrinsics_root = LayoutV0::<BlakeTwo256>::ordered_trie_root(
+			extrinsics.iter().map(|extrinsic| &extrinsic[..]),
+		);
+		sp_trie::verify_trie_proof::<LayoutV0<BlakeTwo256>, _, _, _>(
+			&extrinsics_root,
+			&proof.proof,
+			&[(codec::Compact(index).encode(), Some(&proof.ext))],
+		)
+		.unwrap();
+	};
+
+	// the timestamp inherent first, as in standard runtimes
+	let extrinsics = vec![timestamp.clone(), validation_data.clone(), signed.clone()];
+	proven_at(&extrinsics, None, 0);
+	proven_at(&extrinsics, Some((3, 0)), 0);
+
+	// after other inherents, it's found by its shape or indices
+	let extrinsics = vec![validation_data.clone(), signed.clone(), timestamp.clone()];
+	proven_at(&extrinsics, None, 2);
+	proven_at(&extrinsics, Some((3, 0)), 2);
+
+	// inherents of the same shape need the indices to be told apart
+	let extrinsics = vec![other_inherent.clone(), validation_data.clone(), timestamp.clone()];
+	proven_at(&extrinsics, Some((3, 0)), 2);
+
+	// no timestamp inherent
+	let extrinsics = vec![validation_data, signed];
+	assert!(matches!(
+		prove_timestamp_inherent(&extrinsics, None, block),
+		Err(ProverError::TimestampInherentNotFound { block: found }) if found == block
+	));
+	let extrinsics = vec![other_inherent, timestamp];
+	assert!(matches!(
+		prove_timestamp_inherent(&extrinsics, Some((4, 0)), block),
+		Err(ProverError::TimestampInherentNotFound { .. })
+	));
+}
+
+#[cfg(feature = "dry-run")]
+#[test]
+fn test_dry_run_rejects_corrupted_state_proof() {
+	use crate::dry_run_verify;
+	use sp_core::Pair;
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let pair = sp_core::ed25519::Pair::from_seed(&[1; 32]);
+	let relay_hash = |header: &RelayHeader| sp_runtime::traits::Header::hash(header);
+
+	// a parachain header committing to its timestamp extrinsic
+	let extrinsic = (4u8, 3u8, 0u8, codec::Compact(1_668_000_000_000u64)).encode().encode();
+	let extrinsic_key = codec::Compact(0u32).encode();
+	let mut db = sp_trie::MemoryDB::<BlakeTwo256>::default();
+	let mut extrinsics_root = H256::default();
+	{
+		let mut trie =
+			TrieDBMutBuilder::<LayoutV0<BlakeTwo256>>::new(&mut db, &mut extrinsics_root).build();
+		trie.insert(&extrinsic_key, &extrinsic).unwrap();
+	}
+	let extrinsic_proof = sp_trie::generate_trie_proof::<LayoutV0<BlakeTwo256>, _, _, _>(
+		&db,
+		extrinsics_root,
+		&[&extrinsic_key],
+	)
+	.unwrap();
+	let para_header = RelayHeader::new(
+		7,
+		extrinsics_root,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+
+	// the relay chain block finalized by the proof includes it
+	let para_storage_key = parachain_header_storage_key(2000);
+	let para_key = para_storage_key.0.clone();
+	let (state_root, state_proof) =
+		read_proof(&[(para_key.clone(), para_header.encode().encode())], &para_key);
+	let base = RelayHeader::new(
+		1,
+		Default::default(),
+		Default::default(),
+		H256::zero(),
+		Default::default(),
+	);
+	let target =
+		RelayHeader::new(2, Default::default(), state_root, relay_hash(&base), Default::default());
+
+	let justification = signed_justification(&pair, &target);
+
+	let client_state = primitives::ClientState {
+		current_authorities: vec![(pair.public().into(), 1)],
+		current_set_id: 0,
+		latest_relay_height: 1,
+		latest_para_height: 6,
+		latest_relay_hash: relay_hash(&base),
+		para_id: 2000,
+	};
+	let proof = |state_proof: Vec<Vec<u8>>| ParachainHeadersWithFinalityProof::<RelayHeader> {
+		finality_proof: FinalityProof {
+			block: relay_hash(&target),
+			justification: justification.encode(),
+			unknown_headers: vec![base.clone(), target.clone()],
+		},
+		parachain_headers: [(
+			relay_hash(&target),
+			ParachainHeaderProofs {
+				state_proof,
+				extrinsic: extrinsic.clone(),
+				extrinsic_proof: extrinsic_proof.clone(),
+				storage_key: Some(para_key.clone()),
+				extrinsic_index: 0,
+			},
+		)]
+		.into_iter()
+		.collect(),
+	};
+
+	let updated =
+		dry_run_verify(client_state.clone(), &para_storage_key, &proof(state_proof.clone()))
+			.unwrap();
+	assert_eq!(updated.latest_relay_hash, relay_hash(&target));
+	assert_eq!(updated.latest_para_height, 7);
+
+	// a state proof with a tampered node no longer proves the parachain header
+	let mut corrupted = state_proof;
+	let node = corrupted.last_mut().unwrap();
+	*node.last_mut().unwrap() ^= 1;
+	assert!(matches!(
+		dry_run_verify(client_state, &para_storage_key, &proof(corrupted)),
+		Err(ProverError::ProofRejected { .. })
+	));
+}
+
+#[test]
+fn test_stalled_parachain() {
+	let head = |number: u32| {
+		RelayHeader::new(
+			number,
+			Default::default(),
+			Default::default(),
+			H256::zero(),
+			Default::default(),
+		)
+		.encode()
+	};
+
+	// the parachain produced no header within the range, its last known head is still proven
+	let latest = LatestParaHeader::classify(Some(&head(5)), &head(5), "proof of 5");
+	assert_eq!(latest, LatestParaHeader::Stalled("proof of 5"));
+	assert!(latest.is_stalled());
+	assert_eq!(latest.into_inner(), "proof of 5");
+
+	// it advanced, possibly by several headers
+	let latest = LatestParaHeader::classify(Some(&head(5)), &head(8), "proof of 8");
+	assert_eq!(latest, LatestParaHeader::Updated("proof of 8"));
+	assert!(!latest.is_stalled());
+
+	// the parachain was onboarded within the range
+	let latest = LatestParaHeader::classify(None, &head(1), "proof of 1");
+	assert_eq!(latest, LatestParaHeader::Updated("proof of 1"));
+}
+
+#[test]
+fn test_custom_para_head_key() {
+	use sp_core::storage::StorageKey;
+	use std::{collections::BTreeMap, sync::Arc};
+
+	// a relay chain that renamed the `Paras` pallet to `Parachains`
+	let custom: ParaHeadKey = Arc::new(|para_id: u32| {
+		let mut key = sp_core::twox_128(b"Parachains").to_vec();
+		key.extend(sp_core::twox_128(b"Heads"));
+		key.extend(sp_core::twox_64(&para_id.encode()));
+		key.extend(para_id.encode());
+		StorageKey(key)
+	});
+	assert_eq!(para_head_storage_key(None, 2000), parachain_header_storage_key(2000));
+	assert_ne!(para_head_storage_key(Some(&custom), 2000), parachain_header_storage_key(2000));
+
+	// only the heads under the custom key are the parachain's
+	let change_set = vec![
+		(100u32, vec![(para_head_storage_key(Some(&custom), 2000).0, Some(head_data(5)))]),
+		(101, vec![(parachain_header_storage_key(2000).0, Some(head_data(6)))]),
+		(102, vec![(para_head_storage_key(Some(&custom), 2000).0, Some(head_data(7)))]),
+	];
+	let header_numbers = [(2000, vec![5, 6, 7])].into_iter().collect::<BTreeMap<_, _>>();
+	let included =
+		requested_para_headers_by_id::<ParaHeader, _>(change_set, &header_numbers, |para_id| {
+			para_head_storage_key(Some(&custom), para_id)
+		})
+		.unwrap();
+	let included = included[&2000]
+		.iter()
+		.map(|(block, header)| (*block, header.number))
+		.collect::<Vec<_>>();
+	assert_eq!(included, vec![(100, 5), (102, 7)]);
+}
+
+#[cfg(feature = "dry-run")]
+#[tokio::test]
+async fn test_custom_para_head_key_proof_verifies() {
+	use crate::{dry_run_verify, mock::mock_prover};
+	use codec::Compact;
+	use sp_core::{storage::StorageKey, Pair};
+	use std::sync::Arc;
+
+	const PARA_ID: u32 = 2000;
+
+	// a relay chain that renamed the `Paras` pallet to `Parachains`
+	let custom: ParaHeadKey = Arc::new(|para_id: u32| {
+		let mut key = sp_core::twox_128(b"Parachains").to_vec();
+		key.extend(sp_core::twox_128(b"Heads"));
+		key.extend(sp_core::twox_64(&para_id.encode()));
+		key.extend(para_id.encode());
+		StorageKey(key)
+	});
+	let pair = sp_core::ed25519::Pair::from_seed(&[1; 32]);
+	let (relay_chain, para_chain) = finalized_mock_chains(&pair, &custom(PARA_ID).0, |number| {
+		vec![(4u8, 3u8, 0u8, Compact(number * 12_000)).encode().encode()]
+	});
+	let client_state = mock_client_state(&pair, &relay_chain, PARA_ID);
+	let prover = mock_prover::<subxt::PolkadotConfig>(relay_chain, para_chain, PARA_ID)
+		.await
+		.unwrap()
+		.with_para_head_key(custom)
+		.await
+		.unwrap();
+
+	let proof = prover
+		.query_finalized_parachain_headers_with_proof::<ParaHeader>(1, 12, None, (1..=6).collect())
+		.await
+		.unwrap();
+
+	// the verifier reads the heads from the prover's key
+	let updated =
+		dry_run_verify(client_state.clone(), &prover.para_head_storage_key(PARA_ID), &proof)
+			.unwrap();
+	assert_eq!(updated.latest_relay_height, 12);
+	assert_eq!(updated.latest_para_height, 6);
+
+	// but not from `Paras::Heads`
+	assert!(matches!(
+		dry_run_verify(client_state, &parachain_header_storage_key(PARA_ID), &proof),
+		Err(ProverError::ProofRejected { .. })
+	));
+}
+
+#[cfg(feature = "dry-run")]
+#[tokio::test]
+async fn test_timestamp_inherent_after_other_inherents_verifies() {
+	use crate::{dry_run_verify, mock::mock_prover};
+	use codec::Compact;
+	use sp_core::Pair;
+
+	const PARA_ID: u32 = 2000;
+
+	// the timestamp inherent follows two other inherents in every parachain block
+	let pair = sp_core::ed25519::Pair::from_seed(&[1; 32]);
+	let para_key = parachain_header_storage_key(PARA_ID);
+	let (relay_chain, para_chain) = finalized_mock_chains(&pair, &para_key.0, |number| {
+		vec![
+			(4u8, 1u8, 0u8, vec![7u8; 40]).encode().encode(),
+			(4u8, 2u8, 0u8, vec![8u8; 40]).encode().encode(),
+			(4u8, 3u8, 0u8, Compact(number * 12_000)).encode().encode(),
+		]
+	});
+	let client_state = mock_client_state(&pair, &relay_chain, PARA_ID);
+	let prover = mock_prover::<subxt::PolkadotConfig>(relay_chain, para_chain, PARA_ID)
+		.await
+		.unwrap();
+
+	let proof = prover
+		.query_finalized_parachain_headers_with_proof::<ParaHeader>(1, 12, None, (1..=6).collect())
+		.await
+		.unwrap();
+	assert!(!proof.parachain_headers.is_empty());
+	assert!(proof.parachain_headers.values().all(|proofs| proofs.extrinsic_index == 2));
+
+	let updated = dry_run_verify(client_state.clone(), &para_key, &proof).unwrap();
+	assert_eq!(updated.latest_para_height, 6);
+
+	// the proofs don't verify at the first extrinsic
+	let mut at_first = proof;
+	at_first
+		.parachain_headers
+		.values_mut()
+		.for_each(|proofs| proofs.extrinsic_index = 0);
+	assert!(matches!(
+		dry_run_verify(client_state, &para_key, &at_first),
+		Err(ProverError::ProofRejected { .. })
+	));
+}
+
+#[cfg(feature = "dry-run")]
+#[tokio::test]
+async fn test_prove_next_update_across_set_change() {
+	use crate::{dry_run_verify, mock::mock_prover};
+	use codec::Compact;
+	use sp_core::Pair;
+	use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID};
+	use sp_runtime::{Digest, DigestItem};
+
+	const PARA_ID: u32 = 2000;
+
+	// the relay chain of `finalized_mock_chains`, with a set change signalled at block 4 and
+	// enacted at block 6, so blocks 1..=6 are finalized by `pair` and the rest by `next_pair`
+	let pair = sp_core::ed25519::Pair::from_seed(&[1; 32]);
+	let next_pair = sp_core::ed25519::Pair::from_seed(&[2; 32]);
+	let para_key = parachain_header_storage_key(PARA_ID);
+	let (_, para_chain) = finalized_mock_chains(&pair, &para_key.0, |number| {
+		vec![(4u8, 3u8, 0u8, Compact(number * 12_000)).encode().encode()]
+	});
+	let para_heads =
+		para_chain.headers().map(|header| header.encode().encode()).collect::<Vec<_>>();
+	let mut relay_chain = crate::mock::MockChain::default();
+	for number in 1..=12 {
+		let mut digest = Digest::default();
+		if number == 4 {
+			let change = ScheduledChange {
+				next_authorities: vec![(next_pair.public().into(), 1)],
+				delay: 2,
+			};
+			let log = ConsensusLog::ScheduledChange(change).encode();
+			digest.push(DigestItem::Consensus(GRANDPA_ENGINE_ID, log));
+		}
+		let state = vec![(para_key.0.clone(), para_heads[number / 2].clone())];
+		relay_chain.push_block_with_digest(state, vec![], digest);
+	}
+	let headers = relay_chain.headers().cloned().collect::<Vec<_>>();
+	for (number, pair, set_id) in [(6, &pair, 0), (12, &next_pair, 1)] {
+		let target = &headers[number];
+		let finality_proof = FinalityProof::<ParaHeader> {
+			block: sp_runtime::traits::Header::hash(target),
+			justification: signed_justification_in_set(pair, set_id, target).encode(),
+			unknown_headers: vec![],
+		};
+		relay_chain.set_finality_proof(number as u32, finality_proof.encode());
+	}
+	let client_state = mock_client_state(&pair, &relay_chain, PARA_ID);
+	let prover = mock_prover::<subxt::PolkadotConfig>(relay_chain, para_chain, PARA_ID)
+		.await
+		.unwrap();
+
+	// the first update ends at the last block finalized by the client's set
+	let proof = prover.prove_next_update::<ParaHeader>(&client_state).await.unwrap().unwrap();
+	let client_state = dry_run_verify(client_state, &para_key, &proof).unwrap();
+	assert_eq!(client_state.latest_relay_height, 6);
+	assert_eq!(client_state.latest_para_height, 3);
+	assert_eq!(client_state.current_set_id, 1);
+	assert_eq!(client_state.current_authorities, vec![(next_pair.public().into(), 1)]);
+
+	// the next one continues with the rotated set up to the finalized head
+	let proof = prover.prove_next_update::<ParaHeader>(&client_state).await.unwrap().unwrap();
+	let client_state = dry_run_verify(client_state, &para_key, &proof).unwrap();
+	assert_eq!(client_state.latest_relay_height, 12);
+	assert_eq!(client_state.latest_para_height, 6);
+
+	// and the client is then up to date
+	assert!(prover.prove_next_update::<ParaHeader>(&client_state).await.unwrap().is_none());
+}
+
+#[test]
+fn test_checked_block_number() {
+	assert_eq!(checked_block_number(12u32).unwrap(), 12);
+	assert_eq!(checked_block_number(u64::from(u32::MAX)).unwrap(), u32::MAX);
+
+	// wider block numbers past the 32-bit limit aren't truncated
+	assert!(matches!(
+		checked_block_number(u64::from(u32::MAX) + 1),
+		Err(ProverError::BlockNumberOutOfRange { number }) if number == "4294967296"
+	));
+	assert!(matches!(
+		checked_block_number(u128::MAX),
+		Err(ProverError::BlockNumberOutOfRange { .. })
+	));
+}
+
+#[test]
+fn test_client_init_proof() {
+	use primitives::current_set_id_storage_key;
+	use sp_core::Pair;
+	use sp_finality_grandpa::{AuthorityList, VersionedAuthorityList, GRANDPA_AUTHORITIES_KEY};
+	use sp_state_machine::TrieBackendBuilder;
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let authorities = |seeds: &[u8]| {
+		seeds
+			.iter()
+			.map(|seed| (sp_core::ed25519::Pair::from_seed(&[*seed; 32]).public().into(), 1))
+			.collect::<AuthorityList>()
+	};
+	let set_id_key = current_set_id_storage_key().0;
+	let entries = vec![
+		(
+			GRANDPA_AUTHORITIES_KEY.to_vec(),
+			VersionedAuthorityList::from(authorities(&[1, 2, 3])).encode(),
+		),
+		(set_id_key.clone(), 7u64.encode()),
+		(b"unrelated".to_vec(), vec![1; 64]),
+	];
+
+	let mut db = sp_trie::MemoryDB::<BlakeTwo256>::default();
+	let mut state_root = H256::default();
+	{
+		let mut trie =
+			TrieDBMutBuilder::<LayoutV0<BlakeTwo256>>::new(&mut db, &mut state_root).build();
+		for (key, value) in &entries {
+			trie.insert(key, value).unwrap();
+		}
+	}
+	let backend = TrieBackendBuilder::new(db, state_root).build();
+	let proof =
+		sp_state_machine::prove_read(backend, &[GRANDPA_AUTHORITIES_KEY, &set_id_key[..]]).unwrap();
+
+	let init = GrandpaClientInit {
+		block: H256::repeat_byte(1),
+		height: 10,
+		state_root,
+		set_id: 7,
+		authorities: authorities(&[1, 2, 3]),
+		state_proof: proof.into_iter_nodes().collect(),
+	};
+	assert!(init.verify().is_ok());
+
+	// the proof doesn't prove another authority set, or another set id
+	let forged = GrandpaClientInit { authorities: authorities(&[1, 2, 4]), ..init.clone() };
+	assert!(matches!(forged.verify(), Err(ProverError::AuthoritySetMismatch { .. })));
+	let forged = GrandpaClientInit { set_id: 8, ..init.clone() };
+	assert!(matches!(
+		forged.verify(),
+		Err(ProverError::AuthoritySetMismatch { expected_set_id: 8, found_set_id: 7, .. })
+	));
+
+	// nor against another state root
+	let forged = GrandpaClientInit { state_root: H256::repeat_byte(2), ..init.clone() };
+	assert!(matches!(forged.verify(), Err(ProverError::ProofKeyMismatch { .. })));
+
+	// a proof of only the set id doesn't prove the authorities
+	let (_, set_id_proof) = read_proof(&entries, &set_id_key);
+	let forged = GrandpaClientInit { state_proof: set_id_proof, ..init };
+	assert!(matches!(forged.verify(), Err(ProverError::ProofKeyMismatch { .. })));
+}
+
+#[tokio::test]
+async fn test_resume_from_checkpoint() {
+	use codec::Decode;
+
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: H256::repeat_byte(9),
+		justification: vec![1, 2, 3],
+		unknown_headers: vec![],
+	};
+	let collected = (1..=6u8).map(|i| (H256::repeat_byte(i), vec![i; 8])).collect::<Vec<_>>();
+	let prove = |block: H256, para_header: Vec<u8>| async move {
+		Ok::<_, anyhow::Error>(ParachainHeaderProofs {
+			state_proof: vec![block.as_bytes().to_vec()],
+			extrinsic: para_header,
+			extrinsic_proof: vec![],
+			storage_key: None,
+			extrinsic_index: 0,
+		})
+	};
+
+	let mut one_shot = ProverCheckpoint::new(&finality_proof, collected.clone());
+	advance_checkpoint(&mut one_shot, prove, |_| Ok(())).await.unwrap();
+	let one_shot = one_shot.into_proof::<ParaHeader>().unwrap().encode();
+
+	for split in 0..collected.len() {
+		// the sync crashes while proving the header after `split` headers have been proven
+		let mut checkpoint = ProverCheckpoint::new(&finality_proof, collected.clone());
+		let mut saved = checkpoint.encode();
+		let mut proven = 0;
+		let result = advance_checkpoint(
+			&mut checkpoint,
+			|block, para_header| {
+				proven += 1;
+				let crashed = proven > split;
+				async move {
+					if crashed {
+						return Err(anyhow::anyhow!("crashed"))
+					}
+					prove(block, para_header).await
+				}
+			},
+			|checkpoint| {
+				saved = checkpoint.encode();
+				Ok(())
+			},
+		)
+		.await;
+		assert!(result.is_err());
+
+		let mut resumed = ProverCheckpoint::decode(&mut &saved[..]).unwrap();
+		assert_eq!(resumed.pending().len(), collected.len() - split);
+		assert!(resumed.clone().into_proof::<ParaHeader>().is_err());
+		let mut calls = 0;
+		advance_checkpoint(
+			&mut resumed,
+			|block, para_header| {
+				calls += 1;
+				prove(block, para_header)
+			},
+			|_| Ok(()),
+		)
+		.await
+		.unwrap();
+		// only the headers left are proven again
+		assert_eq!(calls, collected.len() - split);
+		assert_eq!(resumed.into_proof::<ParaHeader>().unwrap().encode(), one_shot);
+	}
+}
+
+#[test]
+fn test_save_checkpoints_sharing_a_stem() {
+	let dir =
+		std::env::temp_dir().join(format!("grandpa-prover-checkpoints-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: H256::repeat_byte(9),
+		justification: vec![1, 2, 3],
+		unknown_headers: vec![],
+	};
+	let checkpoint = |byte: u8| {
+		ProverCheckpoint::new(&finality_proof, vec![(H256::repeat_byte(byte), vec![byte; 8])])
+	};
+
+	// each is written to its own temp file, which is moved into place
+	let paths = [dir.join("checkpoint.first"), dir.join("checkpoint.second")];
+	for (byte, path) in paths.iter().enumerate() {
+		crate::GrandpaProver::<subxt::PolkadotConfig>::save_checkpoint(
+			&checkpoint(byte as u8),
+			path,
+		)
+		.unwrap();
+	}
+	for (byte, path) in paths.iter().enumerate() {
+		assert_eq!(std::fs::read(path).unwrap(), checkpoint(byte as u8).encode());
+	}
+	let mut files = std::fs::read_dir(&dir)
+		.unwrap()
+		.map(|entry| entry.unwrap().file_name())
+		.collect::<Vec<_>>();
+	files.sort();
+	assert_eq!(files, ["checkpoint.first", "checkpoint.second"]);
+
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_query_from_snapshot_over_mock_rpc() {
+	use crate::mock::{mock_prover, MockChain};
+	use codec::Compact;
+	use primitives::SnapshotAnchor;
+	use std::collections::BTreeSet;
+
+	const PARA_ID: u32 = 2000;
+
+	// the parachain's head changes at every other relay chain block
+	let mut para_chain = MockChain::default();
+	let mut para_heads = vec![para_chain.headers().next().unwrap().encode().encode()];
+	for number in 1..=10u64 {
+		let timestamp = (4u8, 3u8, 0u8, Compact(number * 12_000)).encode();
+		let header = para_chain.push_block(vec![], vec![timestamp.encode()]);
+		para_heads.push(header.encode().encode());
+	}
+	let para_key = parachain_header_storage_key(PARA_ID).0;
+	let mut relay_chain = MockChain::default();
+	for number in 1..=20 {
+		relay_chain.push_block(vec![(para_key.clone(), para_heads[number / 2].clone())], vec![]);
+	}
+	let relay_headers = relay_chain.headers().cloned().collect::<Vec<_>>();
+	let relay_hash = |header: &RelayHeader| sp_runtime::traits::Header::hash(header);
+	let target = &relay_headers[20];
+	let justification = GrandpaJustification::<ParaHeader> {
+		round: 1,
+		commit: Commit { target_hash: relay_hash(target), target_number: 20, precommits: vec![] },
+		votes_ancestries: vec![],
+	};
+	let finality_proof = FinalityProof::<ParaHeader> {
+		block: relay_hash(target),
+		justification: justification.encode(),
+		unknown_headers: vec![],
+	};
+	relay_chain.set_finality_proof(20, finality_proof.encode());
+	let prover = mock_prover::<subxt::PolkadotConfig>(relay_chain, para_chain, PARA_ID)
+		.await
+		.unwrap();
+
+	let anchor = SnapshotAnchor {
+		hash: relay_hash(&relay_headers[10]),
+		height: 10,
+		set_id: 0,
+		authorities: vec![],
+	};
+	let proof = prover
+		.query_finalized_parachain_headers_with_proof_from_snapshot::<ParaHeader>(
+			&anchor,
+			2,
+			20,
+			None,
+			(1..=10).collect(),
+		)
+		.await
+		.unwrap();
+	// the ancestry and head changes are only collected back to the snapshot
+	let numbers = proof.finality_proof.unknown_headers.iter().map(|header| header.number);
+	assert_eq!(numbers.collect::<Vec<_>>(), (10..=20).collect::<Vec<_>>());
+	let proven = (10..=20).step_by(2).map(|number| relay_hash(&relay_headers[number]));
+	assert_eq!(
+		proof.parachain_headers.keys().copied().collect::<BTreeSet<_>>(),
+		proven.collect::<BTreeSet<_>>()
+	);
+	assert!(proof
+		.parachain_headers
+		.values()
+		.all(|proofs| proofs.storage_key.as_ref() == Some(&para_key)));
+
+	// a more recent client update takes precedence over the snapshot
+	let proof = prover
+		.query_finalized_parachain_headers_with_proof_from_snapshot::<ParaHeader>(
+			&anchor,
+			16,
+			20,
+			None,
+			(1..=10).collect(),
+		)
+		.await
+		.unwrap();
+	assert_eq!(proof.finality_proof.unknown_headers.first().map(|header| header.number), Some(16));
+
+	// snapshots of blocks that aren't canonical are rejected
+	let anchor = SnapshotAnchor { hash: H256::repeat_byte(1), ..anchor };
+	let err = prover
+		.query_finalized_parachain_headers_with_proof_from_snapshot::<ParaHeader>(
+			&anchor,
+			2,
+			20,
+			None,
+			(1..=10).collect(),
+		)
+		.await
+		.unwrap_err();
+	assert!(err.to_string().contains("is not in the canonical chain"), "{err}");
+}
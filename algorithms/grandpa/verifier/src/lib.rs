@@ -21,19 +21,22 @@
 
 extern crate alloc;
 
-use alloc::vec;
+use alloc::{collections::BTreeSet, vec};
 use anyhow::anyhow;
-use codec::{Decode, Encode};
+use codec::{Decode, DecodeAll, Encode};
+use core::marker::PhantomData;
 use finality_grandpa::Chain;
 use hash_db::Hasher;
 use light_client_common::state_machine;
 use primitives::{
 	error,
-	justification::{find_scheduled_change, AncestryChain, GrandpaJustification},
-	parachain_header_storage_key, ClientState, HostFunctions, ParachainHeaderProofs,
-	ParachainHeadersWithFinalityProof,
+	helpers::{authority_set_at, authority_set_changes, is_mandatory_header, pending_set_change},
+	justification::{AncestryChain, GrandpaJustification},
+	parachain_header_storage_key, ClientState, CompactParachainHeadersWithFinalityProof,
+	HostFunctions, MmrAncestryProof, ParachainHead, ParachainHeaderProofs,
+	ParachainHeadersWithFinalityProof, RelayTimestampProof,
 };
-use sp_core::H256;
+use sp_core::{storage::ChildInfo, H256};
 use sp_runtime::traits::Header;
 use sp_trie::{LayoutV0, StorageProof};
 
@@ -44,9 +47,37 @@ mod tests;
 ///
 /// Next, we prove the finality of parachain headers, by verifying patricia-merkle trie state proofs
 /// of these headers, stored at the recently finalized relay chain heights.
+///
+/// The justification is verified against the set voting on the finalized block, and the client
+/// moves on to the set voting on the blocks after it, applying the set changes signalled in the
+/// unknown headers that are enacted by then, see [`authority_set_at`]. Proofs ending between a
+/// change's signal and the last block of the set it replaces are rejected, see
+/// [`pending_set_change`].
 pub fn verify_parachain_headers_with_grandpa_finality_proof<H, Host>(
+	client_state: ClientState,
+	proof: ParachainHeadersWithFinalityProof<H>,
+) -> Result<ClientState, error::Error>
+where
+	H: Header<Hash = H256, Number = u32>,
+	H::Number: finality_grandpa::BlockNumberOps + Into<u32>,
+	Host: HostFunctions,
+	Host::BlakeTwo256: Hasher<Out = H256>,
+{
+	let para_head_key = parachain_header_storage_key(client_state.para_id).0;
+	verify_parachain_headers_with_grandpa_finality_proof_at_key::<H, Host>(
+		client_state,
+		proof,
+		&para_head_key,
+	)
+}
+
+/// Verifies the proof like [`verify_parachain_headers_with_grandpa_finality_proof`], reading the
+/// parachain headers from `para_head_key` rather than from `Paras::Heads`, for relay chains with a
+/// renamed `Paras` pallet or a different storage layout.
+pub fn verify_parachain_headers_with_grandpa_finality_proof_at_key<H, Host>(
 	mut client_state: ClientState,
 	proof: ParachainHeadersWithFinalityProof<H>,
+	para_head_key: &[u8],
 ) -> Result<ClientState, error::Error>
 where
 	H: Header<Hash = H256, Number = u32>,
@@ -97,8 +128,22 @@ where
 	})?;
 	finalized.sort();
 
-	// 2. verify justification.
-	justification.verify::<Host>(client_state.current_set_id, &client_state.current_authorities)?;
+	// the client would never apply a change that's still pending at the target.
+	let unknown_headers = &finality_proof.unknown_headers;
+	let pending =
+		pending_set_change(&client_state, unknown_headers, target.hash(), *target.number());
+	if let Some(change) = pending {
+		Err(error::Error::PendingAuthoritySetChange {
+			signalled_at: change.signalled_at(),
+			enacted_at: change.enacted_at(),
+			finalized: *target.number(),
+		})?
+	}
+
+	// 2. verify justification, signed by the set voting on the target once the set changes
+	// enacted below it are applied.
+	let voters = authority_set_at(&client_state, unknown_headers, target.hash(), *target.number());
+	justification.verify::<Host>(voters.current_set_id, &voters.current_authorities)?;
 
 	// 3. verify state proofs of parachain headers in finalized relay chain headers.
 	let mut para_heights = vec![];
@@ -109,44 +154,488 @@ where
 		}
 		let relay_chain_header =
 			headers.header(&hash).expect("Headers have been checked by AncestryChain; qed");
-
-		let ParachainHeaderProofs { extrinsic_proof, extrinsic, state_proof } = proofs;
-		let proof = StorageProof::new(state_proof);
-		let key = parachain_header_storage_key(client_state.para_id);
-		// verify patricia-merkle state proofs
-		let header = state_machine::read_proof_check::<Host::BlakeTwo256, _>(
-			relay_chain_header.state_root(),
-			proof,
-			&[key.as_ref()],
-		)
-		.map_err(|err| anyhow!("error verifying parachain header state proof: {err}"))?
-		.remove(key.as_ref())
-		.flatten()
-		.ok_or_else(|| anyhow!("Invalid proof, parachain header not found"))?;
-		let parachain_header = H::decode(&mut &header[..])?;
-		para_heights.push(parachain_header.number().clone().into());
-		// Timestamp extrinsic should be the first inherent and hence the first extrinsic
-		// https://github.com/paritytech/substrate/blob/d602397a0bbb24b5d627795b797259a44a5e29e9/primitives/trie/src/lib.rs#L99-L101
-		let key = codec::Compact(0u32).encode();
-		// verify extrinsic proof for timestamp extrinsic
-		sp_trie::verify_trie_proof::<LayoutV0<Host::BlakeTwo256>, _, _, _>(
-			parachain_header.extrinsics_root(),
-			&extrinsic_proof,
-			&vec![(key, Some(&extrinsic[..]))],
-		)
-		.map_err(|_| anyhow!("Invalid extrinsic proof"))?;
+		para_heights.push(verify_parachain_header_proofs::<H, Host>(
+			para_head_key,
+			relay_chain_header,
+			proofs,
+		)?);
 	}
 
-	// 4. set new client state, optionally rotating authorities
+	// 4. set new client state, rotating to the set voting on the blocks after the target.
+	client_state =
+		authority_set_at(&client_state, unknown_headers, target.hash(), *target.number() + 1);
 	client_state.latest_relay_hash = target.hash();
 	client_state.latest_relay_height = (*target.number()).into();
 	if let Some(max_height) = para_heights.into_iter().max() {
 		client_state.latest_para_height = max_height;
 	}
-	if let Some(scheduled_change) = find_scheduled_change::<H>(&target) {
+
+	Ok(client_state)
+}
+
+/// Reassembles the per-block state proofs of a [`CompactParachainHeadersWithFinalityProof`], so
+/// it can be verified with [`verify_parachain_headers_with_grandpa_finality_proof`]. Fails if a
+/// referenced trie node is missing or doesn't match its hash.
+pub fn expand_compact_proof<H, Host>(
+	proof: CompactParachainHeadersWithFinalityProof<H>,
+) -> Result<ParachainHeadersWithFinalityProof<H>, error::Error>
+where
+	H: codec::Codec,
+	Host: HostFunctions,
+	Host::BlakeTwo256: Hasher<Out = H256>,
+{
+	let CompactParachainHeadersWithFinalityProof {
+		finality_proof,
+		state_proof_nodes,
+		parachain_headers,
+	} = proof;
+
+	for (hash, node) in &state_proof_nodes {
+		if Host::BlakeTwo256::hash(node) != *hash {
+			Err(anyhow!("State proof node doesn't match its hash: {hash:?}"))?
+		}
+	}
+
+	let parachain_headers = parachain_headers
+		.into_iter()
+		.map(|(relay_hash, proofs)| {
+			let state_proof = proofs
+				.state_proof
+				.iter()
+				.map(|hash| {
+					state_proof_nodes
+						.get(hash)
+						.cloned()
+						.ok_or_else(|| anyhow!("Missing state proof node: {hash:?}"))
+				})
+				.collect::<Result<_, _>>()?;
+			let proofs = ParachainHeaderProofs {
+				state_proof,
+				extrinsic: proofs.extrinsic,
+				extrinsic_proof: proofs.extrinsic_proof,
+				storage_key: proofs.storage_key,
+				extrinsic_index: proofs.extrinsic_index,
+			};
+			Ok((relay_hash, proofs))
+		})
+		.collect::<Result<_, anyhow::Error>>()?;
+
+	Ok(ParachainHeadersWithFinalityProof { finality_proof, parachain_headers })
+}
+
+/// Verifies a pruned finality proof, see [`MmrAncestryProof`], against the root of an MMR of relay
+/// chain block hashes held by the client, see [`ancestry_mmr_leaf`]. The MMR proof takes the place
+/// of the relay chain ancestry: it shows the shipped headers are canonical, and as they're above
+/// the client's latest relay chain block, that they descend from it. Parachain headers can only be
+/// proven in the shipped headers.
+///
+/// The headers signalling authority set changes up to the finalized block must all be shipped,
+/// which the MMR leaves' counts of them show. Proofs spanning a set change must end at the last
+/// block of the client's set, rotating it.
+pub fn verify_parachain_headers_with_mmr_ancestry<H, Host>(
+	client_state: ClientState,
+	mmr_root: H256,
+	proof: MmrAncestryProof<H>,
+) -> Result<ClientState, error::Error>
+where
+	H: Header<Hash = H256, Number = u32>,
+	H::Number: finality_grandpa::BlockNumberOps + Into<u32>,
+	Host: HostFunctions,
+	Host::BlakeTwo256: Hasher<Out = H256>,
+{
+	let para_head_key = parachain_header_storage_key(client_state.para_id).0;
+	verify_parachain_headers_with_mmr_ancestry_at_key::<H, Host>(
+		client_state,
+		mmr_root,
+		proof,
+		&para_head_key,
+	)
+}
+
+/// Verifies the proof like [`verify_parachain_headers_with_mmr_ancestry`], reading the parachain
+/// headers from `para_head_key` rather than from `Paras::Heads`.
+pub fn verify_parachain_headers_with_mmr_ancestry_at_key<H, Host>(
+	mut client_state: ClientState,
+	mmr_root: H256,
+	proof: MmrAncestryProof<H>,
+	para_head_key: &[u8],
+) -> Result<ClientState, error::Error>
+where
+	H: Header<Hash = H256, Number = u32>,
+	H::Number: finality_grandpa::BlockNumberOps + Into<u32>,
+	Host: HostFunctions,
+	Host::BlakeTwo256: Hasher<Out = H256>,
+{
+	let MmrAncestryProof {
+		block,
+		justification,
+		headers,
+		set_changes_signalled,
+		anchor_set_changes_signalled,
+		mmr_leaf_count,
+		mmr_proof,
+		parachain_headers,
+	} = proof;
+
+	// 1. check that the headers are in the client's MMR, above its latest relay chain block.
+	let target_index = headers
+		.iter()
+		.position(|header| header.hash() == block)
+		.ok_or_else(|| anyhow!("Finalized block's header is missing from the proof"))?;
+	let target = &headers[target_index];
+	if headers
+		.iter()
+		.any(|header| *header.number() <= client_state.latest_relay_height)
+	{
+		Err(anyhow!("Headers at or below the client's latest relay chain block can't be proven"))?;
+	}
+	if mmr_leaf_count == 0 {
+		Err(anyhow!("Headers can't be proven against an empty MMR"))?;
+	}
+	// each height has a single leaf, so a header shipped twice would be counted twice below.
+	let heights = headers.iter().map(|header| *header.number()).collect::<BTreeSet<_>>();
+	if heights.len() != headers.len() {
+		Err(anyhow!("Headers must be at distinct heights"))?;
+	}
+	if set_changes_signalled.len() != headers.len() {
+		Err(anyhow!("Expected a count of set changes signalled for each header"))?;
+	}
+	let anchor = (
+		client_state.latest_relay_height,
+		client_state.latest_relay_hash,
+		anchor_set_changes_signalled,
+	);
+	let mut leaves = headers
+		.iter()
+		.zip(&set_changes_signalled)
+		.map(|(header, signalled)| (*header.number(), header.hash(), *signalled))
+		.chain(Some(anchor))
+		.map(|(number, hash, signalled)| {
+			let leaf = ancestry_mmr_leaf::<Host::BlakeTwo256>(hash, signalled);
+			(mmr_lib::leaf_index_to_pos(number as u64), leaf)
+		})
+		.collect::<Vec<_>>();
+	leaves.sort_by_key(|(pos, _)| *pos);
+	let mmr_size = mmr_lib::leaf_index_to_mmr_size(mmr_leaf_count - 1);
+	let proven =
+		mmr_lib::MerkleProof::<H256, AncestryMerge<Host::BlakeTwo256>>::new(mmr_size, mmr_proof)
+			.verify(mmr_root, leaves)
+			.map_err(|err| anyhow!("Invalid MMR proof: {err:?}"))?;
+	if !proven {
+		Err(anyhow!("Relay chain headers aren't in the client's MMR"))?;
+	}
+
+	// the proven counts show how many headers signal set changes up to the target, which must all
+	// be shipped.
+	let target_signalled = set_changes_signalled[target_index];
+	let up_to_target = headers.iter().filter(|header| header.number() <= target.number());
+	let shipped_signals =
+		up_to_target.clone().filter(|header| is_mandatory_header(*header)).count();
+	if target_signalled.checked_sub(anchor_set_changes_signalled) != Some(shipped_signals as u64) {
+		Err(anyhow!("Headers signalling authority set changes are missing from the proof"))?;
+	}
+	let set_changes = up_to_target.flat_map(authority_set_changes).collect::<Vec<_>>();
+	if set_changes
+		.iter()
+		.any(|change| change.last_block_of_previous_set() != *target.number())
+	{
+		Err(anyhow!("Proofs spanning a set change must end at the last block of the current set"))?;
+	}
+
+	// 2. verify justification.
+	let justification = GrandpaJustification::<H>::decode(&mut &justification[..])?;
+	if justification.commit.target_hash != block {
+		Err(anyhow!("Justification target hash and finality proof block hash mismatch"))?;
+	}
+	justification.verify::<Host>(client_state.current_set_id, &client_state.current_authorities)?;
+
+	// 3. verify state proofs of parachain headers in the proven relay chain headers.
+	let mut para_heights = vec![];
+	for (hash, proofs) in parachain_headers {
+		let relay_chain_header = match headers.iter().find(|header| header.hash() == hash) {
+			Some(header) => header,
+			None => continue,
+		};
+		para_heights.push(verify_parachain_header_proofs::<H, Host>(
+			para_head_key,
+			relay_chain_header,
+			proofs,
+		)?);
+	}
+
+	// 4. set new client state, optionally rotating authorities
+	client_state.latest_relay_hash = block;
+	client_state.latest_relay_height = *target.number();
+	if let Some(max_height) = para_heights.into_iter().max() {
+		client_state.latest_para_height = max_height;
+	}
+	for change in set_changes {
 		client_state.current_set_id += 1;
-		client_state.current_authorities = scheduled_change.next_authorities;
+		client_state.current_authorities = change.into_change().next_authorities;
 	}
 
 	Ok(client_state)
 }
+
+/// Leaf of relay chain block `block_hash` in the MMR that pruned proofs are verified against, see
+/// [`verify_parachain_headers_with_mmr_ancestry`]. It commits to the number of headers up to and
+/// including the block that signal authority set changes, see [`is_mandatory_header`].
+pub fn ancestry_mmr_leaf<H: Hasher<Out = H256>>(
+	block_hash: H256,
+	set_changes_signalled: u64,
+) -> H256 {
+	H::hash(&(block_hash, set_changes_signalled).encode())
+}
+
+/// Merges MMR nodes of relay chain block hashes by hashing their concatenation.
+pub struct AncestryMerge<H>(PhantomData<H>);
+
+impl<H: Hasher<Out = H256>> mmr_lib::Merge for AncestryMerge<H> {
+	type Item = H256;
+
+	fn merge(left: &Self::Item, right: &Self::Item) -> Self::Item {
+		let mut concat = left.as_bytes().to_vec();
+		concat.extend_from_slice(right.as_bytes());
+		H::hash(&concat)
+	}
+}
+
+/// Verifies the state proof of the parachain header stored at `para_head_key` in the given relay
+/// chain header and its timestamp extrinsic proof, returning the parachain header's number.
+fn verify_parachain_header_proofs<H, Host>(
+	para_head_key: &[u8],
+	relay_chain_header: &H,
+	proofs: ParachainHeaderProofs,
+) -> Result<u32, error::Error>
+where
+	H: Header<Hash = H256, Number = u32>,
+	Host: HostFunctions,
+	Host::BlakeTwo256: Hasher<Out = H256>,
+{
+	let ParachainHeaderProofs {
+		extrinsic_proof,
+		extrinsic,
+		state_proof,
+		storage_key,
+		extrinsic_index,
+	} = proofs;
+	if let Some(storage_key) = storage_key {
+		ensure_storage_key(para_head_key, storage_key)?;
+	}
+	let header = verify_parachain_header_state_proof_at_key::<Host::BlakeTwo256>(
+		relay_chain_header.state_root(),
+		state_proof,
+		para_head_key,
+	)?;
+	let parachain_header = ParachainHead::<H>::decode(&header)?.into_header()?;
+	verify_timestamp_extrinsic_proof::<Host::BlakeTwo256>(
+		parachain_header.extrinsics_root(),
+		&extrinsic_proof,
+		&extrinsic,
+		extrinsic_index,
+	)?;
+
+	Ok(*parachain_header.number())
+}
+
+/// Checks that the storage key carried by a parachain header proof matches the `Paras::Heads` key
+/// computed by the verifier for the given `para_id`.
+pub fn ensure_parachain_header_storage_key(
+	para_id: u32,
+	storage_key: Vec<u8>,
+) -> Result<(), error::Error> {
+	ensure_storage_key(&parachain_header_storage_key(para_id).0, storage_key)
+}
+
+fn ensure_storage_key(expected: &[u8], storage_key: Vec<u8>) -> Result<(), error::Error> {
+	if expected != &storage_key[..] {
+		Err(error::Error::StorageKeyMismatch { expected: expected.to_vec(), found: storage_key })?
+	}
+
+	Ok(())
+}
+
+/// Verifies the proof of the timestamp extrinsic at `extrinsic_index` in the block against the
+/// parachain header's `extrinsics_root`.
+pub fn verify_timestamp_extrinsic_proof<H>(
+	extrinsics_root: &H256,
+	extrinsic_proof: &[Vec<u8>],
+	extrinsic: &[u8],
+	extrinsic_index: u32,
+) -> Result<(), error::Error>
+where
+	H: Hasher<Out = H256>,
+{
+	// extrinsics are keyed by their compact encoded index in the block
+	// https://github.com/paritytech/substrate/blob/d602397a0bbb24b5d627795b797259a44a5e29e9/primitives/trie/src/lib.rs#L99-L101
+	let key = codec::Compact(extrinsic_index).encode();
+	sp_trie::verify_trie_proof::<LayoutV0<H>, _, _, _>(
+		extrinsics_root,
+		extrinsic_proof,
+		&vec![(key, Some(extrinsic))],
+	)
+	.map_err(|_| anyhow!("Invalid extrinsic proof"))?;
+
+	Ok(())
+}
+
+/// Verifies the timestamp extrinsic at `extrinsic_index` against the `extrinsics_root` of the block
+/// it was included in, returning the timestamp it sets in milliseconds. The extrinsic has to be an
+/// unsigned `Timestamp::set` call of the pallet at `pallet_index` in the runtime, since other
+/// inherents may share its shape.
+pub fn verify_timestamp_extrinsic<H>(
+	extrinsics_root: H256,
+	extrinsic: &[u8],
+	proof: &[Vec<u8>],
+	extrinsic_index: u32,
+	pallet_index: u8,
+) -> Result<u64, error::Error>
+where
+	H: Hasher<Out = H256>,
+{
+	verify_timestamp_extrinsic_proof::<H>(&extrinsics_root, proof, extrinsic, extrinsic_index)?;
+
+	// extrinsics are encoded as byte vectors, the call follows the extrinsic version.
+	let call = Vec::<u8>::decode_all(&mut &extrinsic[..])?;
+	let (version, call_pallet_index, call_index, timestamp) =
+		<(u8, u8, u8, codec::Compact<u64>)>::decode_all(&mut &call[..])
+			.map_err(|_| anyhow!("Extrinsic isn't the timestamp inherent"))?;
+	// version 4 without the signed bit, `set` is the only call of the timestamp pallet
+	if version != 4 || call_pallet_index != pallet_index || call_index != 0 {
+		Err(anyhow!("Extrinsic isn't the timestamp inherent"))?
+	}
+
+	Ok(timestamp.0)
+}
+
+/// Verifies the timestamp extrinsic proof of the given relay chain header, returning the relay
+/// chain timestamp in milliseconds. `pallet_index` is the index of the timestamp pallet in the
+/// relay chain runtime. The header's finality must have been verified already.
+pub fn verify_relay_timestamp<H, R>(
+	relay_header: &R,
+	proof: &RelayTimestampProof,
+	pallet_index: u8,
+) -> Result<u64, error::Error>
+where
+	H: Hasher<Out = H256>,
+	R: Header<Hash = H256>,
+{
+	if relay_header.hash() != proof.block {
+		Err(anyhow!(
+			"Relay timestamp proof is for block {:?}, expected {:?}",
+			proof.block,
+			relay_header.hash()
+		))?
+	}
+
+	verify_timestamp_extrinsic::<H>(
+		*relay_header.extrinsics_root(),
+		&proof.extrinsic,
+		&proof.extrinsic_proof,
+		proof.extrinsic_index,
+		pallet_index,
+	)
+}
+
+/// Verifies the patricia-merkle state proof of the parachain header stored in `Paras::Heads` for
+/// the given `para_id` against the relay chain `state_root`, returning the encoded parachain
+/// header. `Paras::Heads` stores the head as `HeadData`, which is decoded to the header's bytes.
+///
+/// The proof is checked against an in-memory trie backend built from the proof nodes using the
+/// hasher `H`, so any trie produced with `sp-trie` can be checked.
+pub fn verify_parachain_header_state_proof<H>(
+	state_root: &H256,
+	state_proof: Vec<Vec<u8>>,
+	para_id: u32,
+) -> Result<Vec<u8>, error::Error>
+where
+	H: Hasher<Out = H256>,
+{
+	verify_parachain_header_state_proof_at_key::<H>(
+		state_root,
+		state_proof,
+		&parachain_header_storage_key(para_id).0,
+	)
+}
+
+/// Verifies the state proof of the parachain header stored at `para_head_key` like
+/// [`verify_parachain_header_state_proof`], for relay chains with a non-standard `Paras` pallet.
+pub fn verify_parachain_header_state_proof_at_key<H>(
+	state_root: &H256,
+	state_proof: Vec<Vec<u8>>,
+	para_head_key: &[u8],
+) -> Result<Vec<u8>, error::Error>
+where
+	H: Hasher<Out = H256>,
+{
+	let proof = StorageProof::new(state_proof);
+	// verify patricia-merkle state proofs
+	let header = state_machine::read_proof_check::<H, _>(state_root, proof, &[para_head_key])
+		.map_err(|err| anyhow!("error verifying parachain header state proof: {err}"))?
+		.remove(para_head_key)
+		.flatten()
+		.ok_or_else(|| anyhow!("Invalid proof, parachain header not found"))?;
+
+	Ok(Vec::<u8>::decode(&mut &header[..])?)
+}
+
+/// A storage key proven by a relay chain state proof, either in the main trie or in a default
+/// child trie.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateProofKey {
+	/// Key in the main trie
+	Main(Vec<u8>),
+	/// Key in the default child trie stored under `child_trie`, which is the unprefixed storage
+	/// key of the child trie.
+	Child {
+		/// Unprefixed storage key of the child trie
+		child_trie: Vec<u8>,
+		/// Key in the child trie
+		key: Vec<u8>,
+	},
+}
+
+/// Verifies that the state proof proves each key has the expected SCALE decoded value against
+/// `state_root`, `None` proving the key is absent. Child trie keys are verified against the child
+/// trie root stored in the main trie.
+pub fn verify_state_proof_values<H>(
+	state_root: &H256,
+	state_proof: Vec<Vec<u8>>,
+	items: Vec<(StateProofKey, Option<Vec<u8>>)>,
+) -> Result<(), error::Error>
+where
+	H: Hasher<Out = H256>,
+{
+	let proof = StorageProof::new(state_proof);
+	for (key, expected) in items {
+		match key {
+			StateProofKey::Main(key) => {
+				let found = state_machine::read_proof_check::<H, _>(
+					state_root,
+					proof.clone(),
+					[key.as_slice()],
+				)
+				.map_err(|err| anyhow!("error verifying state proof: {err}"))?
+				.remove(&key)
+				.flatten();
+				if found != expected {
+					Err(anyhow!(
+						"Value mismatch for key: {key:?}, expected: {expected:?}, found: {found:?}"
+					))?
+				}
+			},
+			StateProofKey::Child { child_trie, key } => {
+				let child_info = ChildInfo::new_default(&child_trie);
+				state_machine::read_child_proof_check::<H, _>(
+					*state_root,
+					proof.clone(),
+					child_info,
+					[(key, expected)],
+				)
+				.map_err(|err| anyhow!("error verifying child trie proof: {err}"))?;
+			},
+		}
+	}
+
+	Ok(())
+}
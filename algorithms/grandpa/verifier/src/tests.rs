@@ -13,15 +13,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::verify_parachain_headers_with_grandpa_finality_proof;
+use crate::{
+	ensure_parachain_header_storage_key, expand_compact_proof, verify_parachain_header_state_proof,
+	verify_parachain_header_state_proof_at_key,
+	verify_parachain_headers_with_grandpa_finality_proof, verify_relay_timestamp,
+	verify_state_proof_values, verify_timestamp_extrinsic, StateProofKey,
+};
 use codec::{Decode, Encode};
 use futures::StreamExt;
 use grandpa_prover::{
 	beefy_prover::helpers::unsafe_arc_cast, host_functions::HostFunctionsProvider, GrandpaProver,
+	ProverError,
 };
 use polkadot_core_primitives::Header;
 use primitives::{
-	justification::GrandpaJustification, FinalityProof, ParachainHeadersWithFinalityProof,
+	justification::GrandpaJustification, parachain_header_storage_key,
+	CompactParachainHeadersWithFinalityProof, FinalityProof, ParachainHeaderProofs,
+	ParachainHeadersWithFinalityProof, RelayTimestampProof,
 };
 use serde::{Deserialize, Serialize};
 use sp_core::H256;
@@ -32,6 +40,26 @@ use subxt::{
 
 pub type Justification = GrandpaJustification<Header>;
 
+type Hasher = sp_runtime::traits::BlakeTwo256;
+
+/// Builds an in-memory trie backend containing the given entries.
+fn trie_backend(
+	entries: &[(Vec<u8>, Vec<u8>)],
+) -> sp_state_machine::TrieBackend<sp_trie::MemoryDB<Hasher>, Hasher> {
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let mut db = sp_trie::MemoryDB::default();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMutBuilder::<LayoutV0<Hasher>>::new(&mut db, &mut root).build();
+		for (key, value) in entries {
+			trie.insert(key, value).unwrap();
+		}
+	}
+
+	sp_state_machine::TrieBackendBuilder::new(db, root).build()
+}
+
 /// An encoded justification proving that the given header has been finalized
 #[derive(Clone, Serialize, Deserialize)]
 pub struct JustificationNotification(sp_core::Bytes);
@@ -150,3 +178,502 @@ async fn follow_grandpa_justifications() {
 		println!("========= Successfully verified grandpa justification =========");
 	}
 }
+
+#[test]
+fn test_verify_parachain_header_state_proof() {
+	let para_id = 2000;
+	let para_header = <Header as sp_runtime::traits::Header>::new(
+		10,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let key = parachain_header_storage_key(para_id).0;
+	let backend = trie_backend(&[
+		(key.clone(), para_header.encode().encode()),
+		(b"unrelated".to_vec(), vec![1, 2, 3].encode()),
+	]);
+	let state_root = *backend.root();
+
+	let proof = sp_state_machine::prove_read(backend, &[&key]).unwrap();
+	let state_proof = proof.into_iter_nodes().collect::<Vec<_>>();
+
+	let header =
+		verify_parachain_header_state_proof::<Hasher>(&state_root, state_proof.clone(), para_id)
+			.unwrap();
+	assert_eq!(Header::decode(&mut &header[..]).unwrap(), para_header);
+	assert_eq!(
+		verify_parachain_header_state_proof_at_key::<Hasher>(
+			&state_root,
+			state_proof.clone(),
+			&key
+		)
+		.unwrap(),
+		header
+	);
+
+	// the key for a different para id isn't in the proof
+	assert!(verify_parachain_header_state_proof::<Hasher>(
+		&state_root,
+		state_proof.clone(),
+		para_id + 1,
+	)
+	.is_err());
+
+	// the proof doesn't match a different state root
+	assert!(verify_parachain_header_state_proof::<Hasher>(
+		&H256::repeat_byte(1),
+		state_proof,
+		para_id,
+	)
+	.is_err());
+}
+
+#[test]
+fn test_parachain_header_storage_key_mismatch() {
+	let para_id = 2000;
+	let key = parachain_header_storage_key(para_id).0;
+	assert!(ensure_parachain_header_storage_key(para_id, key.clone()).is_ok());
+
+	// the prover computed the key with a different layout
+	let found = parachain_header_storage_key(para_id + 1).0;
+	match ensure_parachain_header_storage_key(para_id, found.clone()) {
+		Err(primitives::error::Error::StorageKeyMismatch { expected, found: actual }) => {
+			assert_eq!(expected, key);
+			assert_eq!(actual, found);
+		},
+		_ => panic!("expected a storage key mismatch"),
+	}
+}
+
+#[test]
+fn test_compact_proof_over_many_blocks() {
+	let para_id = 2000;
+	let key = parachain_header_storage_key(para_id).0;
+	// the rest of the relay chain state, with one entry changing every block in another subtrie
+	let entries = (0..500u32)
+		.map(|i| (sp_io::hashing::blake2_256(&i.encode()).to_vec(), vec![i as u8; 32]))
+		.collect::<Vec<_>>();
+	let mut block_number_key = key.clone();
+	block_number_key[0] ^= 0xff;
+
+	// the parachain head changes every 10 relay chain blocks
+	let head = |block: u32| vec![(block / 10) as u8; 256];
+	let mut proof = ParachainHeadersWithFinalityProof::<Header>::empty(H256::zero(), vec![]);
+	let mut state_roots = vec![];
+	for block in 0..200u32 {
+		let mut entries = entries.clone();
+		entries.push((block_number_key.clone(), block.encode()));
+		entries.push((key.clone(), head(block).encode()));
+		let backend = trie_backend(&entries);
+		let relay_hash = H256::from_low_u64_be(block as u64);
+		state_roots.push((relay_hash, block, *backend.root()));
+
+		let state_proof = sp_state_machine::prove_read(backend, &[&key])
+			.unwrap()
+			.into_iter_nodes()
+			.collect();
+		let proofs = ParachainHeaderProofs {
+			state_proof,
+			extrinsic: vec![],
+			extrinsic_proof: vec![],
+			storage_key: None,
+			extrinsic_index: 0,
+		};
+		proof.push_parachain_header(relay_hash, proofs);
+	}
+
+	let compact = CompactParachainHeadersWithFinalityProof::from(proof.clone());
+	let (size, compact_size) = (proof.encode().len(), compact.encode().len());
+	println!("state proofs of 200 blocks: {size} bytes, compacted: {compact_size} bytes");
+	assert!(compact_size * 2 < size);
+
+	// the state proofs are reassembled as they were
+	let expanded = expand_compact_proof::<_, HostFunctionsProvider>(compact.clone()).unwrap();
+	assert_eq!(expanded.parachain_headers, proof.parachain_headers);
+	for (relay_hash, block, state_root) in state_roots {
+		let state_proof = expanded.parachain_headers[&relay_hash].state_proof.clone();
+		let found =
+			verify_parachain_header_state_proof::<Hasher>(&state_root, state_proof, para_id)
+				.unwrap();
+		assert_eq!(found, head(block));
+	}
+
+	// a node that doesn't match its hash is rejected
+	let mut tampered = compact.clone();
+	tampered.state_proof_nodes.values_mut().next().unwrap().push(0);
+	assert!(expand_compact_proof::<_, HostFunctionsProvider>(tampered).is_err());
+
+	// as is a proof referencing a missing node
+	let mut missing = compact;
+	let node = *missing.state_proof_nodes.keys().next().unwrap();
+	missing.state_proof_nodes.remove(&node);
+	assert!(expand_compact_proof::<_, HostFunctionsProvider>(missing).is_err());
+}
+
+#[test]
+fn test_verify_relay_timestamp() {
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let timestamp = 1_668_000_000_000u64;
+	// unsigned `Timestamp::set` extrinsic, the first inherent of every relay chain block
+	let extrinsic = (4u8, 3u8, 0u8, codec::Compact(timestamp)).encode().encode();
+	let key = codec::Compact(0u32).encode();
+	let mut db = sp_trie::MemoryDB::<Hasher>::default();
+	let mut extrinsics_root = H256::default();
+	{
+		let mut trie =
+			TrieDBMutBuilder::<LayoutV0<Hasher>>::new(&mut db, &mut extrinsics_root).build();
+		trie.insert(&key, &extrinsic).unwrap();
+		trie.insert(&codec::Compact(1u32).encode(), b"para inherent").unwrap();
+	}
+	let extrinsic_proof =
+		sp_trie::generate_trie_proof::<LayoutV0<Hasher>, _, _, _>(&db, extrinsics_root, &[&key])
+			.unwrap();
+	let relay_header = <Header as sp_runtime::traits::Header>::new(
+		20,
+		extrinsics_root,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let proof = RelayTimestampProof {
+		block: sp_runtime::traits::Header::hash(&relay_header),
+		extrinsic,
+		extrinsic_proof,
+		extrinsic_index: 0,
+	};
+
+	assert_eq!(verify_relay_timestamp::<Hasher, _>(&relay_header, &proof, 3).unwrap(), timestamp);
+
+	// the relay chain's timestamp pallet is at another index
+	assert!(verify_relay_timestamp::<Hasher, _>(&relay_header, &proof, 2).is_err());
+
+	// the proof is for another relay chain block
+	let other = <Header as sp_runtime::traits::Header>::new(
+		21,
+		extrinsics_root,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	assert!(verify_relay_timestamp::<Hasher, _>(&other, &proof, 3).is_err());
+
+	// the extrinsic isn't the one committed to by the header
+	let mut forged = proof.clone();
+	forged.extrinsic = (4u8, 3u8, 0u8, codec::Compact(timestamp + 1)).encode().encode();
+	assert!(verify_relay_timestamp::<Hasher, _>(&relay_header, &forged, 3).is_err());
+}
+
+#[test]
+fn test_verify_timestamp_extrinsic() {
+	use sp_trie::{LayoutV0, TrieDBMutBuilder, TrieMut};
+
+	let timestamp = 1_668_000_000_000u64;
+	let extrinsic = (4u8, 3u8, 0u8, codec::Compact(timestamp)).encode().encode();
+	// a signed extrinsic, which can't be an inherent
+	let signed = (0x84u8, 3u8, 0u8, codec::Compact(timestamp)).encode().encode();
+	// an unsigned call other than `set`
+	let other_call = (4u8, 3u8, 1u8, codec::Compact(timestamp)).encode().encode();
+	// the extrinsic at `index`, after other inherents if it isn't the first
+	let extrinsics_root = |extrinsic: &[u8], index: u32| {
+		let key = codec::Compact(index).encode();
+		let mut db = sp_trie::MemoryDB::<Hasher>::default();
+		let mut root = H256::default();
+		{
+			let mut trie = TrieDBMutBuilder::<LayoutV0<Hasher>>::new(&mut db, &mut root).build();
+			for other in 0..=index + 1 {
+				trie.insert(&codec::Compact(other).encode(), b"para inherent").unwrap();
+			}
+			trie.insert(&key, extrinsic).unwrap();
+		}
+		let proof =
+			sp_trie::generate_trie_proof::<LayoutV0<Hasher>, _, _, _>(&db, root, &[&key]).unwrap();
+		(root, proof)
+	};
+	let verify = |root: H256, extrinsic: &[u8], proof: &[Vec<u8>], index: u32| {
+		verify_timestamp_extrinsic::<Hasher>(root, extrinsic, proof, index, 3)
+	};
+
+	let (root, proof) = extrinsics_root(&extrinsic, 0);
+	assert_eq!(verify(root, &extrinsic, &proof, 0).unwrap(), timestamp);
+
+	// runtimes that put other inherents first
+	let (root, proof) = extrinsics_root(&extrinsic, 2);
+	assert_eq!(verify(root, &extrinsic, &proof, 2).unwrap(), timestamp);
+	assert!(verify(root, &extrinsic, &proof, 0).is_err());
+
+	// the timestamp pallet is at another index in the runtime
+	assert!(verify_timestamp_extrinsic::<Hasher>(root, &extrinsic, &proof, 2, 4).is_err());
+
+	// the proof doesn't match another root
+	assert!(verify(H256::repeat_byte(1), &extrinsic, &proof, 2).is_err());
+
+	// the extrinsic isn't the one committed to by the root
+	let forged = (4u8, 3u8, 0u8, codec::Compact(timestamp + 1)).encode().encode();
+	assert!(verify(root, &forged, &proof, 2).is_err());
+
+	// proven extrinsics that aren't the timestamp inherent
+	for extrinsic in [signed, other_call, b"para inherent".to_vec().encode()] {
+		let (root, proof) = extrinsics_root(&extrinsic, 0);
+		assert!(verify(root, &extrinsic, &proof, 0).is_err());
+	}
+}
+
+#[test]
+fn test_verify_child_trie_state_proof() {
+	use sp_core::storage::{ChildInfo, StateVersion};
+
+	let child_trie = b"crowdloan".to_vec();
+	let child_info = ChildInfo::new_default(&child_trie);
+	let backend = || {
+		sp_state_machine::InMemoryBackend::<Hasher>::from((
+			vec![
+				(
+					Some(child_info.clone()),
+					vec![
+						(b"contributor".to_vec(), Some(vec![7u8; 4].encode())),
+						(b"other".to_vec(), Some(vec![8u8].encode())),
+					],
+				),
+				(None, vec![(b"main".to_vec(), Some(vec![1u8, 2, 3].encode()))]),
+			],
+			StateVersion::V0,
+		))
+	};
+	let state_root = *backend().root();
+
+	let child_proof =
+		sp_state_machine::prove_child_read(backend(), &child_info, &[b"contributor"]).unwrap();
+	let main_proof = sp_state_machine::prove_read(backend(), &[b"main"]).unwrap();
+	let state_proof = sp_trie::StorageProof::merge([child_proof, main_proof])
+		.into_iter_nodes()
+		.collect::<Vec<_>>();
+
+	let contributor =
+		StateProofKey::Child { child_trie: child_trie.clone(), key: b"contributor".to_vec() };
+	verify_state_proof_values::<Hasher>(
+		&state_root,
+		state_proof.clone(),
+		vec![
+			(contributor.clone(), Some(vec![7u8; 4])),
+			(StateProofKey::Main(b"main".to_vec()), Some(vec![1, 2, 3])),
+		],
+	)
+	.unwrap();
+
+	// the child trie value doesn't match
+	assert!(verify_state_proof_values::<Hasher>(
+		&state_root,
+		state_proof.clone(),
+		vec![(contributor, Some(vec![8u8; 4]))],
+	)
+	.is_err());
+
+	// the key isn't in a child trie with this storage key
+	let wrong_trie =
+		StateProofKey::Child { child_trie: b"other".to_vec(), key: b"contributor".to_vec() };
+	assert!(verify_state_proof_values::<Hasher>(
+		&state_root,
+		state_proof,
+		vec![(wrong_trie, Some(vec![7u8; 4]))],
+	)
+	.is_err());
+}
+
+#[tokio::test]
+async fn test_proof_builder_matches_direct_query() {
+	let relay = std::env::var("RELAY_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+	let para = std::env::var("PARA_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+	let relay_ws_url = format!("ws://{relay}:9944");
+	let para_ws_url = format!("ws://{para}:9188");
+
+	let prover = GrandpaProver::<PolkadotConfig>::new(&relay_ws_url, &para_ws_url, 2000)
+		.await
+		.unwrap();
+	let client_state = prover.initialize_client_state().await.unwrap();
+
+	// wait for the relay chain to finalize past the client state
+	let finalized = prover
+		.relay_client
+		.blocks()
+		.subscribe_finalized()
+		.await
+		.unwrap()
+		.filter_map(|result| futures::future::ready(result.ok()))
+		.skip_while(|h| futures::future::ready(h.number() <= client_state.latest_relay_height + 2))
+		.next()
+		.await
+		.unwrap();
+
+	// prove with a fixed justification, so both proofs are for the same relay chain block
+	let (finality_proof, target_number) = grandpa_prover::fetch_finality_proof::<
+		SubstrateHeader<u32, BlakeTwo256>,
+	>(&*prover.grandpa_rpc, finalized.number())
+	.await
+	.unwrap();
+	let finalized_para_header =
+		prover.query_latest_finalized_parachain_header(target_number).await.unwrap();
+	let header_numbers =
+		((client_state.latest_para_height + 1)..=finalized_para_header.number).collect::<Vec<_>>();
+
+	let direct = prover
+		.query_finalized_parachain_headers_with_proof::<SubstrateHeader<u32, BlakeTwo256>>(
+			client_state.latest_relay_height,
+			target_number,
+			Some(finality_proof.justification.clone()),
+			header_numbers.clone(),
+		)
+		.await
+		.unwrap();
+	let built = prover
+		.proof()
+		.from(client_state.latest_relay_height)
+		.to(target_number)
+		.justification(finality_proof.justification)
+		.headers(header_numbers)
+		.max_query_storage_blocks(2)
+		.generate::<SubstrateHeader<u32, BlakeTwo256>>()
+		.await
+		.unwrap();
+
+	assert_eq!(built.encode(), direct.encode());
+}
+
+#[tokio::test]
+async fn test_prove_at_non_contiguous_relay_blocks() {
+	let relay = std::env::var("RELAY_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+	let para = std::env::var("PARA_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+	let relay_ws_url = format!("ws://{relay}:9944");
+	let para_ws_url = format!("ws://{para}:9188");
+
+	let prover = GrandpaProver::<PolkadotConfig>::new(&relay_ws_url, &para_ws_url, 2000)
+		.await
+		.unwrap();
+	let client_state = prover.initialize_client_state().await.unwrap();
+
+	// wait for a few parachain heads to be finalized past the client state
+	let finalized = prover
+		.relay_client
+		.blocks()
+		.subscribe_finalized()
+		.await
+		.unwrap()
+		.filter_map(|result| futures::future::ready(result.ok()))
+		.skip_while(|h| futures::future::ready(h.number() <= client_state.latest_relay_height + 12))
+		.next()
+		.await
+		.unwrap();
+
+	// every other relay chain block that included a parachain header
+	let key = parachain_header_storage_key(2000);
+	let hashes = prover
+		.query_storage_range(
+			&[key.as_ref()],
+			client_state.latest_relay_height + 1,
+			finalized.number(),
+		)
+		.await
+		.unwrap()
+		.into_iter()
+		.map(|changes| changes.block)
+		.step_by(2)
+		.collect::<Vec<_>>();
+	assert!(hashes.len() >= 2);
+
+	let proof = prover
+		.prove_at_relay_blocks::<SubstrateHeader<u32, BlakeTwo256>>(hashes.clone())
+		.await
+		.unwrap();
+
+	let mut expected = hashes.into_iter().map(H256::from).collect::<Vec<_>>();
+	expected.sort();
+	assert_eq!(proof.parachain_headers.keys().cloned().collect::<Vec<_>>(), expected);
+	// the unknown headers link every proven block to the finalized block
+	let unknown_headers = proof
+		.finality_proof
+		.unknown_headers
+		.iter()
+		.map(subxt::config::Header::hash)
+		.collect::<Vec<_>>();
+	assert!(expected.iter().all(|hash| unknown_headers.contains(hash)));
+	assert_eq!(unknown_headers.last(), Some(&proof.finality_proof.block));
+}
+
+#[tokio::test]
+async fn test_prove_lagging_client_state() {
+	let relay = std::env::var("RELAY_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+	let para = std::env::var("PARA_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+	let relay_ws_url = format!("ws://{relay}:9944");
+	let para_ws_url = format!("ws://{para}:9188");
+
+	let prover = GrandpaProver::<PolkadotConfig>::new(&relay_ws_url, &para_ws_url, 2000)
+		.await
+		.unwrap();
+	let client_state = prover.initialize_client_state().await.unwrap();
+
+	// let the client state fall behind the relay chain
+	prover
+		.relay_client
+		.blocks()
+		.subscribe_finalized()
+		.await
+		.unwrap()
+		.filter_map(|result| futures::future::ready(result.ok()))
+		.skip_while(|h| futures::future::ready(h.number() <= client_state.latest_relay_height + 4))
+		.next()
+		.await
+		.unwrap();
+
+	let proof = prover
+		.prove_next_update::<SubstrateHeader<u32, BlakeTwo256>>(&client_state)
+		.await
+		.unwrap()
+		.expect("client state is behind the finalized head");
+	let proof = ParachainHeadersWithFinalityProof::<Header>::decode(&mut &*proof.encode()).unwrap();
+
+	let new_client_state = verify_parachain_headers_with_grandpa_finality_proof::<
+		Header,
+		HostFunctionsProvider,
+	>(client_state.clone(), proof.clone())
+	.unwrap();
+	assert!(new_client_state.latest_relay_height > client_state.latest_relay_height);
+	if !proof.parachain_headers.is_empty() {
+		assert!(new_client_state.latest_para_height > client_state.latest_para_height);
+	}
+}
+
+#[tokio::test]
+async fn test_reconfigure_para_id() {
+	let relay = std::env::var("RELAY_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+	let para = std::env::var("PARA_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+	let relay_ws_url = format!("ws://{relay}:9944");
+	let para_ws_url = format!("ws://{para}:9188");
+
+	let mut prover = GrandpaProver::<PolkadotConfig>::new(&relay_ws_url, &para_ws_url, 2000)
+		.await
+		.unwrap();
+	let client_state = prover.initialize_client_state().await.unwrap();
+	assert_eq!(client_state.para_id, 2000);
+
+	// no parachain is registered under the new id, so the prover is left unchanged
+	let err = prover.reconfigure(Some(2001), None, None).await.unwrap_err();
+	assert!(matches!(err, ProverError::UnknownParaId { para_id: 2001 }));
+	assert_eq!(prover.para_id, 2000);
+
+	// reconnecting to the same endpoints under the registered id
+	let stale = prover.clone();
+	prover
+		.reconfigure(Some(2000), Some(&relay_ws_url), Some(&para_ws_url))
+		.await
+		.unwrap();
+	let client_state = prover.initialize_client_state().await.unwrap();
+	assert_eq!(client_state.para_id, 2000);
+	// the replaced connections are closed
+	assert!(stale.initialize_client_state().await.is_err());
+}
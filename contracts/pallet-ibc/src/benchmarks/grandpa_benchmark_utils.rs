@@ -137,6 +137,8 @@ pub fn generate_finality_proof(
 				state_proof: state_proof.clone(),
 				extrinsic: timestamp_extrinsic.clone(),
 				extrinsic_proof: extrinsic_proof.clone(),
+				storage_key: None,
+				extrinsic_index: 0,
 			},
 		);
 	}
@@ -153,6 +155,7 @@ pub fn generate_finality_proof(
 		para_id,
 		current_set_id: set_id,
 		current_authorities: authorities.into_iter().map(|authority| (authority, 100)).collect(),
+		max_anchor_age: None,
 		_phantom: Default::default(),
 	};
 
@@ -401,7 +401,8 @@ where
 					.0;
 
 				let mut trusted_finality_proof =
-					FinalityProof::<RelayChainHeader>::decode(&mut &encoded[..])?;
+					FinalityProof::<RelayChainHeader>::decode_versioned(&encoded)
+						.map_err(|err| anyhow!("{err}"))?;
 				let trusted_justification =
 					GrandpaJustification::decode(&mut &*trusted_finality_proof.justification)?;
 				let to_block = trusted_justification.commit.target_number;
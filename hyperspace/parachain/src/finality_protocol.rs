@@ -393,7 +393,8 @@ where
 	.ok_or_else(|| anyhow!("No justification found for block: {:?}", next_relay_height))?
 	.0;
 
-	let finality_proof = FinalityProof::<T::Header>::decode(&mut &encoded[..])?;
+	let finality_proof =
+		FinalityProof::<T::Header>::decode_versioned(&encoded).map_err(|err| anyhow!("{err}"))?;
 
 	let justification =
 		GrandpaJustification::<T::Header>::decode(&mut &finality_proof.justification[..])?;
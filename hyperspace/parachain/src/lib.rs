@@ -271,13 +271,13 @@ where
 	pub fn grandpa_prover(&self) -> GrandpaProver<T> {
 		let relay_ws_client = unsafe { unsafe_cast_to_jsonrpsee_client(&self.relay_ws_client) };
 		let para_ws_client = unsafe { unsafe_cast_to_jsonrpsee_client(&self.para_ws_client) };
-		GrandpaProver {
-			relay_client: self.relay_client.clone(),
+		GrandpaProver::from_parts(
+			self.relay_client.clone(),
 			relay_ws_client,
-			para_client: self.para_client.clone(),
+			self.para_client.clone(),
 			para_ws_client,
-			para_id: self.para_id,
-		}
+			self.para_id,
+		)
 	}
 
 	/// Queries parachain headers that have been finalized by BEEFY in between the given relay chain
@@ -544,13 +544,13 @@ where
 	{
 		let relay_ws_client = unsafe { unsafe_cast_to_jsonrpsee_client(&self.relay_ws_client) };
 		let para_ws_client = unsafe { unsafe_cast_to_jsonrpsee_client(&self.para_ws_client) };
-		let prover = GrandpaProver {
-			relay_client: self.relay_client.clone(),
+		let prover = GrandpaProver::from_parts(
+			self.relay_client.clone(),
 			relay_ws_client,
-			para_client: self.para_client.clone(),
+			self.para_client.clone(),
 			para_ws_client,
-			para_id: self.para_id,
-		};
+			self.para_id,
+		);
 		let api = self.relay_client.storage();
 		let para_client_api = self.para_client.storage();
 		loop {
@@ -594,6 +594,12 @@ where
 			client_state.latest_para_height = block_number;
 			client_state.para_id = self.para_id;
 			client_state.latest_relay_height = light_client_state.latest_relay_height;
+			// relayers identify the client by its canonical id, which fails to derive for relay
+			// chain ids that can't be part of an IBC identifier.
+			let client_id = client_state
+				.derived_client_id()
+				.map_err(|e| Error::from(format!("Error deriving client id: {e:?}")))?;
+			log::info!(target: "hyperspace", "Initializing GRANDPA client {client_id}");
 
 			let subxt_block_number: subxt::rpc::types::BlockNumber = block_number.into();
 			let block_hash =
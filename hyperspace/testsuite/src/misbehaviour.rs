@@ -187,6 +187,8 @@ where
 				state_proof: state_proof.clone(),
 				extrinsic: timestamp_extrinsic.clone(),
 				extrinsic_proof: extrinsic_proof.clone(),
+				storage_key: None,
+				extrinsic_index: 0,
 			},
 		);
 	}
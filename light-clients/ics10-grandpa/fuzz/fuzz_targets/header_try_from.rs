@@ -0,0 +1,31 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Feeds arbitrary bytes through the protobuf decoding of GRANDPA headers, malformed headers have
+//! to be rejected with an error rather than a panic. The corpus is seeded with a valid header.
+//!
+//! Run with `cargo fuzz run header_try_from` from `light-clients/ics10-grandpa`.
+
+#![no_main]
+
+use ics10_grandpa::{client_message::Header, proto::Header as RawHeader};
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+
+fuzz_target!(|data: &[u8]| {
+	if let Ok(raw_header) = RawHeader::decode(data) {
+		let _ = Header::try_from(raw_header);
+	}
+});
@@ -13,19 +13,24 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{client_state::ClientState, consensus_state::ConsensusState, error::Error};
+use crate::{
+	client_state::ClientState,
+	consensus_state::ConsensusState,
+	error::{Error, VerificationStage},
+};
 use ibc::core::ics02_client::{
 	client_consensus::ConsensusState as _, client_state::ClientState as _,
 };
 
-use crate::client_message::{ClientMessage, RelayChainHeader};
+use crate::client_message::{ClientMessage, Header as GrandpaHeader, RelayChainHeader};
 use alloc::{format, string::ToString, vec, vec::Vec};
 use codec::Decode;
 use core::marker::PhantomData;
 use finality_grandpa::Chain;
 use grandpa_client_primitives::{
-	justification::{find_scheduled_change, AncestryChain, GrandpaJustification},
-	ParachainHeadersWithFinalityProof,
+	helpers,
+	justification::{AncestryChain, GrandpaJustification},
+	FinalityProof, ParachainHead, ParachainHeaderProofs, ParachainHeadersWithFinalityProof,
 };
 use ibc::{
 	core::{
@@ -49,12 +54,14 @@ use ibc::{
 		},
 		ics26_routing::context::ReaderContext,
 	},
+	timestamp::Timestamp,
 	Height,
 };
 use light_client_common::{
 	state_machine, verify_delay_passed, verify_membership, verify_non_membership,
 };
 use sp_core::H256;
+use sp_finality_grandpa::AuthorityList;
 use sp_runtime::traits::Header;
 use sp_trie::StorageProof;
 use tendermint_proto::Protobuf;
@@ -65,6 +72,434 @@ const CONSENSUS_STATE_UPGRADE_PATH: &[u8] = b"consensus-state-upgrade-path";
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
 pub struct GrandpaClient<T>(PhantomData<T>);
 
+/// Verifies the GRANDPA finality proof and the parachain header proofs contained in the header,
+/// returning the consensus state for every finalized parachain header in ascending height order.
+pub fn verify_grandpa_header<H>(
+	client_state: &ClientState<H>,
+	header: GrandpaHeader,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	verify_grandpa_header_with_observer(client_state, header, None)
+}
+
+/// Same as [`verify_grandpa_header`], but invokes `observer` with the height, timestamp in
+/// milliseconds and state root of every verified parachain header in ascending height order, once
+/// the whole header has been verified.
+pub fn verify_grandpa_header_with_observer<H>(
+	client_state: &ClientState<H>,
+	header: GrandpaHeader,
+	observer: Option<&mut dyn FnMut(Height, u64, H256)>,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	verify_header(client_state, header, VerificationMode::Full, observer)
+}
+
+/// Verifies the header like [`verify_grandpa_header`], returning the consensus state of the
+/// parachain header at `height` rather than all of them, or `None` if the header doesn't prove
+/// that height. Useful for checking membership proofs at heights below the header's latest.
+pub fn verify_consensus_state_at<H>(
+	client_state: &ClientState<H>,
+	header: GrandpaHeader,
+	height: Height,
+) -> Result<Option<ConsensusState>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	let consensus_states = verify_grandpa_header(client_state, header)?;
+	let consensus_state = consensus_states
+		.binary_search_by_key(&height, |(height, ..)| *height)
+		.ok()
+		.map(|index| consensus_states[index].1.clone());
+
+	Ok(consensus_state)
+}
+
+/// Same as [`verify_grandpa_header`], but only verifies the state proof of the parachain header
+/// included in the highest finalized relay chain block, returning its consensus state alone.
+///
+/// The justification and the relay chain ancestry are verified as usual, so the tip is as secure
+/// as with full verification. The parachain headers below the tip are dropped unverified: a
+/// malicious relay endpoint can include garbage proofs for them without the update failing, and
+/// their consensus states are never stored, so proofs against the client at those heights aren't
+/// possible. Only use this with a trusted relay endpoint, where only the latest parachain state
+/// matters.
+pub fn verify_tip_only<H>(
+	client_state: &ClientState<H>,
+	header: GrandpaHeader,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	verify_header(client_state, header, VerificationMode::TipOnly, None)
+}
+
+/// Which of the finalized parachain headers have their state proofs verified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VerificationMode {
+	/// Every finalized parachain header
+	Full,
+	/// Only the parachain header included in the highest finalized relay chain block
+	TipOnly,
+}
+
+fn verify_header<H>(
+	client_state: &ClientState<H>,
+	header: GrandpaHeader,
+	mode: VerificationMode,
+	observer: Option<&mut dyn FnMut(Height, u64, H256)>,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	let ParachainHeadersWithFinalityProof { finality_proof, parachain_headers } =
+		ParachainHeadersWithFinalityProof::from(header);
+
+	let justification =
+		GrandpaJustification::<RelayChainHeader>::decode(&mut &finality_proof.justification[..])
+			.map_err(failed_at(VerificationStage::Finality))?;
+	let ancestry = AncestryChain::<RelayChainHeader>::new(&finality_proof.unknown_headers);
+	// checked before verifying finality, so that updates the client refuses anyway are rejected
+	// cheaply.
+	if let Some(max_anchor_age) = client_state.max_anchor_age {
+		let target = ancestry
+			.header(&finality_proof.block)
+			.ok_or_else(|| Error::Custom("Finalized relay chain header not found".to_string()))?;
+		let finalized_height = target.number;
+		if finalized_height.saturating_sub(client_state.latest_relay_height) > max_anchor_age {
+			Err(Error::AnchorTooOld {
+				anchor_height: client_state.latest_relay_height,
+				finalized_height,
+				max_anchor_age,
+			})?
+		}
+	}
+	// the client doesn't track pending set changes, so it isn't updated past a change's signal
+	// without also reaching the last block of the set it replaces.
+	let pending = helpers::pending_set_change(
+		&client_state.clone().into(),
+		&finality_proof.unknown_headers,
+		justification.commit.target_hash,
+		justification.commit.target_number,
+	);
+	if let Some(change) = pending {
+		Err(Error::PendingAuthoritySetChange {
+			signalled_at: change.signalled_at(),
+			enacted_at: change.enacted_at(),
+			finalized: justification.commit.target_number,
+		})?
+	}
+	// the justification is signed by the set voting on its target, which isn't the client's set
+	// once changes signalled in the unknown headers are enacted below the target. The verifier
+	// applies the same changes, so it's given the client's own state.
+	let voters = authority_set_at(
+		client_state,
+		&finality_proof.unknown_headers,
+		justification.commit.target_hash,
+		justification.commit.target_number,
+	);
+	// votes by authorities outside of the voting set mean the header was proven under another
+	// authority set, rather than being forged.
+	let unknown_voter =
+		justification.commit.precommits.iter().find(|precommit| {
+			!voters.current_authorities.iter().any(|(id, _)| *id == precommit.id)
+		});
+	if let Some(precommit) = unknown_voter {
+		Err(Error::Verification {
+			stage: VerificationStage::AuthoritySet,
+			reason: format!("{:?} is not in authority set {}", precommit.id, voters.current_set_id),
+		})?
+	}
+
+	ensure_commit_target_descends(
+		client_state,
+		justification.commit.target_hash,
+		&finality_proof.unknown_headers,
+	)?;
+	ensure_vote_ancestries(&justification)?;
+
+	// the parachain headers are verified below, so that failures can be attributed to their stage.
+	grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<RelayChainHeader, H>(
+		client_state.clone().into(),
+		ParachainHeadersWithFinalityProof {
+			finality_proof: finality_proof.clone(),
+			parachain_headers: Default::default(),
+		},
+	)
+	.map_err(failed_at(VerificationStage::Finality))?;
+
+	let mut finalized = ancestry
+		.ancestry(client_state.latest_relay_hash, finality_proof.block)
+		.map_err(failed_at(VerificationStage::Finality))?;
+	finalized.sort();
+
+	let mut proofs = vec![];
+	for (relay_hash, parachain_header_proof) in parachain_headers {
+		// parachain headers that aren't in the finalized chain are ignored by the verifier.
+		if finalized.binary_search(&relay_hash).is_err() {
+			continue
+		}
+
+		let header = ancestry.header(&relay_hash).ok_or_else(|| {
+			Error::Custom(format!("No relay chain header found for hash: {relay_hash:?}"))
+		})?;
+		proofs.push((header, parachain_header_proof));
+	}
+	if mode == VerificationMode::TipOnly {
+		proofs = proofs.into_iter().max_by_key(|(header, _)| header.number).into_iter().collect();
+	}
+
+	// finality has been verified, so the state proofs can be verified independently.
+	#[cfg(feature = "parallel")]
+	let mut consensus_states = verify_state_proofs_parallel::<H>(client_state.para_id, proofs)?;
+	#[cfg(not(feature = "parallel"))]
+	let mut consensus_states = verify_state_proofs::<H>(client_state.para_id, proofs)?;
+	consensus_states.sort_by_key(|(height, ..)| *height);
+
+	if let Some(observer) = observer {
+		for (height, consensus_state) in &consensus_states {
+			let timestamp = Timestamp::from(consensus_state.timestamp).nanoseconds() / 1_000_000;
+			observer(*height, timestamp, H256::from_slice(consensus_state.root.as_bytes()));
+		}
+	}
+
+	Ok(consensus_states)
+}
+
+/// Returns the client state with the authority set that votes on the relay chain block at
+/// `height`, applying the set changes signalled between the client's latest relay chain block and
+/// `target` that are enacted by `height`, see [`helpers::authority_set_at`], which the verifier
+/// rotates the set with as well. As the client doesn't track pending changes, headers ending
+/// between a change's signal and its enactment are rejected, see
+/// [`Error::PendingAuthoritySetChange`].
+pub fn authority_set_at<H: Clone>(
+	client_state: &ClientState<H>,
+	unknown_headers: &[RelayChainHeader],
+	target: H256,
+	height: u32,
+) -> ClientState<H> {
+	let rotated =
+		helpers::authority_set_at(&client_state.clone().into(), unknown_headers, target, height);
+
+	ClientState {
+		current_set_id: rotated.current_set_id,
+		current_authorities: rotated.current_authorities,
+		..client_state.clone()
+	}
+}
+
+/// Checks that the commit target descends from the client's latest relay chain block, rather than
+/// a sibling of it, by walking back from the target through the unknown headers until the
+/// client's height is reached.
+fn ensure_commit_target_descends<H>(
+	client_state: &ClientState<H>,
+	target: H256,
+	unknown_headers: &[RelayChainHeader],
+) -> Result<(), Error> {
+	let ancestry = AncestryChain::<RelayChainHeader>::new(unknown_headers);
+	let mut hash = target;
+	while hash != client_state.latest_relay_hash {
+		match ancestry.header(&hash) {
+			Some(header) if header.number > client_state.latest_relay_height =>
+				hash = header.parent_hash,
+			_ =>
+				return Err(Error::ForkedCommitTarget {
+					target,
+					latest_relay_hash: client_state.latest_relay_hash,
+				}),
+		}
+	}
+
+	Ok(())
+}
+
+/// Checks that every precommit target descends from the commit target, by following the parent
+/// links of the vote ancestries from the precommit target down to the commit target.
+fn ensure_vote_ancestries(
+	justification: &GrandpaJustification<RelayChainHeader>,
+) -> Result<(), Error> {
+	let commit = &justification.commit;
+	let ancestry = AncestryChain::<RelayChainHeader>::new(&justification.votes_ancestries);
+	for precommit in commit.precommits.iter().map(|signed| &signed.precommit) {
+		let mut hash = precommit.target_hash;
+		while hash != commit.target_hash {
+			match ancestry.header(&hash) {
+				Some(header) if header.number > commit.target_number => hash = header.parent_hash,
+				_ =>
+					return Err(Error::InvalidVoteAncestry {
+						precommit_target: precommit.target_hash,
+						commit_target: commit.target_hash,
+					}),
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/// Verifies the justification of the finality proof against the authority set `set_id`, returning
+/// the hash of the relay chain block it finalizes. Unlike [`verify_grandpa_header`], the unknown
+/// headers aren't checked against a client's latest relay chain block, so this only proves that
+/// the authorities finalized the block, for tooling that verifies finality proofs in isolation.
+pub fn verify_grandpa_finality<H>(
+	proof: &FinalityProof<RelayChainHeader>,
+	authorities: &AuthorityList,
+	set_id: u64,
+) -> Result<H256, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	let justification =
+		GrandpaJustification::<RelayChainHeader>::decode(&mut &proof.justification[..])
+			.map_err(failed_at(VerificationStage::Finality))?;
+	if justification.commit.target_hash != proof.block {
+		Err(Error::Verification {
+			stage: VerificationStage::Finality,
+			reason: format!(
+				"Justification finalizes {:?} rather than {:?}",
+				justification.commit.target_hash, proof.block
+			),
+		})?
+	}
+	ensure_vote_ancestries(&justification)?;
+	justification
+		.verify::<H>(set_id, authorities)
+		.map_err(failed_at(VerificationStage::Finality))?;
+
+	Ok(proof.block)
+}
+
+/// Verifies the proofs of parachain headers included in the given finalized relay chain headers,
+/// one after the other.
+pub fn verify_state_proofs<H>(
+	para_id: u32,
+	proofs: Vec<(&RelayChainHeader, ParachainHeaderProofs)>,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	proofs
+		.into_iter()
+		.map(|(header, proof)| verify_state_proof::<H>(para_id, header, proof))
+		.collect()
+}
+
+/// Same as [`verify_state_proofs`], but the proofs are verified on the rayon thread pool.
+#[cfg(feature = "parallel")]
+pub fn verify_state_proofs_parallel<H>(
+	para_id: u32,
+	proofs: Vec<(&RelayChainHeader, ParachainHeaderProofs)>,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	use rayon::prelude::*;
+
+	proofs
+		.into_par_iter()
+		.map(|(header, proof)| verify_state_proof::<H>(para_id, header, proof))
+		.collect()
+}
+
+/// Verifies the state proof of the parachain header included in the given relay chain header,
+/// along with its timestamp extrinsic proof.
+fn verify_state_proof<H>(
+	para_id: u32,
+	header: &RelayChainHeader,
+	parachain_header_proof: ParachainHeaderProofs,
+) -> Result<(Height, ConsensusState), Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	let para_header =
+		verify_parachain_header::<H>(header.state_root, para_id, &parachain_header_proof)?;
+	let para_header = ParachainHead::<RelayChainHeader>::decode(&para_header)
+		.and_then(ParachainHead::into_header)
+		.map_err(failed_at(VerificationStage::StateProof))?;
+	grandpa_client::verify_timestamp_extrinsic_proof::<H::BlakeTwo256>(
+		&para_header.extrinsics_root,
+		&parachain_header_proof.extrinsic_proof,
+		&parachain_header_proof.extrinsic,
+		parachain_header_proof.extrinsic_index,
+	)
+	.map_err(failed_at(VerificationStage::Timestamp))?;
+
+	ConsensusState::from_header::<H>(parachain_header_proof, para_id, header.state_root.clone())
+		.map_err(failed_at(VerificationStage::Timestamp))
+}
+
+/// Verifies the state proof of a parachain header against the state root of the relay chain block
+/// it was included in, returning the SCALE encoded parachain head stored under
+/// `Paras::Heads(para_id)`. The timestamp extrinsic proof isn't checked.
+pub fn verify_parachain_header<H>(
+	relay_state_root: H256,
+	para_id: u32,
+	proof: &ParachainHeaderProofs,
+) -> Result<Vec<u8>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions,
+{
+	if let Some(storage_key) = proof.storage_key.clone() {
+		grandpa_client::ensure_parachain_header_storage_key(para_id, storage_key)
+			.map_err(failed_at(VerificationStage::StateProof))?;
+	}
+	grandpa_client::verify_parachain_header_state_proof::<H::BlakeTwo256>(
+		&relay_state_root,
+		proof.state_proof.clone(),
+		para_id,
+	)
+	.map_err(failed_at(VerificationStage::StateProof))
+}
+
+/// Attributes a header verification failure to the stage it occurred at.
+fn failed_at<E: core::fmt::Debug>(stage: VerificationStage) -> impl FnOnce(E) -> Error {
+	move |err| Error::Verification { stage, reason: format!("{err:?}") }
+}
+
+/// Verifies a backlog of headers in order, starting from the given client state. The client state
+/// is advanced after each header, applying authority set changes as they're enacted, so every
+/// header is verified against the state left behind by its predecessor. Returns the consensus
+/// states for all the headers.
+pub fn verify_header_batch<H>(
+	headers: &[GrandpaHeader],
+	client_state: &ClientState<H>,
+) -> Result<Vec<(Height, ConsensusState)>, Error>
+where
+	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
+{
+	let mut client_state = client_state.clone();
+	let mut consensus_states = vec![];
+	for header in headers {
+		let states = verify_grandpa_header(&client_state, header.clone())?;
+
+		let target = header
+			.finality_proof
+			.unknown_headers
+			.iter()
+			.find(|relay_header| relay_header.hash() == header.finality_proof.block)
+			.ok_or_else(|| Error::Custom("Finalized relay chain header not found".to_string()))?;
+		// the client moves on to the set voting on the blocks after the target.
+		client_state = authority_set_at(
+			&client_state,
+			&header.finality_proof.unknown_headers,
+			header.finality_proof.block,
+			target.number + 1,
+		);
+		client_state.latest_relay_hash = header.finality_proof.block;
+		client_state.latest_relay_height = target.number;
+		if let Some((height, ..)) = states.last() {
+			client_state.latest_para_height = height.revision_height as u32;
+		}
+
+		consensus_states.extend(states);
+	}
+
+	Ok(consensus_states)
+}
+
 impl<H> ClientDef for GrandpaClient<H>
 where
 	H: grandpa_client_primitives::HostFunctions<Header = RelayChainHeader>,
@@ -82,16 +517,7 @@ where
 	) -> Result<(), Ics02Error> {
 		match client_message {
 			ClientMessage::Header(header) => {
-				let headers_with_finality_proof = ParachainHeadersWithFinalityProof {
-					finality_proof: header.finality_proof,
-					parachain_headers: header.parachain_headers,
-				};
-
-				grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
-					RelayChainHeader,
-					H,
-				>(client_state.into(), headers_with_finality_proof)
-				.map_err(Error::GrandpaPrimitives)?;
+				verify_grandpa_header(&client_state, header)?;
 			},
 			ClientMessage::Misbehaviour(misbehavior) => {
 				let first_proof = misbehavior.first_finality_proof;
@@ -279,13 +705,16 @@ where
 			client_state.latest_para_height = *max_height
 		}
 
+		// the client moves on to the set voting on the blocks after the target.
+		let target_number = target.number;
+		client_state = authority_set_at(
+			&client_state,
+			&header.finality_proof.unknown_headers,
+			header.finality_proof.block,
+			target_number + 1,
+		);
 		client_state.latest_relay_hash = header.finality_proof.block;
-		client_state.latest_relay_height = target.number;
-
-		if let Some(scheduled_change) = find_scheduled_change(target) {
-			client_state.current_set_id += 1;
-			client_state.current_authorities = scheduled_change.next_authorities;
-		}
+		client_state.latest_relay_height = target_number;
 
 		H::insert_relay_header_hashes(&finalized);
 
@@ -20,32 +20,156 @@ use crate::{
 		Misbehaviour as RawMisbehaviour,
 	},
 };
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::{
+	collections::{BTreeMap, BTreeSet},
+	format,
+	string::String,
+	vec::Vec,
+};
 use anyhow::anyhow;
 use codec::{Decode, Encode};
-use grandpa_client_primitives::{FinalityProof, ParachainHeaderProofs};
+use grandpa_client_primitives::{
+	FinalityProof, ParachainHead, ParachainHeaderProofs, ParachainHeadersWithFinalityProof,
+};
+use ibc::Height;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use sp_core::H256;
-use sp_runtime::traits::BlakeTwo256;
+use sp_runtime::traits::{BlakeTwo256, Header as _};
 use tendermint_proto::Protobuf;
 
 /// Protobuf type url for GRANDPA header
 pub const GRANDPA_CLIENT_MESSAGE_TYPE_URL: &str = "/ibc.lightclients.grandpa.v1.ClientMessage";
 
+/// Default maximum size of a header's justification in bytes, see [`Header::try_from_raw`]. A
+/// justification holds a signed precommit per authority, so this leaves room for thousands of
+/// authorities along with the votes ancestries.
+pub const MAX_JUSTIFICATION_SIZE: usize = 1024 * 1024;
+
 /// Relay chain substrate header type
 pub type RelayChainHeader = sp_runtime::generic::Header<u32, BlakeTwo256>;
 
 /// Parachain headers with a Grandpa finality proof.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Header {
 	/// The grandpa finality proof: contains relay chain headers from the
 	/// last known finalized grandpa block.
 	pub finality_proof: FinalityProof<RelayChainHeader>,
 	/// Contains a map of relay chain header hashes to parachain headers
 	/// finalzed at the relay chain height. We check for this parachain header finalization
-	/// via state proofs. Also contains extrinsic proof for timestamp.
+	/// via state proofs. Also contains extrinsic proof for timestamp. Entries are ordered by hash,
+	/// see [`Header::parachain_headers_in_height_order`] for the order of the blocks. Empty for
+	/// headers that only prove the finality of the relay chain, see [`Header::relay_only`].
 	pub parachain_headers: BTreeMap<H256, ParachainHeaderProofs>,
 }
 
+impl Header {
+	/// A header that only proves the finality of the relay chain, for clients tracking the relay
+	/// chain itself rather than one of its parachains. It updates the client's relay chain block
+	/// and authority set, without adding any consensus states.
+	pub fn relay_only(finality_proof: FinalityProof<RelayChainHeader>) -> Self {
+		Header { finality_proof, parachain_headers: BTreeMap::new() }
+	}
+
+	/// Whether the header only proves the finality of the relay chain, see [`Header::relay_only`].
+	pub fn is_relay_only(&self) -> bool {
+		self.parachain_headers.is_empty()
+	}
+
+	/// The height of the highest parachain header in this header, with `para_id` as the revision
+	/// number. Each parachain header is read from its state proof against the state root of the
+	/// relay chain header it was included in, entries whose relay chain header is missing or whose
+	/// proof can't be read are skipped. The proofs aren't checked for finality, so this is only an
+	/// indication of the height the header would update the client to. With no readable parachain
+	/// headers the height is zero, since the relay chain block number isn't a parachain height.
+	pub fn height(&self, para_id: u32) -> Height {
+		let number = self
+			.parachain_headers
+			.iter()
+			.filter_map(|(relay_hash, proofs)| {
+				self.parachain_header_number(relay_hash, proofs, para_id)
+			})
+			.max()
+			.unwrap_or_default();
+
+		Height::new(para_id as u64, number as u64)
+	}
+
+	/// The parachain headers in ascending order of their block numbers, read as in
+	/// [`Header::height`], skipping the entries that can't be read. [`Header::parachain_headers`]
+	/// is ordered by relay chain block hash, which says nothing about the order of the blocks, so
+	/// checks that depend on the sequence of the headers must iterate them in this order instead.
+	/// Headers with the same number keep the order of their relay chain block hashes.
+	pub fn parachain_headers_in_height_order(
+		&self,
+		para_id: u32,
+	) -> Vec<(u32, &H256, &ParachainHeaderProofs)> {
+		let mut headers = self
+			.parachain_headers
+			.iter()
+			.filter_map(|(relay_hash, proofs)| {
+				let number = self.parachain_header_number(relay_hash, proofs, para_id)?;
+				Some((number, relay_hash, proofs))
+			})
+			.collect::<Vec<_>>();
+		headers.sort_by_key(|(number, ..)| *number);
+
+		headers
+	}
+
+	/// Reads the number of the parachain header proven by `proofs` against the state root of the
+	/// relay chain header with the given hash.
+	fn parachain_header_number(
+		&self,
+		relay_hash: &H256,
+		proofs: &ParachainHeaderProofs,
+		para_id: u32,
+	) -> Option<u32> {
+		let relay_header = self
+			.finality_proof
+			.unknown_headers
+			.iter()
+			.find(|header| header.hash() == *relay_hash)?;
+		let head = grandpa_client::verify_parachain_header_state_proof::<BlakeTwo256>(
+			&relay_header.state_root,
+			proofs.state_proof.clone(),
+			para_id,
+		)
+		.ok()?;
+		ParachainHead::<RelayChainHeader>::decode(&head).ok().map(|head| head.number())
+	}
+
+	/// The hash of the relay chain block finalized by the header's justification.
+	pub fn relay_finalized_block(&self) -> H256 {
+		self.finality_proof.block
+	}
+
+	/// The number of parachain headers carried by the header, zero if it only proves the finality
+	/// of the relay chain.
+	pub fn parachain_header_count(&self) -> usize {
+		self.parachain_headers.len()
+	}
+
+	/// The number of relay chain headers the finality proof spans, which dominates the size of the
+	/// header along with the justification.
+	pub fn unknown_header_count(&self) -> usize {
+		self.finality_proof.unknown_headers.len()
+	}
+}
+
+impl From<ParachainHeadersWithFinalityProof<RelayChainHeader>> for Header {
+	fn from(proof: ParachainHeadersWithFinalityProof<RelayChainHeader>) -> Self {
+		let ParachainHeadersWithFinalityProof { finality_proof, parachain_headers } = proof;
+		Header { finality_proof, parachain_headers }
+	}
+}
+
+impl From<Header> for ParachainHeadersWithFinalityProof<RelayChainHeader> {
+	fn from(header: Header) -> Self {
+		let Header { finality_proof, parachain_headers } = header;
+		ParachainHeadersWithFinalityProof { finality_proof, parachain_headers }
+	}
+}
+
 /// Misbehaviour type for GRANDPA. If both first and second proofs are valid
 /// (that is, form a valid canonical chain of blocks where on of the chain is a fork of
 /// the main one)
@@ -78,14 +202,56 @@ impl TryFrom<RawHeader> for Header {
 	type Error = Error;
 
 	fn try_from(raw_header: RawHeader) -> Result<Self, Self::Error> {
+		Header::try_from_raw(raw_header, MAX_JUSTIFICATION_SIZE)
+	}
+}
+
+impl TryFrom<&[u8]> for Header {
+	type Error = Error;
+
+	fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+		Header::decode_from_protobuf(bytes)
+	}
+}
+
+impl Header {
+	/// Decodes the header from its protobuf encoding, e.g. as carried in an IBC packet. Fails with
+	/// [`Error::ProtoBuf`] if the bytes aren't a [`RawHeader`], or as in [`Header::try_from_raw`]
+	/// if its fields are invalid.
+	pub fn decode_from_protobuf(bytes: &[u8]) -> Result<Self, Error> {
+		let raw_header = <RawHeader as prost::Message>::decode(bytes)?;
+		Header::try_from(raw_header)
+	}
+
+	/// Encodes the header as a protobuf [`RawHeader`], the inverse of
+	/// [`Header::decode_from_protobuf`].
+	pub fn encode_to_protobuf(&self) -> Vec<u8> {
+		prost::Message::encode_to_vec(&RawHeader::from(self.clone()))
+	}
+
+	/// Decodes the header, rejecting justifications that are empty or larger than
+	/// `max_justification_size` bytes before decoding anything else.
+	pub fn try_from_raw(
+		raw_header: RawHeader,
+		max_justification_size: usize,
+	) -> Result<Self, Error> {
 		let finality_proof = raw_header
 			.finality_proof
 			.ok_or_else(|| anyhow!("Grandpa finality proof is required!"))?;
+		let length = finality_proof.justification.len();
+		if length == 0 || length > max_justification_size {
+			Err(Error::InvalidJustification { length, max_length: max_justification_size })?
+		}
 		let block = if finality_proof.block.len() == 32 {
 			sp_core::H256::from_slice(&*finality_proof.block)
 		} else {
 			Err(anyhow!("Invalid hash type with length: {}", finality_proof.block.len()))?
 		};
+		// the justification can't be anchored to the client's latest relay chain block without
+		// any headers.
+		if finality_proof.unknown_headers.is_empty() {
+			Err(Error::NoUnknownHeaders)?
+		}
 
 		let parachain_headers = raw_header
 			.parachain_headers
@@ -96,24 +262,36 @@ impl TryFrom<RawHeader> for Header {
 				} else {
 					Err(anyhow!("Invalid hash type with length: {}", header.relay_hash.len()))?
 				};
-				let proto::ParachainHeaderProofs { state_proof, extrinsic_proof, extrinsic } =
-					header
-						.parachain_header
-						.ok_or_else(|| anyhow!("Parachain header is required!"))?;
-				let parachain_header_proofs =
-					ParachainHeaderProofs { state_proof, extrinsic, extrinsic_proof };
+				let proto::ParachainHeaderProofs {
+					state_proof,
+					extrinsic_proof,
+					extrinsic,
+					storage_key,
+					extrinsic_index,
+				} = header
+					.parachain_header
+					.ok_or_else(|| anyhow!("Parachain header is required!"))?;
+				let parachain_header_proofs = ParachainHeaderProofs {
+					state_proof,
+					extrinsic,
+					extrinsic_proof,
+					storage_key,
+					extrinsic_index,
+				};
 				Ok((block, parachain_header_proofs))
 			})
 			.collect::<Result<_, Error>>()?;
 
-		let unknown_headers = finality_proof
+		let mut unknown_headers = finality_proof
 			.unknown_headers
 			.into_iter()
 			.map(|h| {
 				let header = codec::Decode::decode(&mut &h[..])?;
 				Ok(header)
 			})
-			.collect::<Result<_, Error>>()?;
+			.collect::<Result<Vec<_>, Error>>()?;
+		ensure_contiguous(&mut unknown_headers, block)?;
+		ensure_covered(&parachain_headers, &unknown_headers)?;
 
 		Ok(Header {
 			finality_proof: FinalityProof {
@@ -126,6 +304,41 @@ impl TryFrom<RawHeader> for Header {
 	}
 }
 
+/// Sorts the unknown headers by number and checks that they form a single chain, linked by their
+/// parent hashes, that ends at the finalized block. Gaps and duplicates would otherwise only be
+/// caught by the verifier, with a less helpful error, as would a justification targeting another
+/// block than the tip of the chain.
+fn ensure_contiguous(unknown_headers: &mut [RelayChainHeader], block: H256) -> Result<(), Error> {
+	unknown_headers.sort_by_key(|header| header.number);
+	let unlinked = unknown_headers
+		.windows(2)
+		.find(|pair| pair[1].parent_hash != pair[0].hash())
+		.map(|pair| &pair[1]);
+	if let Some(header) = unlinked {
+		Err(Error::NonContiguousHeaders { number: header.number, hash: header.hash() })?
+	}
+	let tip = unknown_headers.last().map(|tip| tip.hash());
+	if let Some(tip) = tip.filter(|tip| *tip != block) {
+		Err(Error::FinalityBlockMismatch { block, tip })?
+	}
+
+	Ok(())
+}
+
+/// Checks that every parachain header is proven at one of the unknown headers, which the finality
+/// proof covers, since the contiguous unknown headers end at the finalized block.
+fn ensure_covered(
+	parachain_headers: &BTreeMap<H256, ParachainHeaderProofs>,
+	unknown_headers: &[RelayChainHeader],
+) -> Result<(), Error> {
+	let covered = unknown_headers.iter().map(|header| header.hash()).collect::<BTreeSet<_>>();
+	if let Some(relay_hash) = parachain_headers.keys().find(|hash| !covered.contains(hash)) {
+		Err(Error::OrphanParachainHeader(*relay_hash))?
+	}
+
+	Ok(())
+}
+
 impl From<Header> for RawHeader {
 	fn from(header: Header) -> Self {
 		let parachain_headers = header
@@ -137,6 +350,8 @@ impl From<Header> for RawHeader {
 					state_proof: parachain_header_proofs.state_proof,
 					extrinsic: parachain_header_proofs.extrinsic,
 					extrinsic_proof: parachain_header_proofs.extrinsic_proof,
+					storage_key: parachain_header_proofs.storage_key,
+					extrinsic_index: parachain_header_proofs.extrinsic_index,
 				}),
 			})
 			.collect();
@@ -155,6 +370,132 @@ impl From<Header> for RawHeader {
 	}
 }
 
+/// Hex encoded bytes, with a `0x` prefix.
+struct HexBytes(Vec<u8>);
+
+impl Serialize for HexBytes {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_str(&format!("0x{}", hex::encode(&self.0)))
+	}
+}
+
+impl<'de> Deserialize<'de> for HexBytes {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let hex = String::deserialize(deserializer)?;
+		let hex = hex.strip_prefix("0x").unwrap_or(&hex);
+		hex::decode(hex).map(HexBytes).map_err(de::Error::custom)
+	}
+}
+
+fn hex_bytes(bytes: Vec<Vec<u8>>) -> Vec<HexBytes> {
+	bytes.into_iter().map(HexBytes).collect()
+}
+
+fn raw_bytes(bytes: Vec<HexBytes>) -> Vec<Vec<u8>> {
+	bytes.into_iter().map(|HexBytes(bytes)| bytes).collect()
+}
+
+/// JSON representation of a [`Header`], mirroring its protobuf representation [`RawHeader`] with
+/// the bytes hex encoded, so that a header dumped as JSON encodes to the same protobuf bytes.
+#[derive(Serialize, Deserialize)]
+struct HeaderJson {
+	finality_proof: Option<FinalityProofJson>,
+	parachain_headers: Vec<ParachainHeaderWithRelayHashJson>,
+}
+
+/// JSON representation of a [`FinalityProof`], with the unknown headers SCALE encoded.
+#[derive(Serialize, Deserialize)]
+struct FinalityProofJson {
+	block: HexBytes,
+	justification: HexBytes,
+	unknown_headers: Vec<HexBytes>,
+}
+
+/// JSON representation of the parachain header proofs of a relay chain block.
+#[derive(Serialize, Deserialize)]
+struct ParachainHeaderWithRelayHashJson {
+	relay_hash: HexBytes,
+	parachain_header: Option<ParachainHeaderProofsJson>,
+}
+
+/// JSON representation of [`ParachainHeaderProofs`].
+#[derive(Serialize, Deserialize)]
+struct ParachainHeaderProofsJson {
+	state_proof: Vec<HexBytes>,
+	extrinsic: HexBytes,
+	extrinsic_proof: Vec<HexBytes>,
+	storage_key: Option<HexBytes>,
+	#[serde(default)]
+	extrinsic_index: u32,
+}
+
+impl From<RawHeader> for HeaderJson {
+	fn from(raw_header: RawHeader) -> Self {
+		let finality_proof = raw_header.finality_proof.map(|finality_proof| FinalityProofJson {
+			block: HexBytes(finality_proof.block),
+			justification: HexBytes(finality_proof.justification),
+			unknown_headers: hex_bytes(finality_proof.unknown_headers),
+		});
+		let parachain_headers = raw_header
+			.parachain_headers
+			.into_iter()
+			.map(|header| ParachainHeaderWithRelayHashJson {
+				relay_hash: HexBytes(header.relay_hash),
+				parachain_header: header.parachain_header.map(|proofs| ParachainHeaderProofsJson {
+					state_proof: hex_bytes(proofs.state_proof),
+					extrinsic: HexBytes(proofs.extrinsic),
+					extrinsic_proof: hex_bytes(proofs.extrinsic_proof),
+					storage_key: proofs.storage_key.map(HexBytes),
+					extrinsic_index: proofs.extrinsic_index,
+				}),
+			})
+			.collect();
+
+		HeaderJson { finality_proof, parachain_headers }
+	}
+}
+
+impl From<HeaderJson> for RawHeader {
+	fn from(header: HeaderJson) -> Self {
+		let finality_proof = header.finality_proof.map(|finality_proof| proto::FinalityProof {
+			block: finality_proof.block.0,
+			justification: finality_proof.justification.0,
+			unknown_headers: raw_bytes(finality_proof.unknown_headers),
+		});
+		let parachain_headers = header
+			.parachain_headers
+			.into_iter()
+			.map(|header| proto::ParachainHeaderWithRelayHash {
+				relay_hash: header.relay_hash.0,
+				parachain_header: header.parachain_header.map(|proofs| {
+					proto::ParachainHeaderProofs {
+						state_proof: raw_bytes(proofs.state_proof),
+						extrinsic: proofs.extrinsic.0,
+						extrinsic_proof: raw_bytes(proofs.extrinsic_proof),
+						storage_key: proofs.storage_key.map(|HexBytes(key)| key),
+						extrinsic_index: proofs.extrinsic_index,
+					}
+				}),
+			})
+			.collect();
+
+		RawHeader { finality_proof, parachain_headers }
+	}
+}
+
+impl Serialize for Header {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		HeaderJson::from(RawHeader::from(self.clone())).serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for Header {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let raw_header = RawHeader::from(HeaderJson::deserialize(deserializer)?);
+		Header::try_from(raw_header).map_err(de::Error::custom)
+	}
+}
+
 impl Protobuf<RawMisbehaviour> for Misbehaviour {}
 
 impl TryFrom<RawMisbehaviour> for Misbehaviour {
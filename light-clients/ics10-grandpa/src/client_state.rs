@@ -23,7 +23,10 @@ use alloc::{format, string::ToString, vec::Vec};
 use anyhow::anyhow;
 use core::{marker::PhantomData, time::Duration};
 use ibc::{
-	core::{ics02_client::client_state::ClientType, ics24_host::identifier::ChainId},
+	core::{
+		ics02_client::client_state::ClientType,
+		ics24_host::identifier::{ChainId, ClientId},
+	},
 	Height,
 };
 use light_client_common::RelayChain;
@@ -53,10 +56,25 @@ pub struct ClientState<H> {
 	pub current_set_id: u64,
 	/// authorities for the current round
 	pub current_authorities: AuthorityList,
+	/// If set, headers finalizing relay chain blocks more than this many blocks ahead of
+	/// `latest_relay_height` are rejected, limiting exposure to long-range attacks.
+	pub max_anchor_age: Option<u32>,
 	/// phantom type.
 	pub _phantom: PhantomData<H>,
 }
 
+/// Derives the canonical client identifier for a GRANDPA client tracking the parachain `para_id`
+/// of the relay chain `relay_chain_id`, in the form `10-grandpa-{relay_chain_id}-{para_id}`.
+pub fn derive_client_id(relay_chain_id: &str, para_id: u32) -> Result<ClientId, Error> {
+	let relay_chain_id = relay_chain_id.to_lowercase();
+	if relay_chain_id.is_empty() || !relay_chain_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		Err(anyhow!("Invalid relay chain id: {relay_chain_id}"))?
+	}
+	let prefix = format!("{}-{relay_chain_id}", ClientState::<()>::client_type());
+
+	Ok(ClientId::new(&prefix, para_id.into())?)
+}
+
 impl<H> From<ClientState<H>> for grandpa_client_primitives::ClientState {
 	fn from(client_state: ClientState<H>) -> grandpa_client_primitives::ClientState {
 		grandpa_client_primitives::ClientState {
@@ -108,6 +126,11 @@ impl<H> ClientState<H> {
 		"10-grandpa".to_string()
 	}
 
+	/// The canonical client identifier for this client, see [`derive_client_id`].
+	pub fn derived_client_id(&self) -> Result<ClientId, Error> {
+		derive_client_id(self.relay_chain.as_str(), self.para_id)
+	}
+
 	pub fn frozen_height(&self) -> Option<Height> {
 		self.frozen_height
 	}
@@ -217,6 +240,7 @@ impl<H> TryFrom<RawClientState> for ClientState<H> {
 			current_authorities,
 			latest_relay_hash,
 			latest_relay_height: raw.latest_relay_height,
+			max_anchor_age: raw.max_anchor_age,
 			_phantom: Default::default(),
 		})
 	}
@@ -234,6 +258,7 @@ impl<H> From<ClientState<H>> for RawClientState {
 			relay_chain: client_state.relay_chain as i32,
 			para_id: client_state.para_id,
 			latest_para_height: client_state.latest_para_height,
+			max_anchor_age: client_state.max_anchor_age,
 			current_authorities: client_state
 				.current_authorities
 				.into_iter()
@@ -55,7 +55,7 @@ impl ConsensusState {
 	{
 		let key = parachain_header_storage_key(para_id);
 		let proof = StorageProof::new(parachain_header_proof.state_proof);
-		let parachain_header_bytes = state_machine::read_proof_check::<H::BlakeTwo256, _>(
+		let head_data = state_machine::read_proof_check::<H::BlakeTwo256, _>(
 			&relay_state_root,
 			proof,
 			vec![parachain_header_storage_key(para_id)],
@@ -65,6 +65,8 @@ impl ConsensusState {
 		.flatten()
 		.ok_or_else(|| anyhow!("Invalid state proof for parachain header"))?;
 
+		// `Paras::Heads` stores the encoded header as `HeadData`
+		let parachain_header_bytes = Vec::<u8>::decode(&mut &head_data[..])?;
 		let parachain_header =
 			generic::Header::<u32, BlakeTwo256>::decode(&mut &parachain_header_bytes[..])?;
 		let root = parachain_header.state_root.0.to_vec();
@@ -80,6 +82,49 @@ impl ConsensusState {
 			Self { root: root.into(), timestamp },
 		))
 	}
+
+	/// Checks that `next`, proven at `next_height`, is a valid successor of this consensus state
+	/// proven at `height`. Heights aren't part of the consensus state, so they must be supplied
+	/// alongside it. This doesn't verify either state against the relay chain.
+	pub fn verify_successor(
+		&self,
+		height: Height,
+		next: &ConsensusState,
+		next_height: Height,
+	) -> Result<(), Error> {
+		if next_height.revision_number != height.revision_number {
+			Err(Error::Custom(format!(
+				"Consensus state successor is for parachain {}, expected {}",
+				next_height.revision_number, height.revision_number
+			)))?
+		}
+
+		if next_height.revision_height <= height.revision_height {
+			Err(Error::Custom(format!(
+				"Consensus state successor height {} is not greater than {}",
+				next_height.revision_height, height.revision_height
+			)))?
+		}
+
+		if next.timestamp < self.timestamp {
+			Err(Error::Custom(format!(
+				"Consensus state successor timestamp {} is earlier than {}",
+				next.timestamp, self.timestamp
+			)))?
+		}
+
+		for state in [self, next] {
+			// roots are parachain state roots, see [`ConsensusState::from_header`]
+			if state.root.as_bytes().len() != H256::len_bytes() {
+				Err(Error::Custom(format!(
+					"Invalid consensus state root with length: {}",
+					state.root.as_bytes().len()
+				)))?
+			}
+		}
+
+		Ok(())
+	}
 }
 
 impl ibc::core::ics02_client::client_consensus::ConsensusState for ConsensusState {
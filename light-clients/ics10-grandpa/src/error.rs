@@ -20,6 +20,7 @@ use ibc::{
 	timestamp::{ParseTimestampError, TimestampOverflowError},
 };
 use prost::DecodeError;
+use sp_core::H256;
 
 #[derive(derive_more::From, derive_more::Display, Debug)]
 pub enum Error {
@@ -32,9 +33,125 @@ pub enum Error {
 	ProtoBuf(DecodeError),
 	GrandpaPrimitives(grandpa_client_primitives::error::Error),
 	Anyhow(anyhow::Error),
+	#[from(ignore)]
+	NoUnknownHeaders,
+	#[from(ignore)]
+	#[display(
+		fmt = "Header finalizes relay chain block {} which is more than {} blocks ahead of {}",
+		finalized_height,
+		max_anchor_age,
+		anchor_height
+	)]
+	AnchorTooOld {
+		anchor_height: u32,
+		finalized_height: u32,
+		max_anchor_age: u32,
+	},
+	#[from(ignore)]
+	#[display(
+		fmt = "Commit target {:?} doesn't descend from the latest relay chain block {:?}",
+		target,
+		latest_relay_hash
+	)]
+	ForkedCommitTarget {
+		target: H256,
+		latest_relay_hash: H256,
+	},
+	#[from(ignore)]
+	#[display(
+		fmt = "Precommit target {:?} doesn't descend from the commit target {:?}",
+		precommit_target,
+		commit_target
+	)]
+	InvalidVoteAncestry {
+		precommit_target: H256,
+		commit_target: H256,
+	},
+	#[from(ignore)]
+	#[display(
+		fmt = "Unknown header {:?} at height {} isn't part of a chain to the finalized block",
+		hash,
+		number
+	)]
+	NonContiguousHeaders {
+		number: u32,
+		hash: H256,
+	},
+	#[from(ignore)]
+	#[display(fmt = "Finality proof block {:?} isn't the last unknown header {:?}", block, tip)]
+	FinalityBlockMismatch {
+		block: H256,
+		tip: H256,
+	},
+	#[from(ignore)]
+	#[display(
+		fmt = "Parachain header proven at relay chain block {:?} not covered by the finality proof",
+		_0
+	)]
+	OrphanParachainHeader(H256),
+	#[from(ignore)]
+	#[display(
+		fmt = "Justification of {} bytes is empty or larger than the maximum of {} bytes",
+		length,
+		max_length
+	)]
+	InvalidJustification {
+		length: usize,
+		max_length: usize,
+	},
+	#[from(ignore)]
+	#[display(
+		fmt = "Set change signalled at {} and enacted at {} is pending at the finalized block {}",
+		signalled_at,
+		enacted_at,
+		finalized
+	)]
+	PendingAuthoritySetChange {
+		signalled_at: u32,
+		enacted_at: u32,
+		finalized: u32,
+	},
+	#[from(ignore)]
+	#[display(fmt = "Header verification failed at the {:?} stage: {}", stage, reason)]
+	Verification {
+		stage: VerificationStage,
+		reason: String,
+	},
 	Custom(String),
 }
 
+/// Stage of header verification at which a header was rejected, so that failures can be
+/// categorized without matching on error messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerificationStage {
+	/// The relay chain ancestry or GRANDPA justification is invalid.
+	Finality,
+	/// The justification was signed by authorities outside of the client's current set.
+	AuthoritySet,
+	/// The parachain header isn't proven by the relay chain state.
+	StateProof,
+	/// The parachain timestamp extrinsic isn't proven by the parachain header.
+	Timestamp,
+}
+
+impl Error {
+	/// Stage of header verification at which this error was returned, `None` if it isn't a
+	/// verification failure.
+	pub fn verification_stage(&self) -> Option<VerificationStage> {
+		match self {
+			Error::Verification { stage, .. } => Some(*stage),
+			Error::NoUnknownHeaders |
+			Error::NonContiguousHeaders { .. } |
+			Error::InvalidJustification { .. } |
+			Error::AnchorTooOld { .. } |
+			Error::PendingAuthoritySetChange { .. } |
+			Error::ForkedCommitTarget { .. } |
+			Error::InvalidVoteAncestry { .. } => Some(VerificationStage::Finality),
+			_ => None,
+		}
+	}
+}
+
 impl From<Error> for ics02_client::error::Error {
 	fn from(e: Error) -> Self {
 		ics02_client::error::Error::client_error(
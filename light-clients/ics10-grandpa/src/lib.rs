@@ -30,5 +30,8 @@ pub mod proto;
 #[cfg(test)]
 mod mock;
 
+#[cfg(test)]
+mod test_utils;
+
 #[cfg(test)]
 mod tests;
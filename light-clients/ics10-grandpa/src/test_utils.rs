@@ -0,0 +1,274 @@
+// Copyright (C) 2022 ComposableFi.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Utilities for building synthetic, but fully verifiable GRANDPA headers in tests.
+
+use crate::{
+	client_message::{Header, RelayChainHeader},
+	client_state::ClientState,
+	mock::HostFunctionsManager,
+};
+use codec::{Compact, Encode};
+use grandpa_client_primitives::{
+	justification::GrandpaJustification, parachain_header_storage_key, FinalityProof,
+	ParachainHeaderProofs,
+};
+use sp_core::{ed25519, Pair, H256};
+use sp_finality_grandpa::{AuthorityId, AuthorityList};
+use sp_runtime::{
+	traits::{BlakeTwo256, Header as _},
+	Digest,
+};
+use sp_trie::{LayoutV0, MemoryDB, TrieDBMutBuilder, TrieMut};
+use std::collections::BTreeMap;
+
+pub const PARA_ID: u32 = 2000;
+
+/// Number of the relay chain block the client is initialized with.
+pub const BASE_RELAY_HEIGHT: u32 = 100;
+
+/// Deterministic authority keys for tests.
+pub fn authorities(count: u8) -> Vec<ed25519::Pair> {
+	(0..count).map(|i| ed25519::Pair::from_seed(&[i + 1; 32])).collect()
+}
+
+/// Authority list with equal weights for the given keys.
+pub fn authority_list(pairs: &[ed25519::Pair]) -> AuthorityList {
+	pairs.iter().map(|pair| (AuthorityId::from(pair.public()), 1)).collect()
+}
+
+/// An unsigned `Timestamp::set` extrinsic, as found in parachain blocks.
+pub fn timestamp_extrinsic(timestamp: u64) -> Vec<u8> {
+	// extrinsic version 4, pallet index 3, call index 0
+	let call = (4u8, 3u8, 0u8, Compact(timestamp)).encode();
+	call.encode()
+}
+
+/// Inserts the given key-value pairs into a fresh trie, returning its root and every node in it.
+pub fn build_trie(entries: &[(Vec<u8>, Vec<u8>)]) -> (H256, MemoryDB<BlakeTwo256>) {
+	let mut db = MemoryDB::<BlakeTwo256>::default();
+	let mut root = H256::default();
+	{
+		let mut trie = TrieDBMutBuilder::<LayoutV0<BlakeTwo256>>::new(&mut db, &mut root).build();
+		for (key, value) in entries {
+			trie.insert(key, value).expect("in-memory trie insert can't fail; qed");
+		}
+	}
+	(root, db)
+}
+
+/// Returns all live nodes in the database, which is a valid storage proof for any key in it.
+pub fn trie_nodes(mut db: MemoryDB<BlakeTwo256>) -> Vec<Vec<u8>> {
+	let mut nodes = db
+		.drain()
+		.into_iter()
+		.filter(|(_, (_, rc))| *rc > 0)
+		.map(|(_, (node, _))| node)
+		.collect::<Vec<_>>();
+	nodes.sort();
+	nodes
+}
+
+/// A parachain header whose extrinsics root commits to a timestamp inherent at index 0, along
+/// with the timestamp extrinsic and its proof.
+pub fn para_header(number: u32, timestamp: u64) -> (RelayChainHeader, Vec<u8>, Vec<Vec<u8>>) {
+	let extrinsic = timestamp_extrinsic(timestamp);
+	let key = Compact(0u32).encode();
+	let (extrinsics_root, db) = build_trie(&[(key.clone(), extrinsic.clone())]);
+	let extrinsic_proof = sp_trie::generate_trie_proof::<LayoutV0<BlakeTwo256>, _, _, _>(
+		&db,
+		extrinsics_root,
+		&[key],
+	)
+	.expect("key exists in trie; qed");
+	let header = RelayChainHeader::new(
+		number,
+		extrinsics_root,
+		H256::repeat_byte(number as u8),
+		Default::default(),
+		Default::default(),
+	);
+
+	(header, extrinsic, extrinsic_proof)
+}
+
+/// Relay chain state root & storage proof for the given parachain head.
+pub fn relay_state(para_id: u32, para_header: &RelayChainHeader) -> (H256, Vec<Vec<u8>>) {
+	let key = parachain_header_storage_key(para_id).0;
+	// `Paras::Heads` stores the head data as an encoded `Vec<u8>`
+	let (root, db) = build_trie(&[(key, para_header.encode().encode())]);
+	(root, trie_nodes(db))
+}
+
+/// A justification for `target`, signed by all of the given authorities.
+pub fn justification(
+	target: &RelayChainHeader,
+	round: u64,
+	set_id: u64,
+	pairs: &[ed25519::Pair],
+) -> GrandpaJustification<RelayChainHeader> {
+	let precommit =
+		finality_grandpa::Precommit { target_hash: target.hash(), target_number: target.number };
+	let message = finality_grandpa::Message::Precommit(precommit.clone());
+	let precommits = pairs
+		.iter()
+		.map(|pair| {
+			let signature = pair.sign(&(&message, round, set_id).encode());
+			finality_grandpa::SignedPrecommit {
+				precommit: precommit.clone(),
+				signature: signature.into(),
+				id: pair.public().into(),
+			}
+		})
+		.collect();
+
+	GrandpaJustification {
+		round,
+		commit: finality_grandpa::Commit {
+			target_hash: target.hash(),
+			target_number: target.number,
+			precommits,
+		},
+		votes_ancestries: vec![],
+	}
+}
+
+/// A parachain block included in the synthetic relay chain.
+#[derive(Clone, Copy, Debug)]
+pub struct ParaBlock {
+	/// Offset of the including relay chain block from the base block, starting at 1.
+	pub relay_offset: u32,
+	/// Parachain block number.
+	pub number: u32,
+	/// Timestamp of the parachain block in milliseconds.
+	pub timestamp: u64,
+}
+
+/// Output of [`build_fixture`].
+pub struct Fixture {
+	/// Authorities that signed the justification.
+	pub authorities: Vec<ed25519::Pair>,
+	/// Client state which knows the base relay chain block.
+	pub client_state: ClientState<HostFunctionsManager>,
+	/// The header proving all the parachain blocks.
+	pub header: Header,
+	/// The known base relay chain header, followed by all unknown headers.
+	pub relay_headers: Vec<RelayChainHeader>,
+	/// The parachain headers included in the relay chain, in ascending order.
+	pub para_headers: Vec<RelayChainHeader>,
+}
+
+/// Builds `relay_len` relay chain blocks on top of a base block known to the client, including the
+/// given parachain blocks and finalizing the tip with a justification signed by 3 authorities.
+pub fn build_fixture(relay_len: u32, para_blocks: &[ParaBlock]) -> Fixture {
+	let authorities = authorities(3);
+	build_fixture_with(relay_len, para_blocks, &authorities, 0, |_, _| {})
+}
+
+/// Same as [`build_fixture`] but with a custom authority set, set id and a hook to modify each
+/// unknown header (given its offset from the base block) before it's sealed by its child.
+pub fn build_fixture_with(
+	relay_len: u32,
+	para_blocks: &[ParaBlock],
+	authorities: &[ed25519::Pair],
+	set_id: u64,
+	modify: impl FnMut(u32, &mut RelayChainHeader),
+) -> Fixture {
+	let base = RelayChainHeader::new(
+		BASE_RELAY_HEIGHT,
+		Default::default(),
+		Default::default(),
+		H256::repeat_byte(0xff),
+		Digest::default(),
+	);
+	let (header, unknown_headers, para_headers) =
+		build_header_on(&base, relay_len, para_blocks, authorities, set_id, modify);
+	let client_state = ClientState {
+		relay_chain: Default::default(),
+		latest_relay_height: base.number,
+		latest_relay_hash: base.hash(),
+		frozen_height: None,
+		latest_para_height: 0,
+		para_id: PARA_ID,
+		current_set_id: set_id,
+		current_authorities: authority_list(authorities),
+		max_anchor_age: None,
+		_phantom: Default::default(),
+	};
+	let relay_headers = Some(base).into_iter().chain(unknown_headers).collect();
+
+	Fixture { authorities: authorities.to_vec(), client_state, header, relay_headers, para_headers }
+}
+
+/// Builds `relay_len` relay chain blocks on top of `base`, including the given parachain blocks
+/// (relative to `base`) and finalizing the tip with a justification signed by `authorities`.
+/// Returns the header, the new relay chain headers and the included parachain headers.
+pub fn build_header_on(
+	base: &RelayChainHeader,
+	relay_len: u32,
+	para_blocks: &[ParaBlock],
+	authorities: &[ed25519::Pair],
+	set_id: u64,
+	mut modify: impl FnMut(u32, &mut RelayChainHeader),
+) -> (Header, Vec<RelayChainHeader>, Vec<RelayChainHeader>) {
+	let mut relay_headers = vec![base.clone()];
+	let mut para_headers = vec![];
+	let mut parachain_headers = BTreeMap::new();
+
+	for offset in 1..=relay_len {
+		let parent = relay_headers.last().expect("contains base header; qed");
+		let mut header = RelayChainHeader::new(
+			parent.number + 1,
+			Default::default(),
+			Default::default(),
+			parent.hash(),
+			Digest::default(),
+		);
+		let mut proofs = None;
+		if let Some(block) = para_blocks.iter().find(|block| block.relay_offset == offset) {
+			let (para_header, extrinsic, extrinsic_proof) =
+				para_header(block.number, block.timestamp);
+			let (state_root, state_proof) = relay_state(PARA_ID, &para_header);
+			header.state_root = state_root;
+			para_headers.push(para_header);
+			proofs = Some(ParachainHeaderProofs {
+				state_proof,
+				extrinsic,
+				extrinsic_proof,
+				storage_key: Some(parachain_header_storage_key(PARA_ID).0),
+				extrinsic_index: 0,
+			});
+		}
+		modify(offset, &mut header);
+		if let Some(proofs) = proofs {
+			parachain_headers.insert(header.hash(), proofs);
+		}
+		relay_headers.push(header);
+	}
+
+	let unknown_headers = relay_headers.split_off(1);
+	let target = unknown_headers.last().unwrap_or(base).clone();
+	let justification = justification(&target, 1, set_id, authorities);
+	let header = Header {
+		finality_proof: FinalityProof {
+			block: target.hash(),
+			justification: justification.encode(),
+			unknown_headers: unknown_headers.clone(),
+		},
+		parachain_headers,
+	};
+
+	(header, unknown_headers, para_headers)
+}
@@ -14,12 +14,23 @@
 // limitations under the License.
 
 use crate::{
-	client_message::{ClientMessage, Header, RelayChainHeader},
-	client_state::ClientState,
+	client_def::{
+		verify_consensus_state_at, verify_grandpa_finality, verify_grandpa_header,
+		verify_grandpa_header_with_observer, verify_header_batch, verify_parachain_header,
+		verify_tip_only,
+	},
+	client_message::{ClientMessage, Header, RelayChainHeader, MAX_JUSTIFICATION_SIZE},
+	client_state::{derive_client_id, ClientState},
 	consensus_state::ConsensusState,
+	error::{Error, VerificationStage},
 	mock::{
 		AnyClientMessage, AnyClientState, AnyConsensusState, HostFunctionsManager, MockClientTypes,
 	},
+	proto::Header as RawHeader,
+	test_utils::{
+		authorities, authority_list, build_fixture, build_fixture_with, build_header_on,
+		justification, Fixture, ParaBlock, PARA_ID,
+	},
 };
 use beefy_prover::helpers::{
 	fetch_timestamp_extrinsic_with_proof, unsafe_arc_cast, TimeStampExtWithProof,
@@ -28,13 +39,15 @@ use codec::{Decode, Encode};
 use finality_grandpa_rpc::GrandpaApiClient;
 use futures::stream::StreamExt;
 use grandpa_client_primitives::{
-	justification::GrandpaJustification, parachain_header_storage_key, FinalityProof,
-	ParachainHeaderProofs, ParachainHeadersWithFinalityProof,
+	justification::{CompactGrandpaJustification, GrandpaJustification},
+	parachain_header_storage_key, FinalityProof, ParachainHeaderProofs,
+	ParachainHeadersWithFinalityProof,
 };
 use grandpa_prover::{polkadot, GrandpaProver, JustificationNotification};
 use ibc::{
 	core::{
 		ics02_client::{
+			client_consensus::ConsensusState as _,
 			client_state::ClientState as _,
 			context::{ClientKeeper, ClientReader},
 			handler::{dispatch, ClientResult::Update},
@@ -50,7 +63,8 @@ use ibc::{
 	test_utils::get_dummy_account_id,
 	Height,
 };
-use sp_core::{hexdisplay::AsBytesRef, H256};
+use sp_core::{hexdisplay::AsBytesRef, Pair, H256};
+use sp_runtime::traits::Header as _;
 use std::time::Duration;
 use subxt::{
 	config::substrate::{BlakeTwo256, SubstrateHeader},
@@ -146,6 +160,7 @@ async fn test_continuous_update_of_grandpa_client() {
 			para_id: prover.para_id,
 			current_set_id: client_state.current_set_id,
 			current_authorities: client_state.current_authorities,
+			max_anchor_age: None,
 			_phantom: Default::default(),
 		};
 		let subxt_block_number: subxt::rpc::types::BlockNumber = decoded_para_head.number.into();
@@ -170,8 +185,13 @@ async fn test_continuous_update_of_grandpa_client() {
 			.map(|bytes| bytes.0)
 			.collect();
 
-		let header_proof =
-			ParachainHeaderProofs { state_proof, extrinsic: timestamp_extrinsic, extrinsic_proof };
+		let header_proof = ParachainHeaderProofs {
+			state_proof,
+			extrinsic: timestamp_extrinsic,
+			extrinsic_proof,
+			storage_key: None,
+			extrinsic_index: 0,
+		};
 
 		let (_, consensus_state) = ConsensusState::from_header::<HostFunctionsManager>(
 			header_proof,
@@ -256,10 +276,7 @@ async fn test_continuous_update_of_grandpa_client() {
 			justification.commit.target_hash, justification.commit.target_number
 		);
 
-		let header = Header {
-			finality_proof: proof.finality_proof,
-			parachain_headers: proof.parachain_headers.clone(),
-		};
+		let header = Header::from(proof);
 		let msg = MsgUpdateAnyClient {
 			client_id: client_id.clone(),
 			client_message: AnyClientMessage::Grandpa(ClientMessage::Header(header)),
@@ -309,3 +326,1505 @@ async fn test_continuous_update_of_grandpa_client() {
 		}
 	}
 }
+
+#[test]
+fn test_verify_grandpa_header_yields_consensus_state_per_parachain_header() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 4, number: 12, timestamp: 1_012_000 },
+		ParaBlock { relay_offset: 5, number: 13, timestamp: 1_024_000 },
+	];
+	let Fixture { client_state, header, para_headers, .. } = build_fixture(6, &para_blocks);
+
+	let consensus_states = verify_grandpa_header(&client_state, header).unwrap();
+	assert_eq!(consensus_states.len(), para_blocks.len());
+
+	for (((height, consensus_state), block), para_header) in
+		consensus_states.iter().zip(&para_blocks).zip(&para_headers)
+	{
+		assert_eq!(*height, Height::new(PARA_ID as u64, block.number as u64));
+		assert_eq!(consensus_state.root.as_bytes(), para_header.state_root.as_bytes());
+		assert_eq!(consensus_state.timestamp().nanoseconds(), block.timestamp * 1_000_000);
+	}
+}
+
+#[test]
+fn test_verify_grandpa_header_observer() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 4, number: 12, timestamp: 1_012_000 },
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 5, number: 13, timestamp: 1_024_000 },
+	];
+	let Fixture { client_state, header, .. } = build_fixture(6, &para_blocks);
+
+	let mut observed = vec![];
+	let mut observer = |height: Height, timestamp: u64, state_root: H256| {
+		observed.push((height, timestamp, state_root))
+	};
+	let consensus_states =
+		verify_grandpa_header_with_observer(&client_state, header, Some(&mut observer)).unwrap();
+
+	// once per header, in ascending order
+	let expected = [(11, 1_000_000), (12, 1_012_000), (13, 1_024_000)]
+		.into_iter()
+		.map(|(number, timestamp)| {
+			(Height::new(PARA_ID as u64, number), timestamp, H256::repeat_byte(number as u8))
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(observed, expected);
+	assert_eq!(consensus_states.len(), observed.len());
+}
+
+#[test]
+fn test_verify_tip_only() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 4, number: 12, timestamp: 1_012_000 },
+		ParaBlock { relay_offset: 5, number: 13, timestamp: 1_024_000 },
+	];
+	let Fixture { client_state, header, relay_headers, .. } = build_fixture(6, &para_blocks);
+
+	let full = verify_grandpa_header(&client_state, header.clone()).unwrap();
+	let tip = verify_tip_only(&client_state, header.clone()).unwrap();
+	assert_eq!(full.len(), 3);
+	assert_eq!(tip, full[2..]);
+
+	// intermediate state proofs aren't checked
+	let mut header = header;
+	let intermediate = relay_headers[4].hash();
+	header.parachain_headers.get_mut(&intermediate).unwrap().state_proof = vec![];
+	assert!(verify_grandpa_header(&client_state, header.clone()).is_err());
+	assert_eq!(verify_tip_only(&client_state, header.clone()).unwrap(), tip);
+
+	// but finality still is
+	header.finality_proof.justification = vec![];
+	assert!(verify_tip_only(&client_state, header).is_err());
+}
+
+#[test]
+fn test_header_height() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 3, number: 13, timestamp: 1_024_000 },
+		ParaBlock { relay_offset: 5, number: 12, timestamp: 1_012_000 },
+	];
+	let Fixture { header, relay_headers, .. } = build_fixture(6, &para_blocks);
+	assert_eq!(header.height(PARA_ID), Height::new(PARA_ID as u64, 13));
+
+	// proofs for relay chain blocks missing from the unknown headers are skipped
+	let mut unanchored = header.clone();
+	let proofs = unanchored.parachain_headers.remove(&relay_headers[3].hash()).unwrap();
+	unanchored.parachain_headers.insert(H256::repeat_byte(1), proofs);
+	assert_eq!(unanchored.height(PARA_ID), Height::new(PARA_ID as u64, 12));
+
+	// as are proofs that don't prove the parachain's head
+	assert_eq!(header.height(PARA_ID + 1), Height::new(PARA_ID as u64 + 1, 0));
+
+	// without parachain headers the height is zero
+	let mut empty = header;
+	empty.parachain_headers.clear();
+	assert_eq!(empty.height(PARA_ID), Height::new(PARA_ID as u64, 0));
+}
+
+#[test]
+fn test_parachain_headers_in_height_order() {
+	// a parachain header in each of six relay chain blocks, in ascending order
+	let para_blocks = (1..=6)
+		.map(|offset| ParaBlock {
+			relay_offset: offset,
+			number: 10 + offset,
+			timestamp: 1_000_000 + offset as u64 * 12_000,
+		})
+		.collect::<Vec<_>>();
+	let Fixture { header, relay_headers, .. } = build_fixture(6, &para_blocks);
+	let chain_order = relay_headers[1..].iter().map(|header| header.hash()).collect::<Vec<_>>();
+	// the map is ordered by hash, which contradicts the order of the blocks
+	let hash_order = header.parachain_headers.keys().copied().collect::<Vec<_>>();
+	assert_ne!(hash_order, chain_order);
+
+	let headers = header.parachain_headers_in_height_order(PARA_ID);
+	let numbers = headers.iter().map(|(number, ..)| *number).collect::<Vec<_>>();
+	assert_eq!(numbers, (11..=16).collect::<Vec<_>>());
+	let relay_hashes = headers.iter().map(|(_, relay_hash, _)| **relay_hash).collect::<Vec<_>>();
+	assert_eq!(relay_hashes, chain_order);
+	for (_, relay_hash, proofs) in headers {
+		assert_eq!(proofs, &header.parachain_headers[relay_hash]);
+	}
+
+	// entries that can't be read are skipped
+	assert!(header.parachain_headers_in_height_order(PARA_ID + 1).is_empty());
+}
+
+#[test]
+fn test_relay_only_header() {
+	let Fixture { client_state, header, .. } = build_fixture(4, &[]);
+	let relay_only = Header::relay_only(header.finality_proof.clone());
+	assert_eq!(relay_only, header);
+	assert!(relay_only.is_relay_only());
+	assert_eq!(relay_only.parachain_header_count(), 0);
+
+	// the empty map survives the conversions to and from the prover's output and protobuf
+	let proof = ParachainHeadersWithFinalityProof::<RelayChainHeader>::from(relay_only.clone());
+	assert!(proof.parachain_headers.is_empty());
+	assert_eq!(Header::from(proof), relay_only);
+	let raw_header = RawHeader::from(relay_only.clone());
+	assert!(raw_header.parachain_headers.is_empty());
+	assert_eq!(Header::try_from(raw_header).unwrap(), relay_only);
+	assert_eq!(Header::decode_from_protobuf(&relay_only.encode_to_protobuf()).unwrap(), relay_only);
+
+	// it's a valid update that finalizes the relay chain without any parachain consensus states
+	assert!(verify_grandpa_header(&client_state, relay_only.clone()).unwrap().is_empty());
+	assert_eq!(relay_only.height(PARA_ID), Height::new(PARA_ID as u64, 0));
+
+	// and its finality is still verified
+	let mut forged = relay_only;
+	forged.finality_proof.justification = vec![];
+	assert!(verify_grandpa_header(&client_state, forged).is_err());
+}
+
+#[test]
+fn test_header_inspection() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 5, number: 12, timestamp: 1_012_000 },
+	];
+	let Fixture { header, relay_headers, .. } = build_fixture(6, &para_blocks);
+	assert_eq!(header.relay_finalized_block(), relay_headers.last().unwrap().hash());
+	assert_eq!(header.parachain_header_count(), 2);
+	assert_eq!(header.unknown_header_count(), 6);
+
+	// a header proving only the relay chain
+	let mut relay_only = header;
+	relay_only.parachain_headers.clear();
+	relay_only.finality_proof.unknown_headers.truncate(1);
+	assert_eq!(relay_only.parachain_header_count(), 0);
+	assert_eq!(relay_only.unknown_header_count(), 1);
+}
+
+#[test]
+fn test_verify_grandpa_finality() {
+	let Fixture { client_state, header, relay_headers, authorities, .. } = build_fixture(4, &[]);
+	let finality_proof = header.finality_proof;
+	let set_id = client_state.current_set_id;
+	let authority_list = client_state.current_authorities.clone();
+
+	let finalized =
+		verify_grandpa_finality::<HostFunctionsManager>(&finality_proof, &authority_list, set_id)
+			.unwrap();
+	assert_eq!(finalized, relay_headers.last().unwrap().hash());
+
+	// the signatures commit to the set id the justification was signed by
+	let err = verify_grandpa_finality::<HostFunctionsManager>(
+		&finality_proof,
+		&authority_list,
+		set_id + 1,
+	)
+	.unwrap_err();
+	assert_eq!(err.verification_stage(), Some(VerificationStage::Finality));
+
+	// a justification signed by the wrong set id
+	let target = relay_headers.last().unwrap();
+	let mut wrong_set = finality_proof.clone();
+	wrong_set.justification = justification(target, 1, set_id + 1, &authorities).encode();
+	assert!(verify_grandpa_finality::<HostFunctionsManager>(&wrong_set, &authority_list, set_id)
+		.is_err());
+
+	// a justification of another block
+	let mut other_block = finality_proof;
+	other_block.block = relay_headers[1].hash();
+	assert!(matches!(
+		verify_grandpa_finality::<HostFunctionsManager>(&other_block, &authority_list, set_id),
+		Err(Error::Verification { stage: VerificationStage::Finality, .. })
+	));
+}
+
+#[test]
+fn test_derive_client_id() {
+	let client_id = derive_client_id("Rococo", 2000).unwrap();
+	assert_eq!(client_id.as_str(), "10-grandpa-rococo-2000");
+	// derivation is stable
+	assert_eq!(client_id, derive_client_id("rococo", 2000).unwrap());
+	assert_ne!(client_id, derive_client_id("kusama", 2000).unwrap());
+	assert_ne!(client_id, derive_client_id("rococo", 2001).unwrap());
+
+	assert!(derive_client_id("", 2000).is_err());
+	assert!(derive_client_id("roc/oco", 2000).is_err());
+}
+
+#[test]
+fn test_header_and_prover_output_round_trip() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 3, number: 6, timestamp: 1_012_000 },
+	];
+	let Fixture { header, .. } = build_fixture(3, &para_blocks);
+
+	let proof = ParachainHeadersWithFinalityProof::<RelayChainHeader>::from(header.clone());
+	assert_eq!(proof.finality_proof, header.finality_proof);
+	assert_eq!(proof.parachain_headers.encode(), header.parachain_headers.encode());
+
+	let round_tripped = Header::from(proof);
+	assert_eq!(round_tripped.finality_proof, header.finality_proof);
+	assert_eq!(round_tripped.parachain_headers.encode(), header.parachain_headers.encode());
+}
+
+#[test]
+fn test_header_without_unknown_headers_is_rejected() {
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	let Fixture { header, .. } = build_fixture(2, &para_blocks);
+
+	let raw_header = RawHeader::from(header);
+	assert!(Header::try_from(raw_header.clone()).is_ok());
+
+	let mut raw_header = raw_header;
+	raw_header.finality_proof.as_mut().unwrap().unknown_headers.clear();
+	assert!(matches!(Header::try_from(raw_header), Err(Error::NoUnknownHeaders)));
+}
+
+#[test]
+fn test_header_with_invalid_justification_size_is_rejected() {
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	let Fixture { header, .. } = build_fixture(2, &para_blocks);
+	let raw_header = RawHeader::from(header);
+	let length = raw_header.finality_proof.as_ref().unwrap().justification.len();
+	assert!(Header::try_from_raw(raw_header.clone(), length).is_ok());
+
+	// the justification is checked against the configured maximum
+	match Header::try_from_raw(raw_header.clone(), length - 1) {
+		Err(Error::InvalidJustification { length: found, max_length }) => {
+			assert_eq!(found, length);
+			assert_eq!(max_length, length - 1);
+		},
+		result => panic!("unexpected result: {result:?}"),
+	}
+
+	let mut oversized = raw_header.clone();
+	oversized.finality_proof.as_mut().unwrap().justification = vec![0; MAX_JUSTIFICATION_SIZE + 1];
+	assert!(matches!(
+		Header::try_from(oversized),
+		Err(Error::InvalidJustification { length, .. }) if length == MAX_JUSTIFICATION_SIZE + 1
+	));
+
+	let mut empty = raw_header;
+	empty.finality_proof.as_mut().unwrap().justification.clear();
+	assert!(matches!(Header::try_from(empty), Err(Error::InvalidJustification { length: 0, .. })));
+}
+
+#[test]
+fn test_header_json_round_trip() {
+	use tendermint_proto::Protobuf;
+
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 2, number: 6, timestamp: 1_012_000 },
+	];
+	let Fixture { header, .. } = build_fixture(3, &para_blocks);
+
+	let dumped = json::to_string_pretty(&header).unwrap();
+	let reloaded: Header = json::from_str(&dumped).unwrap();
+	assert_eq!(reloaded, header);
+	assert_eq!(reloaded.encode_vec().unwrap(), header.encode_vec().unwrap());
+
+	// hashes and bytes are dumped as hex
+	let value: json::Value = json::from_str(&dumped).unwrap();
+	assert_eq!(
+		value["finality_proof"]["block"],
+		json::Value::String(format!("0x{}", hex::encode(header.finality_proof.block))),
+	);
+
+	// the reloaded header is decoded like its protobuf encoding
+	let mut value = value;
+	value["finality_proof"]["block"] = json::Value::String("0x0102".into());
+	assert!(json::from_value::<Header>(value).is_err());
+}
+
+#[test]
+fn test_fuzz_corpus_seed_is_a_valid_header() {
+	use prost::Message;
+
+	let seed = include_bytes!("../fuzz/corpus/header_try_from/valid_header");
+	let header = Header::try_from(RawHeader::decode(&seed[..]).unwrap()).unwrap();
+	let relay_header = &header.finality_proof.unknown_headers[0];
+	assert_eq!(relay_header.number, 101);
+	assert_eq!(relay_header.hash(), header.finality_proof.block);
+	assert!(header.parachain_headers.contains_key(&header.finality_proof.block));
+}
+
+#[test]
+fn test_header_with_orphan_parachain_header_is_rejected() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 3, number: 6, timestamp: 1_012_000 },
+	];
+	let Fixture { header, relay_headers, .. } = build_fixture(3, &para_blocks);
+	let raw_header = RawHeader::from(header.clone());
+	// every parachain header is proven at an unknown header
+	let decoded = Header::try_from(raw_header.clone()).unwrap();
+	assert_eq!(decoded.parachain_headers.encode(), header.parachain_headers.encode());
+
+	// proofs at the known base block or a block on another chain aren't covered
+	let proofs = header.parachain_headers.values().next().unwrap().clone();
+	for orphan in [relay_headers[0].hash(), H256::repeat_byte(7)] {
+		let mut header = header.clone();
+		header.parachain_headers.insert(orphan, proofs.clone());
+		assert!(matches!(
+			Header::try_from(RawHeader::from(header)),
+			Err(Error::OrphanParachainHeader(hash)) if hash == orphan
+		));
+	}
+}
+
+#[test]
+fn test_header_with_non_contiguous_unknown_headers_is_rejected() {
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	let Fixture { header, relay_headers, .. } = build_fixture(3, &para_blocks);
+	let raw_header = RawHeader::from(header.clone());
+	let with_unknown_headers = |unknown_headers: Vec<&RelayChainHeader>| {
+		let mut raw_header = raw_header.clone();
+		raw_header.finality_proof.as_mut().unwrap().unknown_headers =
+			unknown_headers.into_iter().map(Encode::encode).collect();
+		Header::try_from(raw_header)
+	};
+
+	// headers out of order are sorted
+	let decoded =
+		with_unknown_headers(vec![&relay_headers[3], &relay_headers[1], &relay_headers[2]])
+			.unwrap();
+	assert_eq!(decoded.finality_proof, header.finality_proof);
+
+	// missing link
+	assert!(matches!(
+		with_unknown_headers(vec![&relay_headers[1], &relay_headers[3]]),
+		Err(Error::NonContiguousHeaders { number: 103, .. })
+	));
+
+	// duplicate header
+	assert!(matches!(
+		with_unknown_headers(vec![
+			&relay_headers[1],
+			&relay_headers[2],
+			&relay_headers[2],
+			&relay_headers[3]
+		]),
+		Err(Error::NonContiguousHeaders { number: 102, .. })
+	));
+
+	// the chain doesn't end at the finalized block
+	assert!(matches!(
+		with_unknown_headers(vec![&relay_headers[1], &relay_headers[2]]),
+		Err(Error::FinalityBlockMismatch { tip, .. }) if tip == relay_headers[2].hash()
+	));
+}
+
+#[test]
+fn test_header_with_mismatched_finality_block_is_rejected() {
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	let Fixture { header, relay_headers, .. } = build_fixture(3, &para_blocks);
+	let raw_header = RawHeader::from(header.clone());
+	// the finalized block is the tip of the unknown headers
+	assert_eq!(header.finality_proof.block, relay_headers[3].hash());
+	assert_eq!(Header::try_from(raw_header.clone()).unwrap().finality_proof, header.finality_proof);
+
+	for block in [relay_headers[2].hash(), H256::repeat_byte(7)] {
+		let mut raw_header = raw_header.clone();
+		raw_header.finality_proof.as_mut().unwrap().block = block.as_bytes().to_vec();
+		assert!(matches!(
+			Header::try_from(raw_header),
+			Err(Error::FinalityBlockMismatch { block: found, tip })
+				if found == block && tip == relay_headers[3].hash()
+		));
+	}
+}
+
+#[test]
+fn test_verify_grandpa_header_max_anchor_age() {
+	let para_blocks = [ParaBlock { relay_offset: 2, number: 5, timestamp: 1_000_000 }];
+	let Fixture { mut client_state, header, relay_headers, authorities, .. } =
+		build_fixture(5, &para_blocks);
+
+	// the header finalizes a relay chain block 5 blocks ahead of the client
+	client_state.max_anchor_age = Some(5);
+	assert_eq!(verify_grandpa_header(&client_state, header.clone()).unwrap().len(), 1);
+
+	client_state.max_anchor_age = Some(4);
+	assert!(matches!(
+		verify_grandpa_header(&client_state, header.clone()),
+		Err(Error::AnchorTooOld { finalized_height, max_anchor_age: 4, .. })
+			if finalized_height == client_state.latest_relay_height + 5
+	));
+
+	// the age is checked before the justification is verified
+	let mut unverifiable = header;
+	unverifiable.finality_proof.justification =
+		justification(relay_headers.last().unwrap(), 1, 1, &authorities).encode();
+	assert!(matches!(
+		verify_grandpa_header(&client_state, unverifiable),
+		Err(Error::AnchorTooOld { max_anchor_age: 4, .. })
+	));
+}
+
+#[test]
+fn test_split_proof_at_set_change() {
+	let old_authorities = authorities(3);
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 3, number: 6, timestamp: 1_012_000 },
+	];
+	// the second relay chain block enacts a set change, blocks after it are finalized by the new
+	// authorities.
+	let set_change = sp_finality_grandpa::ConsensusLog::<u32>::ScheduledChange(
+		sp_finality_grandpa::ScheduledChange {
+			next_authorities: authority_list(&new_authorities),
+			delay: 0,
+		},
+	);
+	let Fixture { mut client_state, header, relay_headers, .. } =
+		build_fixture_with(4, &para_blocks, &new_authorities, 1, |offset, header| {
+			if offset == 2 {
+				header.digest.push(sp_runtime::DigestItem::Consensus(
+					sp_finality_grandpa::GRANDPA_ENGINE_ID,
+					set_change.encode(),
+				));
+			}
+		});
+	client_state.current_set_id = 0;
+	client_state.current_authorities = authority_list(&old_authorities);
+	let client_state: grandpa_client_primitives::ClientState = client_state.into();
+
+	let proof = ParachainHeadersWithFinalityProof::<RelayChainHeader>::from(header);
+	// the whole proof is signed by the set the change rotates to, which the verifier applies like
+	// the client does
+	let updated = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+		RelayChainHeader,
+		HostFunctionsManager,
+	>(client_state.clone(), proof.clone())
+	.unwrap();
+	assert_eq!(updated.current_set_id, 1);
+	assert_eq!(updated.current_authorities, authority_list(&new_authorities));
+
+	let set_change_block = &relay_headers[2];
+	let set_change_justification = justification(set_change_block, 1, 0, &old_authorities);
+	let justifications = [(set_change_block.hash(), set_change_justification.encode())]
+		.into_iter()
+		.collect();
+	// the justification of the set change block is required
+	assert!(proof.split_at_set_changes(&Default::default()).is_err());
+
+	let parts = proof.split_at_set_changes(&justifications).unwrap();
+	assert_eq!(parts.len(), 2);
+	assert_eq!(parts[0].finality_proof.block, set_change_block.hash());
+	assert_eq!(parts[0].parachain_headers.len(), 1);
+	assert_eq!(parts[1].finality_proof.block, proof.finality_proof.block);
+	assert_eq!(parts[1].parachain_headers.len(), 1);
+
+	// each part is verified in order, rotating the authority set in between
+	let client_state = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+		RelayChainHeader,
+		HostFunctionsManager,
+	>(client_state, parts[0].clone())
+	.unwrap();
+	assert_eq!(client_state.current_set_id, 1);
+	let client_state = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+		RelayChainHeader,
+		HostFunctionsManager,
+	>(client_state, parts[1].clone())
+	.unwrap();
+	assert_eq!(client_state.latest_para_height, 6);
+}
+
+#[test]
+fn test_split_proof_at_delayed_set_change() {
+	let old_authorities = authorities(3);
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let change = sp_finality_grandpa::ScheduledChange {
+		next_authorities: authority_list(&new_authorities),
+		delay: 2,
+	};
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 5, number: 6, timestamp: 1_012_000 },
+	];
+	// the first relay chain block signals a change enacted at the third, which is the last block
+	// of the old set for a standard change, while a forced change's enactment block is already
+	// finalized by the new set.
+	let changes = [
+		(sp_finality_grandpa::ConsensusLog::<u32>::ScheduledChange(change.clone()), 3),
+		(sp_finality_grandpa::ConsensusLog::<u32>::ForcedChange(0, change), 2),
+	];
+	for (log, last_block_of_old_set) in changes {
+		let Fixture { mut client_state, header, relay_headers, .. } =
+			build_fixture_with(5, &para_blocks, &new_authorities, 1, |offset, header| {
+				if offset == 1 {
+					header.digest.push(sp_runtime::DigestItem::Consensus(
+						sp_finality_grandpa::GRANDPA_ENGINE_ID,
+						log.encode(),
+					));
+				}
+			});
+		client_state.current_set_id = 0;
+		client_state.current_authorities = authority_list(&old_authorities);
+		let client_state: grandpa_client_primitives::ClientState = client_state.into();
+
+		let proof = ParachainHeadersWithFinalityProof::<RelayChainHeader>::from(header);
+		let last_block = &relay_headers[last_block_of_old_set];
+		let justifications =
+			[(last_block.hash(), justification(last_block, 1, 0, &old_authorities).encode())]
+				.into_iter()
+				.collect();
+		let parts = proof.split_at_set_changes(&justifications).unwrap();
+		assert_eq!(parts.len(), 2);
+		assert_eq!(parts[0].finality_proof.block, last_block.hash());
+		assert_eq!(parts[1].finality_proof.block, proof.finality_proof.block);
+
+		// the old set hands over once its last block is finalized
+		let client_state = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+			RelayChainHeader,
+			HostFunctionsManager,
+		>(client_state, parts[0].clone())
+		.unwrap();
+		assert_eq!(client_state.current_set_id, 1);
+		let client_state = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+			RelayChainHeader,
+			HostFunctionsManager,
+		>(client_state, parts[1].clone())
+		.unwrap();
+		assert_eq!(client_state.latest_para_height, 6);
+	}
+}
+
+#[test]
+fn test_verify_across_delayed_set_change() {
+	let old_authorities = authorities(3);
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	// the second relay chain block signals a set change enacted at the fourth, which is still
+	// finalized by the old authorities.
+	let set_change = sp_finality_grandpa::ConsensusLog::<u32>::ScheduledChange(
+		sp_finality_grandpa::ScheduledChange {
+			next_authorities: authority_list(&new_authorities),
+			delay: 2,
+		},
+	);
+	let signal = |offset: u32, header: &mut RelayChainHeader| {
+		if offset == 2 {
+			header.digest.push(sp_runtime::DigestItem::Consensus(
+				sp_finality_grandpa::GRANDPA_ENGINE_ID,
+				set_change.encode(),
+			));
+		}
+	};
+	let para_blocks = [
+		ParaBlock { relay_offset: 3, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 5, number: 6, timestamp: 1_012_000 },
+	];
+	let client_state =
+		build_fixture_with(6, &para_blocks, &old_authorities, 0, signal).client_state;
+
+	// a proof straddling the enactment block is signed by the new authorities
+	let straddling = build_fixture_with(6, &para_blocks, &new_authorities, 1, signal);
+	let heights = verify_grandpa_header(&client_state, straddling.header)
+		.unwrap()
+		.into_iter()
+		.map(|(height, ..)| height.revision_height)
+		.collect::<Vec<_>>();
+	assert_eq!(heights, vec![5, 6]);
+	let header = build_fixture_with(6, &para_blocks, &old_authorities, 0, signal).header;
+	assert!(verify_grandpa_header(&client_state, header).is_err());
+
+	// a proof of the enactment block is signed by the old authorities
+	let enactment = build_fixture_with(4, &para_blocks, &old_authorities, 0, signal);
+	verify_grandpa_header(&client_state, enactment.header.clone()).unwrap();
+	let header = build_fixture_with(4, &para_blocks, &new_authorities, 1, signal).header;
+	assert!(verify_grandpa_header(&client_state, header).is_err());
+
+	// once the client reaches the enactment block, it moves on to the new authorities
+	let tip = enactment.relay_headers.last().unwrap();
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 6, timestamp: 1_012_000 }];
+	let (header, ..) = build_header_on(tip, 2, &para_blocks, &new_authorities, 1, |_, _| {});
+	let consensus_states = verify_header_batch(&[enactment.header, header], &client_state).unwrap();
+	assert_eq!(consensus_states.len(), 2);
+}
+
+#[test]
+fn test_reject_header_ending_before_set_change_enactment() {
+	let old_authorities = authorities(3);
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let change = sp_finality_grandpa::ScheduledChange {
+		next_authorities: authority_list(&new_authorities),
+		delay: 2,
+	};
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	// the second relay chain block signals a change enacted at the fourth, which is the last block
+	// of the old set for a standard change and the one after it for a forced change.
+	let changes = [
+		(sp_finality_grandpa::ConsensusLog::<u32>::ScheduledChange(change.clone()), 4),
+		(sp_finality_grandpa::ConsensusLog::<u32>::ForcedChange(0, change), 3),
+	];
+	for (log, last_block_of_old_set) in changes {
+		let signal = |offset: u32, header: &mut RelayChainHeader| {
+			if offset == 2 {
+				header.digest.push(sp_runtime::DigestItem::Consensus(
+					sp_finality_grandpa::GRANDPA_ENGINE_ID,
+					log.encode(),
+				));
+			}
+		};
+
+		// a header ending before the last block of the old set would leave the change behind the
+		// client, so that it never rotates its set
+		let Fixture { client_state, header, .. } = build_fixture_with(
+			last_block_of_old_set - 1,
+			&para_blocks,
+			&old_authorities,
+			0,
+			signal,
+		);
+		let signalled_at = client_state.latest_relay_height + 2;
+		assert!(matches!(
+			verify_grandpa_header(&client_state, header.clone()),
+			Err(Error::PendingAuthoritySetChange { signalled_at: signalled, enacted_at, finalized })
+				if signalled == signalled_at &&
+					enacted_at == signalled_at + 2 &&
+					finalized == client_state.latest_relay_height + last_block_of_old_set - 1
+		));
+		// which the verifier rejects as well
+		let err = grandpa_client::verify_parachain_headers_with_grandpa_finality_proof::<
+			RelayChainHeader,
+			HostFunctionsManager,
+		>(client_state.clone().into(), header.into())
+		.unwrap_err();
+		assert!(matches!(
+			err,
+			grandpa_client_primitives::error::Error::PendingAuthoritySetChange { .. }
+		));
+
+		// a header ending at the last block of the old set is still signed by it
+		let header =
+			build_fixture_with(last_block_of_old_set, &para_blocks, &old_authorities, 0, signal)
+				.header;
+		verify_grandpa_header(&client_state, header).unwrap();
+	}
+}
+
+#[test]
+fn test_verify_across_forced_set_change_without_delay() {
+	let old_authorities = authorities(3);
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let change = sp_finality_grandpa::ScheduledChange {
+		next_authorities: authority_list(&new_authorities),
+		delay: 0,
+	};
+	// the second relay chain block signals a change enacted immediately
+	let signal = |log: sp_finality_grandpa::ConsensusLog<u32>| {
+		move |offset: u32, header: &mut RelayChainHeader| {
+			if offset == 2 {
+				header.digest.push(sp_runtime::DigestItem::Consensus(
+					sp_finality_grandpa::GRANDPA_ENGINE_ID,
+					log.encode(),
+				));
+			}
+		}
+	};
+	let forced = signal(sp_finality_grandpa::ConsensusLog::ForcedChange(100, change.clone()));
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 2, number: 6, timestamp: 1_012_000 },
+	];
+	let client_state =
+		build_fixture_with(2, &para_blocks, &old_authorities, 0, forced.clone()).client_state;
+
+	// the block before the forced change is still finalized by the old set
+	let header =
+		build_fixture_with(1, &para_blocks[..1], &old_authorities, 0, forced.clone()).header;
+	verify_grandpa_header(&client_state, header).unwrap();
+
+	// the enactment block is finalized by the new set
+	let enactment = build_fixture_with(2, &para_blocks, &new_authorities, 1, forced.clone());
+	verify_grandpa_header(&client_state, enactment.header.clone()).unwrap();
+	let header = build_fixture_with(2, &para_blocks, &old_authorities, 0, forced).header;
+	assert!(verify_grandpa_header(&client_state, header).is_err());
+
+	// unlike a standard change, whose enactment block is finalized by the old set
+	let standard = signal(sp_finality_grandpa::ConsensusLog::ScheduledChange(change));
+	let header = build_fixture_with(2, &para_blocks, &old_authorities, 0, standard).header;
+	verify_grandpa_header(&client_state, header).unwrap();
+
+	// the new set is applied once, so it carries on finalizing the following blocks
+	let tip = enactment.relay_headers.last().unwrap();
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 7, timestamp: 1_024_000 }];
+	let (header, ..) = build_header_on(tip, 2, &para_blocks, &new_authorities, 1, |_, _| {});
+	let consensus_states = verify_header_batch(&[enactment.header, header], &client_state).unwrap();
+	assert_eq!(consensus_states.len(), 3);
+}
+
+#[test]
+fn test_verify_parachain_header() {
+	let para_blocks = [ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 }];
+	let Fixture { header, relay_headers, para_headers, .. } = build_fixture(1, &para_blocks);
+	let relay_header = &relay_headers[1];
+	let proof = &header.parachain_headers[&relay_header.hash()];
+
+	let head =
+		verify_parachain_header::<HostFunctionsManager>(relay_header.state_root, PARA_ID, proof)
+			.unwrap();
+	assert_eq!(head, para_headers[0].encode());
+
+	// the proof doesn't contain the head of another parachain
+	assert!(verify_parachain_header::<HostFunctionsManager>(
+		relay_header.state_root,
+		PARA_ID + 1,
+		proof
+	)
+	.is_err());
+
+	// the proof doesn't match the state root of another relay chain block
+	assert!(verify_parachain_header::<HostFunctionsManager>(
+		relay_headers[0].state_root,
+		PARA_ID,
+		proof
+	)
+	.is_err());
+
+	// tampered proof nodes no longer hash to the state root
+	let mut tampered = proof.clone();
+	for node in &mut tampered.state_proof {
+		*node.last_mut().unwrap() ^= 1;
+	}
+	assert!(verify_parachain_header::<HostFunctionsManager>(
+		relay_header.state_root,
+		PARA_ID,
+		&tampered
+	)
+	.is_err());
+
+	// the proof was generated for another storage key
+	let mut mismatched = proof.clone();
+	mismatched.storage_key = Some(parachain_header_storage_key(PARA_ID + 1).0);
+	assert!(verify_parachain_header::<HostFunctionsManager>(
+		relay_header.state_root,
+		PARA_ID,
+		&mismatched
+	)
+	.is_err());
+}
+
+/// Builds an MMR of the leaves of the relay chain blocks below the first of `relay_headers`, then
+/// of `relay_headers` and a few blocks past them, see [`ancestry_mmr_leaf`]. Returns the MMR's root
+/// and leaf count, a proof of the leaves of the blocks at `proven` heights and their counts of
+/// headers signalling set changes.
+///
+/// [`ancestry_mmr_leaf`]: grandpa_client::ancestry_mmr_leaf
+fn ancestry_mmr(
+	relay_headers: &[RelayChainHeader],
+	proven: &[u32],
+) -> (H256, u64, Vec<H256>, Vec<u64>) {
+	use grandpa_client::{ancestry_mmr_leaf, AncestryMerge};
+	use grandpa_client_primitives::helpers::is_mandatory_header;
+	use mmr_lib::{leaf_index_to_pos, util::MemStore, MMR};
+	use sp_runtime::traits::BlakeTwo256;
+
+	let mut signalled = 0;
+	let mut leaves = (0..relay_headers[0].number)
+		.map(|number| (H256::from_low_u64_be(number.into()), 0))
+		.collect::<Vec<_>>();
+	for header in relay_headers {
+		signalled += is_mandatory_header(header) as u64;
+		leaves.push((header.hash(), signalled));
+	}
+	leaves.extend((0..4).map(|number| (H256::from_low_u64_be(u64::MAX - number), signalled)));
+
+	let store = MemStore::default();
+	let mut mmr = MMR::<_, AncestryMerge<BlakeTwo256>, _>::new(0, &store);
+	for (hash, signalled) in &leaves {
+		mmr.push(ancestry_mmr_leaf::<BlakeTwo256>(*hash, *signalled)).unwrap();
+	}
+	let positions = proven.iter().map(|number| leaf_index_to_pos((*number).into())).collect();
+	let mmr_proof = mmr.gen_proof(positions).unwrap().proof_items().to_vec();
+	let signalled = proven.iter().map(|number| leaves[*number as usize].1).collect();
+
+	(mmr.get_root().unwrap(), leaves.len() as u64, mmr_proof, signalled)
+}
+
+#[test]
+fn test_verify_mmr_ancestry_proof() {
+	use grandpa_client::verify_parachain_headers_with_mmr_ancestry;
+	use grandpa_client_primitives::MmrAncestryProof;
+
+	let para_blocks = [ParaBlock { relay_offset: 6, number: 5, timestamp: 1_000_000 }];
+	let Fixture { client_state, header, relay_headers, .. } = build_fixture(6, &para_blocks);
+	let client_state: grandpa_client_primitives::ClientState = client_state.into();
+	let target = relay_headers.last().unwrap().clone();
+
+	// the client holds the root of an MMR of the relay chain blocks, up to a few blocks past the
+	// target.
+	let (mmr_root, mmr_leaf_count, mmr_proof, signalled) =
+		ancestry_mmr(&relay_headers, &[relay_headers[0].number, target.number]);
+
+	// only the finalized header is shipped, rather than all the headers linking it to the client
+	let proof = MmrAncestryProof {
+		block: target.hash(),
+		justification: header.finality_proof.justification.clone(),
+		headers: vec![target.clone()],
+		set_changes_signalled: vec![signalled[1]],
+		anchor_set_changes_signalled: signalled[0],
+		mmr_leaf_count,
+		mmr_proof,
+		parachain_headers: header.parachain_headers.clone(),
+	};
+	let verify = |mmr_root, proof| {
+		verify_parachain_headers_with_mmr_ancestry::<RelayChainHeader, HostFunctionsManager>(
+			client_state.clone(),
+			mmr_root,
+			proof,
+		)
+	};
+	let updated = verify(mmr_root, proof.clone()).unwrap();
+	assert_eq!(updated.latest_relay_hash, target.hash());
+	assert_eq!(updated.latest_relay_height, target.number);
+	assert_eq!(updated.latest_para_height, 5);
+
+	// the client holds another MMR root
+	assert!(verify(H256::repeat_byte(1), proof.clone()).is_err());
+
+	// a header that isn't in the client's MMR
+	let mut forged = target.clone();
+	forged.state_root = H256::repeat_byte(9);
+	let forged_proof =
+		MmrAncestryProof { block: forged.hash(), headers: vec![forged], ..proof.clone() };
+	assert!(verify(mmr_root, forged_proof).is_err());
+
+	// the client is already past the proven header
+	let mut client_state = client_state.clone();
+	client_state.latest_relay_height = target.number;
+	assert!(verify_parachain_headers_with_mmr_ancestry::<RelayChainHeader, HostFunctionsManager>(
+		client_state,
+		mmr_root,
+		proof,
+	)
+	.is_err());
+}
+
+#[test]
+fn test_verify_mmr_ancestry_proof_across_set_change() {
+	use grandpa_client::verify_parachain_headers_with_mmr_ancestry;
+	use grandpa_client_primitives::MmrAncestryProof;
+
+	let new_authorities = (10..13u8)
+		.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let para_blocks = [ParaBlock { relay_offset: 6, number: 5, timestamp: 1_000_000 }];
+	// the second relay chain block signals a change, which hands over from the client's set at
+	// the block given by the delay.
+	let fixture = |delay: u32| {
+		let set_change = sp_finality_grandpa::ConsensusLog::<u32>::ScheduledChange(
+			sp_finality_grandpa::ScheduledChange {
+				next_authorities: authority_list(&new_authorities),
+				delay,
+			},
+		);
+		let Fixture { client_state, header, relay_headers, .. } =
+			build_fixture_with(6, &para_blocks, &authorities(3), 0, |offset, header| {
+				if offset == 2 {
+					header.digest.push(sp_runtime::DigestItem::Consensus(
+						sp_finality_grandpa::GRANDPA_ENGINE_ID,
+						set_change.encode(),
+					));
+				}
+			});
+		let client_state: grandpa_client_primitives::ClientState = client_state.into();
+		let (target, signal) = (relay_headers[6].clone(), relay_headers[2].clone());
+		let (mmr_root, mmr_leaf_count, mmr_proof, signalled) =
+			ancestry_mmr(&relay_headers, &[relay_headers[0].number, signal.number, target.number]);
+		let proof = MmrAncestryProof {
+			block: target.hash(),
+			justification: header.finality_proof.justification,
+			headers: vec![signal, target],
+			set_changes_signalled: signalled[1..].to_vec(),
+			anchor_set_changes_signalled: signalled[0],
+			mmr_leaf_count,
+			mmr_proof,
+			parachain_headers: header.parachain_headers,
+		};
+		(client_state, mmr_root, proof)
+	};
+	let verify = |(client_state, mmr_root, proof)| {
+		verify_parachain_headers_with_mmr_ancestry::<RelayChainHeader, HostFunctionsManager>(
+			client_state,
+			mmr_root,
+			proof,
+		)
+	};
+
+	// the target is the last block of the client's set, so the set is rotated
+	let updated = verify(fixture(4)).unwrap();
+	assert_eq!(updated.current_set_id, 1);
+	assert_eq!(updated.current_authorities, authority_list(&new_authorities));
+	assert_eq!(updated.latest_para_height, 5);
+
+	// the MMR shows the header signalling the change was skipped
+	let (client_state, mmr_root, mut proof) = fixture(4);
+	proof.headers.remove(0);
+	proof.set_changes_signalled.remove(0);
+	let err = verify((client_state, mmr_root, proof)).unwrap_err();
+	assert!(format!("{err:?}").contains("missing"), "{err:?}");
+
+	// the client would miss the change if it moved past the signal without rotating its set
+	assert!(verify(fixture(5)).is_err());
+}
+
+#[test]
+fn test_verify_consensus_state_at() {
+	let para_blocks = [
+		ParaBlock { relay_offset: 1, number: 5, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 2, number: 6, timestamp: 1_012_000 },
+		ParaBlock { relay_offset: 3, number: 7, timestamp: 1_024_000 },
+	];
+	let Fixture { client_state, header, para_headers, .. } = build_fixture(3, &para_blocks);
+	let height = |number: u64| Height::new(PARA_ID as u64, number);
+
+	let consensus_state = verify_consensus_state_at(&client_state, header.clone(), height(6))
+		.unwrap()
+		.unwrap();
+	assert_eq!(consensus_state.root.as_bytes(), para_headers[1].state_root.as_bytes());
+	assert_eq!(consensus_state, verify_grandpa_header(&client_state, header.clone()).unwrap()[1].1);
+
+	// heights the header doesn't prove
+	assert!(verify_consensus_state_at(&client_state, header.clone(), height(4))
+		.unwrap()
+		.is_none());
+	assert!(verify_consensus_state_at(&client_state, header, height(8)).unwrap().is_none());
+}
+
+#[test]
+fn test_verify_header_batch() {
+	let para_blocks = [ParaBlock { relay_offset: 2, number: 5, timestamp: 1_000_000 }];
+	let Fixture { authorities, client_state, header, relay_headers, .. } =
+		build_fixture(3, &para_blocks);
+
+	// two more headers, each building on the relay chain finalized by the previous one
+	let mut headers = vec![header];
+	let mut tip = relay_headers.last().unwrap().clone();
+	for (number, timestamp) in [(6, 1_012_000), (7, 1_024_000)] {
+		let para_blocks = [ParaBlock { relay_offset: 1, number, timestamp }];
+		let (header, unknown_headers, _) =
+			build_header_on(&tip, 2, &para_blocks, &authorities, 0, |_, _| {});
+		tip = unknown_headers.last().unwrap().clone();
+		headers.push(header);
+	}
+
+	let consensus_states = verify_header_batch(&headers, &client_state).unwrap();
+	let heights = consensus_states
+		.iter()
+		.map(|(height, ..)| height.revision_height)
+		.collect::<Vec<_>>();
+	assert_eq!(heights, vec![5, 6, 7]);
+
+	// the backlog can't be verified out of order
+	headers.swap(0, 1);
+	assert!(verify_header_batch(&headers, &client_state).is_err());
+}
+
+#[test]
+fn test_consensus_state_successor() {
+	let state = |root: Vec<u8>, secs: i64| {
+		ConsensusState::new(root, tendermint::time::Time::from_unix_timestamp(secs, 0).unwrap())
+	};
+	let height = |number: u64| Height::new(PARA_ID as u64, number);
+	let current = state(vec![1; 32], 1_000);
+
+	// larger height, same or later timestamp
+	current
+		.verify_successor(height(5), &state(vec![2; 32], 1_012), height(6))
+		.unwrap();
+	current
+		.verify_successor(height(5), &state(vec![2; 32], 1_000), height(9))
+		.unwrap();
+
+	// height doesn't increase
+	assert!(current
+		.verify_successor(height(5), &state(vec![2; 32], 1_012), height(5))
+		.is_err());
+	assert!(current
+		.verify_successor(height(5), &state(vec![2; 32], 1_012), height(4))
+		.is_err());
+	// different parachain
+	assert!(current
+		.verify_successor(height(5), &state(vec![2; 32], 1_012), Height::new(1, 6))
+		.is_err());
+	// timestamp goes backwards
+	assert!(current
+		.verify_successor(height(5), &state(vec![2; 32], 999), height(6))
+		.is_err());
+	// malformed root
+	assert!(current
+		.verify_successor(height(5), &state(vec![2; 31], 1_012), height(6))
+		.is_err());
+	assert!(state(vec![], 1_000)
+		.verify_successor(height(5), &state(vec![2; 32], 1_012), height(6))
+		.is_err());
+}
+
+/// Host functions for a relay chain whose GRANDPA authorities sign with sr25519.
+#[derive(Clone, Default, PartialEq, Debug, Eq)]
+struct Sr25519HostFunctions;
+
+impl grandpa_client_primitives::HostFunctions for Sr25519HostFunctions {
+	type Header = RelayChainHeader;
+
+	fn ed25519_verify(
+		sig: &sp_core::ed25519::Signature,
+		msg: &[u8],
+		pub_key: &sp_core::ed25519::Public,
+	) -> bool {
+		<HostFunctionsManager as grandpa_client_primitives::HostFunctions>::ed25519_verify(
+			sig, msg, pub_key,
+		)
+	}
+
+	fn verify_authority_signature(
+		sig: &sp_finality_grandpa::AuthoritySignature,
+		msg: &[u8],
+		id: &sp_finality_grandpa::AuthorityId,
+	) -> bool {
+		let ed25519_sig: &sp_core::ed25519::Signature = sig.as_ref();
+		let ed25519_id: &sp_core::ed25519::Public = id.as_ref();
+		sp_core::sr25519::Pair::verify(
+			&sp_core::sr25519::Signature::from_raw(ed25519_sig.0),
+			msg,
+			&sp_core::sr25519::Public::from_raw(ed25519_id.0),
+		)
+	}
+
+	fn insert_relay_header_hashes(headers: &[H256]) {
+		<HostFunctionsManager as grandpa_client_primitives::HostFunctions>::insert_relay_header_hashes(
+			headers,
+		)
+	}
+
+	fn contains_relay_header_hash(hash: H256) -> bool {
+		<HostFunctionsManager as grandpa_client_primitives::HostFunctions>::contains_relay_header_hash(
+			hash,
+		)
+	}
+}
+
+impl light_client_common::HostFunctions for Sr25519HostFunctions {
+	type BlakeTwo256 = sp_runtime::traits::BlakeTwo256;
+}
+
+#[test]
+fn test_verify_sr25519_justification() {
+	let pairs = (1..=3u8)
+		.map(|i| sp_core::sr25519::Pair::from_seed(&[i; 32]))
+		.collect::<Vec<_>>();
+	let target = RelayChainHeader::new(
+		1,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let (round, set_id) = (1u64, 0u64);
+
+	// the votes are signed with sr25519, but carried in the ed25519 sized GRANDPA types
+	let precommit =
+		finality_grandpa::Precommit { target_hash: target.hash(), target_number: target.number };
+	let message = finality_grandpa::Message::Precommit(precommit.clone());
+	let precommits = pairs
+		.iter()
+		.map(|pair| {
+			let signature = pair.sign(&(&message, round, set_id).encode());
+			finality_grandpa::SignedPrecommit {
+				precommit: precommit.clone(),
+				signature: sp_core::ed25519::Signature::from_raw(signature.0).into(),
+				id: sp_core::ed25519::Public::from_raw(pair.public().0).into(),
+			}
+		})
+		.collect::<Vec<_>>();
+	let authorities = precommits
+		.iter()
+		.map(|precommit| (precommit.id.clone(), 1))
+		.collect::<sp_finality_grandpa::AuthorityList>();
+	let justification = GrandpaJustification::<RelayChainHeader> {
+		round,
+		commit: finality_grandpa::Commit {
+			target_hash: target.hash(),
+			target_number: target.number,
+			precommits,
+		},
+		votes_ancestries: vec![],
+	};
+
+	justification.verify::<Sr25519HostFunctions>(set_id, &authorities).unwrap();
+	// the same justification doesn't verify with the default ed25519 scheme
+	assert!(justification.verify::<HostFunctionsManager>(set_id, &authorities).is_err());
+	// nor under a different set id
+	assert!(justification.verify::<Sr25519HostFunctions>(set_id + 1, &authorities).is_err());
+}
+
+#[test]
+fn test_verification_failure_stages() {
+	let para_blocks = [ParaBlock { relay_offset: 2, number: 5, timestamp: 1_000_000 }];
+	let Fixture { authorities, client_state, header, relay_headers, .. } =
+		build_fixture(3, &para_blocks);
+	let stage = |header: Header, client_state: &ClientState<HostFunctionsManager>| {
+		verify_grandpa_header(client_state, header).unwrap_err().verification_stage()
+	};
+	assert_eq!(verify_grandpa_header(&client_state, header.clone()).unwrap().len(), 1);
+
+	// justification that can't be decoded
+	let mut invalid = header.clone();
+	invalid.finality_proof.justification.truncate(8);
+	assert_eq!(stage(invalid, &client_state), Some(VerificationStage::Finality));
+
+	// justification signed by the client's authorities, but for another set id
+	let mut invalid = header.clone();
+	let target = relay_headers.last().unwrap();
+	invalid.finality_proof.justification = justification(target, 1, 1, &authorities).encode();
+	assert_eq!(stage(invalid, &client_state), Some(VerificationStage::Finality));
+
+	// justification signed by authorities the client doesn't know about
+	let mut other_set = client_state.clone();
+	other_set.current_authorities = authority_list(
+		&(10..13u8)
+			.map(|i| sp_core::ed25519::Pair::from_seed(&[i; 32]))
+			.collect::<Vec<_>>(),
+	);
+	assert_eq!(stage(header.clone(), &other_set), Some(VerificationStage::AuthoritySet));
+
+	// parachain header that isn't in the relay chain state
+	let mut invalid = header.clone();
+	invalid.parachain_headers.values_mut().next().unwrap().state_proof = vec![];
+	assert_eq!(stage(invalid, &client_state), Some(VerificationStage::StateProof));
+
+	let mut invalid = header.clone();
+	invalid.parachain_headers.values_mut().next().unwrap().storage_key =
+		Some(parachain_header_storage_key(PARA_ID + 1).0);
+	assert_eq!(stage(invalid, &client_state), Some(VerificationStage::StateProof));
+
+	// timestamp extrinsic that isn't in the parachain header
+	let mut invalid = header;
+	let proofs = invalid.parachain_headers.values_mut().next().unwrap();
+	*proofs.extrinsic.last_mut().unwrap() ^= 1;
+	assert_eq!(stage(invalid, &client_state), Some(VerificationStage::Timestamp));
+
+	// errors unrelated to header verification have no stage
+	assert_eq!(Error::Custom("other".to_string()).verification_stage(), None);
+}
+
+#[test]
+fn test_commit_target_on_sibling_fork() {
+	let para_blocks = [ParaBlock { relay_offset: 2, number: 5, timestamp: 1_000_000 }];
+	let Fixture { authorities, client_state, header, relay_headers, .. } =
+		build_fixture(3, &para_blocks);
+	assert!(verify_grandpa_header(&client_state, header).is_ok());
+
+	// a sibling of the client's latest relay chain block, finalized by the same authorities
+	let base = &relay_headers[0];
+	let sibling = RelayChainHeader::new(
+		base.number,
+		Default::default(),
+		H256::repeat_byte(1),
+		base.parent_hash,
+		Default::default(),
+	);
+	assert_ne!(sibling.hash(), base.hash());
+	let (forked, ..) = build_header_on(&sibling, 3, &para_blocks, &authorities, 0, |_, _| {});
+
+	let err = verify_grandpa_header(&client_state, forked).unwrap_err();
+	assert!(matches!(
+		err,
+		Error::ForkedCommitTarget { latest_relay_hash, .. } if latest_relay_hash == base.hash()
+	));
+	assert_eq!(err.verification_stage(), Some(VerificationStage::Finality));
+}
+
+#[test]
+fn test_precommit_target_not_descending_from_commit_target() {
+	let Fixture { client_state, header, relay_headers, .. } = build_fixture(3, &[]);
+	let target = &relay_headers[3];
+	let with_precommit_on = |precommit_target: &RelayChainHeader| {
+		let mut justification = GrandpaJustification::<RelayChainHeader>::decode(
+			&mut &header.finality_proof.justification[..],
+		)
+		.unwrap();
+		justification.commit.precommits[0].precommit = finality_grandpa::Precommit {
+			target_hash: precommit_target.hash(),
+			target_number: precommit_target.number,
+		};
+		justification.votes_ancestries = vec![precommit_target.clone()];
+		let mut header = header.clone();
+		header.finality_proof.justification = justification.encode();
+		header
+	};
+
+	// a block at the height of the commit target's child, built on the commit target's parent
+	let unrelated = RelayChainHeader::new(
+		target.number + 1,
+		Default::default(),
+		Default::default(),
+		relay_headers[2].hash(),
+		Default::default(),
+	);
+	let err = verify_grandpa_header(&client_state, with_precommit_on(&unrelated)).unwrap_err();
+	assert!(matches!(
+		err,
+		Error::InvalidVoteAncestry { precommit_target, commit_target }
+			if precommit_target == unrelated.hash() && commit_target == target.hash()
+	));
+	assert_eq!(err.verification_stage(), Some(VerificationStage::Finality));
+
+	// a descendant of the commit target passes the ancestry check, the vote's signature doesn't
+	let child = RelayChainHeader::new(
+		target.number + 1,
+		Default::default(),
+		Default::default(),
+		target.hash(),
+		Default::default(),
+	);
+	let err = verify_grandpa_header(&client_state, with_precommit_on(&child)).unwrap_err();
+	assert!(matches!(err, Error::Verification { stage: VerificationStage::Finality, .. }));
+}
+
+/// Upper bound on the encoded size of the standard proof in [`test_proof_size`], which is about
+/// 1.8 KiB today. Raise it deliberately when a change justifies larger proofs.
+const MAX_STANDARD_PROOF_SIZE: usize = 2048;
+
+#[test]
+fn test_proof_size() {
+	// six relay chain blocks finalized by three authorities, three of them including a parachain
+	// header.
+	let para_blocks = [
+		ParaBlock { relay_offset: 2, number: 11, timestamp: 1_000_000 },
+		ParaBlock { relay_offset: 4, number: 12, timestamp: 1_012_000 },
+		ParaBlock { relay_offset: 5, number: 13, timestamp: 1_024_000 },
+	];
+	let Fixture { header, .. } = build_fixture(6, &para_blocks);
+	assert_eq!(header.finality_proof.unknown_headers.len(), 6);
+	assert_eq!(header.parachain_headers.len(), 3);
+
+	let size = ParachainHeadersWithFinalityProof::from(header).encode().len();
+	assert!(
+		size <= MAX_STANDARD_PROOF_SIZE,
+		"standard proof is {size} bytes, more than the {MAX_STANDARD_PROOF_SIZE} bytes allowed"
+	);
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_parallel_state_proof_verification() {
+	use crate::client_def::{verify_state_proofs, verify_state_proofs_parallel};
+
+	let para_blocks = (1..=5)
+		.map(|i| ParaBlock { relay_offset: i, number: i + 4, timestamp: 1_000_000 + i as u64 })
+		.collect::<Vec<_>>();
+	let Fixture { client_state, header, relay_headers, .. } = build_fixture(6, &para_blocks);
+	let proofs = |header: &Header| {
+		header
+			.parachain_headers
+			.iter()
+			.map(|(hash, proof)| {
+				let relay_header =
+					relay_headers.iter().find(|relay_header| relay_header.hash() == *hash).unwrap();
+				(relay_header, proof.clone())
+			})
+			.collect::<Vec<_>>()
+	};
+
+	let sequential = verify_state_proofs::<HostFunctionsManager>(PARA_ID, proofs(&header)).unwrap();
+	let parallel =
+		verify_state_proofs_parallel::<HostFunctionsManager>(PARA_ID, proofs(&header)).unwrap();
+	assert_eq!(sequential.len(), 5);
+	assert_eq!(sequential, parallel);
+	// the parallel path is what verification uses with the feature enabled
+	assert_eq!(verify_grandpa_header(&client_state, header.clone()).unwrap(), sequential);
+
+	// invalid proofs are rejected by both
+	let mut invalid = header;
+	invalid.parachain_headers.values_mut().nth(2).unwrap().state_proof = vec![];
+	let sequential = verify_state_proofs::<HostFunctionsManager>(PARA_ID, proofs(&invalid));
+	let parallel = verify_state_proofs_parallel::<HostFunctionsManager>(PARA_ID, proofs(&invalid));
+	assert_eq!(sequential.unwrap_err().verification_stage(), Some(VerificationStage::StateProof));
+	assert_eq!(parallel.unwrap_err().verification_stage(), Some(VerificationStage::StateProof));
+}
+
+#[test]
+fn test_compact_justification() {
+	let pairs = authorities(7);
+	let authorities = authority_list(&pairs);
+	let target = RelayChainHeader::new(
+		1,
+		Default::default(),
+		Default::default(),
+		Default::default(),
+		Default::default(),
+	);
+	let (round, set_id) = (1u64, 0u64);
+	let justification = justification(&target, round, set_id, &pairs);
+
+	let compact = justification.compact(&authorities).unwrap();
+	// each precommit saves the 32 byte public key, less the compact encoded index
+	assert_eq!(justification.encode().len() - compact.encode().len(), pairs.len() * 31);
+	let decoded =
+		CompactGrandpaJustification::<RelayChainHeader>::decode(&mut &*compact.encode()).unwrap();
+	assert_eq!(decoded.clone().expand(&authorities).unwrap(), justification);
+
+	// both forms verify against the same authority set
+	justification.verify::<HostFunctionsManager>(set_id, &authorities).unwrap();
+	decoded.verify::<HostFunctionsManager>(set_id, &authorities).unwrap();
+
+	// expanding against a different authority set attributes the votes to the wrong voters
+	let mut reordered = authorities.clone();
+	reordered.reverse();
+	assert!(decoded.verify::<HostFunctionsManager>(set_id, &reordered).is_err());
+
+	// voters outside the authority set can't be compacted
+	assert!(justification.compact(&authority_list(&pairs[1..])).is_err());
+}
+
+mod raw_header_round_trip {
+	use super::*;
+	use proptest::{collection::vec, option, prelude::*};
+	use std::collections::BTreeMap;
+
+	fn arb_h256() -> impl Strategy<Value = H256> {
+		any::<[u8; 32]>().prop_map(H256::from)
+	}
+
+	fn arb_bytes() -> impl Strategy<Value = Vec<u8>> {
+		vec(any::<u8>(), 0..64)
+	}
+
+	fn arb_parachain_header_proofs() -> impl Strategy<Value = ParachainHeaderProofs> {
+		(
+			vec(arb_bytes(), 0..4),
+			arb_bytes(),
+			vec(arb_bytes(), 0..4),
+			option::of(arb_bytes()),
+			any::<u32>(),
+		)
+			.prop_map(
+				|(state_proof, extrinsic, extrinsic_proof, storage_key, extrinsic_index)| {
+					ParachainHeaderProofs {
+						state_proof,
+						extrinsic,
+						extrinsic_proof,
+						storage_key,
+						extrinsic_index,
+					}
+				},
+			)
+	}
+
+	/// A chain of relay chain headers, since unknown headers that aren't contiguous are rejected.
+	fn arb_unknown_headers() -> impl Strategy<Value = Vec<RelayChainHeader>> {
+		(0..u32::MAX - 8, arb_h256(), vec((arb_h256(), arb_h256()), 1..8)).prop_map(
+			|(start, parent_hash, roots)| {
+				let mut parent_hash = parent_hash;
+				roots
+					.into_iter()
+					.zip(start..)
+					.map(|((state_root, extrinsics_root), number)| {
+						let header = RelayChainHeader::new(
+							number,
+							extrinsics_root,
+							state_root,
+							parent_hash,
+							Default::default(),
+						);
+						parent_hash = header.hash();
+						header
+					})
+					.collect()
+			},
+		)
+	}
+
+	fn arb_header() -> impl Strategy<Value = Header> {
+		(
+			arb_unknown_headers(),
+			// empty justifications are rejected
+			vec(any::<u8>(), 1..256),
+			vec(arb_parachain_header_proofs(), 0..4),
+		)
+			.prop_map(|(unknown_headers, justification, proofs)| Header {
+				// parachain headers are only proven at the unknown headers
+				parachain_headers: unknown_headers
+					.iter()
+					.map(|header| header.hash())
+					.zip(proofs)
+					.collect(),
+				finality_proof: FinalityProof {
+					block: unknown_headers.last().unwrap().hash(),
+					justification,
+					unknown_headers,
+				},
+			})
+	}
+
+	/// The hash resized to `len` bytes, truncated or padded with zeros.
+	fn resized(hash: H256, len: usize) -> Vec<u8> {
+		let mut bytes = hash.as_bytes().to_vec();
+		bytes.resize(len, 0);
+		bytes
+	}
+
+	proptest! {
+		#[test]
+		fn test_raw_header_round_trip(header in arb_header()) {
+			let raw_header = RawHeader::from(header.clone());
+			prop_assert_eq!(Header::try_from(raw_header).unwrap(), header);
+		}
+
+		#[test]
+		fn test_protobuf_bytes_round_trip(header in arb_header()) {
+			let bytes = header.encode_to_protobuf();
+			let raw_header = RawHeader::from(header.clone());
+			prop_assert_eq!(&bytes, &prost::Message::encode_to_vec(&raw_header));
+			prop_assert_eq!(Header::decode_from_protobuf(&bytes).unwrap(), header.clone());
+			prop_assert_eq!(Header::try_from(&bytes[..]).unwrap(), header);
+		}
+
+		#[test]
+		fn test_protobuf_bytes_truncated(header in arb_header()) {
+			// dropping the last byte cuts the last field short, whatever its type
+			let bytes = header.encode_to_protobuf();
+			prop_assert!(matches!(
+				Header::decode_from_protobuf(&bytes[..bytes.len() - 1]),
+				Err(Error::ProtoBuf(_))
+			));
+		}
+
+		#[test]
+		fn test_raw_header_finalized_block_length(header in arb_header(), len in 0usize..=64) {
+			let mut raw_header = RawHeader::from(header.clone());
+			raw_header.finality_proof.as_mut().unwrap().block =
+				resized(header.finality_proof.block, len);
+			prop_assert_eq!(Header::try_from(raw_header).is_ok(), len == 32);
+		}
+
+		#[test]
+		fn test_raw_header_relay_hash_length(
+			header in arb_header(),
+			proofs in arb_parachain_header_proofs(),
+			len in 0usize..=64
+		) {
+			let mut header = header;
+			let relay_hash = header.finality_proof.block;
+			header.parachain_headers = BTreeMap::from([(relay_hash, proofs)]);
+			let mut raw_header = RawHeader::from(header);
+			raw_header.parachain_headers[0].relay_hash = resized(relay_hash, len);
+			prop_assert_eq!(Header::try_from(raw_header).is_ok(), len == 32);
+		}
+	}
+}